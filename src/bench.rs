@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::compiled::CompiledExpr;
+use crate::vm::{RuntimeError, VirtualMachine};
+
+/// Per-run times of a benchmark.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchStats {
+    pub runs: usize,
+    pub min: Duration,
+    pub mean: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for BenchStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} runs: min {:.1?}, mean {:.1?}, max {:.1?}",
+            self.runs, self.min, self.mean, self.max
+        )
+    }
+}
+
+/// Evaluates `expr` `runs` times and times each run. Whatever the runs
+/// assign or define is undone afterwards, and no answer is recorded, so
+/// that benchmarking leaves the session as it was. Stops at the first
+/// error, e.g. when interrupted.
+pub fn bench(
+    vm: &mut VirtualMachine,
+    expr: &CompiledExpr,
+    runs: usize,
+) -> Result<BenchStats, RuntimeError> {
+    let checkpoint = vm.checkpoint();
+    let bindings = HashMap::new();
+    let mut times = Vec::with_capacity(runs);
+    let mut result = Ok(());
+    for _ in 0..runs {
+        let start = Instant::now();
+        if let Err(e) = expr.eval_on(vm, &bindings) {
+            result = Err(e);
+            break;
+        }
+        times.push(start.elapsed());
+    }
+    vm.rollback(checkpoint);
+    result?;
+
+    let total: Duration = times.iter().sum();
+    Ok(BenchStats {
+        runs,
+        min: times.iter().min().copied().unwrap_or_default(),
+        mean: total.div_f64(runs.max(1) as f64),
+        max: times.iter().max().copied().unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::Operation::*;
+
+    #[test]
+    fn test_bench() {
+        let mut vm = VirtualMachine::new();
+        let expr = CompiledExpr::from_operations(vec![Load("x".to_string()), Const(1.0), Add, Store("x".to_string())]);
+        vm.interpret(&[Const(0.0), Store("x".to_string())]).unwrap();
+
+        let stats = bench(&mut vm, &expr, 50).unwrap();
+        assert_eq!(stats.runs, 50);
+        assert!(stats.min <= stats.mean && stats.mean <= stats.max);
+        assert_eq!(vm.get_prev_ans().unwrap().as_number(), Some(0.0));
+        assert_eq!(vm.interpret(&[Load("x".to_string())]).unwrap().result().as_number(), Some(0.0));
+
+        let undefined = CompiledExpr::from_operations(vec![Load("undefined".to_string())]);
+        assert!(bench(&mut vm, &undefined, 3).is_err());
+    }
+}
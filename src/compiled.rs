@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::lexer;
+use crate::operation::Operation;
+use crate::optimize;
+use crate::script::{self, StatementError};
+use crate::value::Value;
+use crate::vm::{self, RuntimeError, VirtualMachine, BUILTINS};
+
+/// An expression compiled once and then evaluated many times with different
+/// values for its free variables, e.g. to tabulate `x^2 + 1` over many `x`
+/// without scanning and parsing it again.
+pub struct CompiledExpr {
+    operations: Vec<Operation>,
+    variables: Vec<String>,
+    /// The VM [`eval`](Self::eval) runs on, created by the first call and
+    /// then reused, stack and all.
+    vm: RefCell<Option<VirtualMachine>>,
+}
+
+// The binary only benchmarks with `from_operations` and `eval_on`; the rest
+// is for embedding.
+#[allow(dead_code)]
+impl CompiledExpr {
+    /// Compiles and [optimizes](optimize::optimize) `source`.
+    pub fn new(source: &str) -> Result<Self, StatementError> {
+        let tokens = lexer::scan(source).map_err(StatementError::Lex)?;
+        Ok(Self::from_operations(optimize::optimize(script::compile(tokens)?)))
+    }
+
+    /// Wraps already compiled `operations` as they are, so that they still
+    /// match the spans they were compiled with.
+    pub fn from_operations(operations: Vec<Operation>) -> Self {
+        let mut variables: Vec<String> = Vec::new();
+        free_variables(&operations, &mut Vec::new(), &mut variables);
+        Self { operations, variables, vm: RefCell::new(None) }
+    }
+
+    /// The free variables, in order of first use. Builtin constants like
+    /// `pi` are not included.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// Evaluates the expression with its free variables bound to
+    /// `bindings`. A variable without a binding is an `UndefinedVariable`.
+    pub fn eval(&self, bindings: &HashMap<&str, f64>) -> Result<Value, RuntimeError> {
+        let mut vm = self.vm.borrow_mut();
+        self.eval_on(vm.get_or_insert_with(VirtualMachine::new), bindings)
+    }
+
+    /// Like [`eval`](Self::eval), but on `vm`, whose own variables the
+    /// bindings shadow without assigning them and whose other names the
+    /// expression can use. The answer is not recorded.
+    pub fn eval_on(&self, vm: &mut VirtualMachine, bindings: &HashMap<&str, f64>) -> Result<Value, RuntimeError> {
+        let locals = bindings.iter().map(|(&name, &x)| (name.to_string(), Value::Number(x))).collect();
+        vm.evaluate_with(&self.operations, locals)
+    }
+}
+
+/// Appends the names loaded by `operations` that are neither builtins, such
+/// as `pi` or a function passed by name like `sqrt`, nor bound by a `let`
+/// in `bound` or in `operations` itself.
+pub fn free_variables<'a>(
+    operations: &'a [Operation],
+    bound: &mut Vec<&'a String>,
+    variables: &mut Vec<String>,
+) {
+    for op in operations {
+        match op {
+            Operation::PushScope(name) => bound.push(name),
+            Operation::PopScope => {
+                bound.pop();
+            }
+            Operation::If(branches) => {
+                free_variables(&branches.then, bound, variables);
+                free_variables(&branches.otherwise, bound, variables);
+            }
+            Operation::While(body) => {
+                free_variables(&body.condition, bound, variables);
+                free_variables(&body.body, bound, variables);
+            }
+            Operation::For(body) => {
+                bound.push(&body.variable);
+                free_variables(&body.body, bound, variables);
+                bound.pop();
+            }
+            Operation::SolveFor(equation) => {
+                bound.push(&equation.variable);
+                free_variables(&equation.residual, bound, variables);
+                bound.pop();
+            }
+            Operation::Load(name)
+                if !variables.contains(name)
+                    && !bound.contains(&name)
+                    && !BUILTINS.iter().any(|&(builtin, _)| builtin == name)
+                    && vm::builtin_function(name).is_none() =>
+            {
+                variables.push(name.clone());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_many_times() {
+        let expr = CompiledExpr::new("x^2 + 3*x - y + 0*pi").unwrap();
+        assert_eq!(expr.variables(), ["x", "y"]);
+
+        for x in 0..10 {
+            let bindings = HashMap::from([("x", x as f64), ("y", 1.0)]);
+            let expected = (x * x + 3 * x - 1) as f64;
+            assert_eq!(expr.eval(&bindings), Ok(Value::Number(expected)));
+        }
+    }
+
+    #[test]
+    fn test_operations_are_optimized() {
+        let expr = CompiledExpr::new("x * (2 * 3) + 0").unwrap();
+        let expected = [Operation::Load("x".to_string()), Operation::Const(6.0), Operation::Times];
+        assert_eq!(expr.operations(), expected);
+        assert_eq!(expr.eval(&HashMap::from([("x", 2.0)])), Ok(Value::Number(12.0)));
+    }
+
+    #[test]
+    fn test_eval_on_a_vm() {
+        let expr = CompiledExpr::new("x^2 + 3*x - y").unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.set_variable("x", Value::Number(100.0));
+        let bindings = HashMap::from([("x", 2.0), ("y", 1.0)]);
+        assert_eq!(expr.eval_on(&mut vm, &bindings), Ok(Value::Number(9.0)));
+        assert_eq!(vm.get_variable("x"), Ok(Value::Number(100.0)));
+        assert!(vm.get_prev_ans().is_err());
+
+        // Free variables without a binding are the VM's own.
+        assert_eq!(expr.eval_on(&mut vm, &HashMap::from([("y", 0.0)])), Ok(Value::Number(10300.0)));
+    }
+
+    #[test]
+    fn test_unbound_variable() {
+        let expr = CompiledExpr::new("2 * rate").unwrap();
+        let result = expr.eval(&HashMap::new());
+        assert_eq!(result, Err(RuntimeError::UndefinedVariable("rate".to_string())));
+    }
+
+    #[test]
+    fn test_compile_error() {
+        assert!(CompiledExpr::new("2 +").is_err());
+    }
+
+    #[test]
+    fn test_let_bound_names_are_not_free() {
+        let expr = CompiledExpr::new("let a = x + 1 in a * a").unwrap();
+        assert_eq!(expr.variables(), ["x"]);
+        assert_eq!(expr.eval(&HashMap::from([("x", 2.0)])), Ok(Value::Number(9.0)));
+
+        let expr = CompiledExpr::new("let a = 1 in if x then a else y").unwrap();
+        assert_eq!(expr.variables(), ["x", "y"]);
+
+        let expr = CompiledExpr::new("integrate(sqrt, 0, x)").unwrap();
+        assert_eq!(expr.variables(), ["x"]);
+    }
+}
@@ -0,0 +1,54 @@
+use crate::highlight::{ERROR, RESET};
+use crate::token::Span;
+
+/// Reprints the line of `source` that `span` starts in, with `^` under the
+/// span and `message` beside them:
+///
+/// ```text
+/// sqrt(2 +* 3)
+///         ^ Expected an expression at 8
+/// ```
+///
+/// A span past the end of the source, as for a missing `)`, points just
+/// after it.
+pub fn caret(source: &str, span: Span, message: &str, colour: bool) -> String {
+    let mut start = span.0.min(source.len());
+    while !source.is_char_boundary(start) {
+        start -= 1;
+    }
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+    let line = source[line_start..line_end].trim_end_matches('\r');
+
+    // Tabs are kept so that the carets line up however wide they are shown.
+    let indent: String = source[line_start..start]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = source
+        .get(start..span.1.clamp(start, line_end))
+        .map_or(0, |text| text.chars().count())
+        .max(1);
+    let carets = "^".repeat(width);
+    if colour {
+        format!("{}\n{}{}{} {}{}", line, indent, ERROR, carets, message, RESET)
+    } else {
+        format!("{}\n{}{} {}", line, indent, carets, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_caret() {
+        assert_eq!(caret("sqrt(2 +* 3)", (8, 9), "oops", false), "sqrt(2 +* 3)\n        ^ oops");
+        assert_eq!(caret("1 + foo bar", (8, 11), "oops", false), "1 + foo bar\n        ^^^ oops");
+        assert_eq!(caret("(1", (2, 3), "oops", false), "(1\n  ^ oops");
+        assert_eq!(
+            caret("1 +\n\t2 $", (7, 8), "oops", true),
+            format!("\t2 $\n\t  {}^ oops{}", ERROR, RESET)
+        );
+    }
+}
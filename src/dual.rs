@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use crate::operation::Operation::*;
+use crate::operation::{Function, Operation};
+use crate::value::Value;
+use crate::vm::{RuntimeError, VirtualMachine};
+
+/// A dual number `re + eps·ε` with `ε² = 0`. Evaluating a function on
+/// `x + ε` yields `f(x) + f'(x)·ε`, so the derivative is exact to machine
+/// precision rather than approximated by finite differences.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dual {
+    pub re: f64,
+    pub eps: f64,
+}
+
+impl Dual {
+    pub fn constant(re: f64) -> Self {
+        Self { re, eps: 0.0 }
+    }
+
+    pub fn variable(re: f64) -> Self {
+        Self { re, eps: 1.0 }
+    }
+}
+
+/// Evaluates `operations` on dual numbers, with every `Ans` standing for the
+/// independent variable at `x`, and returns `f(x)` and `f'(x)`. Variables
+/// and functions are those of `vm`: variables are constants, and calls to
+/// user functions are differentiated through their bodies.
+///
+/// Real parts are computed by the VM's own operations, so values and domain
+/// errors match normal evaluation exactly; only the derivative parts are
+/// propagated here. Operations whose operands do not depend on the variable
+/// therefore work even without a derivative rule.
+pub fn evaluate(vm: &mut VirtualMachine, operations: &[Operation], x: f64) -> Result<Dual, RuntimeError> {
+    Evaluator { vm, x, depth: 0 }.run(operations, &HashMap::new())
+}
+
+struct Evaluator<'a> {
+    vm: &'a mut VirtualMachine,
+    x: f64,
+    /// How many user function calls deep the evaluation is.
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    /// Evaluates a program, or a function body with its parameters bound
+    /// to `params`.
+    fn run(&mut self, operations: &[Operation], params: &HashMap<String, Dual>) -> Result<Dual, RuntimeError> {
+        let stack = &mut Vec::new();
+
+        for (i, op) in operations.iter().enumerate() {
+            let arity = op.arity();
+            if stack.len() < arity {
+                return Err(RuntimeError::Underflow);
+            }
+            let args: Vec<Dual> = stack.split_off(stack.len() - arity);
+
+            let result = match op {
+                Ans => Dual::variable(self.x),
+                Const(val) => Dual::constant(*val),
+                Load(name) => match params.get(name) {
+                    Some(&param) => param,
+                    None => {
+                        let value = self.vm.get_variable(name)?;
+                        Dual::constant(value.as_number().ok_or(RuntimeError::TypeError)?)
+                    }
+                },
+                If(branches) => {
+                    let branch = if args[0].re != 0.0 { &branches.then } else { &branches.otherwise };
+                    self.run(branch, params)?
+                }
+                // These would change the session, or bind names that the
+                // dual environment does not track.
+                Store(_) | Define(_) | PushScope(_) | PopScope | For(_) | While(_) => {
+                    return Err(RuntimeError::NotImplemented);
+                }
+                _ => {
+                    let in_operation = |error| RuntimeError::Operation {
+                        error: Box::new(error),
+                        index: i,
+                        operation: op.clone(),
+                        operands: args.iter().map(|a| Value::Number(a.re)).collect(),
+                        span: None,
+                    };
+                    let depends = args.iter().any(|a| a.eps != 0.0);
+                    if let Call(name, _) = op
+                        && depends
+                        && let Some(function) = self.vm.function(name)
+                    {
+                        self.call(&function, &args).map_err(in_operation)?
+                    } else {
+                        let operands = args.iter().map(|a| Value::Number(a.re)).collect();
+                        let re = self
+                            .vm
+                            .apply(op, operands)
+                            .map_err(in_operation)?
+                            .as_number()
+                            .ok_or(RuntimeError::TypeError)?;
+                        let eps = if depends { tangent(op, &args, re, self.vm.use_radians)? } else { 0.0 };
+                        Dual { re, eps }
+                    }
+                }
+            };
+            stack.push(result);
+        }
+
+        match stack[..] {
+            [result] => Ok(result),
+            [] => Err(RuntimeError::Underflow),
+            _ => Err(RuntimeError::NotImplemented),
+        }
+    }
+
+    /// Differentiates through a call of a user function on `args`.
+    fn call(&mut self, function: &Function, args: &[Dual]) -> Result<Dual, RuntimeError> {
+        if args.len() != function.params.len() {
+            return Err(RuntimeError::ArgumentCount {
+                function: function.name.clone(),
+                expected: function.params.len(),
+                found: args.len(),
+            });
+        }
+        let max_depth = self.vm.limits.max_call_depth;
+        if self.depth >= max_depth {
+            return Err(RuntimeError::RecursionLimit(max_depth));
+        }
+        let params = function.params.iter().cloned().zip(args.iter().copied()).collect();
+        self.depth += 1;
+        let result = self.run(&function.body, &params);
+        self.depth -= 1;
+        result
+    }
+}
+
+/// Derivative part of `op` applied to `args`, given the real result `re`.
+fn tangent(op: &Operation, args: &[Dual], re: f64, use_radians: bool) -> Result<f64, RuntimeError> {
+    // Trig functions take degrees, and inverse trig functions return them,
+    // when not in radian mode.
+    let to_radians = if use_radians { 1.0 } else { PI / 180.0 };
+    let from_radians = 1.0 / to_radians;
+
+    let eps = match (op, args) {
+        (Negate, &[a]) => -a.eps,
+        (Add, &[a, b]) => a.eps + b.eps,
+        (Subtract, &[a, b]) => a.eps - b.eps,
+        (Times, &[a, b]) => a.eps * b.re + a.re * b.eps,
+        (Divide, &[a, b]) => (a.eps * b.re - a.re * b.eps) / (b.re * b.re),
+        (Power, &[a, b]) if b.eps == 0.0 => {
+            if b.re == 0.0 {
+                0.0
+            } else {
+                b.re * a.re.powf(b.re - 1.0) * a.eps
+            }
+        }
+        (Power, &[a, b]) => re * (b.eps * a.re.ln() + b.re * a.eps / a.re),
+        (Min | Max, &[a, b]) => if re == b.re { b.eps } else { a.eps },
+
+        (Sin, &[a]) => to_radians * (a.re * to_radians).cos() * a.eps,
+        (Cos, &[a]) => -to_radians * (a.re * to_radians).sin() * a.eps,
+        (Tan, &[a]) => to_radians / (a.re * to_radians).cos().powi(2) * a.eps,
+        (ArcSin, &[a]) => from_radians / (1.0 - a.re * a.re).sqrt() * a.eps,
+        (ArcCos, &[a]) => -from_radians / (1.0 - a.re * a.re).sqrt() * a.eps,
+        (ArcTan, &[a]) => from_radians / (1.0 + a.re * a.re) * a.eps,
+        // atan2(y, x) compiles to `y x ArcTan2`.
+        (ArcTan2, &[y, x]) => {
+            from_radians * (x.re * y.eps - y.re * x.eps) / (x.re * x.re + y.re * y.re)
+        }
+        (SinD, &[a]) => PI / 180.0 * a.re.to_radians().cos() * a.eps,
+        (CosD, &[a]) => -PI / 180.0 * a.re.to_radians().sin() * a.eps,
+        (TanD, &[a]) => PI / 180.0 / a.re.to_radians().cos().powi(2) * a.eps,
+        (Deg2Rad, &[a]) => a.eps.to_radians(),
+        (Rad2Deg, &[a]) => a.eps.to_degrees(),
+        (Hypot, &[a, b]) => (a.re * a.eps + b.re * b.eps) / re,
+
+        (Sinh, &[a]) => a.re.cosh() * a.eps,
+        (Cosh, &[a]) => a.re.sinh() * a.eps,
+        (Tanh, &[a]) => (1.0 - re * re) * a.eps,
+        (ArcSinh, &[a]) => a.eps / (a.re * a.re + 1.0).sqrt(),
+        (ArcCosh, &[a]) => a.eps / (a.re * a.re - 1.0).sqrt(),
+        (ArcTanh, &[a]) => a.eps / (1.0 - a.re * a.re),
+
+        (Ln, &[a]) => a.eps / a.re,
+        (Log10, &[a]) => a.eps / (a.re * 10f64.ln()),
+        (Log2, &[a]) => a.eps / (a.re * 2f64.ln()),
+        (LogBase, &[base, a]) => (a.eps / a.re - re * base.eps / base.re) / base.re.ln(),
+        (Exp, &[a]) => re * a.eps,
+        (Sqrt, &[a]) => a.eps / (2.0 * re),
+        (Cbrt, &[a]) => a.eps / (3.0 * re * re),
+        (Root, &[n, a]) => re * (a.eps / (n.re * a.re) - n.eps * a.re.abs().ln() / (n.re * n.re)),
+
+        (Abs, &[a]) => if a.re == 0.0 { 0.0 } else { a.re.signum() * a.eps },
+        (Sign | Floor | Ceil | Round | Trunc, _) => 0.0,
+        (Less | LessEqual | Greater | GreaterEqual | Equal | NotEqual, _) => 0.0,
+        (Frac, &[a]) => a.eps,
+
+        (Erf, &[a]) => 2.0 / PI.sqrt() * (-a.re * a.re).exp() * a.eps,
+        (Erfc, &[a]) => -2.0 / PI.sqrt() * (-a.re * a.re).exp() * a.eps,
+
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+    Ok(eps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, script};
+
+    const EPS: f64 = 1e-12;
+
+    fn evaluate_in(use_radians: bool, operations: &[Operation], x: f64) -> Result<Dual, RuntimeError> {
+        let mut vm = VirtualMachine::new();
+        vm.use_radians = use_radians;
+        evaluate(&mut vm, operations, x)
+    }
+
+    fn derivative(operations: &[Operation], x: f64) -> f64 {
+        evaluate_in(true, operations, x).unwrap().eps
+    }
+
+    fn assert_approx_eq(a: f64, b: f64) {
+        assert!((a - b).abs() < EPS * b.abs().max(1.0), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_polynomial() {
+        // x^3 - 2x
+        let ops = [Ans, Const(3.0), Power, Const(2.0), Ans, Times, Subtract];
+        let result = evaluate_in(true, &ops, 2.0).unwrap();
+        assert_eq!(result, Dual { re: 4.0, eps: 10.0 });
+    }
+
+    #[test]
+    fn test_quotient_and_chain_rules() {
+        // sin(x^2) / x
+        let ops = [Ans, Const(2.0), Power, Sin, Ans, Divide];
+        let x: f64 = 1.3;
+        let expected = 2.0 * (x * x).cos() - (x * x).sin() / (x * x);
+        assert_approx_eq(derivative(&ops, x), expected);
+    }
+
+    #[test]
+    fn test_variable_exponent() {
+        // x^x
+        let ops = [Ans, Ans, Power];
+        let x: f64 = 1.7;
+        assert_approx_eq(derivative(&ops, x), x.powf(x) * (x.ln() + 1.0));
+    }
+
+    #[test]
+    fn test_degree_mode() {
+        let result = evaluate_in(false, &[Ans, Sin], 60.0).unwrap();
+        assert_approx_eq(result.re, 3f64.sqrt() / 2.0);
+        assert_approx_eq(result.eps, PI / 180.0 * 0.5);
+    }
+
+    #[test]
+    fn test_constant_subexpressions_need_no_rule() {
+        // gcd(4, 6) * x
+        let ops = [Const(4.0), Const(6.0), Gcd, Ans, Times];
+        assert_eq!(derivative(&ops, 5.0), 2.0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = evaluate_in(true, &[Ans, Ln], -1.0).unwrap_err();
+        assert_eq!(error.kind(), &RuntimeError::DomainError);
+        assert!(matches!(error, RuntimeError::Operation { index: 1, .. }));
+        assert_eq!(evaluate_in(true, &[Ans, Gamma], 2.0), Err(RuntimeError::NotImplemented));
+    }
+
+    #[test]
+    fn test_session_variables_and_functions() {
+        let compile = |source| script::compile(lexer::scan(source).unwrap()).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.interpret(&compile("a = 3")).unwrap();
+        vm.interpret(&compile("f(t) = if t > 0 then t^2 else 0")).unwrap();
+
+        // a * f(x)
+        let ops = compile("a * f(ans)");
+        assert_eq!(evaluate(&mut vm, &ops, 2.0), Ok(Dual { re: 12.0, eps: 12.0 }));
+        assert_eq!(evaluate(&mut vm, &ops, -2.0), Ok(Dual { re: 0.0, eps: 0.0 }));
+
+        let ops = compile("b = ans");
+        assert_eq!(evaluate(&mut vm, &ops, 1.0), Err(RuntimeError::NotImplemented));
+        assert!(vm.get_variable("b").is_err());
+    }
+}
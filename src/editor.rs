@@ -0,0 +1,661 @@
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Environment variable with the number of lines of history to keep.
+pub const CALC_HISTORY_SIZE: &str = "CALC_HISTORY_SIZE";
+pub const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// A line editor for the REPL. When both stdin and stdout are terminals it
+/// switches the terminal to raw mode while a line is typed, so that the
+/// cursor can move within the line and the arrow keys recall earlier lines.
+/// Otherwise, e.g. when input is piped, lines are read as they are.
+#[derive(Debug, Clone)]
+pub struct Editor {
+    history: Vec<String>,
+    /// The most lines of history kept; older lines are forgotten first.
+    max_history: usize,
+    /// The words Tab completes to.
+    completions: Vec<String>,
+    /// Decorates the line as it is shown, e.g. with colours, without
+    /// changing the characters in it.
+    highlighter: Option<fn(&str) -> String>,
+}
+
+/// A key press, decoded from the bytes a terminal sends for it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    /// Control and a letter, e.g. `Ctrl('a')`, for keys not given above.
+    Ctrl(char),
+    Unknown,
+}
+
+/// What a key press did to the line being edited.
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    Continue,
+    Accept,
+    /// Ctrl-C: abandon the line.
+    Cancel,
+    /// Ctrl-D on an empty line.
+    EndOfInput,
+}
+
+impl Default for Editor {
+    /// Keeps the number of lines in `CALC_HISTORY_SIZE`, or
+    /// `DEFAULT_HISTORY_SIZE` if it is not set.
+    fn default() -> Self {
+        let max_history = std::env::var(CALC_HISTORY_SIZE)
+            .ok()
+            .and_then(|size| size.trim().parse().ok())
+            .unwrap_or(DEFAULT_HISTORY_SIZE);
+        Self::with_history_size(max_history)
+    }
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_history_size(max_history: usize) -> Self {
+        Self { history: Vec::new(), max_history, completions: Vec::new(), highlighter: None }
+    }
+
+    pub fn set_highlighter(&mut self, highlighter: fn(&str) -> String) {
+        self.highlighter = Some(highlighter);
+    }
+
+    /// Sets the words that Tab completes a partly typed word to. Words
+    /// starting with `:` are only offered at the start of the line.
+    pub fn set_completions(&mut self, mut words: Vec<String>) {
+        words.sort();
+        words.dedup();
+        self.completions = words;
+    }
+
+    /// Adds an entered line to the history that Up and Down recall,
+    /// skipping blank lines and immediate repeats.
+    pub fn add_history(&mut self, line: &str) {
+        let line = line.trim_end();
+        if !line.trim().is_empty() && self.history.last().map(String::as_str) != Some(line) {
+            self.history.push(line.to_string());
+        }
+        let excess = self.history.len().saturating_sub(self.max_history);
+        self.history.drain(..excess);
+    }
+
+    /// The lines entered so far, oldest first.
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Expands a line that starts with `!!`, the last line entered, or
+    /// `!n`, the n-th line of the history counting from 1; the rest of the
+    /// line is kept, so `!! * 2` appends `* 2` to the last line. Other lines
+    /// are returned as they are, and `None` means there is no such line.
+    pub fn expand_history(&self, line: &str) -> Option<String> {
+        let trimmed = line.trim_start();
+        let (entry, rest) = if let Some(rest) = trimmed.strip_prefix("!!") {
+            (self.history.last(), rest)
+        } else if let Some(rest) = trimmed.strip_prefix('!')
+            && rest.starts_with(|c: char| c.is_ascii_digit())
+        {
+            let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            let n: usize = rest[..digits].parse().ok()?;
+            (n.checked_sub(1).and_then(|i| self.history.get(i)), &rest[digits..])
+        } else {
+            return Some(line.to_string());
+        };
+        entry.map(|entry| format!("{}{}", entry, rest))
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Adds the lines saved in the file at `path` to the history. A missing
+    /// file is an empty history.
+    pub fn load_history(&mut self, path: &Path) -> io::Result<()> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for line in text.lines() {
+            self.add_history(line);
+        }
+        Ok(())
+    }
+
+    /// Writes the history to the file at `path`, one line per entry,
+    /// creating its directory if needed.
+    pub fn save_history(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut text = String::new();
+        for line in &self.history {
+            text.push_str(line);
+            text.push('\n');
+        }
+        std::fs::write(path, text)
+    }
+
+    /// Shows `prompt` and reads a line, without its line ending. Gives
+    /// `None` at the end of input, e.g. Ctrl-D on an empty line, and an
+    /// empty line if editing is cancelled with Ctrl-C.
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let mut stdout = io::stdout();
+        if io::stdin().is_terminal()
+            && stdout.is_terminal()
+            && let Some(_raw) = raw_mode::enable()
+        {
+            return edit(prompt, &mut io::stdin().lock(), &mut stdout, self);
+        }
+
+        write!(stdout, "{}", prompt)?;
+        stdout.flush()?;
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+    }
+}
+
+/// Where the REPL keeps its history: `$XDG_STATE_HOME/calc_history` if
+/// `XDG_STATE_HOME` is set, otherwise `~/.calc_history`.
+pub fn history_path() -> Option<PathBuf> {
+    let non_empty = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    if let Some(state) = non_empty("XDG_STATE_HOME") {
+        Some(PathBuf::from(state).join("calc_history"))
+    } else {
+        non_empty("HOME").map(|home| PathBuf::from(home).join(".calc_history"))
+    }
+}
+
+/// How many rows the terminal on stdout has, or `None` if stdout is not a
+/// terminal or the size is unknown.
+pub fn terminal_height() -> Option<usize> {
+    raw_mode::window_rows()
+}
+
+/// The line being edited, with the cursor as an index into `buffer`.
+#[derive(Debug, Clone, Default)]
+struct LineState {
+    buffer: Vec<char>,
+    cursor: usize,
+    /// The history entry shown, or `history.len()` for the new line.
+    history_index: usize,
+    /// The new line, kept while history entries are shown instead.
+    draft: Vec<char>,
+}
+
+impl LineState {
+    fn new(history: &[String]) -> Self {
+        Self { history_index: history.len(), ..Self::default() }
+    }
+
+    fn set(&mut self, text: Vec<char>) {
+        self.cursor = text.len();
+        self.buffer = text;
+    }
+
+    fn recall(&mut self, history: &[String], index: usize) {
+        if self.history_index == history.len() {
+            self.draft = self.buffer.clone();
+        }
+        self.history_index = index;
+        let text = match history.get(index) {
+            Some(line) => line.chars().collect(),
+            None => self.draft.clone(),
+        };
+        self.set(text);
+    }
+
+    fn handle(&mut self, key: Key, history: &[String]) -> Action {
+        match key {
+            Key::Char(c) => {
+                self.buffer.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Enter => return Action::Accept,
+            Key::Ctrl('c') => return Action::Cancel,
+            Key::Ctrl('d') if self.buffer.is_empty() => return Action::EndOfInput,
+            Key::Delete | Key::Ctrl('d') => {
+                if self.cursor < self.buffer.len() {
+                    self.buffer.remove(self.cursor);
+                }
+            }
+            Key::Backspace | Key::Ctrl('h') => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.buffer.remove(self.cursor);
+                }
+            }
+            Key::Left | Key::Ctrl('b') => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right | Key::Ctrl('f') => self.cursor = (self.cursor + 1).min(self.buffer.len()),
+            Key::Home | Key::Ctrl('a') => self.cursor = 0,
+            Key::End | Key::Ctrl('e') => self.cursor = self.buffer.len(),
+            Key::Up | Key::Ctrl('p') => {
+                if self.history_index > 0 {
+                    self.recall(history, self.history_index - 1);
+                }
+            }
+            Key::Down | Key::Ctrl('n') => {
+                if self.history_index < history.len() {
+                    self.recall(history, self.history_index + 1);
+                }
+            }
+            Key::Ctrl('k') => self.buffer.truncate(self.cursor),
+            Key::Ctrl('u') => {
+                self.buffer.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::Ctrl('w') => {
+                // Deletes the word before the cursor and any spaces after it.
+                let mut start = self.cursor;
+                while start > 0 && self.buffer[start - 1] == ' ' {
+                    start -= 1;
+                }
+                while start > 0 && self.buffer[start - 1] != ' ' {
+                    start -= 1;
+                }
+                self.buffer.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            Key::Tab | Key::Ctrl(_) | Key::Unknown => {}
+        }
+        Action::Continue
+    }
+
+    /// Completes the word before the cursor from `completions`, as far as
+    /// all the words it could be agree. Gives the words to list when that
+    /// adds nothing and there is more than one.
+    fn complete<'a>(&mut self, completions: &'a [String]) -> Vec<&'a str> {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let mut start = self.cursor;
+        while start > 0 && is_word(self.buffer[start - 1]) {
+            start -= 1;
+        }
+        let at_line_start = self.buffer[..start.saturating_sub(1)].iter().all(|c| *c == ' ');
+        if start > 0 && self.buffer[start - 1] == ':' && at_line_start {
+            start -= 1;
+        }
+        let prefix: String = self.buffer[start..self.cursor].iter().collect();
+        if prefix.is_empty() {
+            return Vec::new();
+        }
+
+        let matches: Vec<&str> = completions
+            .iter()
+            .map(String::as_str)
+            .filter(|word| word.starts_with(&prefix))
+            .filter(|word| prefix.starts_with(':') || !word.starts_with(':'))
+            .collect();
+        let Some(first) = matches.first() else {
+            return Vec::new();
+        };
+        let common: String = matches.iter().fold(first.to_string(), |common, word| {
+            common.chars().zip(word.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a).collect()
+        });
+        let rest: Vec<char> = common.chars().skip(prefix.chars().count()).collect();
+        if !rest.is_empty() {
+            let len = rest.len();
+            self.buffer.splice(self.cursor..self.cursor, rest);
+            self.cursor += len;
+            return Vec::new();
+        }
+        if matches.len() > 1 { matches } else { Vec::new() }
+    }
+
+    /// Redraws the line in place and puts the terminal cursor back where
+    /// the editing cursor is.
+    fn render<W: Write>(
+        &self,
+        prompt: &str,
+        output: &mut W,
+        highlighter: Option<fn(&str) -> String>,
+    ) -> io::Result<()> {
+        let mut text: String = self.buffer.iter().collect();
+        if let Some(highlight) = highlighter {
+            text = highlight(&text);
+        }
+        write!(output, "\r{}{}\x1b[K", prompt, text)?;
+        let back = self.buffer.len() - self.cursor;
+        if back > 0 {
+            write!(output, "\x1b[{}D", back)?;
+        }
+        output.flush()
+    }
+}
+
+/// Edits a line read key by key from `input`, redrawing it on `output`
+/// after every key. Completions that Tab cannot choose between are listed
+/// below the line.
+fn edit<R: Read, W: Write>(
+    prompt: &str,
+    input: &mut R,
+    output: &mut W,
+    editor: &Editor,
+) -> io::Result<Option<String>> {
+    let history = &editor.history;
+    let mut state = LineState::new(history);
+    state.render(prompt, output, editor.highlighter)?;
+    loop {
+        let Some(key) = read_key(input)? else {
+            return Ok(None);
+        };
+        if key == Key::Tab {
+            let matches = state.complete(&editor.completions);
+            if !matches.is_empty() {
+                write!(output, "\r\n{}\r\n", matches.join("  "))?;
+            }
+        }
+        let action = state.handle(key, history);
+        state.render(prompt, output, editor.highlighter)?;
+        match action {
+            Action::Continue => {}
+            Action::Accept => {
+                write!(output, "\r\n")?;
+                return Ok(Some(state.buffer.iter().collect()));
+            }
+            Action::Cancel => {
+                write!(output, "^C\r\n")?;
+                return Ok(Some(String::new()));
+            }
+            Action::EndOfInput => return Ok(None),
+        }
+    }
+}
+
+fn read_byte<R: Read>(input: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Reads one key press, or `None` at the end of input. Escape sequences
+/// are those of VT100-style terminals such as xterm.
+fn read_key<R: Read>(input: &mut R) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f => Key::Backspace,
+        0x1b => read_escape(input)?,
+        0x01..=0x1a => Key::Ctrl((b'a' + byte - 1) as char),
+        byte if byte < 0x80 => Key::Char(byte as char),
+        byte => {
+            // The rest of a UTF-8 character, whose length the first byte
+            // gives by its leading ones.
+            let len = byte.leading_ones() as usize;
+            let mut bytes = vec![byte];
+            for _ in 1..len.clamp(1, 4) {
+                bytes.extend(read_byte(input)?);
+            }
+            match std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()) {
+                Some(c) => Key::Char(c),
+                None => Key::Unknown,
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+fn read_escape<R: Read>(input: &mut R) -> io::Result<Key> {
+    let key = match (read_byte(input)?, read_byte(input)?) {
+        (Some(b'[' | b'O'), Some(b'A')) => Key::Up,
+        (Some(b'[' | b'O'), Some(b'B')) => Key::Down,
+        (Some(b'[' | b'O'), Some(b'C')) => Key::Right,
+        (Some(b'[' | b'O'), Some(b'D')) => Key::Left,
+        (Some(b'[' | b'O'), Some(b'H')) => Key::Home,
+        (Some(b'[' | b'O'), Some(b'F')) => Key::End,
+        (Some(b'['), Some(digit @ b'0'..=b'9')) => {
+            // `ESC [ n ~`, possibly with modifiers as in `ESC [ 3 ; 5 ~`.
+            let mut last = digit;
+            while !matches!(last, b'~' | b'A'..=b'Z' | b'a'..=b'z') {
+                match read_byte(input)? {
+                    Some(byte) => last = byte,
+                    None => break,
+                }
+            }
+            match (digit, last) {
+                (b'1' | b'7', b'~') => Key::Home,
+                (b'3', b'~') => Key::Delete,
+                (b'4' | b'8', b'~') => Key::End,
+                _ => Key::Unknown,
+            }
+        }
+        _ => Key::Unknown,
+    };
+    Ok(key)
+}
+
+#[cfg(unix)]
+mod raw_mode {
+    /// Restores the terminal's settings when dropped.
+    pub struct RawMode {
+        original: libc::termios,
+    }
+
+    /// Turns off line buffering, echo and signal keys on stdin, so every
+    /// key press is read as it is typed, Ctrl-C included.
+    pub fn enable() -> Option<RawMode> {
+        // SAFETY: `termios` is plain data that `tcgetattr` fills in, and
+        // both calls only read or write the struct they are given.
+        unsafe {
+            let mut original: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return None;
+            }
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+            raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw) != 0 {
+                return None;
+            }
+            Some(RawMode { original })
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            // SAFETY: restores settings read by `tcgetattr` in `enable`.
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.original);
+            }
+        }
+    }
+
+    /// The row count `TIOCGWINSZ` reports for stdout.
+    pub fn window_rows() -> Option<usize> {
+        // SAFETY: `winsize` is plain data that the ioctl only fills in.
+        unsafe {
+            let mut size: libc::winsize = std::mem::zeroed();
+            if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) != 0 || size.ws_row == 0 {
+                return None;
+            }
+            Some(size.ws_row as usize)
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod raw_mode {
+    pub struct RawMode;
+
+    /// Raw mode is only supported on Unix; elsewhere lines are read as
+    /// they are.
+    pub fn enable() -> Option<RawMode> {
+        None
+    }
+
+    pub fn window_rows() -> Option<usize> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Edits a line from the given key presses.
+    fn type_keys(keys: &str, history: &[&str]) -> Option<String> {
+        let mut editor = Editor::new();
+        editor.history = history.iter().map(|line| line.to_string()).collect();
+        let mut output = Vec::new();
+        edit(">> ", &mut keys.as_bytes(), &mut output, &editor).unwrap()
+    }
+
+    /// Edits a line from the given key presses, completing from `words`,
+    /// and gives the line and what was shown.
+    fn complete_keys(keys: &str, words: &[&str]) -> (Option<String>, String) {
+        let mut editor = Editor::new();
+        editor.completions = words.iter().map(|word| word.to_string()).collect();
+        let mut output = Vec::new();
+        let line = edit(">> ", &mut keys.as_bytes(), &mut output, &editor).unwrap();
+        (line, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_insert_and_move() {
+        assert_eq!(type_keys("1+3\x1b[D\x1b[D2\r", &[]), Some("12+3".to_string()));
+        assert_eq!(type_keys("+2\x01\x1b[C\x1b[H1\r", &[]), Some("1+2".to_string()));
+        assert_eq!(type_keys("sin(xy)\x1b[D\x1b[D\x1b[3~\x7fπ\r", &[]), Some("sin(π)".to_string()));
+    }
+
+    #[test]
+    fn test_kill_commands() {
+        assert_eq!(type_keys("1 + 2 * 3\x17\x17\r", &[]), Some("1 + 2 ".to_string()));
+        assert_eq!(type_keys("abc\x1b[D\x0b\r", &[]), Some("ab".to_string()));
+        assert_eq!(type_keys("abc\x1b[D\x15\r", &[]), Some("c".to_string()));
+    }
+
+    #[test]
+    fn test_history() {
+        let history = ["1 + 1", "2 * 3"];
+        assert_eq!(type_keys("\x1b[A\r", &history), Some("2 * 3".to_string()));
+        assert_eq!(type_keys("\x1b[A\x1b[A\x1b[A\r", &history), Some("1 + 1".to_string()));
+        // Going back down returns to the line being typed.
+        assert_eq!(type_keys("x\x1b[A\x1b[B\r", &history), Some("x".to_string()));
+    }
+
+    #[test]
+    fn test_end_of_input_and_cancel() {
+        assert_eq!(type_keys("\x04", &[]), None);
+        assert_eq!(type_keys("12\x04\r", &[]), Some("12".to_string()));
+        assert_eq!(type_keys("12\x03", &[]), Some(String::new()));
+        assert_eq!(type_keys("12", &[]), None);
+    }
+
+    #[test]
+    fn test_add_history_skips_repeats() {
+        let mut editor = Editor::new();
+        for line in ["1", "1", " ", "2\n", "1"] {
+            editor.add_history(line);
+        }
+        assert_eq!(editor.history, ["1", "2", "1"]);
+    }
+
+    #[test]
+    fn test_history_size() {
+        let mut editor = Editor::with_history_size(2);
+        for line in ["1", "2", "2", " ", "3"] {
+            editor.add_history(line);
+        }
+        assert_eq!(editor.history, ["2", "3"]);
+        editor.clear_history();
+        assert!(editor.history.is_empty());
+    }
+
+    #[test]
+    fn test_save_and_load_history() {
+        let dir = std::env::temp_dir().join(format!("calc-history-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("state").join("calc_history");
+
+        let mut editor = Editor::with_history_size(10);
+        editor.load_history(&path).unwrap();
+        assert!(editor.history.is_empty());
+        editor.add_history("x = 2");
+        editor.add_history("x^2");
+        editor.save_history(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "x = 2\nx^2\n");
+
+        let mut editor = Editor::with_history_size(1);
+        editor.load_history(&path).unwrap();
+        assert_eq!(editor.history, ["x^2"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tab_completion() {
+        let words = ["arcsin", "arccos", "arctan", "sqrt", "speed", ":save", ":seed"];
+        assert_eq!(complete_keys("sq\t(2)\r", &words).0, Some("sqrt(2)".to_string()));
+        assert_eq!(complete_keys("2*sp\t\r", &words).0, Some("2*speed".to_string()));
+        assert_eq!(complete_keys(":sa\t x\r", &words).0, Some(":save x".to_string()));
+        // A command is only completed at the start of the line.
+        assert_eq!(complete_keys("1 :sa\t\r", &words).0, Some("1 :sa".to_string()));
+        // Completes in the middle of a line, before the cursor.
+        let (line, _) = complete_keys("(x)\x1b[D\x1b[D\x1b[Dsq\t\r", &words);
+        assert_eq!(line, Some("sqrt(x)".to_string()));
+    }
+
+    #[test]
+    fn test_tab_lists_matches() {
+        let words = ["arcsin", "arccos", "arctan", "abs"];
+        let (line, shown) = complete_keys("ar\t\r", &words);
+        assert_eq!(line, Some("arc".to_string()));
+        assert!(!shown.contains("arcsin"));
+        let (line, shown) = complete_keys("ar\t\t\r", &words);
+        assert_eq!(line, Some("arc".to_string()));
+        assert!(shown.contains("\r\narcsin  arccos  arctan\r\n"), "{:?}", shown);
+        let (line, shown) = complete_keys("x\t\r", &words);
+        assert_eq!(line, Some("x".to_string()));
+        assert!(!shown.contains("abs"));
+    }
+
+    #[test]
+    fn test_highlighter_keeps_cursor() {
+        let mut editor = Editor::new();
+        editor.set_highlighter(|text| format!("<{}>", text));
+        let mut output = Vec::new();
+        let line = edit(">> ", &mut "12\x1b[D\r".as_bytes(), &mut output, &editor).unwrap();
+        assert_eq!(line, Some("12".to_string()));
+        let shown = String::from_utf8(output).unwrap();
+        assert!(shown.ends_with("\r>> <12>\x1b[K\x1b[1D\r\n"), "{:?}", shown);
+    }
+
+    #[test]
+    fn test_expand_history() {
+        let mut editor = Editor::new();
+        editor.add_history("1 + 1");
+        editor.add_history("x = 3");
+        assert_eq!(editor.expand_history("!!"), Some("x = 3".to_string()));
+        assert_eq!(editor.expand_history("!1 * 2"), Some("1 + 1 * 2".to_string()));
+        assert_eq!(editor.expand_history("  !2"), Some("x = 3".to_string()));
+        assert_eq!(editor.expand_history("!3"), None);
+        assert_eq!(editor.expand_history("!0"), None);
+        assert_eq!(editor.expand_history("5! != 3"), Some("5! != 3".to_string()));
+        assert_eq!(editor.expand_history("!x"), Some("!x".to_string()));
+        assert_eq!(Editor::new().expand_history("!!"), None);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::format::{format_output, format_value, FormatOptions};
+use crate::lexer;
+use crate::parser;
+use crate::script::StatementError;
+use crate::value::Value;
+use crate::verify;
+use crate::vm::VirtualMachine;
+
+/// Evaluates `source` and renders a table of the operations it compiled to,
+/// the source each came from, and the stack after each step, followed by
+/// the result. Values are written as `options` says. A runtime error ends
+/// the table before the failing step.
+pub fn explain(vm: &mut VirtualMachine, source: &str, options: &FormatOptions) -> Result<String, StatementError> {
+    let tokens = lexer::scan(source).map_err(StatementError::Lex)?;
+    let (operations, spans) = parser::parse_with_spans(tokens).map_err(StatementError::Parse)?;
+    verify::verify(&operations).map_err(StatementError::Verify)?;
+
+    let mut rows = vec![["#", "operation", "source", "stack"].map(String::from)];
+    let result = vm.interpret_observed(&operations, |i, stack| {
+        let (start, end) = spans[i];
+        rows.push([
+            (i + 1).to_string(),
+            format!("{:?}", operations[i]),
+            source[start..end].to_string(),
+            format_stack(stack, options),
+        ]);
+    });
+    let steps = rows.len() - 1;
+
+    let widths: Vec<usize> = (0..3)
+        .map(|j| rows.iter().map(|row| row[j].chars().count()).max().unwrap_or(0))
+        .collect();
+    let mut text = String::new();
+    for row in &rows {
+        let line = format!(
+            "{:>w0$}  {:<w1$}  {:<w2$}  {}",
+            row[0], row[1], row[2], row[3],
+            w0 = widths[0], w1 = widths[1], w2 = widths[2],
+        );
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+
+    match result {
+        Ok(output) => text.push_str(&format_output(output.result(), options)),
+        Err(e) => text.push_str(&format!("{} at step {}", e.locate(&spans), steps + 1)),
+    }
+    Ok(text)
+}
+
+fn format_stack(stack: &[Value], options: &FormatOptions) -> String {
+    let items: Vec<String> = stack.iter().map(|v| format_value(v, options)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Notation;
+
+    #[test]
+    fn test_explain_lists_steps() {
+        let mut vm = VirtualMachine::new();
+        let text = explain(&mut vm, "1 + 2 * 3", &FormatOptions::default()).unwrap();
+        assert_eq!(
+            text,
+            "#  operation   source  stack\n\
+             1  Const(1.0)  1       [1]\n\
+             2  Const(2.0)  2       [1, 2]\n\
+             3  Const(3.0)  3       [1, 2, 3]\n\
+             4  Times       *       [1, 6]\n\
+             5  Add         +       [7]\n\
+             Output: 7"
+        );
+    }
+
+    #[test]
+    fn test_explain_uses_the_format() {
+        let mut vm = VirtualMachine::new();
+        let options = FormatOptions { notation: Notation::Fraction, ..Default::default() };
+        let text = explain(&mut vm, "1 / 4", &options).unwrap();
+        assert!(text.ends_with("3  Divide      /       [1/4]\nOutput: 1/4"), "{}", text);
+    }
+
+    #[test]
+    fn test_explain_stops_at_runtime_error() {
+        let mut vm = VirtualMachine::new();
+        let text = explain(&mut vm, "1 / 0", &FormatOptions::default()).unwrap();
+        assert!(text.ends_with("2  Const(0.0)  0       [1, 0]\nmath error in 1 / 0 (at 2..3) at step 3"));
+    }
+}
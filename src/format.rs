@@ -0,0 +1,325 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::rational;
+use crate::value::Value;
+
+/// The largest denominator [`Notation::Fraction`] looks for.
+pub const FRACTION_MAX_DENOMINATOR: f64 = 1_000_000.0;
+
+/// How numbers are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Notation {
+    /// Positional decimal, e.g. `1234.5`.
+    #[default]
+    Plain,
+    /// One digit before the point and a power of ten, e.g. `1.2345e3`.
+    Scientific,
+    /// Like scientific, but with an exponent that is a multiple of three,
+    /// e.g. `1.2345e3` or `12.5e-6`.
+    Engineering,
+    /// A fraction such as `-5/8` when one with a denominator of at most
+    /// [`FRACTION_MAX_DENOMINATOR`] is exactly the number, otherwise plain.
+    Fraction,
+}
+
+impl fmt::Display for Notation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Notation::Plain => "plain",
+            Notation::Scientific => "sci",
+            Notation::Engineering => "eng",
+            Notation::Fraction => "frac",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FormatOptions {
+    pub notation: Notation,
+    /// Digits after the decimal point, or `None` for the shortest exact form.
+    /// Fractions ignore it.
+    pub precision: Option<usize>,
+    /// Lists (and matrix rows/columns) longer than this are elided with `...`.
+    pub max_items: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            notation: Notation::Plain,
+            precision: None,
+            max_items: 20,
+        }
+    }
+}
+
+/// Renders a value for display. Matrices (non-empty lists of equal-length
+/// numeric rows) are printed one row per line with right-aligned columns.
+pub fn format_value(value: &Value, options: &FormatOptions) -> String {
+    match value {
+        Value::Number(x) => format_number(*x, options),
+        Value::Str(s) => s.clone(),
+        Value::Function(name) => format!("<function {}>", name),
+        Value::List(xs) => match as_matrix(xs) {
+            Some(rows) => format_matrix(&rows, options),
+            None => format_list(xs, options),
+        },
+    }
+}
+
+/// Renders a statement's result as the REPL and scripts print it: after an
+/// `Output: ` prefix, with any further lines (e.g. matrix rows) indented to
+/// line up with the first.
+pub fn format_output(value: &Value, options: &FormatOptions) -> String {
+    let prefix = "Output: ";
+    let text = format_value(value, options);
+    format!("{}{}", prefix, text.replace('\n', &format!("\n{:1$}", "", prefix.len())))
+}
+
+/// Renders a value on one line as it would be written in an expression:
+/// strings are quoted and matrices are not split into rows.
+pub fn format_inline(value: &Value, options: &FormatOptions) -> String {
+    match value {
+        Value::Str(s) => format!("{:?}", s),
+        Value::List(xs) => {
+            let items = truncated(xs, options.max_items, |x| format_inline(x, options));
+            format!("[{}]", items.join(", "))
+        }
+        value => format_value(value, options),
+    }
+}
+
+pub fn format_number(x: f64, options: &FormatOptions) -> String {
+    if x.is_nan() {
+        return "nan".to_string();
+    }
+    if x.is_infinite() {
+        return format!("{}", x);
+    }
+    match (options.notation, options.precision) {
+        (Notation::Scientific, Some(precision)) => format!("{:.*e}", precision, x),
+        (Notation::Scientific, None) => format!("{:e}", x),
+        (Notation::Engineering, precision) => format_engineering(x, precision),
+        (Notation::Fraction, _) => format_fraction(x).unwrap_or_else(|| format!("{}", x)),
+        (Notation::Plain, Some(precision)) => format!("{:.*}", precision, x),
+        (Notation::Plain, None) => format!("{}", x),
+    }
+}
+
+/// Moves the point of the shortest scientific form right until the
+/// exponent is a multiple of three, so that no digits are lost, then
+/// rounds the mantissa to `precision` digits if given.
+fn format_engineering(x: f64, precision: Option<usize>) -> String {
+    let scientific = format!("{:e}", x);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let shift = exponent.rem_euclid(3) as usize;
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let digits = mantissa.replace('.', "");
+    let digits = format!("{:0<1$}", digits, shift + 1);
+    let (whole, fraction) = digits.split_at(shift + 1);
+    let mut mantissa = if fraction.is_empty() {
+        whole.to_string()
+    } else {
+        format!("{}.{}", whole, fraction)
+    };
+    let mut exponent = exponent - shift as i32;
+    if let Some(precision) = precision {
+        let rounded: f64 = mantissa.parse().unwrap();
+        mantissa = format!("{:.*}", precision, rounded);
+        // Rounding 999.95 up to 1000.0 needs the next exponent.
+        if mantissa.split('.').next().unwrap().len() > 3 {
+            mantissa = format!("{:.*}", precision, rounded / 1000.0);
+            exponent += 3;
+        }
+    }
+    format!("{}{}e{}", sign, mantissa, exponent)
+}
+
+/// `x` as `p/q` if that is exactly `x` for some `q` from 2 to
+/// [`FRACTION_MAX_DENOMINATOR`].
+fn format_fraction(x: f64) -> Option<String> {
+    let (p, q) = rational::best_rational(x, FRACTION_MAX_DENOMINATOR);
+    (q > 1.0 && p / q == x).then(|| format!("{}/{}", p, q))
+}
+
+/// An integer in base 2, 8 or 16 with a `0b`, `0o` or `0x` prefix after
+/// any sign; other bases are written in decimal.
+pub fn format_radix(x: i64, radix: u32) -> String {
+    let sign = if x < 0 { "-" } else { "" };
+    let n = x.unsigned_abs();
+    match radix {
+        2 => format!("{}0b{:b}", sign, n),
+        8 => format!("{}0o{:o}", sign, n),
+        16 => format!("{}0x{:x}", sign, n),
+        _ => x.to_string(),
+    }
+}
+
+/// The two's complement bit pattern of an integer as a `bits`-bit word,
+/// in hex and binary, e.g. `0xff, 0b11111111` for -1 in 8 bits.
+pub fn format_word(x: i64, bits: u32) -> String {
+    let word = (x as u64) & (u64::MAX >> (64 - bits));
+    format!("0x{:x}, 0b{:b}", word, word)
+}
+
+fn format_list(xs: &[Value], options: &FormatOptions) -> String {
+    let items = truncated(xs, options.max_items, |x| format_value(x, options));
+    format!("[{}]", items.join(", "))
+}
+
+fn format_matrix(rows: &[Vec<f64>], options: &FormatOptions) -> String {
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| truncated(row, options.max_items, |x| format_number(*x, options)))
+        .collect();
+
+    let column_count = cells[0].len();
+    let widths: Vec<usize> = (0..column_count)
+        .map(|j| cells.iter().map(|row| row[j].len()).max().unwrap_or(0))
+        .collect();
+
+    let lines = truncated(&cells, options.max_items, |row| {
+        let aligned: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:>width$}", cell, width = width))
+            .collect();
+        format!("[{}]", aligned.join(", "))
+    });
+
+    format!("[{}]", lines.join(",\n "))
+}
+
+/// Formats every item of `xs`, or, if there are more than `max_items`, the
+/// first `max_items - 1`, an ellipsis, and the last item.
+fn truncated<T, F>(xs: &[T], max_items: usize, format: F) -> Vec<String>
+where
+    F: Fn(&T) -> String,
+{
+    if xs.len() <= max_items.max(2) {
+        return xs.iter().map(format).collect();
+    }
+
+    let head = max_items.max(2) - 1;
+    xs[..head]
+        .iter()
+        .map(&format)
+        .chain(std::iter::once("...".to_string()))
+        .chain(xs.last().map(&format))
+        .collect()
+}
+
+fn as_matrix(rows: &[Value]) -> Option<Vec<Vec<f64>>> {
+    let rows: Vec<Vec<f64>> = rows
+        .iter()
+        .map(|row| match row {
+            Value::List(xs) => xs.iter().map(Value::as_number).collect(),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+
+    let width = rows.first()?.len();
+    if width == 0 || rows.iter().any(|row| row.len() != width) {
+        return None;
+    }
+    Some(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(value: &Value) -> String {
+        format_value(value, &FormatOptions::default())
+    }
+
+    fn matrix(rows: &[&[f64]]) -> Value {
+        Value::List(rows.iter().map(|row| Value::from(row.to_vec())).collect())
+    }
+
+    #[test]
+    fn test_format_list() {
+        assert_eq!(format(&Value::from(vec![1.0, 2.5, -3.0])), "[1, 2.5, -3]");
+        assert_eq!(format(&Value::List(vec![])), "[]");
+    }
+
+    #[test]
+    fn test_format_nested_list() {
+        let value = Value::List(vec![Value::Number(1.0), Value::from(vec![2.0, 3.0])]);
+        assert_eq!(format(&value), "[1, [2, 3]]");
+    }
+
+    #[test]
+    fn test_format_truncates_long_lists() {
+        let options = FormatOptions { max_items: 4, ..FormatOptions::default() };
+        let value = Value::from((1..=10).map(f64::from).collect::<Vec<f64>>());
+        assert_eq!(format_value(&value, &options), "[1, 2, 3, ..., 10]");
+    }
+
+    #[test]
+    fn test_format_matrix_aligns_columns() {
+        let value = matrix(&[&[1.0, -2.5], &[10.0, 3.0]]);
+        assert_eq!(format(&value), "[[ 1, -2.5],\n [10,    3]]");
+    }
+
+    #[test]
+    fn test_format_matrix_truncates_rows() {
+        let options = FormatOptions { max_items: 3, ..FormatOptions::default() };
+        let value = matrix(&[&[1.0], &[2.0], &[3.0], &[4.0]]);
+        assert_eq!(format_value(&value, &options), "[[1],\n [2],\n ...,\n [4]]");
+    }
+
+    #[test]
+    fn test_format_precision() {
+        let options = FormatOptions { precision: Some(2), ..FormatOptions::default() };
+        assert_eq!(format_value(&Value::from(vec![1.0, 2.0 / 3.0]), &options), "[1.00, 0.67]");
+        assert_eq!(format_value(&Value::Number(f64::INFINITY), &options), "inf");
+        assert_eq!(format_value(&Value::Number(f64::NAN), &options), "nan");
+    }
+
+    #[test]
+    fn test_format_word() {
+        assert_eq!(format_word(255, 16), "0xff, 0b11111111");
+        assert_eq!(format_word(-1, 8), "0xff, 0b11111111");
+        assert_eq!(format_word(-2, 64), format!("0x{}e, 0b{}0", "f".repeat(15), "1".repeat(63)));
+    }
+
+    #[test]
+    fn test_format_radix() {
+        assert_eq!(format_radix(255, 16), "0xff");
+        assert_eq!(format_radix(-5, 2), "-0b101");
+        assert_eq!(format_radix(i64::MIN, 16), "-0x8000000000000000");
+        assert_eq!(format_radix(42, 10), "42");
+    }
+
+    #[test]
+    fn test_format_notation() {
+        let options = |notation, precision| FormatOptions { notation, precision, ..FormatOptions::default() };
+        let sci = options(Notation::Scientific, None);
+        assert_eq!(format_number(1234.5, &sci), "1.2345e3");
+        assert_eq!(format_number(-0.00025, &options(Notation::Scientific, Some(2))), "-2.50e-4");
+
+        let eng = options(Notation::Engineering, None);
+        assert_eq!(format_number(1234.5, &eng), "1.2345e3");
+        assert_eq!(format_number(12345.0, &eng), "12.345e3");
+        assert_eq!(format_number(-0.0000125, &eng), "-12.5e-6");
+        assert_eq!(format_number(100.0, &eng), "100e0");
+        assert_eq!(format_number(0.0, &eng), "0e0");
+        assert_eq!(format_number(999.96, &options(Notation::Engineering, Some(1))), "1.0e3");
+
+        let frac = options(Notation::Fraction, None);
+        assert_eq!(format_number(-0.625, &frac), "-5/8");
+        assert_eq!(format_number(1.0 / 3.0, &frac), "1/3");
+        assert_eq!(format_number(3.0, &frac), "3");
+        assert_eq!(format_number(std::f64::consts::PI, &frac), "3.141592653589793");
+        assert_eq!(format_number(f64::INFINITY, &frac), "inf");
+    }
+}
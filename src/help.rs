@@ -0,0 +1,284 @@
+use crate::lexer;
+use crate::script;
+use crate::vm::VirtualMachine;
+
+/// What `:help` knows about a builtin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub category: &'static str,
+    pub signature: &'static str,
+    pub summary: &'static str,
+    /// The arguments it accepts, when not every number is allowed.
+    pub domain: Option<&'static str>,
+    pub example: &'static str,
+}
+
+const fn builtin(
+    name: &'static str,
+    category: &'static str,
+    signature: &'static str,
+    summary: &'static str,
+    domain: Option<&'static str>,
+    example: &'static str,
+) -> Builtin {
+    Builtin { name, category, signature, summary, domain, example }
+}
+
+const TRIG: &str = "Trigonometry";
+const HYPERBOLIC: &str = "Hyperbolic functions";
+const POWERS: &str = "Powers and logarithms";
+const ROUNDING: &str = "Rounding and comparison";
+const NUMBER_THEORY: &str = "Integers";
+const STATISTICS: &str = "Probability and statistics";
+const LISTS: &str = "Lists";
+const LINEAR_ALGEBRA: &str = "Linear algebra and polynomials";
+const CALCULUS: &str = "Calculus and approximation";
+const ENGINEERING: &str = "Engineering and geography";
+const FINANCE: &str = "Finance";
+const CONVERSION: &str = "Conversion and colour";
+const CONSTANTS: &str = "Constants";
+const OTHER: &str = "Scripting";
+
+/// Every builtin function and constant, grouped by category in the order
+/// `:help` lists them.
+pub const REFERENCE: &[Builtin] = &[
+    builtin("sin", TRIG, "sin(x)", "Sine of an angle in the current angle mode.", None, "sin(pi / 2)"),
+    builtin("cos", TRIG, "cos(x)", "Cosine of an angle in the current angle mode.", None, "cos(0)"),
+    builtin("tan", TRIG, "tan(x)", "Tangent of an angle in the current angle mode.", None, "tan(1)"),
+    builtin("sind", TRIG, "sind(x)", "Sine of an angle in degrees, whatever the mode.", None, "sind(90)"),
+    builtin("cosd", TRIG, "cosd(x)", "Cosine of an angle in degrees, whatever the mode.", None, "cosd(180)"),
+    builtin("tand", TRIG, "tand(x)", "Tangent of an angle in degrees, whatever the mode.", None, "tand(45)"),
+    builtin("arcsin", TRIG, "arcsin(x)", "Inverse sine, as an angle in the current mode.", Some("[-1, 1]"), "arcsin(1)"),
+    builtin("arccos", TRIG, "arccos(x)", "Inverse cosine, as an angle in the current mode.", Some("[-1, 1]"), "arccos(0)"),
+    builtin("arctan", TRIG, "arctan(x)", "Inverse tangent, as an angle in the current mode.", None, "arctan(1)"),
+    builtin("atan2", TRIG, "atan2(y, x)", "Angle of the point (x, y) from the positive x axis.", None, "atan2(1, -1)"),
+    builtin("hypot", TRIG, "hypot(x, y)", "Length of the hypotenuse, sqrt(x^2 + y^2), without overflow.", None, "hypot(3, 4)"),
+    builtin("deg2rad", TRIG, "deg2rad(x)", "Converts degrees to radians.", None, "deg2rad(180)"),
+    builtin("rad2deg", TRIG, "rad2deg(x)", "Converts radians to degrees.", None, "rad2deg(pi)"),
+    builtin("topolar", TRIG, "topolar(x, y)", "Polar coordinates [r, theta] of a point.", None, "topolar(1, 1)"),
+    builtin("torect", TRIG, "torect(r, theta)", "Rectangular coordinates [x, y] of a point.", None, "torect(2, 1)"),
+    builtin("sinh", HYPERBOLIC, "sinh(x)", "Hyperbolic sine.", None, "sinh(1)"),
+    builtin("cosh", HYPERBOLIC, "cosh(x)", "Hyperbolic cosine.", None, "cosh(0)"),
+    builtin("tanh", HYPERBOLIC, "tanh(x)", "Hyperbolic tangent.", None, "tanh(1)"),
+    builtin("asinh", HYPERBOLIC, "asinh(x), arcsinh(x)", "Inverse hyperbolic sine.", None, "asinh(1)"),
+    builtin("acosh", HYPERBOLIC, "acosh(x), arccosh(x)", "Inverse hyperbolic cosine.", Some("[1, inf)"), "acosh(2)"),
+    builtin("atanh", HYPERBOLIC, "atanh(x), arctanh(x)", "Inverse hyperbolic tangent.", Some("(-1, 1)"), "atanh(0.5)"),
+    builtin("exp", POWERS, "exp(x)", "e raised to the power x.", None, "exp(1)"),
+    builtin("ln", POWERS, "ln(x)", "Natural logarithm.", Some("(0, inf)"), "ln(e^2)"),
+    builtin("log", POWERS, "log(x), log(b, x)", "Logarithm in base 10, or in base b.", Some("(0, inf), with b positive and not 1"), "log(2, 8)"),
+    builtin("log10", POWERS, "log10(x)", "Logarithm in base 10.", Some("(0, inf)"), "log10(1000)"),
+    builtin("log2", POWERS, "log2(x)", "Logarithm in base 2.", Some("(0, inf)"), "log2(1024)"),
+    builtin("sqrt", POWERS, "sqrt(x)", "Square root.", Some("[0, inf)"), "sqrt(2)"),
+    builtin("cbrt", POWERS, "cbrt(x)", "Cube root, negative for negative x.", None, "cbrt(-27)"),
+    builtin("root", POWERS, "root(n, x)", "The real n-th root of x.", Some("x >= 0 unless n is an odd integer"), "root(4, 81)"),
+    builtin("abs", ROUNDING, "abs(x)", "Absolute value.", None, "abs(-3)"),
+    builtin("sign", ROUNDING, "sign(x)", "-1, 0 or 1 as x is negative, zero or positive.", None, "sign(-2.5)"),
+    builtin("floor", ROUNDING, "floor(x)", "Largest integer not above x.", None, "floor(-2.5)"),
+    builtin("ceil", ROUNDING, "ceil(x)", "Smallest integer not below x.", None, "ceil(2.1)"),
+    builtin("round", ROUNDING, "round(x), round(x, n)", "Rounds half away from zero, to n decimal places if given.", None, "round(pi, 2)"),
+    builtin("trunc", ROUNDING, "trunc(x)", "Integer part of x, rounding towards zero.", None, "trunc(-2.7)"),
+    builtin("frac", ROUNDING, "frac(x)", "Fractional part of x, with the sign of x.", None, "frac(-2.75)"),
+    builtin("sigfig", ROUNDING, "sigfig(x, n)", "Rounds x to n significant figures.", Some("n >= 1"), "sigfig(123456, 2)"),
+    builtin("min", ROUNDING, "min(a, b, ...)", "Smallest of its arguments.", None, "min(3, 1, 2)"),
+    builtin("max", ROUNDING, "max(a, b, ...)", "Largest of its arguments.", None, "max(3, 1, 2)"),
+    builtin("gcd", NUMBER_THEORY, "gcd(a, b)", "Greatest common divisor.", Some("integers"), "gcd(12, 18)"),
+    builtin("lcm", NUMBER_THEORY, "lcm(a, b)", "Least common multiple.", Some("integers"), "lcm(4, 6)"),
+    builtin("isprime", NUMBER_THEORY, "isprime(n)", "1 if n is prime, otherwise 0.", Some("integers"), "isprime(97)"),
+    builtin("nextprime", NUMBER_THEORY, "nextprime(n)", "The smallest prime greater than n.", Some("integers"), "nextprime(100)"),
+    builtin("factor", NUMBER_THEORY, "factor(n)", "Prime factors of n in ascending order.", Some("integers of at least 1"), "factor(360)"),
+    builtin("powmod", NUMBER_THEORY, "powmod(b, e, m)", "b^e modulo m, without overflow.", Some("integers, e >= 0 and m >= 1"), "powmod(2, 100, 7)"),
+    builtin("digits", NUMBER_THEORY, "digits(n)", "Decimal digits of |n|, most significant first.", Some("integers"), "digits(1024)"),
+    builtin("digitsum", NUMBER_THEORY, "digitsum(n)", "Sum of the decimal digits of |n|.", Some("integers"), "digitsum(1024)"),
+    builtin("reversedigits", NUMBER_THEORY, "reversedigits(n)", "n with its decimal digits reversed.", Some("integers"), "reversedigits(1024)"),
+    builtin("fact", NUMBER_THEORY, "fact(n)", "Factorial, also written n!.", Some("a non-negative integer"), "fact(5)"),
+    builtin("gamma", NUMBER_THEORY, "gamma(x)", "The gamma function, with gamma(n) = (n - 1)!.", None, "gamma(0.5)^2"),
+    builtin("erf", STATISTICS, "erf(x)", "Error function.", None, "erf(1)"),
+    builtin("erfc", STATISTICS, "erfc(x)", "Complementary error function, 1 - erf(x).", None, "erfc(1)"),
+    builtin("normpdf", STATISTICS, "normpdf(x), normpdf(x, mu, sigma)", "Normal probability density, standard unless mu and sigma are given.", None, "normpdf(0)"),
+    builtin("normcdf", STATISTICS, "normcdf(x), normcdf(x, mu, sigma)", "Normal cumulative distribution, standard unless mu and sigma are given.", None, "normcdf(1.96)"),
+    builtin("odds", STATISTICS, "odds(p)", "Odds p / (1 - p) of a probability.", Some("[0, 1)"), "odds(0.75)"),
+    builtin("prob", STATISTICS, "prob(o)", "Probability o / (1 + o) of odds.", Some("[0, inf)"), "prob(3)"),
+    builtin("bayes", STATISTICS, "bayes(prior, sensitivity, specificity)", "Probability of a condition given a positive test.", Some("probabilities in [0, 1]"), "bayes(0.01, 0.9, 0.95)"),
+    builtin("mean", STATISTICS, "mean(xs)", "Arithmetic mean of a list.", None, "mean([1, 2, 3, 4])"),
+    builtin("median", STATISTICS, "median(xs)", "Middle value of a list, or the mean of the middle two.", None, "median([3, 1, 4, 1, 5])"),
+    builtin("mode", STATISTICS, "mode(xs)", "Most common value of a list.", None, "mode([1, 2, 2, 3])"),
+    builtin("var", STATISTICS, "var(xs)", "Sample variance of a list.", None, "var([2, 4, 4, 4, 5, 5, 7, 9])"),
+    builtin("stdev", STATISTICS, "stdev(xs)", "Sample standard deviation of a list.", None, "stdev([2, 4, 4, 4, 5, 5, 7, 9])"),
+    builtin("sum", STATISTICS, "sum(xs)", "Sum of a list.", None, "sum(range(1, 100))"),
+    builtin("prod", STATISTICS, "prod(xs)", "Product of a list.", None, "prod([1, 2, 3, 4])"),
+    builtin("wmean", STATISTICS, "wmean(xs, ws)", "Mean of xs weighted by ws.", Some("lists of equal length"), "wmean([1, 2, 3], [3, 2, 1])"),
+    builtin("linreg", STATISTICS, "linreg(xs, ys)", "Least-squares line through the points, as [slope, intercept, r^2].", Some("lists of equal length, at least 2"), "linreg([1, 2, 3], [2, 4, 7])"),
+    builtin("normalize", STATISTICS, "normalize(xs)", "Scales a list to sum to 1.", None, "normalize([1, 1, 2])"),
+    builtin("zscore", STATISTICS, "zscore(xs)", "Standard scores of the elements of a list.", None, "zscore([1, 2, 3])"),
+    builtin("percentile", STATISTICS, "percentile(xs, p)", "The p-th percentile of a list, interpolating between elements.", Some("p in [0, 100]"), "percentile([1, 2, 3, 4, 5], 90)"),
+    builtin("iqr", STATISTICS, "iqr(xs)", "Interquartile range of a list.", None, "iqr([1, 2, 3, 4, 5, 6, 7, 8])"),
+    builtin("rand", STATISTICS, "rand()", "Uniform random number in [0, 1).", None, "rand()"),
+    builtin("randint", STATISTICS, "randint(a, b)", "Random integer from a to b inclusive.", Some("integers with a <= b"), "randint(1, 6)"),
+    builtin("randn", STATISTICS, "randn()", "Random number from the standard normal distribution.", None, "randn()"),
+    builtin("len", LISTS, "len(xs)", "Number of elements in a list.", None, "len([4, 5, 6])"),
+    builtin("range", LISTS, "range(a, b), range(a, b, step)", "The list a, a + step, ... up to b inclusive.", None, "range(0, 1, 0.25)"),
+    builtin("reverse", LISTS, "reverse(xs)", "A list in reverse order.", None, "reverse([1, 2, 3])"),
+    builtin("head", LISTS, "head(xs)", "First element of a list.", Some("non-empty lists"), "head([4, 5, 6])"),
+    builtin("tail", LISTS, "tail(xs)", "A list without its first element.", Some("non-empty lists"), "tail([4, 5, 6])"),
+    builtin("concat", LISTS, "concat(xs, ys)", "The elements of xs followed by those of ys.", None, "concat([1, 2], [3])"),
+    builtin("sort", LISTS, "sort(xs)", "Numbers in ascending order, NaNs last.", None, "sort([3, 1, 2])"),
+    builtin("unique", LISTS, "unique(xs)", "The distinct elements of a list.", None, "unique([1, 2, 2, 3, 1])"),
+    builtin("union", LISTS, "union(xs, ys)", "The distinct elements of either list.", None, "union([1, 2], [2, 3])"),
+    builtin("intersect", LISTS, "intersect(xs, ys)", "The distinct elements of both lists.", None, "intersect([1, 2, 3], [2, 3, 4])"),
+    builtin("cumsum", LISTS, "cumsum(xs)", "Running totals of a list.", None, "cumsum([1, 2, 3])"),
+    builtin("cumprod", LISTS, "cumprod(xs)", "Running products of a list.", None, "cumprod([1, 2, 3])"),
+    builtin("map", LISTS, "map(f, xs)", "Applies a function to every element.", None, "map(sqrt, [1, 4, 9])"),
+    builtin("filter", LISTS, "filter(f, xs)", "The elements for which f is non-zero.", None, "filter(isprime, range(1, 10))"),
+    builtin("reduce", LISTS, "reduce(f, init, xs)", "Folds a list from the left with a two-argument function.", None, "add(a, b) = a + b; reduce(add, 0, [1, 2, 3])"),
+    builtin("transpose", LINEAR_ALGEBRA, "transpose(A)", "Transpose of a matrix.", None, "transpose([[1, 2], [3, 4]])"),
+    builtin("det", LINEAR_ALGEBRA, "det(A)", "Determinant of a square matrix.", Some("square matrices"), "det([[1, 2], [3, 4]])"),
+    builtin("inv", LINEAR_ALGEBRA, "inv(A)", "Inverse of a square matrix.", Some("non-singular square matrices"), "inv([[2, 0], [0, 4]])"),
+    builtin("dot", LINEAR_ALGEBRA, "dot(a, b)", "Dot product of two vectors.", Some("vectors of equal length"), "dot([1, 2, 3], [4, 5, 6])"),
+    builtin("cross", LINEAR_ALGEBRA, "cross(a, b)", "Cross product of two vectors.", Some("two vectors of length 3"), "cross([1, 0, 0], [0, 1, 0])"),
+    builtin("norm", LINEAR_ALGEBRA, "norm(a)", "Euclidean length of a vector.", None, "norm([3, 4])"),
+    builtin("linsolve", LINEAR_ALGEBRA, "linsolve(A, b)", "The x with A @ x == b.", Some("a non-singular square matrix and a vector"), "linsolve([[2, 1], [1, 3]], [3, 5])"),
+    builtin("polyval", LINEAR_ALGEBRA, "polyval(p, x)", "Value at x of a polynomial given by its coefficients, highest degree first.", None, "polyval([1, -3, 2], 5)"),
+    builtin("polyroots", LINEAR_ALGEBRA, "polyroots(p)", "Real roots of a polynomial in ascending order.", None, "polyroots([1, -3, 2])"),
+    builtin("integrate", CALCULUS, "integrate(f, a, b), integrate(f, a, b, tol)", "Definite integral of f from a to b by adaptive Simpson's rule.", None, "integrate(sin, 0, pi)"),
+    builtin("diff", CALCULUS, "diff(f, x)", "Numerical derivative of f at x.", None, "diff(exp, 0)"),
+    builtin("solve", CALCULUS, "solve(f, a, b), solve lhs = rhs for x", "A root of f between a and b, or a solution of an equation.", Some("f(a) and f(b) of opposite sign"), "solve(cos, 0, 2)"),
+    builtin("newton", CALCULUS, "newton(f, x0)", "A root of f near x0 by Newton's method.", None, "newton(cos, 1)"),
+    builtin("minimize", CALCULUS, "minimize(f, a, b)", "[x, f(x)] at the smallest value of f between a and b.", None, "minimize(cos, 0, 6)"),
+    builtin("maximize", CALCULUS, "maximize(f, a, b)", "[x, f(x)] at the largest value of f between a and b.", None, "maximize(sin, 0, 3)"),
+    builtin("deriv", CALCULUS, "deriv(expr, x)", "Symbolic derivative of an expression, as text.", None, "deriv(x * sin(x), x)"),
+    builtin("equiv", CALCULUS, "equiv(a, b)", "Whether two expressions agree at random points.", None, "equiv((x + 1)^2, x^2 + 2*x + 1)"),
+    builtin("rationalize", CALCULUS, "rationalize(x), rationalize(x, max)", "The closest fraction to x with a denominator of at most max (10000).", Some("a finite number and a whole maximum denominator of at least 1"), "rationalize(pi, 1000)"),
+    builtin("confrac", CALCULUS, "confrac(x, n)", "The first n terms of the continued fraction of x.", Some("a finite number and a whole number of terms of at least 1"), "confrac(pi, 4)"),
+    builtin("db", ENGINEERING, "db(x)", "A power ratio in decibels, 10 log10(x).", Some("(0, inf)"), "db(100)"),
+    builtin("undb", ENGINEERING, "undb(x)", "The power ratio of a level in decibels.", None, "undb(20)"),
+    builtin("dbm_to_mw", ENGINEERING, "dbm_to_mw(x)", "Converts dBm to milliwatts.", None, "dbm_to_mw(30)"),
+    builtin("mw_to_dbm", ENGINEERING, "mw_to_dbm(x)", "Converts milliwatts to dBm.", Some("(0, inf)"), "mw_to_dbm(1000)"),
+    builtin("parallel", ENGINEERING, "parallel(r1, r2, ...)", "Resistances combined in parallel.", Some("[0, inf)"), "parallel(100, 100)"),
+    builtin("voltage_divider", ENGINEERING, "voltage_divider(vin, r1, r2)", "Output voltage across r2.", Some("resistances in [0, inf)"), "voltage_divider(5, 1000, 2000)"),
+    builtin("haversine", ENGINEERING, "haversine(lat1, lon1, lat2, lon2), haversine(lat1, lon1, lat2, lon2, r)", "Great-circle distance between two points in degrees, in km unless a radius is given.", Some("latitudes in [-90, 90]"), "haversine(51.5, -0.13, 48.86, 2.35)"),
+    builtin("bearing", ENGINEERING, "bearing(lat1, lon1, lat2, lon2)", "Initial bearing from the first point to the second, in degrees from north.", Some("latitudes in [-90, 90]"), "bearing(51.5, -0.13, 48.86, 2.35)"),
+    builtin("convert", ENGINEERING, "convert(x, \"from\", \"to\")", "Converts a value between units of the same dimension.", None, "convert(26.2, \"mi\", \"km\")"),
+    builtin("compound", FINANCE, "compound(p, rate, years), compound(p, rate, years, n)", "Principal after compound interest, n times a year (once by default).", None, "compound(1000, 0.05, 10, 12)"),
+    builtin("pv", FINANCE, "pv(rate, n, fv), pv(rate, n, fv, pmt)", "Present value of a future amount plus payments over n periods.", Some("rate > -1"), "pv(0.05, 10, 1000)"),
+    builtin("fv", FINANCE, "fv(rate, n, pv), fv(rate, n, pv, pmt)", "Future value of an amount plus payments over n periods.", Some("rate > -1"), "fv(0.05, 10, 1000)"),
+    builtin("npv", FINANCE, "npv(rate, flows)", "Net present value of cash flows, the first one now.", Some("rate > -1"), "npv(0.1, [-100, 60, 60])"),
+    builtin("irr", FINANCE, "irr(flows)", "Internal rate of return of cash flows.", Some("cash flows that change sign"), "irr([-100, 60, 60])"),
+    builtin("to_hex", CONVERSION, "to_hex(n)", "An integer as a hexadecimal string.", Some("integers"), "to_hex(255)"),
+    builtin("to_bin", CONVERSION, "to_bin(n)", "An integer as a binary string.", Some("integers"), "to_bin(10)"),
+    builtin("to_oct", CONVERSION, "to_oct(n)", "An integer as an octal string.", Some("integers"), "to_oct(64)"),
+    builtin("hex2rgb", CONVERSION, "hex2rgb(\"#rrggbb\")", "The [r, g, b] channels of a hex colour.", Some("\"rrggbb\", \"#rrggbb\" or \"rgb\""), "hex2rgb(\"#ff8000\")"),
+    builtin("rgb2hex", CONVERSION, "rgb2hex(r, g, b)", "A hex colour from its channels.", Some("channels in [0, 255]"), "rgb2hex(255, 128, 0)"),
+    builtin("srgb2linear", CONVERSION, "srgb2linear(c)", "Linear intensity of an sRGB channel.", Some("[0, 1]"), "srgb2linear(0.5)"),
+    builtin("linear2srgb", CONVERSION, "linear2srgb(c)", "sRGB channel of a linear intensity.", Some("[0, 1]"), "linear2srgb(0.5)"),
+    builtin("pi", CONSTANTS, "pi", "The ratio of a circle's circumference to its diameter.", None, "pi"),
+    builtin("e", CONSTANTS, "e", "The base of natural logarithms.", None, "e"),
+    builtin("tau", CONSTANTS, "tau", "2 pi.", None, "tau"),
+    builtin("phi", CONSTANTS, "phi", "The golden ratio.", None, "phi"),
+    builtin("ans", CONSTANTS, "ans, ans(n)", "The previous answer, or the n-th most recent.", None, "ans"),
+    builtin("print", OTHER, "print(x)", "Writes x on a line of its own and gives x.", None, "print(42)"),
+    builtin("assert", OTHER, "assert(cond)", "Stops with an error unless cond is non-zero.", None, "assert(1 < 2)"),
+];
+
+/// Other names for builtins in [`REFERENCE`].
+const ALIASES: &[(&str, &str)] = &[("arcsinh", "asinh"), ("arccosh", "acosh"), ("arctanh", "atanh")];
+
+pub fn find(name: &str) -> Option<&'static Builtin> {
+    let name = ALIASES.iter().find(|(alias, _)| *alias == name).map_or(name, |(_, name)| name);
+    REFERENCE.iter().find(|builtin| builtin.name == name)
+}
+
+/// The names of all builtins, a line per category.
+pub fn index() -> String {
+    let mut text = String::new();
+    let mut category = "";
+    for builtin in REFERENCE {
+        if builtin.category != category {
+            category = builtin.category;
+            text.push_str(&format!("\n\n{}:\n ", category));
+        }
+        let line_len = text.len() - text.rfind('\n').map_or(0, |i| i + 1);
+        if line_len + builtin.name.len() > 78 {
+            text.push_str("\n ");
+        }
+        text.push(' ');
+        text.push_str(builtin.name);
+    }
+    text.push_str("\n\nType ':help <name>' for more about one of them.");
+    text.trim_start().to_string()
+}
+
+/// The signature, summary, domain and an example of the builtin `name`.
+/// The example is evaluated, so its result is always current.
+pub fn describe(name: &str) -> Option<String> {
+    let builtin = find(name)?;
+    let mut text = format!("{}\n  {}\n", builtin.signature, builtin.summary);
+    if let Some(domain) = builtin.domain {
+        text.push_str(&format!("  Domain: {}\n", domain));
+    }
+    match evaluate(builtin.example) {
+        Ok(result) => {
+            // Lines a matrix up under its first row.
+            let prefix = format!("  Example: {} = ", builtin.example);
+            let result = result.replace('\n', &format!("\n{:1$}", "", prefix.len()));
+            text.push_str(&format!("{}{}", prefix, result));
+        }
+        Err(_) => text.push_str(&format!("  Example: {}", builtin.example)),
+    }
+    Some(text)
+}
+
+/// Runs the statements of an example in a fresh VM and gives the value of
+/// the last one.
+fn evaluate(source: &str) -> Result<String, script::StatementError> {
+    let mut vm = VirtualMachine::new();
+    vm.output = Box::new(std::io::sink());
+    let tokens = lexer::scan(source).map_err(script::StatementError::Lex)?;
+    let mut result = String::new();
+    for statement in script::statements(tokens) {
+        let operations = script::compile(statement)?;
+        result = vm.evaluate(&operations).map_err(script::StatementError::Runtime)?.to_string();
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_keyword_is_described() {
+        let syntax = ["let", "in", "if", "then", "else", "for", "to", "while", "import", "mod", "xor"];
+        for keyword in lexer::KEYWORDS {
+            assert!(syntax.contains(keyword) || find(keyword).is_some(), "{}", keyword);
+        }
+    }
+
+    #[test]
+    fn test_examples_evaluate() {
+        for builtin in REFERENCE {
+            if builtin.name != "ans" {
+                let result = evaluate(builtin.example);
+                assert!(result.is_ok(), "{}: {:?}", builtin.example, result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            describe("sqrt").unwrap(),
+            "sqrt(x)\n  Square root.\n  Domain: [0, inf)\n  Example: sqrt(2) = 1.4142135623730951"
+        );
+        assert!(describe("arcsinh").unwrap().starts_with("asinh(x), arcsinh(x)"));
+        assert_eq!(describe("nonsense"), None);
+    }
+
+    #[test]
+    fn test_index_groups_by_category() {
+        let index = index();
+        assert!(index.starts_with("Trigonometry:\n  sin cos tan"));
+        assert!(index.contains("\n\nConstants:\n  pi e tau phi ans\n"));
+        assert!(index.lines().all(|line| line.len() <= 80));
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::HashSet;
+
+use crate::lexer;
+use crate::token::{Token, TokenType};
+
+pub const RESET: &str = "\x1b[0m";
+pub const NUMBER: &str = "\x1b[36m";
+pub const OPERATOR: &str = "\x1b[33m";
+pub const BUILTIN: &str = "\x1b[35m";
+pub const STRING: &str = "\x1b[32m";
+pub const ERROR: &str = "\x1b[1;31m";
+
+/// Colours `source` for a terminal by the tokens the lexer finds in it:
+/// numbers, operators, builtin names and strings, with brackets that have
+/// no partner in red. Text between tokens, such as comments, is left as it
+/// is, and so is source that does not scan.
+pub fn highlight(source: &str) -> String {
+    let Ok(tokens) = lexer::scan(source) else {
+        return source.to_string();
+    };
+    let unmatched = unmatched_brackets(&tokens);
+    let mut highlighted = String::new();
+    let mut end = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        let (start, stop) = token.span;
+        let (Some(gap), Some(text)) = (source.get(end..start), source.get(start..stop)) else {
+            break;
+        };
+        highlighted.push_str(gap);
+        let colour = if unmatched.contains(&i) { Some(ERROR) } else { colour(token) };
+        match colour {
+            Some(colour) => {
+                highlighted.push_str(colour);
+                highlighted.push_str(text);
+                highlighted.push_str(RESET);
+            }
+            None => highlighted.push_str(text),
+        }
+        end = stop;
+    }
+    highlighted.push_str(source.get(end..).unwrap_or_default());
+    highlighted
+}
+
+fn colour(token: &Token) -> Option<&'static str> {
+    use TokenType::*;
+    match token.token_type {
+        Number => Some(NUMBER),
+        Str => Some(STRING),
+        Identifier | EOF | LeftParen | RightParen | LeftBracket | RightBracket | LeftBrace
+        | RightBrace | Comma | Semicolon => None,
+        _ if token.lexeme.starts_with(|c: char| c.is_alphabetic()) => Some(BUILTIN),
+        _ => Some(OPERATOR),
+    }
+}
+
+/// The indices of the brackets that are never closed, closed by the wrong
+/// kind of bracket, or close nothing.
+fn unmatched_brackets(tokens: &[Token]) -> HashSet<usize> {
+    use TokenType::*;
+    let mut open: Vec<(usize, TokenType)> = Vec::new();
+    let mut unmatched = HashSet::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let opener = match token.token_type {
+            LeftParen | LeftBracket | LeftBrace => {
+                open.push((i, token.token_type.clone()));
+                continue;
+            }
+            RightParen => LeftParen,
+            RightBracket => LeftBracket,
+            RightBrace => LeftBrace,
+            _ => continue,
+        };
+        match open.pop() {
+            Some((_, kind)) if kind == opener => {}
+            Some((j, _)) => {
+                unmatched.insert(j);
+                unmatched.insert(i);
+            }
+            None => {
+                unmatched.insert(i);
+            }
+        }
+    }
+    unmatched.extend(open.into_iter().map(|(i, _)| i));
+    unmatched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coloured(colour: &str, text: &str) -> String {
+        format!("{}{}{}", colour, text, RESET)
+    }
+
+    #[test]
+    fn test_highlight() {
+        let expected = format!(
+            "{}({} {} x) # note",
+            coloured(BUILTIN, "sin"),
+            coloured(NUMBER, "2"),
+            coloured(OPERATOR, "*"),
+        );
+        assert_eq!(highlight("sin(2 * x) # note"), expected);
+        assert_eq!(highlight("\"open"), "\"open");
+    }
+
+    #[test]
+    fn test_unmatched_brackets() {
+        let expected = format!("{}{}{}", coloured(ERROR, "("), coloured(NUMBER, "1"), "[]");
+        assert_eq!(highlight("(1[]"), expected);
+        assert_eq!(highlight("(]"), format!("{}{}", coloured(ERROR, "("), coloured(ERROR, "]")));
+        assert_eq!(highlight("x)"), format!("x{}", coloured(ERROR, ")")));
+    }
+}
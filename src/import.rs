@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::operation::Operation;
+use crate::script::{self, EmptyStatements, ScriptError, StatementError};
+use crate::vm::VirtualMachine;
+
+/// Environment variable with extra directories to search for imports,
+/// separated like `PATH`.
+pub const CALC_PATH: &str = "CALC_PATH";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportError {
+    NotFound(String),
+    /// The chain of files that import each other, ending where it started.
+    Cycle(Vec<PathBuf>),
+    Io { path: PathBuf, message: String },
+    /// A library statement that is not a function or variable definition.
+    NotADefinition,
+    Script { path: PathBuf, error: Box<ScriptError> },
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::NotFound(name) => write!(f, "Cannot find '{}' to import", name),
+            ImportError::Cycle(chain) => {
+                let chain: Vec<String> = chain.iter().map(|path| path.display().to_string()).collect();
+                write!(f, "Import cycle: {}", chain.join(" -> "))
+            }
+            ImportError::Io { path, message } => write!(f, "{}: {}", path.display(), message),
+            ImportError::NotADefinition => {
+                write!(f, "Imported files may only define functions and variables")
+            }
+            ImportError::Script { path, error } => write!(f, "{}: {}", path.display(), error),
+        }
+    }
+}
+
+/// Loads `import "file"` statements. A file is looked up relative to the
+/// file importing it, then in each search directory. Files already loaded
+/// by this importer are skipped, so a library imported twice is only run
+/// once.
+#[derive(Debug, Clone, Default)]
+pub struct Importer {
+    search_path: Vec<PathBuf>,
+    /// Where imports that are not in a file are looked up first, if set.
+    directory: Option<PathBuf>,
+    /// Files being loaded, outermost first.
+    loading: Vec<PathBuf>,
+    loaded: HashSet<PathBuf>,
+}
+
+impl Importer {
+    /// Searches `search_path` followed by the directories in `CALC_PATH`.
+    pub fn new(mut search_path: Vec<PathBuf>) -> Self {
+        if let Some(paths) = std::env::var_os(CALC_PATH) {
+            search_path.extend(std::env::split_paths(&paths));
+        }
+        Self { search_path, ..Self::default() }
+    }
+
+    /// Looks up imports made outside any imported file in `directory`
+    /// first, as those in a file are looked up next to it. Used while
+    /// running a startup file; `None` goes back to just the search path.
+    pub fn set_directory(&mut self, directory: Option<PathBuf>) {
+        self.directory = directory;
+    }
+
+    fn resolve(&self, name: &str) -> Option<PathBuf> {
+        let importing = match self.loading.last() {
+            Some(path) => path.parent(),
+            None => self.directory.as_deref(),
+        };
+        importing
+            .into_iter()
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(name))
+            .chain(Path::new(name).is_absolute().then(|| PathBuf::from(name)))
+            .find(|path| path.is_file())
+            .and_then(|path| path.canonicalize().ok())
+    }
+
+    /// Runs the definitions in the file `name`. If any of them fails, the
+    /// functions and variables defined by the import are rolled back.
+    pub fn import(&mut self, vm: &mut VirtualMachine, name: &str) -> Result<(), ImportError> {
+        let path = self.resolve(name).ok_or_else(|| ImportError::NotFound(name.to_string()))?;
+        if let Some(start) = self.loading.iter().position(|loading| *loading == path) {
+            let mut chain = self.loading[start..].to_vec();
+            chain.push(path);
+            return Err(ImportError::Cycle(chain));
+        }
+        if self.loaded.contains(&path) {
+            return Ok(());
+        }
+
+        let source = std::fs::read_to_string(&path)
+            .map_err(|e| ImportError::Io { path: path.clone(), message: e.to_string() })?;
+        let checkpoint = self.loading.is_empty().then(|| vm.checkpoint());
+        self.loading.push(path.clone());
+        let result = self.load(vm, &source);
+        self.loading.pop();
+
+        match result {
+            Ok(()) => {
+                self.loaded.insert(path);
+                Ok(())
+            }
+            Err(error) => {
+                if let Some(checkpoint) = checkpoint {
+                    vm.rollback(checkpoint);
+                }
+                Err(ImportError::Script { path, error: Box::new(error) })
+            }
+        }
+    }
+
+    fn load(&mut self, vm: &mut VirtualMachine, source: &str) -> Result<(), ScriptError> {
+        script::for_each_statement(source, EmptyStatements::Skip, |buffers| {
+            if let Some(name) = script::import_name(&buffers.statement) {
+                return self.import(vm, name).map_err(StatementError::Import);
+            }
+            buffers.compile()?;
+            if !matches!(buffers.operations.last(), Some(Operation::Define(_) | Operation::Store(_))) {
+                return Err(StatementError::Import(ImportError::NotADefinition));
+            }
+            vm.evaluate(&buffers.operations)
+                .map(|_| ())
+                .map_err(|e| StatementError::Runtime(e.locate(&buffers.spans)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+
+    /// A temporary directory, removed with its files when dropped.
+    struct Library(PathBuf);
+
+    impl Drop for Library {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A fresh directory holding the given files.
+    fn library(test: &str, files: &[(&str, &str)]) -> Library {
+        let dir = std::env::temp_dir().join(format!("calc-import-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, source) in files {
+            std::fs::write(dir.join(name), source).unwrap();
+        }
+        Library(dir)
+    }
+
+    #[test]
+    fn test_import_definitions() {
+        let dir = library("definitions", &[
+            ("physics.calc", "import \"units.calc\"\nkinetic(m, v) = m * v^2 / 2\n"),
+            ("units.calc", "# constants\ng = 9.81\n"),
+        ]);
+        let mut vm = VirtualMachine::new();
+        let mut importer = Importer::new(vec![dir.0.clone()]);
+        importer.import(&mut vm, "physics.calc").unwrap();
+        assert_eq!(vm.evaluate(&[Operation::Load("g".to_string())]), Ok(Value::Number(9.81)));
+
+        let call = [Operation::Const(2.0), Operation::Const(3.0), Operation::Call("kinetic".to_string(), 2)];
+        assert_eq!(vm.evaluate(&call), Ok(Value::Number(9.0)));
+    }
+
+    #[test]
+    fn test_import_cycle() {
+        let dir = library("cycle", &[
+            ("a.calc", "import \"b.calc\"\n"),
+            ("b.calc", "x = 1; import \"a.calc\"\n"),
+        ]);
+        let mut vm = VirtualMachine::new();
+        let error = Importer::new(vec![dir.0.clone()]).import(&mut vm, "a.calc").unwrap_err();
+        assert!(error.to_string().contains("Import cycle"), "{}", error);
+        assert!(vm.evaluate(&[Operation::Load("x".to_string())]).is_err(), "a failed import is rolled back");
+    }
+
+    #[test]
+    fn test_import_not_found() {
+        let mut vm = VirtualMachine::new();
+        let result = Importer::new(Vec::new()).import(&mut vm, "missing-library.calc");
+        assert_eq!(result, Err(ImportError::NotFound("missing-library.calc".to_string())));
+    }
+
+    #[test]
+    fn test_import_only_definitions() {
+        let dir = library("expression", &[("lib.calc", "f(x) = x + 1\nf(2)\n")]);
+        let mut vm = VirtualMachine::new();
+        let error = Importer::new(vec![dir.0.clone()]).import(&mut vm, "lib.calc").unwrap_err();
+        let ImportError::Script { error, .. } = error else { panic!("{:?}", error) };
+        assert_eq!(error.line, 2);
+        assert_eq!(error.error, StatementError::Import(ImportError::NotADefinition));
+    }
+}
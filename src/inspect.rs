@@ -0,0 +1,168 @@
+use crate::format::{format_number, FormatOptions};
+use crate::operation::Operation;
+use crate::token::Span;
+
+/// Draws the expression tree of compiled `operations`: each operation is a
+/// node whose children are the operations that pushed the values it pops.
+/// Sub-programs, such as the branches of an `if`, hang under a label.
+///
+/// ```text
+/// Add
+/// ├─ 1
+/// └─ Times
+///    ├─ 2
+///    └─ 3
+/// ```
+pub fn ast(operations: &[Operation]) -> String {
+    let mut lines = Vec::new();
+    for root in tree(operations) {
+        root.draw("", "", &mut lines);
+    }
+    lines.join("\n")
+}
+
+/// Lists compiled `operations` one per line, numbered, with the text of
+/// `source` each came from. Sub-programs are listed under their operation,
+/// indented.
+pub fn ops(operations: &[Operation], spans: &[Span], source: &str) -> String {
+    let width = operations.iter().map(|op| label(op).chars().count()).max().unwrap_or(0);
+    let mut lines = Vec::new();
+    for (i, op) in operations.iter().enumerate() {
+        let text = spans.get(i).and_then(|&(start, end)| source.get(start..end)).unwrap_or("");
+        let line = format!("{:>3}  {:<width$}  {}", i + 1, label(op), text, width = width);
+        lines.push(line.trim_end().to_string());
+        for (name, program) in op.sub_programs() {
+            lines.push(format!("     {}:", name));
+            list_nested(program, "       ", &mut lines);
+        }
+    }
+    lines.join("\n")
+}
+
+fn list_nested(operations: &[Operation], indent: &str, lines: &mut Vec<String>) {
+    for (i, op) in operations.iter().enumerate() {
+        lines.push(format!("{}{:>3}  {}", indent, i + 1, label(op)));
+        for (name, program) in op.sub_programs() {
+            lines.push(format!("{}     {}:", indent, name));
+            list_nested(program, &format!("{}       ", indent), lines);
+        }
+    }
+}
+
+struct Node {
+    label: String,
+    children: Vec<Node>,
+}
+
+impl Node {
+    /// Appends this node's line and its children's, each line starting
+    /// with `prefix`, or `first` for the node's own.
+    fn draw(&self, first: &str, prefix: &str, lines: &mut Vec<String>) {
+        lines.push(format!("{}{}", first, self.label));
+        for (i, child) in self.children.iter().enumerate() {
+            let (branch, rest) = if i + 1 == self.children.len() {
+                ("└─ ", "   ")
+            } else {
+                ("├─ ", "│  ")
+            };
+            child.draw(&format!("{}{}", prefix, branch), &format!("{}{}", prefix, rest), lines);
+        }
+    }
+}
+
+/// Rebuilds the trees of `operations` by their stack effects and returns
+/// the roots left on the stack, which is one for a whole statement.
+fn tree(operations: &[Operation]) -> Vec<Node> {
+    let mut stack: Vec<Node> = Vec::new();
+    for op in operations {
+        let mut children = stack.split_off(stack.len().saturating_sub(op.arity()));
+        for (name, program) in op.sub_programs() {
+            children.push(Node { label: format!("{}:", name), children: tree(program) });
+        }
+        let label = match op {
+            Operation::Const(x) => format_number(*x, &FormatOptions::default()),
+            Operation::Load(name) => name.clone(),
+            op => label(op),
+        };
+        stack.push(Node { label, children });
+    }
+    stack
+}
+
+/// How an operation is shown: its `Debug` form, or for those holding
+/// sub-programs, its name and the variable it binds.
+fn label(op: &Operation) -> String {
+    match op {
+        Operation::Define(function) => {
+            format!("Define {}({})", function.name, function.params.join(", "))
+        }
+        Operation::For(body) => format!("For {}", body.variable),
+        Operation::SolveFor(equation) => format!("SolveFor {}", equation.variable),
+        Operation::Deriv(derivative) => format!("Deriv {}", derivative.variable),
+        Operation::If(_) | Operation::While(_) | Operation::Equiv(_) => op.name(),
+        op => format!("{:?}", op),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, script};
+
+    fn compile(source: &str) -> (Vec<Operation>, Vec<Span>) {
+        script::compile_with_spans(lexer::scan(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_ast() {
+        let (operations, _) = compile("1 + 2 * sin(x)");
+        assert_eq!(
+            ast(&operations),
+            "Add\n\
+             ├─ 1\n\
+             └─ Times\n   \
+                ├─ 2\n   \
+                └─ Sin\n      \
+                   └─ x"
+        );
+
+        let (operations, _) = compile("if x > 0 then 1 else -1");
+        assert_eq!(
+            ast(&operations),
+            "If\n\
+             ├─ Greater\n\
+             │  ├─ x\n\
+             │  └─ 0\n\
+             ├─ then:\n\
+             │  └─ 1\n\
+             └─ else:\n   \
+                └─ Negate\n      \
+                   └─ 1"
+        );
+    }
+
+    #[test]
+    fn test_ops() {
+        let source = "2 * (x + 1)";
+        let (operations, spans) = compile(source);
+        assert_eq!(
+            ops(&operations, &spans, source),
+            "  1  Const(2.0)  2\n  \
+               2  Load(\"x\")   x\n  \
+               3  Const(1.0)  1\n  \
+               4  Add         +\n  \
+               5  Times       *"
+        );
+
+        let source = "f(x) = x^2";
+        let (operations, spans) = compile(source);
+        assert_eq!(
+            ops(&operations, &spans, source),
+            "  1  Define f(x)  f(x) = x^2\n     \
+                  body:\n         \
+                    1  Load(\"x\")\n         \
+                    2  Const(2.0)\n         \
+                    3  Power"
+        );
+    }
+}
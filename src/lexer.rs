@@ -4,9 +4,43 @@ use crate::token::*;
 
 
 
+/// Every name [`scan`] treats as a keyword rather than an identifier,
+/// e.g. for completing a partly typed name.
+pub const KEYWORDS: &[&str] = &[
+    "sin", "cos", "tan", "sind", "cosd", "tand", "deg2rad", "rad2deg", "arcsin",
+    "arccos", "arctan", "atan2", "hypot", "topolar", "torect", "sinh", "cosh", "tanh",
+    "asinh", "arcsinh", "acosh", "arccosh", "atanh", "arctanh", "ln", "log", "log10",
+    "log2", "exp", "sqrt", "cbrt", "root", "abs", "sign", "floor", "ceil", "round",
+    "trunc", "frac", "haversine", "bearing", "db", "undb", "dbm_to_mw", "mw_to_dbm",
+    "parallel", "voltage_divider", "odds", "prob", "bayes", "xor", "to_hex", "to_bin",
+    "to_oct", "sigfig", "convert", "compound", "pv", "fv", "npv", "irr", "hex2rgb",
+    "rgb2hex", "srgb2linear", "linear2srgb", "digits", "digitsum", "reversedigits",
+    "gamma", "fact", "erf", "erfc", "normpdf", "normcdf", "mean", "median", "mode",
+    "var", "stdev", "sum", "prod", "wmean", "linreg", "normalize", "zscore", "sort",
+    "percentile", "iqr", "unique", "union", "intersect", "cumsum", "cumprod", "len",
+    "range", "reverse", "head", "tail", "concat", "transpose", "det", "inv", "dot",
+    "cross", "norm", "linsolve", "polyval", "polyroots", "integrate", "diff", "solve",
+    "newton", "rationalize", "confrac", "minimize", "maximize", "deriv", "equiv", "map",
+    "filter", "reduce", "min", "max", "rand", "randint", "randn", "print", "assert",
+    "gcd", "lcm", "isprime", "nextprime", "factor", "powmod", "mod", "ans", "e", "pi",
+    "tau", "phi", "let", "in", "if", "then", "else", "for", "to", "while", "import",
+];
+
 pub fn scan<T: AsRef<[u8]>>(source: T) -> Result<Vec<Token>, LexError> {
+    let mut tokens = Vec::new();
+    scan_into(source, &mut tokens)?;
+    Ok(tokens)
+}
+
+/// Like [`scan`], but writes into `tokens`, which is cleared first, so that
+/// a caller scanning line after line can keep reusing one buffer.
+pub fn scan_into<T: AsRef<[u8]>>(source: T, tokens: &mut Vec<Token>) -> Result<(), LexError> {
+    tokens.clear();
     let mut lexer = Lexer::from_bytes(source.as_ref());
-    lexer.scan().cloned()
+    lexer.tokens = std::mem::take(tokens);
+    let result = lexer.scan().map(|_| ());
+    *tokens = lexer.tokens;
+    result
 }
 
 
@@ -14,26 +48,37 @@ pub fn scan<T: AsRef<[u8]>>(source: T) -> Result<Vec<Token>, LexError> {
 
 
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum LexError {
     UnexpectedChar { char: String, span: (usize, usize) },
-    UnknownIdentifier { lexeme: String, span: (usize, usize) },
     InvalidNumber { lexeme: String, span: (usize, usize) },
+    UnterminatedString { span: (usize, usize) },
     InvalidUTF8 { span: (usize, usize) },
 }
 
+impl LexError {
+    pub fn span(&self) -> Span {
+        match self {
+            LexError::UnexpectedChar { span, .. }
+            | LexError::InvalidNumber { span, .. }
+            | LexError::UnterminatedString { span }
+            | LexError::InvalidUTF8 { span } => *span,
+        }
+    }
+}
+
 impl fmt::Display for LexError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             LexError::UnexpectedChar { char, span } => {
                 write!(f, "Unexpected character '{}' at {}..{}", char, span.0, span.1)
             }
-            LexError::UnknownIdentifier { lexeme, span } => {
-                write!(f, "Unknown identifier '{}' at {}..{}", lexeme, span.0, span.1)
-            }
             LexError::InvalidNumber { lexeme, span } => {
                 write!(f, "Invalid number '{}' at {}..{}", lexeme, span.0, span.1)
             }
+            LexError::UnterminatedString { span } => {
+                write!(f, "Unterminated string at {}..{}", span.0, span.1)
+            }
             LexError::InvalidUTF8 { span } => {
                 write!(f, "Invalid UTF-8 sequence at {}..{}", span.0, span.1)
             }
@@ -58,6 +103,7 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    #[allow(dead_code)]
     pub fn from_str(source: &'a str) -> Self {
         Self {
             source: source.as_bytes(),
@@ -74,24 +120,60 @@ impl<'a> Lexer<'a> {
             match c {
                 '(' => self.add_token(TokenType::LeftParen, c),
                 ')' => self.add_token(TokenType::RightParen, c),
+                '[' => self.add_token(TokenType::LeftBracket, c),
+                ']' => self.add_token(TokenType::RightBracket, c),
+                '{' => self.add_token(TokenType::LeftBrace, c),
+                '}' => self.add_token(TokenType::RightBrace, c),
                 ',' => self.add_token(TokenType::Comma, c),
+                ';' => self.add_token(TokenType::Semicolon, c),
                 '-' => self.add_token(TokenType::Minus, c),
                 '+' => self.add_token(TokenType::Plus, c),
                 '*' => self.add_token(TokenType::Star, c),
+                '@' => self.add_token(TokenType::At, c),
+                '/' if self.peek() == '/' => {
+                    self.advance();
+                    self.add_token(TokenType::SlashSlash, "//")
+                }
                 '/' => self.add_token(TokenType::Slash, c),
                 '^' => self.add_token(TokenType::Caret, c),
+                '!' if self.peek() == '=' => {
+                    self.advance();
+                    self.add_token(TokenType::BangEqual, "!=")
+                }
+                '!' => self.add_token(TokenType::Bang, c),
+                '&' => self.add_token(TokenType::Ampersand, c),
+                '|' => self.add_token(TokenType::Pipe, c),
+                '~' => self.add_token(TokenType::Tilde, c),
+                '=' if self.peek() == '=' => {
+                    self.advance();
+                    self.add_token(TokenType::EqualEqual, "==")
+                }
+                '=' => self.add_token(TokenType::Equal, c),
+                '<' if self.peek() == '<' => {
+                    self.advance();
+                    self.add_token(TokenType::LessLess, "<<")
+                }
+                '<' if self.peek() == '=' => {
+                    self.advance();
+                    self.add_token(TokenType::LessEqual, "<=")
+                }
+                '<' => self.add_token(TokenType::Less, c),
+                '>' if self.peek() == '>' => {
+                    self.advance();
+                    self.add_token(TokenType::GreaterGreater, ">>")
+                }
+                '>' if self.peek() == '=' => {
+                    self.advance();
+                    self.add_token(TokenType::GreaterEqual, ">=")
+                }
+                '>' => self.add_token(TokenType::Greater, c),
 
                 ' ' | '\r' | '\n' | '\t' => {},
+                '#' => self.comment(),
 
-                '0'..='9' | '.' => match self.number() {
-                    Err(e) => { return Err(e); },
-                    _ => {},
-                    
-                },
-                'a'..'z' | 'A'..='Z' => match self.identifier() {
-                    Err(e) => { return Err(e); },
-                    _ => {},
-                },
+                '"' => self.string()?,
+                '0'..='9' | '.' => self.number()?,
+                'a'..='z' | 'A'..='Z' => self.identifier()?,
                 _ => {
                     return Err(LexError::UnexpectedChar {
                         char: c.to_string(), span: (self.start, self.curr)
@@ -115,7 +197,7 @@ impl<'a> Lexer<'a> {
     }
 
     fn identifier(&mut self) -> Result<(), LexError> {
-        while Self::is_alpha(self.peek()) {
+        while Self::is_alphanumeric(self.peek()) {
             self.advance();
         }
 
@@ -134,22 +216,182 @@ impl<'a> Lexer<'a> {
             "sin" => Ok(TokenType::Sin),
             "cos" => Ok(TokenType::Cos),
             "tan" => Ok(TokenType::Tan),
+            "sind" => Ok(TokenType::SinD),
+            "cosd" => Ok(TokenType::CosD),
+            "tand" => Ok(TokenType::TanD),
+            "deg2rad" => Ok(TokenType::Deg2Rad),
+            "rad2deg" => Ok(TokenType::Rad2Deg),
             "arcsin" => Ok(TokenType::ArcSin),
             "arccos" => Ok(TokenType::ArcCos),
             "arctan" => Ok(TokenType::ArcTan),
+            "atan2" => Ok(TokenType::ArcTan2),
+            "hypot" => Ok(TokenType::Hypot),
+            "topolar" => Ok(TokenType::ToPolar),
+            "torect" => Ok(TokenType::ToRect),
+            "sinh" => Ok(TokenType::Sinh),
+            "cosh" => Ok(TokenType::Cosh),
+            "tanh" => Ok(TokenType::Tanh),
+            "asinh" | "arcsinh" => Ok(TokenType::ArcSinh),
+            "acosh" | "arccosh" => Ok(TokenType::ArcCosh),
+            "atanh" | "arctanh" => Ok(TokenType::ArcTanh),
             "ln" => Ok(TokenType::Ln),
+            "log" => Ok(TokenType::Log),
+            "log10" => Ok(TokenType::Log10),
+            "log2" => Ok(TokenType::Log2),
             "exp" => Ok(TokenType::Exp),
+            "sqrt" => Ok(TokenType::Sqrt),
+            "cbrt" => Ok(TokenType::Cbrt),
+            "root" => Ok(TokenType::Root),
+            "abs" => Ok(TokenType::Abs),
+            "sign" => Ok(TokenType::Sign),
+            "floor" => Ok(TokenType::Floor),
+            "ceil" => Ok(TokenType::Ceil),
+            "round" => Ok(TokenType::Round),
+            "trunc" => Ok(TokenType::Trunc),
+            "frac" => Ok(TokenType::Frac),
+            "haversine" => Ok(TokenType::Haversine),
+            "bearing" => Ok(TokenType::Bearing),
+            "db" => Ok(TokenType::Db),
+            "undb" => Ok(TokenType::UnDb),
+            "dbm_to_mw" => Ok(TokenType::DbmToMw),
+            "mw_to_dbm" => Ok(TokenType::MwToDbm),
+            "parallel" => Ok(TokenType::Parallel),
+            "voltage_divider" => Ok(TokenType::VoltageDivider),
+            "odds" => Ok(TokenType::Odds),
+            "prob" => Ok(TokenType::Prob),
+            "bayes" => Ok(TokenType::Bayes),
+            "xor" => Ok(TokenType::Xor),
+            "to_hex" => Ok(TokenType::ToHex),
+            "to_bin" => Ok(TokenType::ToBin),
+            "to_oct" => Ok(TokenType::ToOct),
+            "sigfig" => Ok(TokenType::SigFig),
+            "convert" => Ok(TokenType::Convert),
+            "compound" => Ok(TokenType::Compound),
+            "pv" => Ok(TokenType::Pv),
+            "fv" => Ok(TokenType::Fv),
+            "npv" => Ok(TokenType::Npv),
+            "irr" => Ok(TokenType::Irr),
+            "hex2rgb" => Ok(TokenType::Hex2Rgb),
+            "rgb2hex" => Ok(TokenType::Rgb2Hex),
+            "srgb2linear" => Ok(TokenType::SrgbToLinear),
+            "linear2srgb" => Ok(TokenType::LinearToSrgb),
+            "digits" => Ok(TokenType::Digits),
+            "digitsum" => Ok(TokenType::DigitSum),
+            "reversedigits" => Ok(TokenType::ReverseDigits),
+            "gamma" => Ok(TokenType::Gamma),
+            "fact" => Ok(TokenType::Fact),
+            "erf" => Ok(TokenType::Erf),
+            "erfc" => Ok(TokenType::Erfc),
+            "normpdf" => Ok(TokenType::NormPdf),
+            "normcdf" => Ok(TokenType::NormCdf),
+            "mean" => Ok(TokenType::Mean),
+            "median" => Ok(TokenType::Median),
+            "mode" => Ok(TokenType::Mode),
+            "var" => Ok(TokenType::Var),
+            "stdev" => Ok(TokenType::Stdev),
+            "sum" => Ok(TokenType::Sum),
+            "prod" => Ok(TokenType::Prod),
+            "wmean" => Ok(TokenType::WMean),
+            "linreg" => Ok(TokenType::LinReg),
+            "normalize" => Ok(TokenType::Normalize),
+            "zscore" => Ok(TokenType::ZScore),
+            "sort" => Ok(TokenType::Sort),
+            "percentile" => Ok(TokenType::Percentile),
+            "iqr" => Ok(TokenType::Iqr),
+            "unique" => Ok(TokenType::Unique),
+            "union" => Ok(TokenType::Union),
+            "intersect" => Ok(TokenType::Intersect),
+            "cumsum" => Ok(TokenType::CumSum),
+            "cumprod" => Ok(TokenType::CumProd),
+            "len" => Ok(TokenType::Len),
+            "range" => Ok(TokenType::Range),
+            "reverse" => Ok(TokenType::Reverse),
+            "head" => Ok(TokenType::Head),
+            "tail" => Ok(TokenType::Tail),
+            "concat" => Ok(TokenType::Concat),
+            "transpose" => Ok(TokenType::Transpose),
+            "det" => Ok(TokenType::Det),
+            "inv" => Ok(TokenType::Inv),
+            "dot" => Ok(TokenType::Dot),
+            "cross" => Ok(TokenType::Cross),
+            "norm" => Ok(TokenType::Norm),
+            "linsolve" => Ok(TokenType::LinSolve),
+            "polyval" => Ok(TokenType::PolyVal),
+            "polyroots" => Ok(TokenType::PolyRoots),
+            "integrate" => Ok(TokenType::Integrate),
+            "diff" => Ok(TokenType::Diff),
+            "solve" => Ok(TokenType::Solve),
+            "newton" => Ok(TokenType::Newton),
+            "rationalize" => Ok(TokenType::Rationalize),
+            "confrac" => Ok(TokenType::ConFrac),
+            "minimize" => Ok(TokenType::Minimize),
+            "maximize" => Ok(TokenType::Maximize),
+            "deriv" => Ok(TokenType::Deriv),
+            "equiv" => Ok(TokenType::Equiv),
+            "map" => Ok(TokenType::Map),
+            "filter" => Ok(TokenType::Filter),
+            "reduce" => Ok(TokenType::Reduce),
+            "min" => Ok(TokenType::Min),
+            "max" => Ok(TokenType::Max),
+            "rand" => Ok(TokenType::Rand),
+            "randint" => Ok(TokenType::RandInt),
+            "randn" => Ok(TokenType::RandN),
+            "print" => Ok(TokenType::Print),
+            "assert" => Ok(TokenType::Assert),
+            "gcd" => Ok(TokenType::Gcd),
+            "lcm" => Ok(TokenType::Lcm),
+            "isprime" => Ok(TokenType::IsPrime),
+            "nextprime" => Ok(TokenType::NextPrime),
+            "factor" => Ok(TokenType::Factor),
+            "powmod" => Ok(TokenType::PowMod),
+            "mod" => Ok(TokenType::Mod),
             "ans" => Ok(TokenType::Ans),
             "e" => Ok(TokenType::E),
             "pi" => Ok(TokenType::Pi),
-            _ => Err(LexError::UnknownIdentifier { lexeme: lexeme.into(), span: (self.start, self.curr) }),
+            "tau" => Ok(TokenType::Tau),
+            "phi" => Ok(TokenType::Phi),
+            "let" => Ok(TokenType::Let),
+            "in" => Ok(TokenType::In),
+            "if" => Ok(TokenType::If),
+            "then" => Ok(TokenType::Then),
+            "else" => Ok(TokenType::Else),
+            "for" => Ok(TokenType::For),
+            "to" => Ok(TokenType::To),
+            "while" => Ok(TokenType::While),
+            "import" => Ok(TokenType::Import),
+            _ => Ok(TokenType::Identifier),
         }
     } 
 
+    /// Skips a `#` comment up to the end of the line.
+    fn comment(&mut self) {
+        while !self.is_at_end() && self.peek() != '\n' {
+            self.advance();
+        }
+    }
+
+    /// Scans a double-quoted string literal. The token's lexeme is the
+    /// contents without the quotes.
+    fn string(&mut self) -> Result<(), LexError> {
+        while !self.is_at_end() && self.peek() != '"' {
+            self.advance();
+        }
+
+        if self.is_at_end() {
+            return Err(LexError::UnterminatedString { span: (self.start, self.curr) });
+        }
+        self.advance(); // consume closing '"'
+
+        let lexeme = str::from_utf8(&self.source[self.start + 1..self.curr - 1])
+            .map_err(|_| LexError::InvalidUTF8 { span: (self.start, self.curr) })?;
+
+        self.add_token(TokenType::Str, lexeme);
+        Ok(())
+    }
+
     fn number(&mut self) -> Result<(), LexError> {
         while Self::is_digit(self.peek()) {
-            let c = self.advance();
-            println!("{}", c);
+            self.advance();
         }
 
         // Optional decimal part
@@ -175,24 +417,24 @@ impl<'a> Lexer<'a> {
     }
 
     fn is_digit(c: char) -> bool {
-        '0' <= c && c <= '9'
+        c.is_ascii_digit()
     }
 
-    fn is_alpha(c: char) -> bool {
-        'a' <= c && c <= 'z' || 'A' <= c && c <= 'Z'
+    fn is_alphanumeric(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_'
     }
 
     fn peek(&mut self) -> char {
         if self.is_at_end() {
             return '\0';
         }
-        return self.source[self.curr] as char;
+        self.source[self.curr] as char
     }
 
     fn advance(&mut self) -> char {
         let res = self.source[self.curr] as char;
         self.increment();
-        return res;
+        res
     }
 
     fn is_at_end(&self) -> bool {
@@ -315,7 +557,7 @@ mod tests {
 
     #[test]
     fn test_unexpected_char() {
-        let bad_chars = vec!["@", "#", "!", ";"];
+        let bad_chars = vec!["`", "$", "?"];
             for c in bad_chars {
                 assert_lex_error(
                     c,
@@ -334,4 +576,159 @@ mod tests {
             ]);
     }
 
-}
\ No newline at end of file
+
+    #[test]
+    fn test_identifier_with_digits() {
+        assert_lex(
+            "log2(8)",
+            &vec![
+                make_token(TokenType::Log2, "log2", (0, 4)),
+                make_token(TokenType::LeftParen, "(", (4, 5)),
+                make_token(TokenType::Number, "8", (5, 6)),
+                make_token(TokenType::RightParen, ")", (6, 7)),
+                make_token(TokenType::EOF, "", (7, 8)),
+            ]);
+    }
+
+    #[test]
+    fn test_string() {
+        assert_lex(
+            "\"ff8800\"",
+            &vec![
+                make_token(TokenType::Str, "ff8800", (0, 8)),
+                make_token(TokenType::EOF, "", (8, 9)),
+            ]);
+        assert_lex_error(
+            "\"ff",
+            LexError::UnterminatedString { span: (0, 3) }
+        );
+    }
+
+    #[test]
+    fn test_floor_division_and_mod() {
+        assert_lex(
+            "7//2 mod 3",
+            &vec![
+                make_token(TokenType::Number, "7", (0, 1)),
+                make_token(TokenType::SlashSlash, "//", (1, 3)),
+                make_token(TokenType::Number, "2", (3, 4)),
+                make_token(TokenType::Mod, "mod", (5, 8)),
+                make_token(TokenType::Number, "3", (9, 10)),
+                make_token(TokenType::EOF, "", (10, 11)),
+            ]);
+    }
+
+    #[test]
+    fn test_brackets() {
+        assert_lex(
+            "[1,2]",
+            &vec![
+                make_token(TokenType::LeftBracket, "[", (0, 1)),
+                make_token(TokenType::Number, "1", (1, 2)),
+                make_token(TokenType::Comma, ",", (2, 3)),
+                make_token(TokenType::Number, "2", (3, 4)),
+                make_token(TokenType::RightBracket, "]", (4, 5)),
+                make_token(TokenType::EOF, "", (5, 6)),
+            ]);
+    }
+
+    #[test]
+    fn test_comment_is_skipped() {
+        assert_lex(
+            "1 # one; two\n",
+            &vec![
+                make_token(TokenType::Number, "1", (0, 1)),
+                make_token(TokenType::EOF, "", (13, 14)),
+            ]);
+    }
+
+    #[test]
+    fn test_semicolon() {
+        assert_lex(
+            "1;",
+            &vec![
+                make_token(TokenType::Number, "1", (0, 1)),
+                make_token(TokenType::Semicolon, ";", (1, 2)),
+                make_token(TokenType::EOF, "", (2, 3)),
+            ]);
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        assert_lex(
+            "~1&2|3<<4>>5",
+            &vec![
+                make_token(TokenType::Tilde, "~", (0, 1)),
+                make_token(TokenType::Number, "1", (1, 2)),
+                make_token(TokenType::Ampersand, "&", (2, 3)),
+                make_token(TokenType::Number, "2", (3, 4)),
+                make_token(TokenType::Pipe, "|", (4, 5)),
+                make_token(TokenType::Number, "3", (5, 6)),
+                make_token(TokenType::LessLess, "<<", (6, 8)),
+                make_token(TokenType::Number, "4", (8, 9)),
+                make_token(TokenType::GreaterGreater, ">>", (9, 11)),
+                make_token(TokenType::Number, "5", (11, 12)),
+                make_token(TokenType::EOF, "", (12, 13)),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_scan_into_clears_buffer() {
+        let mut tokens = scan("1 + 2 + 3").unwrap();
+        scan_into("4", &mut tokens).unwrap();
+        assert_eq!(tokens, vec![
+            make_token(TokenType::Number, "4", (0, 1)),
+            make_token(TokenType::EOF, "", (1, 2)),
+        ]);
+    }
+
+    #[test]
+    fn test_comparison_operators() {
+        assert_lex(
+            "1<2<=3>4>=5==6!=7=8",
+            &vec![
+                make_token(TokenType::Number, "1", (0, 1)),
+                make_token(TokenType::Less, "<", (1, 2)),
+                make_token(TokenType::Number, "2", (2, 3)),
+                make_token(TokenType::LessEqual, "<=", (3, 5)),
+                make_token(TokenType::Number, "3", (5, 6)),
+                make_token(TokenType::Greater, ">", (6, 7)),
+                make_token(TokenType::Number, "4", (7, 8)),
+                make_token(TokenType::GreaterEqual, ">=", (8, 10)),
+                make_token(TokenType::Number, "5", (10, 11)),
+                make_token(TokenType::EqualEqual, "==", (11, 13)),
+                make_token(TokenType::Number, "6", (13, 14)),
+                make_token(TokenType::BangEqual, "!=", (14, 16)),
+                make_token(TokenType::Number, "7", (16, 17)),
+                make_token(TokenType::Equal, "=", (17, 18)),
+                make_token(TokenType::Number, "8", (18, 19)),
+                make_token(TokenType::EOF, "", (19, 20)),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_keywords_are_not_identifiers() {
+        for keyword in KEYWORDS {
+            let tokens = scan(keyword).unwrap();
+            assert_ne!(tokens[0].token_type, TokenType::Identifier, "{}", keyword);
+        }
+
+        // And the other way round: every name `identifier_type` matches is
+        // listed, read from the arms of the match in this file.
+        let source = include_str!("lexer.rs");
+        let start = source.find("fn identifier_type").unwrap();
+        let end = start + source[start..].find("_ => Ok(TokenType::Identifier)").unwrap();
+        let matched: Vec<&str> = source[start..end]
+            .lines()
+            .filter_map(|line| line.split_once(" => ").map(|(pattern, _)| pattern))
+            .flat_map(|pattern| pattern.trim().split(" | "))
+            .filter_map(|name| name.strip_prefix('"')?.strip_suffix('"'))
+            .collect();
+        assert_eq!(matched.len(), KEYWORDS.len());
+        for name in matched {
+            assert!(KEYWORDS.contains(&name), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt;
+
+use crate::operation::Operation;
+
+/// A likely mistake in an expression that still evaluates. Warnings are
+/// reported before evaluation and never stop it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// `/`, `//` or `mod` with a literal `0` on the right.
+    DivisionByZero,
+    /// `x - x`, which is always 0.
+    SelfSubtraction,
+    /// `x / x`, which is always 1 (or NaN).
+    SelfDivision,
+    /// A comparison whose result does not depend on any variable, such as
+    /// `x < x` or `1 < 2`, with the result it always has.
+    ConstantComparison(bool),
+    /// A comparison of an expression with itself, such as `x <= x`, whose
+    /// result is the given one except when the expression is NaN.
+    SelfComparison(bool),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::DivisionByZero => write!(f, "division by a literal zero"),
+            Warning::SelfSubtraction => write!(f, "expression subtracted from itself is always 0"),
+            Warning::SelfDivision => write!(f, "expression divided by itself is always 1"),
+            Warning::ConstantComparison(result) => write!(f, "comparison is always {}", result),
+            Warning::SelfComparison(result) => write!(f, "comparison is always {} unless NaN", result),
+        }
+    }
+}
+
+/// Checks compiled operations for likely mistakes. Operations that would
+/// underflow the stack are left for the VM to report.
+pub fn lint(operations: &[Operation]) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    // Start index of the sub-expression that produced each stack slot.
+    let mut starts: Vec<usize> = Vec::new();
+
+    for (i, op) in operations.iter().enumerate() {
+        let arity = op.arity();
+        if starts.len() < arity {
+            return warnings;
+        }
+        let operands = starts.split_off(starts.len() - arity);
+
+        if let [left, right] = operands[..] {
+            let (lhs, rhs) = (&operations[left..right], &operations[right..i]);
+            match op {
+                Operation::Divide | Operation::FloorDivide | Operation::Modulo
+                    if rhs == [Operation::Const(0.0)] =>
+                {
+                    warnings.push(Warning::DivisionByZero);
+                }
+                Operation::Subtract if lhs == rhs && is_deterministic(lhs) => {
+                    warnings.push(Warning::SelfSubtraction);
+                }
+                Operation::Divide if lhs == rhs && is_deterministic(lhs) => {
+                    warnings.push(Warning::SelfDivision);
+                }
+                _ if let (&[Operation::Const(a)], &[Operation::Const(b)]) = (lhs, rhs)
+                    && let Some(result) = compare(op, a, b) =>
+                {
+                    warnings.push(Warning::ConstantComparison(result));
+                }
+                _ if lhs == rhs && is_deterministic(lhs) && let Some(result) = compare(op, 0.0, 0.0) => {
+                    if compare(op, f64::NAN, f64::NAN) == Some(result) {
+                        warnings.push(Warning::ConstantComparison(result));
+                    } else {
+                        warnings.push(Warning::SelfComparison(result));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        starts.push(operands.first().copied().unwrap_or(i));
+    }
+    warnings
+}
+
+/// The result of comparison `op` on `a` and `b`, or `None` if `op` is not
+/// a comparison.
+fn compare(op: &Operation, a: f64, b: f64) -> Option<bool> {
+    Some(match op {
+        Operation::Less => a < b,
+        Operation::LessEqual => a <= b,
+        Operation::Greater => a > b,
+        Operation::GreaterEqual => a >= b,
+        Operation::Equal => a == b,
+        Operation::NotEqual => a != b,
+        _ => return None,
+    })
+}
+
+/// Whether evaluating `operations` twice gives the same value. User
+/// functions may use random numbers, so calls never count.
+fn is_deterministic(operations: &[Operation]) -> bool {
+    !operations.iter().any(|op| {
+        matches!(
+            op,
+            Operation::Rand | Operation::RandInt | Operation::RandN | Operation::Call(..) |
+            Operation::Map | Operation::Filter | Operation::Reduce
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+    use crate::parser;
+
+    fn lint_source(source: &str) -> Vec<Warning> {
+        lint(&parser::parse(lexer::scan(source).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_division_by_literal_zero() {
+        assert_eq!(lint_source("1 / 0"), vec![Warning::DivisionByZero]);
+        assert_eq!(lint_source("5 // 0 + 7 mod 0"), vec![
+            Warning::DivisionByZero,
+            Warning::DivisionByZero,
+        ]);
+        assert_eq!(lint_source("1 / (2 - 1 - 1)"), vec![]);
+    }
+
+    #[test]
+    fn test_self_cancellation() {
+        assert_eq!(lint_source("sin(2) - sin(2)"), vec![Warning::SelfSubtraction]);
+        assert_eq!(lint_source("(ans + 1) / (ans + 1)"), vec![Warning::SelfDivision]);
+        assert_eq!(lint_source("sin(2) - sin(3)"), vec![]);
+        assert_eq!(lint_source("2 - 1 - 1"), vec![]);
+    }
+
+    #[test]
+    fn test_random_operands_are_not_self_cancelling() {
+        assert_eq!(lint_source("rand() - rand()"), vec![]);
+    }
+
+    #[test]
+    fn test_constant_comparisons() {
+        assert_eq!(lint_source("x < x"), vec![Warning::ConstantComparison(false)]);
+        assert_eq!(lint_source("1 < 2"), vec![Warning::ConstantComparison(true)]);
+        assert_eq!(lint_source("2 != 2"), vec![Warning::ConstantComparison(false)]);
+        assert_eq!(lint_source("x <= 1"), vec![]);
+        assert_eq!(lint_source("rand() == rand()"), vec![]);
+        assert_eq!(Warning::ConstantComparison(true).to_string(), "comparison is always true");
+    }
+
+    #[test]
+    fn test_self_comparisons_depend_on_nan() {
+        assert_eq!(lint_source("x == x"), vec![Warning::SelfComparison(true)]);
+        assert_eq!(lint_source("(x + 1) >= (x + 1)"), vec![Warning::SelfComparison(true)]);
+        assert_eq!(lint_source("x != x"), vec![Warning::SelfComparison(false)]);
+        assert_eq!(Warning::SelfComparison(true).to_string(), "comparison is always true unless NaN");
+    }
+}
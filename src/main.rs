@@ -1,66 +1,108 @@
+mod bench;
+mod compiled;
+mod diagnostic;
+mod dual;
+mod editor;
+mod explain;
+mod format;
+mod help;
+mod highlight;
+mod import;
+mod inspect;
 mod lexer;
+mod lint;
+mod numeric;
+mod matrix;
 mod operation;
+mod optimize;
+mod pager;
+mod poly;
+mod rational;
 mod parser;
+mod repl;
+mod replay;
+mod rng;
+mod rpn;
+mod script;
+mod symbolic;
 mod token;
+mod units;
+mod value;
+mod vars;
+mod verify;
 mod vm;
 
-use std::io::{self, Write};
+use std::io;
+use std::path::Path;
 
 fn main() -> io::Result<()> {
-    welcome();
-    repl()
-}
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let use_pager = !args.iter().any(|arg| arg == "--no-pager");
+    let use_lint = args.iter().any(|arg| arg == "--lint");
+    let strict = args.iter().any(|arg| arg == "--strict");
+    let use_rc = !args.iter().any(|arg| arg == "--no-rc");
 
-fn welcome() {
-    println!("Welcome to Calc!\n");
-    println!("Press 'q' to quit.");
-    println!("Type '--mode=radian' to use radians, and '--mode=degree' to use degrees.");
-    println!("");
-}
-
-fn repl() -> io::Result<()> {
-    let mut vm = vm::VirtualMachine::new();
+    if args.first().map(String::as_str) == Some("replay") {
+        return match args.get(1) {
+            Some(path) => run_replay(path),
+            None => {
+                eprintln!("Usage: calc replay <transcript>");
+                std::process::exit(2);
+            }
+        };
+    }
 
-    loop {
-        print!(">> ");
-        io::stdout().flush()?;
-        
-        let mut input = String::new();
-        
-        let bytes = io::stdin().read_line(&mut input)?;
-        if bytes == 0 {
-            println!("\nExiting...");
-            break;
-        }
+    if let Some(path) = args.iter().find(|arg| !arg.starts_with("--")) {
+        let options = script::ScriptOptions {
+            empty_statements: if args.iter().any(|arg| arg == "--strict-empty") {
+                script::EmptyStatements::Error
+            } else {
+                script::EmptyStatements::Skip
+            },
+            lint: use_lint,
+            search_path: Path::new(path).parent().into_iter().map(Path::to_path_buf).collect(),
+            ..Default::default()
+        };
+        return run_script(path, &options, strict);
+    }
 
-        match input.trim() {
-            "q" | "exit" => break,
-            "--mode=radian" => {vm.use_radians=true; continue},
-            "--mode=degree" => {vm.use_radians=false; continue},
-            "" => continue,
-            _ => {},
-        }
+    welcome();
+    repl::run(use_pager, use_lint, strict, use_rc)
+}
 
-        let tokens = lexer::scan(input);
+fn run_script(path: &str, options: &script::ScriptOptions, strict: bool) -> io::Result<()> {
+    let source = std::fs::read_to_string(path)?;
+    let mut vm = vm::VirtualMachine::builder().strict(strict).build();
+    if let Err(e) = script::run(&mut vm, &source, options, &mut io::stdout()) {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-        match tokens {
-            Err(e) => eprintln!("{}", e),
-            Ok(tokens) => {
-                let operations = parser::parse(tokens);
-                
-                match operations {
-                    Err(e) => eprintln!("{}", e),
-                    Ok(operations) => {
+/// Replays a saved session and exits with status 1 if any output changed.
+fn run_replay(path: &str) -> io::Result<()> {
+    let transcript = std::fs::read_to_string(path)?;
+    let report = replay::replay(&transcript, replay::DEFAULT_TOLERANCE);
+    for divergence in &report.divergences {
+        println!("{}: line {}: {}", path, divergence.line, divergence.input);
+        println!("  expected: {}", divergence.expected.join("\n            "));
+        println!("  actual:   {}", divergence.actual.join("\n            "));
+    }
 
-                        let result = vm.interpret(&operations);
-                        match result {
-                            Ok(output ) => println!("{}", output),
-                            Err(e) => eprintln!("{}", e),
-                        }
-                    }
-                }
-            } 
-        }
+    if report.divergences.is_empty() {
+        println!("{}: {} inputs replayed, all outputs match", path, report.inputs);
+        Ok(())
+    } else {
+        println!("{}: {} of {} inputs diverged", path, report.divergences.len(), report.inputs);
+        std::process::exit(1);
     }
-    Ok(())
+}
+
+fn welcome() {
+    println!("Welcome to Calc!\n");
+    println!("Press 'q' to quit.");
+    println!("Type ':mode radian' to use radians, and ':mode degree' to use degrees.");
+    println!("Type ':mode' to see the current settings.");
+    println!();
 }
@@ -0,0 +1,139 @@
+/// A dense matrix as a list of rows of equal length.
+pub type Matrix = Vec<Vec<f64>>;
+
+/// Pivots smaller than this, relative to the largest entry, count as zero.
+const SINGULAR_EPS: f64 = 1e-12;
+
+/// The product `a * b`. The number of columns of `a` must equal the number
+/// of rows of `b`.
+pub fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let cols = b.first().map_or(0, Vec::len);
+    a.iter()
+        .map(|row| {
+            (0..cols)
+                .map(|j| row.iter().zip(b).map(|(x, b_row)| x * b_row[j]).sum())
+                .collect()
+        })
+        .collect()
+}
+
+pub fn transpose(a: &Matrix) -> Matrix {
+    let cols = a.first().map_or(0, Vec::len);
+    (0..cols).map(|j| a.iter().map(|row| row[j]).collect()).collect()
+}
+
+/// The LU decomposition of a square matrix with partial pivoting, `PA = LU`.
+/// `L` (with a unit diagonal) and `U` share one matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lu {
+    lu: Matrix,
+    /// Row `i` of `PA` is row `perm[i]` of `A`.
+    perm: Vec<usize>,
+    /// The sign of the permutation, +1 or -1.
+    sign: f64,
+}
+
+impl Lu {
+    /// Decomposes a square matrix, or gives `None` if it is singular.
+    pub fn new(a: &Matrix) -> Option<Self> {
+        let n = a.len();
+        let scale = a.iter().flatten().fold(0.0f64, |max, x| max.max(x.abs()));
+        let mut lu = a.clone();
+        let mut perm: Vec<usize> = (0..n).collect();
+        let mut sign = 1.0;
+        for k in 0..n {
+            let pivot = (k..n).max_by(|&i, &j| lu[i][k].abs().total_cmp(&lu[j][k].abs()))?;
+            if lu[pivot][k].abs() <= SINGULAR_EPS * scale || !lu[pivot][k].is_finite() {
+                return None;
+            }
+            if pivot != k {
+                lu.swap(pivot, k);
+                perm.swap(pivot, k);
+                sign = -sign;
+            }
+            let (top, bottom) = lu.split_at_mut(k + 1);
+            let pivot_row = &top[k];
+            for row in bottom {
+                let factor = row[k] / pivot_row[k];
+                row[k] = factor;
+                for (x, p) in row[k + 1..].iter_mut().zip(&pivot_row[k + 1..]) {
+                    *x -= factor * p;
+                }
+            }
+        }
+        Some(Self { lu, perm, sign })
+    }
+
+    pub fn det(&self) -> f64 {
+        self.sign * (0..self.lu.len()).map(|i| self.lu[i][i]).product::<f64>()
+    }
+
+    /// The `x` with `Ax = b`, by forward and back substitution.
+    pub fn solve(&self, b: &[f64]) -> Vec<f64> {
+        let n = self.lu.len();
+        let mut x: Vec<f64> = self.perm.iter().map(|&i| b[i]).collect();
+        for i in 0..n {
+            for j in 0..i {
+                x[i] -= self.lu[i][j] * x[j];
+            }
+        }
+        for i in (0..n).rev() {
+            for j in i + 1..n {
+                x[i] -= self.lu[i][j] * x[j];
+            }
+            x[i] /= self.lu[i][i];
+        }
+        x
+    }
+
+    pub fn inverse(&self) -> Matrix {
+        let n = self.lu.len();
+        let columns: Matrix = (0..n)
+            .map(|j| {
+                let unit: Vec<f64> = (0..n).map(|i| if i == j { 1.0 } else { 0.0 }).collect();
+                self.solve(&unit)
+            })
+            .collect();
+        transpose(&columns)
+    }
+}
+
+/// The determinant of a square matrix, 0 if it is singular.
+pub fn det(a: &Matrix) -> f64 {
+    Lu::new(a).map_or(0.0, |lu| lu.det())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Matrix, b: &Matrix) {
+        for (x, y) in a.iter().flatten().zip(b.iter().flatten()) {
+            assert!((x - y).abs() < 1e-12, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_multiply_and_transpose() {
+        let a = vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]];
+        let b = vec![vec![1.0], vec![0.0], vec![-1.0]];
+        assert_eq!(multiply(&a, &b), vec![vec![-2.0], vec![-2.0]]);
+        assert_eq!(transpose(&a), vec![vec![1.0, 4.0], vec![2.0, 5.0], vec![3.0, 6.0]]);
+    }
+
+    #[test]
+    fn test_det_needs_pivoting() {
+        let a = vec![vec![0.0, 1.0], vec![2.0, 3.0]];
+        assert_eq!(det(&a), -2.0);
+        assert_eq!(det(&vec![vec![1.0, 2.0], vec![2.0, 4.0]]), 0.0);
+    }
+
+    #[test]
+    fn test_inverse() {
+        let a = vec![vec![4.0, 7.0, 2.0], vec![3.0, 6.0, 1.0], vec![2.0, 5.0, 3.0]];
+        let inverse = Lu::new(&a).unwrap().inverse();
+        let identity = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0], vec![0.0, 0.0, 1.0]];
+        assert_close(&multiply(&a, &inverse), &identity);
+        assert!(Lu::new(&vec![vec![1.0, 2.0], vec![2.0, 4.0]]).is_none());
+    }
+}
@@ -0,0 +1,428 @@
+/// Recursion depth of adaptive Simpson's rule, which bounds how finely an
+/// interval is split even if the tolerance is never met.
+const MAX_SIMPSON_DEPTH: usize = 50;
+
+/// One interval of adaptive Simpson's rule with the integrand at its ends
+/// and midpoint, and the Simpson estimate over it.
+struct Segment {
+    a: f64,
+    b: f64,
+    fa: f64,
+    fm: f64,
+    fb: f64,
+    whole: f64,
+}
+
+impl Segment {
+    fn new<E>(f: &mut dyn FnMut(f64) -> Result<f64, E>, a: f64, b: f64, fa: f64, fb: f64) -> Result<Self, E> {
+        let fm = f((a + b) / 2.0)?;
+        let whole = (b - a) / 6.0 * (fa + 4.0 * fm + fb);
+        Ok(Self { a, b, fa, fm, fb, whole })
+    }
+}
+
+/// The integral of `f` over `[a, b]` by adaptive Simpson's rule, splitting
+/// intervals until the estimated error is below `tolerance`. Errors from
+/// `f`, such as an evaluation limit, stop the integration.
+pub fn integrate<E>(
+    f: &mut dyn FnMut(f64) -> Result<f64, E>,
+    a: f64,
+    b: f64,
+    tolerance: f64,
+) -> Result<f64, E> {
+    let (fa, fb) = (f(a)?, f(b)?);
+    let segment = Segment::new(f, a, b, fa, fb)?;
+    simpson(f, segment, tolerance, MAX_SIMPSON_DEPTH)
+}
+
+fn simpson<E>(
+    f: &mut dyn FnMut(f64) -> Result<f64, E>,
+    segment: Segment,
+    tolerance: f64,
+    depth: usize,
+) -> Result<f64, E> {
+    let Segment { a, b, fa, fm, fb, whole } = segment;
+    let m = (a + b) / 2.0;
+    let left = Segment::new(f, a, m, fa, fm)?;
+    let right = Segment::new(f, m, b, fm, fb)?;
+    let delta = left.whole + right.whole - whole;
+    if depth == 0 || delta.abs() <= 15.0 * tolerance || !delta.is_finite() {
+        // Richardson extrapolation of the two estimates.
+        return Ok(left.whole + right.whole + delta / 15.0);
+    }
+    Ok(simpson(f, left, tolerance / 2.0, depth - 1)? + simpson(f, right, tolerance / 2.0, depth - 1)?)
+}
+
+/// Rows of the Richardson tableau for `derivative`, each with a step
+/// `DIFF_SHRINK` times smaller than the last.
+const DIFF_ROWS: usize = 10;
+const DIFF_SHRINK: f64 = 1.4;
+
+/// The derivative of `f` at `x` by central differences with Richardson
+/// extrapolation (Ridders' method): the estimate for a shrinking step is
+/// extrapolated to a step of zero, stopping once rounding error dominates.
+pub fn derivative<E>(f: &mut dyn FnMut(f64) -> Result<f64, E>, x: f64) -> Result<f64, E> {
+    let mut h = 0.1 * x.abs().max(1.0);
+    let mut central = |h: f64| -> Result<f64, E> { Ok((f(x + h)? - f(x - h)?) / (2.0 * h)) };
+    let mut previous = vec![central(h)?];
+    let (mut best, mut error) = (previous[0], f64::INFINITY);
+    for _ in 1..DIFF_ROWS {
+        h /= DIFF_SHRINK;
+        let mut row = vec![central(h)?];
+        let mut factor = DIFF_SHRINK * DIFF_SHRINK;
+        for j in 1..=previous.len() {
+            row.push((row[j - 1] * factor - previous[j - 1]) / (factor - 1.0));
+            factor *= DIFF_SHRINK * DIFF_SHRINK;
+            let estimate = (row[j] - row[j - 1]).abs().max((row[j] - previous[j - 1]).abs());
+            if estimate <= error {
+                (best, error) = (row[j], estimate);
+            }
+        }
+        // A higher order that got worse means rounding error has taken over.
+        let last = row.len() - 1;
+        if (row[last] - previous[last - 1]).abs() >= 2.0 * error {
+            break;
+        }
+        previous = row;
+    }
+    Ok(best)
+}
+
+/// Why a root could not be found.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RootError<E> {
+    /// Evaluating the function failed.
+    Function(E),
+    /// `f(a)` and `f(b)` have the same sign, so `[a, b]` need not contain
+    /// a root.
+    NoSignChange { fa: f64, fb: f64 },
+    /// The iteration limit was reached, or Newton's method met a flat
+    /// point, with `x` the last estimate.
+    NoConvergence { x: f64, fx: f64 },
+}
+
+/// Whether a root estimate has stopped moving, relative to its size.
+fn converged(step: f64, x: f64) -> bool {
+    step.abs() <= 1e-12 * x.abs().max(1.0)
+}
+
+/// A root of `f` in `[a, b]` by Brent's method, which combines inverse
+/// quadratic interpolation with bisection so it always converges once the
+/// interval brackets a sign change.
+pub fn brent<E>(
+    f: &mut dyn FnMut(f64) -> Result<f64, E>,
+    a: f64,
+    b: f64,
+    max_iterations: usize,
+) -> Result<f64, RootError<E>> {
+    let (mut a, mut b) = (a, b);
+    let mut fa = f(a).map_err(RootError::Function)?;
+    let mut fb = f(b).map_err(RootError::Function)?;
+    if fa == 0.0 {
+        return Ok(a);
+    }
+    if fb == 0.0 {
+        return Ok(b);
+    }
+    if fa.signum() == fb.signum() {
+        return Err(RootError::NoSignChange { fa, fb });
+    }
+    let (mut c, mut fc) = (b, fb);
+    let (mut d, mut e) = (b - a, b - a);
+    for _ in 0..max_iterations {
+        if fb.signum() == fc.signum() {
+            (c, fc) = (a, fa);
+            (d, e) = (b - a, b - a);
+        }
+        if fc.abs() < fb.abs() {
+            (a, b, c) = (b, c, b);
+            (fa, fb, fc) = (fb, fc, fb);
+        }
+        let tolerance = 2.0 * f64::EPSILON * b.abs() + 1e-15;
+        let m = (c - b) / 2.0;
+        if m.abs() <= tolerance || fb == 0.0 {
+            return Ok(b);
+        }
+        if e.abs() >= tolerance && fa.abs() > fb.abs() {
+            // Interpolate: secant with two points, inverse quadratic with three.
+            let s = fb / fa;
+            let (mut p, mut q) = if a == c {
+                (2.0 * m * s, 1.0 - s)
+            } else {
+                let (q, r) = (fa / fc, fb / fc);
+                (
+                    s * (2.0 * m * q * (q - r) - (b - a) * (r - 1.0)),
+                    (q - 1.0) * (r - 1.0) * (s - 1.0),
+                )
+            };
+            if p > 0.0 {
+                q = -q;
+            } else {
+                p = -p;
+            }
+            if 2.0 * p < (3.0 * m * q - (tolerance * q).abs()).min((e * q).abs()) {
+                (e, d) = (d, p / q);
+            } else {
+                (d, e) = (m, m);
+            }
+        } else {
+            (d, e) = (m, m);
+        }
+        (a, fa) = (b, fb);
+        b += if d.abs() > tolerance { d } else { tolerance.copysign(m) };
+        fb = f(b).map_err(RootError::Function)?;
+    }
+    Err(RootError::NoConvergence { x: b, fx: fb })
+}
+
+/// A root of `f` by Newton's method from `x`, with the slope from
+/// [`derivative`].
+pub fn newton<E>(
+    f: &mut dyn FnMut(f64) -> Result<f64, E>,
+    mut x: f64,
+    max_iterations: usize,
+) -> Result<f64, RootError<E>> {
+    let mut fx = f(x).map_err(RootError::Function)?;
+    for _ in 0..max_iterations {
+        if fx == 0.0 {
+            return Ok(x);
+        }
+        let slope = derivative(f, x).map_err(RootError::Function)?;
+        let step = fx / slope;
+        if !step.is_finite() {
+            break;
+        }
+        x -= step;
+        fx = f(x).map_err(RootError::Function)?;
+        if converged(step, x) {
+            return Ok(x);
+        }
+    }
+    Err(RootError::NoConvergence { x, fx })
+}
+
+/// The smallest step `minimize` takes relative to the size of `x`, below
+/// which rounding hides any change in `f`.
+const SQRT_EPSILON: f64 = 1.4901161193847656e-8;
+
+/// A local minimum of `f` in `[a, b]` and the value there, by Brent's
+/// method: golden-section search, sped up by parabolic interpolation
+/// wherever `f` is smooth. The search never quite reaches the ends of the
+/// interval, so they are checked separately. Gives the best point found if
+/// `max_iterations` runs out first.
+pub fn minimize<E>(
+    f: &mut dyn FnMut(f64) -> Result<f64, E>,
+    a: f64,
+    b: f64,
+    max_iterations: usize,
+) -> Result<(f64, f64), E> {
+    let golden = (3.0 - 5f64.sqrt()) / 2.0;
+    let ends = [a, b];
+    let (mut a, mut b) = (a.min(b), a.max(b));
+    let mut x = a + golden * (b - a);
+    let mut fx = f(x)?;
+    let (mut w, mut fw, mut v, mut fv) = (x, fx, x, fx);
+    // The last two steps, of which `e` bounds the next parabolic one.
+    let (mut d, mut e): (f64, f64) = (0.0, 0.0);
+    for _ in 0..max_iterations {
+        let m = (a + b) / 2.0;
+        let tolerance = SQRT_EPSILON * x.abs() + 1e-12;
+        if (x - m).abs() <= 2.0 * tolerance - (b - a) / 2.0 {
+            break;
+        }
+        let mut parabolic = false;
+        if e.abs() > tolerance {
+            // The vertex of the parabola through x, w and v is x + p / q.
+            let r = (x - w) * (fx - fv);
+            let mut q = (x - v) * (fx - fw);
+            let mut p = (x - v) * q - (x - w) * r;
+            q = 2.0 * (q - r);
+            if q > 0.0 {
+                p = -p;
+            } else {
+                q = -q;
+            }
+            let previous = e;
+            e = d;
+            if p.abs() < (q * previous / 2.0).abs() && p > q * (a - x) && p < q * (b - x) {
+                d = p / q;
+                if x + d - a < 2.0 * tolerance || b - (x + d) < 2.0 * tolerance {
+                    d = tolerance.copysign(m - x);
+                }
+                parabolic = true;
+            }
+        }
+        if !parabolic {
+            e = if x < m { b - x } else { a - x };
+            d = golden * e;
+        }
+        let u = if d.abs() >= tolerance { x + d } else { x + tolerance.copysign(d) };
+        let fu = f(u)?;
+        if fu <= fx {
+            if u < x { b = x } else { a = x }
+            (v, fv, w, fw, x, fx) = (w, fw, x, fx, u, fu);
+        } else {
+            if u < x { a = u } else { b = u }
+            if fu <= fw || w == x {
+                (v, fv, w, fw) = (w, fw, u, fu);
+            } else if fu <= fv || v == x || v == w {
+                (v, fv) = (u, fu);
+            }
+        }
+    }
+    for end in ends {
+        let f_end = f(end)?;
+        if f_end < fx {
+            (x, fx) = (end, f_end);
+        }
+    }
+    Ok((x, fx))
+}
+
+/// Points where `find_root` looks for a sign change, growing geometrically
+/// away from 0 on both sides.
+const SCAN_START: f64 = 1e-3;
+const SCAN_GROWTH: f64 = 1.5;
+const SCAN_STEPS: usize = 100;
+
+/// A root of `f` without a starting point or interval, for equations such
+/// as `x^2 = 2`. A residual that is linear in `x` is solved directly.
+/// Otherwise Newton's method runs from 0 and, if that fails, Brent's method
+/// runs on the first sign change found scanning outwards from 0. Values of
+/// `NaN`, where `f` is undefined, are skipped by the scan.
+pub fn find_root<E>(
+    f: &mut dyn FnMut(f64) -> Result<f64, E>,
+    max_iterations: usize,
+) -> Result<f64, RootError<E>> {
+    let f0 = f(0.0).map_err(RootError::Function)?;
+    if f0 == 0.0 {
+        return Ok(0.0);
+    }
+    let (f1, f_1) = (f(1.0).map_err(RootError::Function)?, f(-1.0).map_err(RootError::Function)?);
+    let scale = f0.abs().max(f1.abs()).max(f_1.abs());
+    if f1 != f0 && ((f1 - f0) - (f0 - f_1)).abs() <= 1e-12 * scale {
+        let x = f0 / (f0 - f1);
+        if f(x).map_err(RootError::Function)?.abs() <= 1e-9 * scale {
+            return Ok(x);
+        }
+    }
+
+    let newton_error = match newton(f, 0.0, max_iterations) {
+        Ok(x) => return Ok(x),
+        Err(RootError::Function(e)) => return Err(RootError::Function(e)),
+        Err(e) => e,
+    };
+    let mut last = [(0.0, f0), (0.0, f0)];
+    let mut t = SCAN_START;
+    for _ in 0..SCAN_STEPS {
+        for (side, (a, fa)) in [1.0, -1.0].into_iter().zip(&mut last) {
+            let x = side * t;
+            let fx = f(x).map_err(RootError::Function)?;
+            if fx.is_nan() {
+                continue;
+            }
+            if fx == 0.0 || (!fa.is_nan() && fa.signum() != fx.signum()) {
+                return brent(f, *a, x, max_iterations);
+            }
+            (*a, *fa) = (x, fx);
+        }
+        t *= SCAN_GROWTH;
+    }
+    Err(newton_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn integral(mut f: impl FnMut(f64) -> f64, a: f64, b: f64) -> f64 {
+        integrate(&mut |x| Ok::<f64, ()>(f(x)), a, b, 1e-10).unwrap()
+    }
+
+    #[test]
+    fn test_integrate() {
+        assert!((integral(f64::sin, 0.0, std::f64::consts::PI) - 2.0).abs() < 1e-9);
+        assert!((integral(|x| x * x, 0.0, 3.0) - 9.0).abs() < 1e-12);
+        assert!((integral(|x| (-x * x).exp(), -10.0, 10.0) - std::f64::consts::PI.sqrt()).abs() < 1e-9);
+        assert!((integral(|x| x, 1.0, 0.0) + 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_derivative() {
+        let slope = |mut f: Box<dyn FnMut(f64) -> f64>, x: f64| {
+            derivative(&mut |x| Ok::<f64, ()>(f(x)), x).unwrap()
+        };
+        assert!((slope(Box::new(f64::sin), 1.0) - 1f64.cos()).abs() < 1e-12);
+        assert!((slope(Box::new(|x| x.powi(3)), 2.0) - 12.0).abs() < 1e-10);
+        assert!((slope(Box::new(f64::exp), 10.0) - 10f64.exp()).abs() < 1e-12 * 10f64.exp());
+        assert!((slope(Box::new(f64::abs), 0.0)).abs() < 1e-12);
+    }
+
+    fn root(method: &str, mut f: impl FnMut(f64) -> f64, a: f64, b: f64) -> Result<f64, RootError<()>> {
+        let mut f = |x| Ok(f(x));
+        match method {
+            "brent" => brent(&mut f, a, b, 100),
+            _ => newton(&mut f, a, 100),
+        }
+    }
+
+    #[test]
+    fn test_brent() {
+        let x = root("brent", |x| x * x - 2.0, 0.0, 2.0).unwrap();
+        assert!((x - 2f64.sqrt()).abs() < 1e-14);
+        let x = root("brent", f64::cos, 0.0, 3.0).unwrap();
+        assert!((x - std::f64::consts::FRAC_PI_2).abs() < 1e-14);
+        assert_eq!(root("brent", |x| x - 1.0, 1.0, 5.0), Ok(1.0));
+        assert_eq!(root("brent", |x| x * x + 1.0, -1.0, 1.0), Err(RootError::NoSignChange { fa: 2.0, fb: 2.0 }));
+    }
+
+    #[test]
+    fn test_newton() {
+        let x = root("newton", |x| x * x - 2.0, 1.0, 0.0).unwrap();
+        assert!((x - 2f64.sqrt()).abs() < 1e-14);
+        let x = root("newton", |x| x.powi(3) - 2.0 * x - 5.0, 2.0, 0.0).unwrap();
+        assert!((x.powi(3) - 2.0 * x - 5.0).abs() < 1e-12);
+        assert!(matches!(root("newton", |x| x * x + 1.0, 0.5, 0.0), Err(RootError::NoConvergence { .. })));
+    }
+
+    #[test]
+    fn test_errors_stop_integration() {
+        let mut evaluations = 0;
+        let result = integrate(
+            &mut |x: f64| {
+                evaluations += 1;
+                if evaluations > 10 { Err(evaluations) } else { Ok(x.sqrt()) }
+            },
+            0.0,
+            1.0,
+            1e-12,
+        );
+        assert_eq!(result, Err(11));
+    }
+
+    #[test]
+    fn test_find_root() {
+        let find = |mut f: Box<dyn FnMut(f64) -> f64>| find_root(&mut |x| Ok::<f64, ()>(f(x)), 100);
+        assert_eq!(find(Box::new(|x| x + 2.0 - 7.0)), Ok(5.0));
+        assert_eq!(find(Box::new(|x| 3.0 * x - 1.0)), Ok(1.0 / 3.0));
+        let x = find(Box::new(|x| x * x - 2.0)).unwrap();
+        assert!((x.abs() - 2f64.sqrt()).abs() < 1e-14);
+        // Newton's method cannot start where the logarithm is undefined.
+        let x = find(Box::new(|x| x.ln() - 1.0)).unwrap();
+        assert!((x - std::f64::consts::E).abs() < 1e-14);
+        assert!(matches!(find(Box::new(|x| x * x + 1.0)), Err(RootError::NoConvergence { .. })));
+    }
+
+    #[test]
+    fn test_minimize() {
+        let least = |mut f: Box<dyn FnMut(f64) -> f64>, a, b| {
+            minimize(&mut |x| Ok::<f64, ()>(f(x)), a, b, 100).unwrap()
+        };
+        let (x, fx) = least(Box::new(|x| (x - 2.0).powi(2) + 1.0), 0.0, 5.0);
+        assert!((x - 2.0).abs() < 1e-7 && (fx - 1.0).abs() < 1e-14);
+        let (x, _) = least(Box::new(f64::cos), 0.0, 6.0);
+        assert!((x - std::f64::consts::PI).abs() < 1e-7);
+        // The minimum of an increasing function is at the left end.
+        assert_eq!(least(Box::new(f64::exp), 3.0, 1.0), (1.0, 1f64.exp()));
+    }
+}
@@ -1,21 +1,310 @@
-#[derive(Debug, Clone, PartialEq)]
+use serde::{Deserialize, Serialize};
+
+/// A user-defined function such as `f(x) = x^2 + 1`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Operation>,
+}
+
+/// The two sub-programs of an `if`, of which only the chosen one runs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Branches {
+    pub then: Vec<Operation>,
+    pub otherwise: Vec<Operation>,
+}
+
+/// The sub-programs of a `for` or `while` loop. A `for` loop binds
+/// `variable` to each counter value while `body` runs; a `while` loop runs
+/// `body` as long as `condition` gives a non-zero value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Loop {
+    pub variable: String,
+    pub condition: Vec<Operation>,
+    pub body: Vec<Operation>,
+}
+
+/// An equation such as `x + 2 = 7` to solve for `variable`, kept as the
+/// sub-program for `left - right`, which is 0 at a solution.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Equation {
+    pub variable: String,
+    pub residual: Vec<Operation>,
+}
+
+/// An expression to differentiate symbolically with respect to `variable`,
+/// as in `deriv(x^2, x)`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Derivative {
+    pub variable: String,
+    pub expression: Vec<Operation>,
+}
+
+/// Two expressions to compare at random values of their variables, as in
+/// `equiv((x + 1)^2, x^2 + 2*x + 1)`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Equivalence {
+    pub left: Vec<Operation>,
+    pub right: Vec<Operation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Operation {
     Ans,
+    AnsAt,
     Const(f64),
+    Load(String),
+    Store(String),
+    Define(Box<Function>),
+    /// Calls a user function by name with this many arguments.
+    Call(String, usize),
+    /// Binds the name to the value on top of the stack, which stays there,
+    /// for the operations up to the matching `PopScope`.
+    PushScope(String),
+    /// Ends the innermost scope, dropping its value from under the result.
+    PopScope,
+    /// Pops a condition and runs `then` if it is non-zero, else `otherwise`.
+    If(Box<Branches>),
+    /// Pops the first and last counter values and runs the loop body for
+    /// each step of 1 between them.
+    For(Box<Loop>),
+    While(Box<Loop>),
+    /// Drops the value under the top of the stack, so `a; b` gives `b`.
+    Sequence,
+    Str(String),
+    MakeList(usize),
 
     // Unary Operations
     Negate,
+    Factorial,
     
     // Binary Operations
     Add,
     Subtract,
     Times,
     Divide,
+    FloorDivide,
+    Modulo,
     Power,
+    Min,
+    Max,
+
+    // Comparisons
+    Less, LessEqual, Greater, GreaterEqual, Equal, NotEqual,
+
+    // Bitwise Operations
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
 
     // Functions
     Sin, Cos, Tan,
-    ArcSin, ArcCos, ArcTan,
+    SinD, CosD, TanD, Deg2Rad, Rad2Deg,
+    ArcSin, ArcCos, ArcTan, ArcTan2,
+    Hypot, ToPolar, ToRect,
+    Sinh, Cosh, Tanh,
+    ArcSinh, ArcCosh, ArcTanh,
+
+    Ln, Exp,
+    Log10, Log2, LogBase,
+    Sqrt, Cbrt, Root,
+
+    // Rounding
+    Abs, Sign, Floor, Ceil, Round, Trunc, Frac,
+    RoundTo, SigFig,
+
+    // Geodesy
+    Haversine, Bearing,
+
+    // Decibels
+    Db, UnDb, DbmToMw, MwToDbm,
+
+    // Electronics
+    Parallel, VoltageDivider,
+
+    // Probability
+    Odds, Prob, Bayes,
+
+    // Finance
+    Compound, Pv, Fv, Npv, Irr,
+
+    // Units
+    Convert,
+
+    // Bases
+    ToHex, ToBin, ToOct,
+
+    // Lists
+    Mean, Median, Mode, Var, Stdev, Sum, Prod,
+    WMean, Normalize, ZScore, LinReg,
+    Sort, Percentile, Iqr,
+    Unique, Union, Intersect,
+    CumSum, CumProd,
+    Reverse, Head, Tail, Concat,
+    Len,
+    /// Pops a 1-based index and then a list, and pushes that element.
+    Index,
+    /// Pops a step and the first and last values, and pushes the list from
+    /// first to last inclusive.
+    Range,
+
+    // Higher-order Functions
+    /// Pops a list and a function, and calls the function on each element.
+    Map,
+    /// Like `Map`, keeping the elements for which the function is non-zero.
+    Filter,
+    /// Pops a list, a starting value and a function of two arguments, and
+    /// folds the list from the left.
+    Reduce,
+
+    // Numerical Methods
+    /// Pops a tolerance, the bounds `a` and `b` and a function, and pushes
+    /// the integral of the function from `a` to `b`.
+    Integrate,
+    /// Pops `x` and a function, and pushes the derivative of the function
+    /// at `x`.
+    Diff,
+    /// Pops the bounds `a` and `b` and a function, and pushes a root of the
+    /// function between them.
+    Solve,
+    /// Pops a starting point and a function, and pushes a nearby root.
+    Newton,
+    /// Pops the bounds `a` and `b` and a function, and pushes `[x, f(x)]` for
+    /// the smallest or largest value of the function between them.
+    Minimize,
+    Maximize,
+    /// Solves an equation for its variable, as in `solve x^2 = 2 for x`.
+    SolveFor(Box<Equation>),
+    /// Gives the derivative of an expression as text, e.g. `2 * x`.
+    Deriv(Box<Derivative>),
+    /// Checks whether two expressions agree, giving the result as text.
+    Equiv(Box<Equivalence>),
+
+    // Matrices
+    /// Matrix product; a flat list is a row vector on the left and a column
+    /// vector on the right.
+    MatMul,
+    Transpose, Det, Inv,
+    Dot, Cross, Norm,
+    /// Pops `b` and then `A`, and solves `Ax = b` for `x`.
+    LinSolve,
+
+    // Polynomials, as coefficient lists with the highest degree first
+    PolyVal, PolyRoots,
+
+    // Rational approximation
+    /// Pops a maximum denominator and `x`, and pushes the closest fraction
+    /// to `x` as text, e.g. `1/3`.
+    Rationalize,
+    /// Pops a number of terms `n` and `x`, and pushes the first `n` terms of
+    /// the continued fraction of `x`.
+    ConFrac,
+
+    // Colors
+    Hex2Rgb, Rgb2Hex, SrgbToLinear, LinearToSrgb,
+
+    // Integer Utilities
+    Digits, DigitSum, ReverseDigits,
+    Gcd, Lcm,
+    IsPrime, NextPrime, Factor,
+    PowMod,
+    Gamma,
+    Erf, Erfc, NormPdf, NormCdf,
+
+    // Random Numbers
+    Rand, RandInt, RandN,
+
+    // Scripting
+    Print, Assert,
+}
+impl Operation {
+    /// The opcode name without any payload, e.g. `Const` for `Const(2.0)`.
+    pub fn name(&self) -> String {
+        let name = format!("{:?}", self);
+        match name.find('(') {
+            Some(paren) => name[..paren].to_string(),
+            None => name,
+        }
+    }
+
+    /// The named sub-programs the operation holds, e.g. the `then` and
+    /// `else` of an `If`. Each runs on a stack of its own and leaves one
+    /// value.
+    pub fn sub_programs(&self) -> Vec<(&'static str, &[Operation])> {
+        use Operation::*;
+        match self {
+            Define(function) => vec![("body", &function.body)],
+            If(branches) => vec![("then", &branches.then), ("else", &branches.otherwise)],
+            For(body) => vec![("body", &body.body)],
+            While(body) => vec![("condition", &body.condition), ("body", &body.body)],
+            SolveFor(equation) => vec![("residual", &equation.residual)],
+            Deriv(derivative) => vec![("expression", &derivative.expression)],
+            Equiv(equivalence) => vec![("left", &equivalence.left), ("right", &equivalence.right)],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether the operation maps numbers to a number and so is broadcast
+    /// over list operands, e.g. `sin([0, pi])` or `[1, 2] + 1`.
+    pub fn is_elementwise(&self) -> bool {
+        use Operation::*;
+        matches!(
+            self,
+            Negate | Factorial |
+            Add | Subtract | Times | Divide | FloorDivide | Modulo | Power |
+            BitAnd | BitOr | BitXor | BitNot | ShiftLeft | ShiftRight |
+            Less | LessEqual | Greater | GreaterEqual | Equal | NotEqual |
+            Sin | Cos | Tan | ArcSin | ArcCos | ArcTan | ArcTan2 | Hypot |
+            SinD | CosD | TanD | Deg2Rad | Rad2Deg |
+            Sinh | Cosh | Tanh | ArcSinh | ArcCosh | ArcTanh |
+            Ln | Exp | Log10 | Log2 | LogBase | Sqrt | Cbrt | Root |
+            Abs | Sign | Floor | Ceil | Round | Trunc | Frac | RoundTo | SigFig |
+            Db | UnDb | DbmToMw | MwToDbm | Odds | Prob |
+            Digits | DigitSum | ReverseDigits | Gcd | Lcm | IsPrime | NextPrime | PowMod |
+            Gamma | Erf | Erfc | NormPdf | NormCdf
+        )
+    }
+
+    /// Number of values the operation pops from the stack. Every operation
+    /// pushes exactly one value.
+    pub fn arity(&self) -> usize {
+        use Operation::*;
+        match self {
+            Ans | Const(_) | Load(_) | Define(_) | While(_) | SolveFor(_) | Deriv(_) | Equiv(_) | Str(_) | Rand | RandN => 0,
+            MakeList(len) | Call(_, len) => *len,
+
+            Add | Subtract | Times | Divide | FloorDivide | Modulo | Power |
+            BitAnd | BitOr | BitXor | ShiftLeft | ShiftRight |
+            Less | LessEqual | Greater | GreaterEqual | Equal | NotEqual |
+            RoundTo | SigFig |
+            Min | Max | ArcTan2 | Hypot | ToPolar | ToRect | LogBase | Root |
+            Parallel | WMean | LinReg | Npv | Percentile | Union | Intersect |
+            Gcd | Lcm | RandInt | PopScope | For(_) | Sequence | Index | Concat | MatMul | Dot | Cross | LinSolve | PolyVal | Diff | Newton |
+            Rationalize | ConFrac | Map | Filter => 2,
+
+            VoltageDivider | Bayes | Convert | Rgb2Hex | PowMod | NormPdf | NormCdf |
+            Reduce | Range | Solve | Minimize | Maximize => 3,
+            Bearing | Compound | Pv | Fv | Integrate => 4,
+            Haversine => 5,
 
-    Ln, Exp
-}
\ No newline at end of file
+            AnsAt | Store(_) | PushScope(_) | If(_) | Negate | Factorial | BitNot |
+            Sin | Cos | Tan | ArcSin | ArcCos | ArcTan |
+            SinD | CosD | TanD | Deg2Rad | Rad2Deg |
+            Sinh | Cosh | Tanh | ArcSinh | ArcCosh | ArcTanh |
+            Ln | Exp | Log10 | Log2 | Sqrt | Cbrt |
+            Abs | Sign | Floor | Ceil | Round | Trunc | Frac |
+            Db | UnDb | DbmToMw | MwToDbm | Odds | Prob |
+            Mean | Median | Mode | Var | Stdev | Sum | Prod |
+            Normalize | ZScore | Sort | Iqr | Unique | CumSum | CumProd | Len |
+            Reverse | Head | Tail | Transpose | Det | Inv | Norm | PolyRoots |
+            Hex2Rgb | SrgbToLinear | LinearToSrgb |
+            Digits | DigitSum | ReverseDigits | IsPrime | NextPrime | Factor |
+            Gamma | Erf | Erfc | Irr |
+            ToHex | ToBin | ToOct | Print | Assert => 1,
+        }
+    }
+}
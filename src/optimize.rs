@@ -0,0 +1,162 @@
+use crate::operation::{Branches, Equation, Loop, Operation};
+use crate::operation::Operation::*;
+use crate::value::Value;
+use crate::vm::VirtualMachine;
+
+/// Rewrites an operation sequence into a shorter one with the same result:
+/// constants are folded, e.g. `Const(2) Const(3) Times` becomes `Const(6)`
+/// and `Const(x) Negate` becomes `Const(-x)`, and adding 0 to or
+/// multiplying by 1 a value known to be a number is dropped. An `if` with a
+/// constant condition is replaced by the branch it would take.
+///
+/// The output no longer lines up with the parser's spans, so it is meant
+/// for expressions that are evaluated many times rather than for the REPL.
+pub fn optimize(operations: Vec<Operation>) -> Vec<Operation> {
+    optimize_on(&mut VirtualMachine::new(), operations)
+}
+
+/// Like [`optimize`], folding constants on `vm`, which is shared by every
+/// sub-program.
+fn optimize_on(vm: &mut VirtualMachine, operations: Vec<Operation>) -> Vec<Operation> {
+    let mut out: Vec<Operation> = Vec::with_capacity(operations.len());
+    for op in operations {
+        let op = match op {
+            If(branches) => If(Box::new(Branches {
+                then: optimize_on(vm, branches.then),
+                otherwise: optimize_on(vm, branches.otherwise),
+            })),
+            For(body) => For(Box::new(optimize_loop(vm, *body))),
+            While(body) => While(Box::new(optimize_loop(vm, *body))),
+            SolveFor(equation) => SolveFor(Box::new(Equation {
+                variable: equation.variable,
+                residual: optimize_on(vm, equation.residual),
+            })),
+            op => op,
+        };
+        out.push(op);
+        while reduce_tail(vm, &mut out) {}
+    }
+    out
+}
+
+fn optimize_loop(vm: &mut VirtualMachine, body: Loop) -> Loop {
+    Loop {
+        variable: body.variable,
+        condition: optimize_on(vm, body.condition),
+        body: optimize_on(vm, body.body),
+    }
+}
+
+/// Applies one rewrite to the end of `out`, returning whether it did.
+fn reduce_tail(vm: &mut VirtualMachine, out: &mut Vec<Operation>) -> bool {
+    match out.as_slice() {
+        [.., Const(condition), If(_)] => {
+            let taken = *condition != 0.0;
+            let Some(If(branches)) = out.pop() else { unreachable!() };
+            out.pop();
+            out.extend(if taken { branches.then } else { branches.otherwise });
+            true
+        }
+        [.., Const(x), Negate] => {
+            let x = *x;
+            out.truncate(out.len() - 2);
+            out.push(Const(-x));
+            true
+        }
+        [.., Const(a), Const(b), op] if is_foldable(op) => match fold(vm, *a, *b, op) {
+            Some(result) => {
+                out.truncate(out.len() - 3);
+                out.push(Const(result));
+                true
+            }
+            None => false,
+        },
+        // `x + 0` and `x * 1`, once `x` is a complete sub-expression, as
+        // long as it is a number; for a string they are type errors.
+        [.., x, Const(0.0), Add] | [.., x, Const(1.0), Times] if is_numeric(x) => {
+            out.truncate(out.len() - 2);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Whether the sub-expression ending in `op` always gives a number, or a
+/// list of numbers from broadcasting, never a string or a function.
+fn is_numeric(op: &Operation) -> bool {
+    matches!(op, Const(_)) || op.is_elementwise()
+}
+
+/// Binary operations that depend only on their operands, not on the angle
+/// mode, the random number generator or previous answers.
+fn is_foldable(op: &Operation) -> bool {
+    matches!(
+        op,
+        Add | Subtract | Times | Divide | FloorDivide | Modulo | Power |
+        Min | Max | Hypot | LogBase | Root | Gcd | Lcm |
+        Less | LessEqual | Greater | GreaterEqual | Equal | NotEqual
+    )
+}
+
+/// Evaluates `a b op` as the VM would. Errors and non-finite results are
+/// left for evaluation, so that they are reported (or not, outside strict
+/// mode) exactly as without optimization.
+fn fold(vm: &mut VirtualMachine, a: f64, b: f64, op: &Operation) -> Option<f64> {
+    let result = vm.apply(op, vec![Value::Number(a), Value::Number(b)]).ok()?;
+    result.as_number().filter(|x| x.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+    use crate::script;
+
+    fn optimize_source(source: &str) -> Vec<Operation> {
+        optimize(script::compile(lexer::scan(source).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        assert_eq!(optimize_source("1 + 2 * 3"), vec![Const(7.0)]);
+        assert_eq!(optimize_source("-(2^10)"), vec![Const(-1024.0)]);
+        assert_eq!(
+            optimize_source("x * (60 * 60)"),
+            vec![Load("x".to_string()), Const(3600.0), Times]
+        );
+    }
+
+    #[test]
+    fn test_drop_identities() {
+        assert_eq!(optimize_source("sqrt(x) + 0"), vec![Load("x".to_string()), Sqrt]);
+        assert_eq!(optimize_source("(x + 1 - 1) * 1"), vec![
+            Load("x".to_string()), Const(1.0), Add, Const(1.0), Subtract,
+        ]);
+        assert_eq!(optimize_source("-x * (3 - 2)"), vec![Load("x".to_string()), Negate]);
+    }
+
+    #[test]
+    fn test_keep_identities_of_unknown_types() {
+        // `x` could be a string, for which `x * 1` is a type error.
+        assert_eq!(optimize_source("x * 1"), vec![Load("x".to_string()), Const(1.0), Times]);
+        assert_eq!(optimize_source("\"a\" + 0"), vec![Str("a".to_string()), Const(0.0), Add]);
+        assert_eq!(optimize_source("f(2) * 1"), vec![Const(2.0), Call("f".to_string(), 1), Const(1.0), Times]);
+    }
+
+    #[test]
+    fn test_keep_errors_and_mode_dependent_ops() {
+        assert_eq!(optimize_source("1 / 0"), vec![Const(1.0), Const(0.0), Divide]);
+        assert_eq!(optimize_source("atan2(1, 1)"), vec![Const(1.0), Const(1.0), ArcTan2]);
+        assert_eq!(optimize_source("randint(1, 6)"), vec![Const(1.0), Const(6.0), RandInt]);
+    }
+
+    #[test]
+    fn test_constant_condition_selects_branch() {
+        assert_eq!(optimize_source("if 1 < 2 then -x * 1 else 1 / 0"), vec![Load("x".to_string()), Negate]);
+        assert_eq!(optimize_source("2 * if pi then 1 else 2"), vec![
+            Const(2.0), Load("pi".to_string()),
+            If(Box::new(Branches { then: vec![Const(1.0)], otherwise: vec![Const(2.0)] })),
+            Times,
+        ]);
+    }
+}
@@ -0,0 +1,108 @@
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+use crate::editor;
+
+const DEFAULT_HEIGHT: usize = 24;
+
+/// Prints `text`, paging it if it is taller than the terminal. `$PAGER` is
+/// used when set; otherwise a minimal built-in pager shows one screen at a
+/// time. Output that is not going to a terminal is never paged.
+pub fn print(text: &str, enabled: bool) -> io::Result<()> {
+    let height = terminal_height();
+    if !enabled || !io::stdout().is_terminal() || text.lines().count() < height {
+        println!("{}", text);
+        return Ok(());
+    }
+
+    if let Ok(pager) = env::var("PAGER")
+        && !pager.trim().is_empty()
+        && run_external(&pager, text).is_ok()
+    {
+        return Ok(());
+    }
+
+    page(text, height, &mut io::stdin().lock(), &mut io::stdout())
+}
+
+/// Terminal height as the terminal reports it, else from `$LINES`, falling
+/// back to a conventional 24 rows.
+fn terminal_height() -> usize {
+    editor::terminal_height()
+        .or_else(|| env::var("LINES").ok().and_then(|lines| lines.parse().ok()))
+        .filter(|&lines| lines > 1)
+        .unwrap_or(DEFAULT_HEIGHT)
+}
+
+fn run_external(pager: &str, text: &str) -> io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(pager)
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may exit before reading everything (e.g. `q` in less).
+        let _ = writeln!(stdin, "{}", text);
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Writes `text` a screen at a time, waiting for a line of input between
+/// screens. Entering `q` stops paging.
+fn page<R: BufRead, W: Write>(
+    text: &str,
+    height: usize,
+    input: &mut R,
+    output: &mut W,
+) -> io::Result<()> {
+    let lines: Vec<&str> = text.lines().collect();
+    let page_size = height.saturating_sub(1).max(1);
+
+    for (i, chunk) in lines.chunks(page_size).enumerate() {
+        if i > 0 {
+            write!(output, "-- more ({}/{}), Enter to continue, q to quit --",
+                i * page_size, lines.len())?;
+            output.flush()?;
+
+            let mut answer = String::new();
+            if input.read_line(&mut answer)? == 0 || answer.trim() == "q" {
+                return Ok(());
+            }
+        }
+        for line in chunk {
+            writeln!(output, "{}", line)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_page(text: &str, height: usize, input: &str) -> String {
+        let mut output = Vec::new();
+        page(text, height, &mut input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_page_waits_between_screens() {
+        let output = run_page("1\n2\n3\n4\n5", 3, "\n\n");
+        assert_eq!(
+            output,
+            "1\n2\n\
+             -- more (2/5), Enter to continue, q to quit --3\n4\n\
+             -- more (4/5), Enter to continue, q to quit --5\n"
+        );
+    }
+
+    #[test]
+    fn test_page_quit() {
+        let output = run_page("1\n2\n3\n4\n5", 3, "q\n");
+        assert_eq!(output, "1\n2\n-- more (2/5), Enter to continue, q to quit --");
+    }
+}
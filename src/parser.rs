@@ -1,19 +1,179 @@
 use std::fmt;
 
-use crate::operation::Operation;
+use crate::operation::{Branches, Derivative, Equation, Equivalence, Function, Loop, Operation};
 use crate::token::*;
+use crate::vm::{EARTH_RADIUS_KM, INTEGRATE_TOLERANCE, RATIONALIZE_MAX_DENOMINATOR};
 
 
-pub fn parse(tokens: Vec<Token>) -> Result<Vec<Operation>, ParseError> {
+pub fn parse(mut tokens: Vec<Token>) -> Result<Vec<Operation>, ParseError> {
+    let mut operations = Vec::new();
+    parse_into(&mut tokens, &mut operations, &mut Vec::new())?;
+    Ok(operations)
+}
+
+/// Like [`parse_with_spans`], but writes into `operations` and `spans`,
+/// which are cleared first. Every buffer is handed back to the caller, with
+/// its allocation, whether or not parsing succeeds.
+pub fn parse_into(
+    tokens: &mut Vec<Token>,
+    operations: &mut Vec<Operation>,
+    spans: &mut Vec<Span>,
+) -> Result<(), ParseError> {
+    operations.clear();
+    spans.clear();
     let mut parser = Parser::new();
-    parser.parse(&tokens).cloned()
+    parser.tokens = std::mem::take(tokens);
+    parser.operations = std::mem::take(operations);
+    parser.spans = std::mem::take(spans);
+    let result = parser.parse_tokens();
+    *tokens = parser.tokens;
+    *operations = parser.operations;
+    *spans = parser.spans;
+    result
+}
+
+/// Like [`parse`], but also returns the source span each operation came
+/// from: the operator token for infix operations, e.g. the `+` for an
+/// `Add`, and the whole call or literal for prefix ones, e.g. `sin(x)`.
+pub fn parse_with_spans(
+    mut tokens: Vec<Token>,
+) -> Result<(Vec<Operation>, Vec<Span>), ParseError> {
+    let (mut operations, mut spans) = (Vec::new(), Vec::new());
+    parse_into(&mut tokens, &mut operations, &mut spans)?;
+    Ok((operations, spans))
+}
+
+/// Whether a token can only continue an expression, like `*` or `!`, and
+/// never start one. `-` is not, since it can negate.
+pub fn is_infix_only(token_type: &TokenType) -> bool {
+    let rule = Parser::get_parse_rule(token_type);
+    rule.prefix.is_none() && rule.infix.is_some()
 }
 
+/// The operation of a prefix operator or single-argument builtin, e.g.
+/// `Sqrt` for `sqrt`.
+pub fn unary_operation(token_type: &TokenType) -> Option<Operation> {
+    use TokenType::*;
+    let operation = match token_type {
+        Minus => Operation::Negate,
+        Tilde => Operation::BitNot,
+        Sin => Operation::Sin,
+        Cos => Operation::Cos,
+        Tan => Operation::Tan,
+        SinD => Operation::SinD,
+        CosD => Operation::CosD,
+        TanD => Operation::TanD,
+        Deg2Rad => Operation::Deg2Rad,
+        Rad2Deg => Operation::Rad2Deg,
+        ArcSin => Operation::ArcSin,
+        ArcCos => Operation::ArcCos,
+        ArcTan => Operation::ArcTan,
+        Sinh => Operation::Sinh,
+        Cosh => Operation::Cosh,
+        Tanh => Operation::Tanh,
+        ArcSinh => Operation::ArcSinh,
+        ArcCosh => Operation::ArcCosh,
+        ArcTanh => Operation::ArcTanh,
+        Ln => Operation::Ln,
+        Exp => Operation::Exp,
+        Log10 => Operation::Log10,
+        Log2 => Operation::Log2,
+        Sqrt => Operation::Sqrt,
+        Cbrt => Operation::Cbrt,
+        Abs => Operation::Abs,
+        Sign => Operation::Sign,
+        Floor => Operation::Floor,
+        Ceil => Operation::Ceil,
+        Round => Operation::Round,
+        Trunc => Operation::Trunc,
+        Frac => Operation::Frac,
+        Db => Operation::Db,
+        UnDb => Operation::UnDb,
+        DbmToMw => Operation::DbmToMw,
+        MwToDbm => Operation::MwToDbm,
+        Hex2Rgb => Operation::Hex2Rgb,
+        SrgbToLinear => Operation::SrgbToLinear,
+        LinearToSrgb => Operation::LinearToSrgb,
+        Gamma => Operation::Gamma,
+        Erf => Operation::Erf,
+        Erfc => Operation::Erfc,
+        IsPrime => Operation::IsPrime,
+        NextPrime => Operation::NextPrime,
+        Factor => Operation::Factor,
+        Fact => Operation::Factorial,
+        Normalize => Operation::Normalize,
+        ZScore => Operation::ZScore,
+        Irr => Operation::Irr,
+        ToHex => Operation::ToHex,
+        ToBin => Operation::ToBin,
+        ToOct => Operation::ToOct,
+        Mean => Operation::Mean,
+        Median => Operation::Median,
+        Mode => Operation::Mode,
+        Var => Operation::Var,
+        Stdev => Operation::Stdev,
+        Sum => Operation::Sum,
+        Prod => Operation::Prod,
+        Sort => Operation::Sort,
+        Iqr => Operation::Iqr,
+        Unique => Operation::Unique,
+        CumSum => Operation::CumSum,
+        CumProd => Operation::CumProd,
+        Len => Operation::Len,
+        Reverse => Operation::Reverse,
+        Head => Operation::Head,
+        Tail => Operation::Tail,
+        Transpose => Operation::Transpose,
+        Det => Operation::Det,
+        Inv => Operation::Inv,
+        Norm => Operation::Norm,
+        PolyRoots => Operation::PolyRoots,
+        Odds => Operation::Odds,
+        Prob => Operation::Prob,
+        Digits => Operation::Digits,
+        DigitSum => Operation::DigitSum,
+        ReverseDigits => Operation::ReverseDigits,
+        _ => return None,
+    };
+    Some(operation)
+}
 
+/// The operation of an infix operator, e.g. `Add` for `+`.
+pub fn binary_operation(token_type: &TokenType) -> Option<Operation> {
+    use TokenType::*;
+    let operation = match token_type {
+        Plus => Operation::Add,
+        Minus => Operation::Subtract,
+        Star => Operation::Times,
+        Slash => Operation::Divide,
+        SlashSlash => Operation::FloorDivide,
+        Mod => Operation::Modulo,
+        At => Operation::MatMul,
+        Caret => Operation::Power,
+        Ampersand => Operation::BitAnd,
+        Pipe => Operation::BitOr,
+        Xor => Operation::BitXor,
+        LessLess => Operation::ShiftLeft,
+        GreaterGreater => Operation::ShiftRight,
+        Less => Operation::Less,
+        LessEqual => Operation::LessEqual,
+        Greater => Operation::Greater,
+        GreaterEqual => Operation::GreaterEqual,
+        EqualEqual => Operation::Equal,
+        BangEqual => Operation::NotEqual,
+        _ => return None,
+    };
+    Some(operation)
+}
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 enum Precedence {
     None,
+    Comparison,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
     Term,
     Factor, 
     Unary,
@@ -25,7 +185,12 @@ impl Precedence {
     fn next(self) -> Self {
         use Precedence::*;
         match self {
-            None => Term,
+            None => Comparison,
+            Comparison => BitOr,
+            BitOr => BitXor,
+            BitXor => BitAnd,
+            BitAnd => Shift,
+            Shift => Term,
             Term => Factor,
             Factor => Exponent,
             Exponent => Unary,
@@ -40,19 +205,70 @@ impl Precedence {
 pub struct Parser {
     tokens: Vec<Token>,
     operations: Vec<Operation>,
+    spans: Vec<Span>,
     curr: usize,
     prev: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum ParseError {
     ExpectExpression { token: Token },
     ExpectEndOfExpression,
     ExpectRightParenAfterExpression { token: Token },
+    ExpectLeftParenAfterFunction { token: Token },
+    ExpectRightParenAfterArguments { token: Token },
+    ExpectArgumentCount { token: Token, found: usize },
+    ExpectRightBracketAfterElements { token: Token },
+    ExpectRightBracketAfterIndex { token: Token },
+    ExpectNameAfterLet { token: Token },
+    ExpectEqualAfterLetName { token: Token },
+    ExpectInAfterLetValue { token: Token },
+    ExpectThenAfterCondition { token: Token },
+    ExpectElseAfterBranch { token: Token },
+    ExpectNameAfterFor { token: Token },
+    ExpectEqualInEquation { token: Token },
+    ExpectVariableToDifferentiate { token: Token },
+    ExpectForAfterEquation { token: Token },
+    ExpectEqualAfterForName { token: Token },
+    ExpectToAfterForStart { token: Token },
+    ExpectLeftBraceBeforeBlock { token: Token },
+    ExpectRightBraceAfterBlock { token: Token },
+    ExpectImportStatement { token: Token },
 }
 
 use ParseError::*;
 
+impl ParseError {
+    /// The source span of the token where parsing failed, if there is one.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ExpectEndOfExpression => None,
+            ExpectExpression { token }
+            | ExpectRightParenAfterExpression { token }
+            | ExpectLeftParenAfterFunction { token }
+            | ExpectRightParenAfterArguments { token }
+            | ExpectArgumentCount { token, .. }
+            | ExpectRightBracketAfterElements { token }
+            | ExpectRightBracketAfterIndex { token }
+            | ExpectNameAfterLet { token }
+            | ExpectEqualAfterLetName { token }
+            | ExpectInAfterLetValue { token }
+            | ExpectThenAfterCondition { token }
+            | ExpectElseAfterBranch { token }
+            | ExpectNameAfterFor { token }
+            | ExpectEqualInEquation { token }
+            | ExpectVariableToDifferentiate { token }
+            | ExpectForAfterEquation { token }
+            | ExpectEqualAfterForName { token }
+            | ExpectToAfterForStart { token }
+            | ExpectLeftBraceBeforeBlock { token }
+            | ExpectRightBraceAfterBlock { token }
+            | ExpectImportStatement { token } => Some(token.span),
+        }
+    }
+}
+
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -65,13 +281,73 @@ impl fmt::Display for ParseError {
             ExpectRightParenAfterExpression { token } => {
                 write!(f, "Expected ')' after expression at {}", token.span.0)
             }
+            ExpectLeftParenAfterFunction { token } => {
+                write!(f, "Expected '(' after function name at {}", token.span.0)
+            }
+            ExpectRightParenAfterArguments { token } => {
+                write!(f, "Expected ')' after arguments at {}", token.span.0)
+            }
+            ExpectRightBracketAfterElements { token } => {
+                write!(f, "Expected ']' after list elements at {}", token.span.0)
+            }
+            ExpectRightBracketAfterIndex { token } => {
+                write!(f, "Expected ']' after index at {}", token.span.0)
+            }
+            ExpectNameAfterLet { token } => {
+                write!(f, "Expected a name after 'let' at {}", token.span.0)
+            }
+            ExpectEqualAfterLetName { token } => {
+                write!(f, "Expected '=' after let name at {}", token.span.0)
+            }
+            ExpectInAfterLetValue { token } => {
+                write!(f, "Expected 'in' after let value at {}", token.span.0)
+            }
+            ExpectThenAfterCondition { token } => {
+                write!(f, "Expected 'then' after condition at {}", token.span.0)
+            }
+            ExpectElseAfterBranch { token } => {
+                write!(f, "Expected 'else' after 'then' branch at {}", token.span.0)
+            }
+            ExpectNameAfterFor { token } => {
+                write!(f, "Expected a name after 'for' at {}", token.span.0)
+            }
+            ExpectVariableToDifferentiate { token } => {
+                write!(f, "Expected a variable to differentiate by at {}", token.span.0)
+            }
+            ExpectEqualInEquation { token } => {
+                write!(f, "Expected '=' between the sides of the equation at {}", token.span.0)
+            }
+            ExpectForAfterEquation { token } => {
+                write!(f, "Expected 'for' after equation at {}", token.span.0)
+            }
+            ExpectEqualAfterForName { token } => {
+                write!(f, "Expected '=' after loop variable at {}", token.span.0)
+            }
+            ExpectToAfterForStart { token } => {
+                write!(f, "Expected 'to' after loop start at {}", token.span.0)
+            }
+            ExpectLeftBraceBeforeBlock { token } => {
+                write!(f, "Expected '{{' before loop body at {}", token.span.0)
+            }
+            ExpectRightBraceAfterBlock { token } => {
+                write!(f, "Expected '}}' after loop body at {}", token.span.0)
+            }
+            ExpectImportStatement { token } => {
+                write!(f, "Expected 'import \"file\"' as a statement of its own at {}", token.span.0)
+            }
+            ExpectArgumentCount { token, found } => {
+                write!(f, "Unexpected number of arguments ({}) for '{}' at {}",
+                    found, token.lexeme, token.span.0)
+            }
         }
     }
 }
 
+type ParseFn = fn(&mut Parser) -> Result<(), ParseError>;
+
 struct ParseRule {
-    prefix: Option<fn(&mut Parser) -> Result<(), ParseError>>,
-    infix: Option<fn(&mut Parser) -> Result<(), ParseError>>,
+    prefix: Option<ParseFn>,
+    infix: Option<ParseFn>,
     precedence: Precedence,
 }
 
@@ -80,6 +356,7 @@ impl Parser {
         Self {
             tokens: Vec::new(),
             operations: Vec::new(),
+            spans: Vec::new(),
             curr: 0,
             prev: 0,
         }
@@ -87,6 +364,11 @@ impl Parser {
     fn get_parse_rule(token_type: &TokenType) -> ParseRule {
         use TokenType::*;
         match token_type {
+            Bang => ParseRule {
+                prefix: None,
+                infix: Some(|parser| parser.postfix()),
+                precedence: Precedence::Call,
+            },
             Caret => ParseRule {
                 prefix: None,
                 infix: Some(|parser| parser.binary()),
@@ -97,6 +379,41 @@ impl Parser {
                 infix: None,
                 precedence: Precedence::Call,
             },
+            LeftBracket => ParseRule {
+                prefix: Some(|parser| parser.list()),
+                infix: Some(|parser| parser.index()),
+                precedence: Precedence::Call,
+            },
+            Pipe => ParseRule {
+                prefix: None,
+                infix: Some(|parser| parser.binary()),
+                precedence: Precedence::BitOr,
+            },
+            Xor => ParseRule {
+                prefix: None,
+                infix: Some(|parser| parser.binary()),
+                precedence: Precedence::BitXor,
+            },
+            Ampersand => ParseRule {
+                prefix: None,
+                infix: Some(|parser| parser.binary()),
+                precedence: Precedence::BitAnd,
+            },
+            Less | LessEqual | Greater | GreaterEqual | EqualEqual | BangEqual => ParseRule {
+                prefix: None,
+                infix: Some(|parser| parser.binary()),
+                precedence: Precedence::Comparison,
+            },
+            LessLess | GreaterGreater => ParseRule {
+                prefix: None,
+                infix: Some(|parser| parser.binary()),
+                precedence: Precedence::Shift,
+            },
+            Tilde => ParseRule {
+                prefix: Some(|parser| parser.unary()),
+                infix: None,
+                precedence: Precedence::None,
+            },
             Minus => ParseRule {
                 prefix: Some(|parser| parser.unary()),
                 infix: Some(|parser| parser.binary()),
@@ -117,6 +434,42 @@ impl Parser {
                 infix: Some(|parser| parser.binary()),
                 precedence: Precedence::Factor,
             },
+            SlashSlash | Mod | At => ParseRule {
+                prefix: None,
+                infix: Some(|parser| parser.binary()),
+                precedence: Precedence::Factor,
+            },
+            Identifier => ParseRule {
+                prefix: Some(|parser| parser.variable()),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            // `import "file"` statements are handled before parsing.
+            Import => ParseRule {
+                prefix: Some(|parser| Err(ExpectImportStatement { token: parser.prev().clone() })),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            For => ParseRule {
+                prefix: Some(|parser| parser.for_loop()),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            While => ParseRule {
+                prefix: Some(|parser| parser.while_loop()),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            If => ParseRule {
+                prefix: Some(|parser| parser.conditional()),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            Let => ParseRule {
+                prefix: Some(|parser| parser.let_in()),
+                infix: None,
+                precedence: Precedence::None,
+            },
             Ans => ParseRule {
                 prefix: Some(|parser| parser.ans()),
                 infix: None,
@@ -127,18 +480,73 @@ impl Parser {
                 infix: None,
                 precedence: Precedence::None,
             },
-            E | Pi => ParseRule {
-                prefix: Some(|parser| parser.constant()),
+            Str => ParseRule {
+                prefix: Some(|parser| parser.string()),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            E | Pi | Tau | Phi => ParseRule {
+                prefix: Some(|parser| parser.variable()),
                 infix: None,
                 precedence: Precedence::None,
             },
             Sin | Cos | Tan | 
+            SinD | CosD | TanD | Deg2Rad | Rad2Deg |
             ArcSin | ArcCos | ArcTan |
-            Exp | Ln => ParseRule {
+            Sinh | Cosh | Tanh |
+            ArcSinh | ArcCosh | ArcTanh |
+            Exp | Ln | Log10 | Log2 |
+            Sqrt | Cbrt |
+            Abs | Sign | Floor | Ceil | Trunc | Frac |
+            Db | UnDb | DbmToMw | MwToDbm |
+            Hex2Rgb | SrgbToLinear | LinearToSrgb |
+            Odds | Prob |
+            Gamma | Fact | Erf | Erfc |
+            IsPrime | NextPrime | Factor |
+            Mean | Median | Mode | Var | Stdev | Sum | Prod |
+            Normalize | ZScore | Sort | Iqr | Unique |
+            CumSum | CumProd | Irr | Len | Reverse | Head | Tail |
+            Transpose | Det | Inv | Norm | PolyRoots |
+            ToHex | ToBin | ToOct |
+            Digits | DigitSum | ReverseDigits => ParseRule {
                 prefix: Some(|parser| parser.unary()),
                 infix: None,
                 precedence: Precedence::Term,
             },
+            Solve => ParseRule {
+                prefix: Some(|parser| parser.solve()),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            Deriv => ParseRule {
+                prefix: Some(|parser| parser.derivative()),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            Equiv => ParseRule {
+                prefix: Some(|parser| parser.equivalence()),
+                infix: None,
+                precedence: Precedence::None,
+            },
+            Round => ParseRule {
+                prefix: Some(|parser| parser.unary_or_call()),
+                infix: None,
+                precedence: Precedence::Term,
+            },
+            Log | Root | Haversine | Bearing | SigFig |
+            Rgb2Hex | Parallel | VoltageDivider |
+            Bayes | Gcd | Lcm | WMean | LinReg |
+            Compound | Pv | Fv | Npv | Convert |
+            Percentile | Union | Intersect | Concat | Dot | Cross | LinSolve | PolyVal |
+            Rationalize | ConFrac |
+            Min | Max | ArcTan2 | Hypot | ToPolar | ToRect |
+            Rand | RandInt | RandN | PowMod | Print | Assert |
+            NormPdf | NormCdf | Map | Filter | Reduce | Range | Integrate | Diff | Newton |
+            Minimize | Maximize => ParseRule {
+                prefix: Some(|parser| parser.call()),
+                infix: None,
+                precedence: Precedence::None,
+            },
             _ => ParseRule {
                 prefix: None,
                 infix: None,
@@ -147,20 +555,198 @@ impl Parser {
         }
     }
 
-    pub fn parse(&mut self, tokens: &Vec<Token>) -> Result<&Vec<Operation>, ParseError> {
-        self.tokens = tokens.clone();
-        self.expression()?;
+    fn parse_tokens(&mut self) -> Result<(), ParseError> {
+        if self.is_definition() {
+            self.definition()?;
+        } else {
+            self.statement()?;
+        }
         self.consume(TokenType::EOF, 
             |_| ExpectEndOfExpression
-        )?;
-        Ok(&self.operations)
+        )
+    }
+
+    /// An assignment or an expression.
+    fn statement(&mut self) -> Result<(), ParseError> {
+        if self.is_assignment() {
+            self.assignment()
+        } else {
+            self.expression()
+        }
+    }
+
+    /// Whether the next statement is `name = expression`. Builtin constants
+    /// can be assigned too; the VM decides whether that is allowed.
+    fn is_assignment(&self) -> bool {
+        use TokenType::*;
+        matches!(
+            (self.tokens.get(self.curr), self.tokens.get(self.curr + 1)),
+            (Some(name), Some(equal))
+                if matches!(name.token_type, Identifier | E | Pi | Tau | Phi)
+                    && equal.token_type == Equal
+        )
+    }
+
+    /// Whether the statement is `name(param, ...) = expression`.
+    fn is_definition(&self) -> bool {
+        use TokenType::*;
+        let types: Vec<&TokenType> = self.tokens.iter().map(|t| &t.token_type).collect();
+        let [Identifier, LeftParen, rest @ ..] = &types[..] else {
+            return false;
+        };
+        let mut rest = rest;
+        if let [Identifier, tail @ ..] = rest {
+            rest = tail;
+            while let [Comma, Identifier, tail @ ..] = rest {
+                rest = tail;
+            }
+        }
+        matches!(rest, [RightParen, Equal, ..])
+    }
+
+    /// Parses a function definition into a single `Define` holding the
+    /// body's operations, which only run when the function is called.
+    fn definition(&mut self) -> Result<(), ParseError> {
+        self.advance();
+        let name = self.prev().clone();
+        let mut params = Vec::new();
+        self.advance();
+        while !self.matches(TokenType::RightParen) {
+            self.advance();
+            if self.prev().token_type == TokenType::Identifier {
+                params.push(self.prev().lexeme.clone());
+            }
+        }
+        self.advance();
+        self.advance();
+
+        let body = self.branch()?;
+        self.operations.push(Operation::Define(Box::new(Function {
+            name: name.lexeme,
+            params,
+            body,
+        })));
+        self.record_spans((name.span.0, self.prev().span.1));
+        Ok(())
+    }
+
+    fn assignment(&mut self) -> Result<(), ParseError> {
+        self.advance();
+        let name = self.prev().clone();
+        self.advance();
+        self.expression()?;
+        self.operations.push(Operation::Store(name.lexeme));
+        self.record_spans((name.span.0, self.prev().span.1));
+        Ok(())
     }
 
     fn expression(&mut self) -> Result<(), ParseError> {
-        self.parse_precedence(Precedence::Term)?;
+        self.parse_precedence(Precedence::Comparison)?;
+        Ok(())
+    }
+
+    /// Parses `let name = value in body`. The name is only bound while the
+    /// body runs, which extends as far to the right as possible.
+    fn let_in(&mut self) -> Result<(), ParseError> {
+        self.consume(TokenType::Identifier, |s| {
+            ExpectNameAfterLet { token: s.curr().clone() }
+        })?;
+        let name = self.prev().clone();
+        self.consume(TokenType::Equal, |s| {
+            ExpectEqualAfterLetName { token: s.curr().clone() }
+        })?;
+        self.expression()?;
+        self.consume(TokenType::In, |s| {
+            ExpectInAfterLetValue { token: s.curr().clone() }
+        })?;
+        self.operations.push(Operation::PushScope(name.lexeme));
+        self.record_spans(name.span);
+        self.expression()?;
+        self.operations.push(Operation::PopScope);
+        Ok(())
+    }
+
+    /// Parses `if condition then a else b` into the condition followed by a
+    /// single `If` holding both branches, so only the chosen one runs. The
+    /// `else` branch extends as far to the right as possible.
+    fn conditional(&mut self) -> Result<(), ParseError> {
+        self.expression()?;
+        self.consume(TokenType::Then, |s| {
+            ExpectThenAfterCondition { token: s.curr().clone() }
+        })?;
+        let then = self.branch()?;
+        self.consume(TokenType::Else, |s| {
+            ExpectElseAfterBranch { token: s.curr().clone() }
+        })?;
+        let otherwise = self.branch()?;
+        self.operations.push(Operation::If(Box::new(Branches { then, otherwise })));
+        Ok(())
+    }
+
+    /// Parses an expression and takes its operations back out, together
+    /// with their spans, to be run as a sub-program.
+    fn branch(&mut self) -> Result<Vec<Operation>, ParseError> {
+        let start = self.operations.len();
+        self.expression()?;
+        self.spans.truncate(start);
+        Ok(self.operations.split_off(start))
+    }
+
+    /// Parses `for name = first to last { body }`.
+    fn for_loop(&mut self) -> Result<(), ParseError> {
+        self.consume(TokenType::Identifier, |s| {
+            ExpectNameAfterFor { token: s.curr().clone() }
+        })?;
+        let variable = self.prev().lexeme.clone();
+        self.consume(TokenType::Equal, |s| {
+            ExpectEqualAfterForName { token: s.curr().clone() }
+        })?;
+        self.expression()?;
+        self.consume(TokenType::To, |s| {
+            ExpectToAfterForStart { token: s.curr().clone() }
+        })?;
+        self.expression()?;
+        let body = self.block()?;
+        self.operations.push(Operation::For(Box::new(Loop {
+            variable,
+            condition: Vec::new(),
+            body,
+        })));
+        Ok(())
+    }
+
+    /// Parses `while condition { body }`.
+    fn while_loop(&mut self) -> Result<(), ParseError> {
+        let condition = self.branch()?;
+        let body = self.block()?;
+        self.operations.push(Operation::While(Box::new(Loop {
+            variable: String::new(),
+            condition,
+            body,
+        })));
         Ok(())
     }
 
+    /// Parses `{ statement; statement; ... }` into a sub-program that gives
+    /// the value of the last statement.
+    fn block(&mut self) -> Result<Vec<Operation>, ParseError> {
+        self.consume(TokenType::LeftBrace, |s| {
+            ExpectLeftBraceBeforeBlock { token: s.curr().clone() }
+        })?;
+        let start = self.operations.len();
+        self.statement()?;
+        while self.matches(TokenType::Semicolon) {
+            self.advance();
+            self.statement()?;
+            self.operations.push(Operation::Sequence);
+        }
+        self.consume(TokenType::RightBrace, |s| {
+            ExpectRightBraceAfterBlock { token: s.curr().clone() }
+        })?;
+        self.spans.truncate(start);
+        Ok(self.operations.split_off(start))
+    }
+
     fn grouping(&mut self) -> Result<(), ParseError> {
         self.expression()?;
         self.consume(TokenType::RightParen, |s| {
@@ -170,20 +756,257 @@ impl Parser {
     }
 
     fn unary(&mut self) -> Result<(), ParseError> {
-        let prev_token_type = self.prev().token_type.clone();
+        let function = self.prev().clone();
+        // A builtin named without an argument, as in `map(sqrt, xs)`, is a
+        // function value.
+        if !matches!(function.token_type, TokenType::Minus | TokenType::Tilde)
+            && (self.check(TokenType::Comma) || self.check(TokenType::RightParen))
+        {
+            self.operations.push(Operation::Load(function.lexeme));
+            return Ok(());
+        }
         self.parse_precedence(Precedence::Unary)?;
+        self.operations.extend(unary_operation(&function.token_type));
+        Ok(())
+    }
+
+    /// Parses either a call `solve(f, a, b)` or an equation to solve for a
+    /// variable, `solve x + 2 = 7 for x`, which is told apart by its `=`.
+    fn solve(&mut self) -> Result<(), ParseError> {
+        if !self.is_equation() {
+            return self.call();
+        }
+        let start = self.operations.len();
+        self.expression()?;
+        self.consume(TokenType::Equal, |s| {
+            ExpectEqualInEquation { token: s.curr().clone() }
+        })?;
+        self.expression()?;
+        self.operations.push(Operation::Subtract);
+        self.consume(TokenType::For, |s| {
+            ExpectForAfterEquation { token: s.curr().clone() }
+        })?;
+        self.consume(TokenType::Identifier, |s| {
+            ExpectNameAfterFor { token: s.curr().clone() }
+        })?;
+        let variable = self.prev().lexeme.clone();
+        self.spans.truncate(start);
+        let residual = self.operations.split_off(start);
+        self.operations.push(Operation::SolveFor(Box::new(Equation { variable, residual })));
+        Ok(())
+    }
+
+    /// Parses `deriv(expr, x)`, keeping the expression's operations to be
+    /// differentiated rather than evaluated.
+    fn derivative(&mut self) -> Result<(), ParseError> {
+        let function = self.prev().clone();
+        self.consume(TokenType::LeftParen, |s| {
+            ExpectLeftParenAfterFunction { token: s.curr().clone() }
+        })?;
+        let expression = self.branch()?;
+        self.consume(TokenType::Comma, |_| {
+            ExpectArgumentCount { token: function, found: 1 }
+        })?;
+        self.consume(TokenType::Identifier, |s| {
+            ExpectVariableToDifferentiate { token: s.curr().clone() }
+        })?;
+        let variable = self.prev().lexeme.clone();
+        self.consume(TokenType::RightParen, |s| {
+            ExpectRightParenAfterArguments { token: s.curr().clone() }
+        })?;
+        self.operations.push(Operation::Deriv(Box::new(Derivative { variable, expression })));
+        Ok(())
+    }
+
+    /// Parses `equiv(left, right)`, keeping both expressions to be
+    /// evaluated at sample points.
+    fn equivalence(&mut self) -> Result<(), ParseError> {
+        let function = self.prev().clone();
+        self.consume(TokenType::LeftParen, |s| {
+            ExpectLeftParenAfterFunction { token: s.curr().clone() }
+        })?;
+        let left = self.branch()?;
+        self.consume(TokenType::Comma, |_| {
+            ExpectArgumentCount { token: function, found: 1 }
+        })?;
+        let right = self.branch()?;
+        self.consume(TokenType::RightParen, |s| {
+            ExpectRightParenAfterArguments { token: s.curr().clone() }
+        })?;
+        self.operations.push(Operation::Equiv(Box::new(Equivalence { left, right })));
+        Ok(())
+    }
+
+    /// Whether an `=` follows outside any brackets, before the statement
+    /// ends.
+    fn is_equation(&self) -> bool {
+        use TokenType::*;
+        let mut depth = 0;
+        for token in &self.tokens[self.curr..] {
+            match token.token_type {
+                LeftParen | LeftBracket | LeftBrace => depth += 1,
+                RightParen | RightBracket | RightBrace if depth == 0 => return false,
+                RightParen | RightBracket | RightBrace => depth -= 1,
+                Equal if depth == 0 => return true,
+                Semicolon | EOF if depth == 0 => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// For functions with an optional second argument, like `round(x, n)`:
+    /// parses a parenthesised call if there is one, and otherwise falls back
+    /// to the unary form, e.g. `round 2.5`.
+    fn unary_or_call(&mut self) -> Result<(), ParseError> {
+        if self.check(TokenType::LeftParen) {
+            self.call()
+        } else {
+            self.unary()
+        }
+    }
+
+    /// Parses a function call with a parenthesised argument list, e.g.
+    /// `root(3, 27)`, checking the number of arguments against the function.
+    /// Variadic functions are compiled to a fold of a binary operation.
+    fn call(&mut self) -> Result<(), ParseError> {
+        use Operation as Op;
+        let function = self.prev().clone();
+        let arg_count = self.arguments()?;
+
+        let operations = match (&function.token_type, arg_count) {
+            (TokenType::Ans, 1) => vec![Op::AnsAt],
+            (TokenType::Log, 1) => vec![Op::Log10],
+            (TokenType::Log, 2) => vec![Op::LogBase],
+            (TokenType::Root, 2) => vec![Op::Root],
+            (TokenType::Round, 1) => vec![Op::Round],
+            (TokenType::Print, 1) => vec![Op::Print],
+            (TokenType::Assert, 1) => vec![Op::Assert],
+            (TokenType::Round, 2) => vec![Op::RoundTo],
+            (TokenType::SigFig, 2) => vec![Op::SigFig],
+            (TokenType::ArcTan2, 2) => vec![Op::ArcTan2],
+            (TokenType::Hypot, 2) => vec![Op::Hypot],
+            (TokenType::ToPolar, 2) => vec![Op::ToPolar],
+            (TokenType::ToRect, 2) => vec![Op::ToRect],
+            (TokenType::Haversine, 4) => vec![Op::Const(EARTH_RADIUS_KM), Op::Haversine],
+            (TokenType::Haversine, 5) => vec![Op::Haversine],
+            (TokenType::Bearing, 4) => vec![Op::Bearing],
+            (TokenType::Rgb2Hex, 3) => vec![Op::Rgb2Hex],
+            (TokenType::Parallel, n) if n > 0 => vec![Op::Parallel; n - 1],
+            (TokenType::Min, n) if n > 0 => vec![Op::Min; n - 1],
+            (TokenType::Max, n) if n > 0 => vec![Op::Max; n - 1],
+            (TokenType::VoltageDivider, 3) => vec![Op::VoltageDivider],
+            (TokenType::Bayes, 3) => vec![Op::Bayes],
+            (TokenType::Compound, 3) => vec![Op::Const(1.0), Op::Compound],
+            (TokenType::Compound, 4) => vec![Op::Compound],
+            (TokenType::Pv, 3) => vec![Op::Const(0.0), Op::Pv],
+            (TokenType::Pv, 4) => vec![Op::Pv],
+            (TokenType::Fv, 3) => vec![Op::Const(0.0), Op::Fv],
+            (TokenType::Fv, 4) => vec![Op::Fv],
+            (TokenType::Npv, 2) => vec![Op::Npv],
+            (TokenType::Convert, 3) => vec![Op::Convert],
+            (TokenType::WMean, 2) => vec![Op::WMean],
+            (TokenType::LinReg, 2) => vec![Op::LinReg],
+            (TokenType::Percentile, 2) => vec![Op::Percentile],
+            (TokenType::Union, 2) => vec![Op::Union],
+            (TokenType::Intersect, 2) => vec![Op::Intersect],
+            (TokenType::Concat, 2) => vec![Op::Concat],
+            (TokenType::Dot, 2) => vec![Op::Dot],
+            (TokenType::Cross, 2) => vec![Op::Cross],
+            (TokenType::LinSolve, 2) => vec![Op::LinSolve],
+            (TokenType::PolyVal, 2) => vec![Op::PolyVal],
+            (TokenType::Rationalize, 1) => vec![Op::Const(RATIONALIZE_MAX_DENOMINATOR), Op::Rationalize],
+            (TokenType::Rationalize, 2) => vec![Op::Rationalize],
+            (TokenType::ConFrac, 2) => vec![Op::ConFrac],
+            (TokenType::Rand, 0) => vec![Op::Rand],
+            (TokenType::RandInt, 2) => vec![Op::RandInt],
+            (TokenType::RandN, 0) => vec![Op::RandN],
+            (TokenType::PowMod, 3) => vec![Op::PowMod],
+            (TokenType::NormPdf, 1) => vec![Op::Const(0.0), Op::Const(1.0), Op::NormPdf],
+            (TokenType::NormPdf, 3) => vec![Op::NormPdf],
+            (TokenType::NormCdf, 1) => vec![Op::Const(0.0), Op::Const(1.0), Op::NormCdf],
+            (TokenType::NormCdf, 3) => vec![Op::NormCdf],
+            (TokenType::Gcd, 2) => vec![Op::Gcd],
+            (TokenType::Map, 2) => vec![Op::Map],
+            (TokenType::Filter, 2) => vec![Op::Filter],
+            (TokenType::Reduce, 3) => vec![Op::Reduce],
+            (TokenType::Integrate, 3) => vec![Op::Const(INTEGRATE_TOLERANCE), Op::Integrate],
+            (TokenType::Integrate, 4) => vec![Op::Integrate],
+            (TokenType::Diff, 2) => vec![Op::Diff],
+            (TokenType::Solve, 3) => vec![Op::Solve],
+            (TokenType::Newton, 2) => vec![Op::Newton],
+            (TokenType::Minimize, 3) => vec![Op::Minimize],
+            (TokenType::Maximize, 3) => vec![Op::Maximize],
+            (TokenType::Range, 2) => vec![Op::Const(1.0), Op::Range],
+            (TokenType::Range, 3) => vec![Op::Range],
+            (TokenType::Lcm, 2) => vec![Op::Lcm],
+            _ => {
+                return Err(ExpectArgumentCount { token: function, found: arg_count });
+            }
+        };
+        self.operations.extend(operations);
+        Ok(())
+    }
+
+    /// Parses `(expr, expr, ...)` and returns the number of arguments.
+    fn arguments(&mut self) -> Result<usize, ParseError> {
+        self.consume(TokenType::LeftParen, |s| {
+            ExpectLeftParenAfterFunction { token: s.curr().clone() }
+        })?;
+
+        let mut arg_count = 0;
+        if !self.matches(TokenType::RightParen) {
+            loop {
+                self.expression()?;
+                arg_count += 1;
+                if !self.matches(TokenType::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+        }
 
-        match prev_token_type {
-            TokenType::Minus => self.operations.push(Operation::Negate),
-            TokenType::Sin => self.operations.push(Operation::Sin),
-            TokenType::Cos => self.operations.push(Operation::Cos),
-            TokenType::Tan => self.operations.push(Operation::Tan),
-            TokenType::ArcSin => self.operations.push(Operation::ArcSin),
-            TokenType::ArcCos => self.operations.push(Operation::ArcCos),
-            TokenType::ArcTan => self.operations.push(Operation::ArcTan),
-            TokenType::Ln => self.operations.push(Operation::Ln),
-            TokenType::Exp => self.operations.push(Operation::Exp),
-            _ => {}
+        self.consume(TokenType::RightParen, |s| {
+            ExpectRightParenAfterArguments { token: s.curr().clone() }
+        })?;
+        Ok(arg_count)
+    }
+
+    /// Parses a list literal `[expr, expr, ...]` into its elements followed by
+    /// a `MakeList` of the element count.
+    fn list(&mut self) -> Result<(), ParseError> {
+        let mut len = 0;
+        if !self.matches(TokenType::RightBracket) {
+            loop {
+                self.expression()?;
+                len += 1;
+                if !self.matches(TokenType::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+
+        self.consume(TokenType::RightBracket, |s| {
+            ExpectRightBracketAfterElements { token: s.curr().clone() }
+        })?;
+        self.operations.push(Operation::MakeList(len));
+        Ok(())
+    }
+
+    /// Parses the `[i]` of an indexing expression such as `xs[2]`.
+    fn index(&mut self) -> Result<(), ParseError> {
+        self.expression()?;
+        self.consume(TokenType::RightBracket, |s| {
+            ExpectRightBracketAfterIndex { token: s.curr().clone() }
+        })?;
+        self.operations.push(Operation::Index);
+        Ok(())
+    }
+
+    fn postfix(&mut self) -> Result<(), ParseError> {
+        if self.prev().token_type == TokenType::Bang {
+            self.operations.push(Operation::Factorial);
         }
         Ok(())
     }
@@ -193,13 +1016,8 @@ impl Parser {
         let parse_rule = Self::get_parse_rule(&operator_type);
         self.parse_precedence(parse_rule.precedence.next())?;
 
-        match operator_type {
-            TokenType::Plus => self.operations.push(Operation::Add),
-            TokenType::Minus => self.operations.push(Operation::Subtract),
-            TokenType::Star => self.operations.push(Operation::Times),
-            TokenType::Slash => self.operations.push(Operation::Divide),
-            TokenType::Caret => self.operations.push(Operation::Power),
-            _ => {}
+        if let Some(operation) = binary_operation(&operator_type) {
+            self.operations.push(operation);
         }
         Ok(())
     }
@@ -210,18 +1028,30 @@ impl Parser {
         Ok(())
     }
 
-    fn constant(&mut self) -> Result<(), ParseError> {
-        use std::f64::consts::{E, PI};
-        let prev_token_type = self.prev().clone().token_type;
-        match prev_token_type {
-            TokenType::E => self.operations.push(Operation::Const(E)),
-            TokenType::Pi => self.operations.push(Operation::Const(PI)),
-            _ => {},
+    fn string(&mut self) -> Result<(), ParseError> {
+        let val = self.prev().lexeme.clone();
+        self.operations.push(Operation::Str(val));
+        Ok(())
+    }
+
+    /// A variable, or a builtin constant like `pi`, which the VM defines as
+    /// a protected variable. A name followed by `(` calls a user function.
+    fn variable(&mut self) -> Result<(), ParseError> {
+        let name = self.prev().lexeme.clone();
+        if self.prev().token_type == TokenType::Identifier && self.check(TokenType::LeftParen) {
+            let arg_count = self.arguments()?;
+            self.operations.push(Operation::Call(name, arg_count));
+            return Ok(());
         }
+        self.operations.push(Operation::Load(name));
         Ok(())
     }
 
+    /// `ans` is the previous answer and `ans(n)` the n-th most recent one.
     fn ans(&mut self) -> Result<(), ParseError> {
+        if self.check(TokenType::LeftParen) {
+            return self.call();
+        }
         self.operations.push(Operation::Ans);
         Ok(())
     }
@@ -241,7 +1071,7 @@ impl Parser {
     fn parse_precedence(&mut self, precedence: Precedence) -> Result<(), ParseError> {
         self.advance();
         let prev_token_type = self.prev().token_type.clone();
-        let prefix_rule: Option<fn(&mut Parser) -> Result<(), ParseError>> =
+        let prefix_rule: Option<ParseFn> =
             Self::get_parse_rule(&prev_token_type).prefix;
         match prefix_rule {
             None => {
@@ -250,7 +1080,9 @@ impl Parser {
                 });
             }
             Some(prefix_rule) => {
+                let start = self.prev().span.0;
                 prefix_rule(self)?;
+                self.record_spans((start, self.prev().span.1));
             }
         }
 
@@ -265,7 +1097,9 @@ impl Parser {
             match infix_rule {
                 None => {}
                 Some(infix_rule) => {
+                    let span = self.prev().span;
                     infix_rule(self)?;
+                    self.record_spans(span);
                 }
             }
         }
@@ -273,11 +1107,21 @@ impl Parser {
         Ok(())
     }
 
+    /// Attributes operations emitted by a rule to `span`. Operands parsed by
+    /// nested rules have already been attributed by then.
+    fn record_spans(&mut self, span: Span) {
+        self.spans.resize(self.operations.len(), span);
+    }
+
     fn advance(&mut self) {
         self.prev = self.curr;
         self.curr += 1;
     }
 
+    fn matches(&self, token_type: TokenType) -> bool {
+        !self.is_at_end() && self.check(token_type)
+    }
+
     fn check(&self, token_type: TokenType) -> bool {
         self.curr().token_type == token_type
     }
@@ -318,14 +1162,12 @@ mod tests {
     }
 
     fn assert_parse(tokens: Vec<Token>, expected_ops: &[Operation]) {
-        let mut parser = Parser::new();
-        let ops = parser.parse(&tokens).expect("Parser failed");
+        let ops = parse(tokens).expect("Parser failed");
         assert_eq!(ops.as_slice(), expected_ops);
     }
 
     fn assert_parse_error(tokens: Vec<Token>, expected_error: ParseError) {
-        let mut parser = Parser::new();
-        let result = parser.parse(&tokens);
+        let result = parse(tokens);
         match result {
             Ok(_) => panic!("Expected parser error {:?}, but got Ok", expected_error),
             Err(e) => assert_eq!(e, expected_error),
@@ -448,4 +1290,533 @@ mod tests {
 
         );
     }
+
+    #[test]
+    fn test_call_parse() {
+        assert_parse(
+            vec![
+                make_token(Root, "root", (0, 4)),
+                make_token(LeftParen, "(", (4, 5)),
+                make_token(Number, "3", (5, 6)),
+                make_token(Comma, ",", (6, 7)),
+                make_token(Number, "27", (7, 9)),
+                make_token(RightParen, ")", (9, 10)),
+                make_token(EOF, "", (10, 11)),
+            ],
+            &[Op::Const(3.0), Op::Const(27.0), Op::Root]
+        );
+    }
+
+    #[test]
+    fn test_call_argument_count() {
+        assert_parse_error(
+            vec![
+                make_token(Root, "root", (0, 4)),
+                make_token(LeftParen, "(", (4, 5)),
+                make_token(Number, "27", (5, 7)),
+                make_token(RightParen, ")", (7, 8)),
+                make_token(EOF, "", (8, 9)),
+            ],
+            ExpectArgumentCount {
+                token: make_token(Root, "root", (0, 4)),
+                found: 1,
+            },
+        );
+    }
+
+    #[test]
+    fn test_modulo_precedence() {
+        // 1 + 7 mod 4 => 1 + (7 mod 4)
+        assert_parse(
+            vec![
+                make_token(Number, "1", (0, 1)),
+                make_token(Plus, "+", (2, 3)),
+                make_token(Number, "7", (4, 5)),
+                make_token(Mod, "mod", (6, 9)),
+                make_token(Number, "4", (10, 11)),
+                make_token(EOF, "", (11, 12)),
+            ],
+            &[Op::Const(1.0), Op::Const(7.0), Op::Const(4.0), Op::Modulo, Op::Add]
+        );
+    }
+
+    #[test]
+    fn test_variadic_call_is_folded() {
+        assert_parse(
+            vec![
+                make_token(Parallel, "parallel", (0, 8)),
+                make_token(LeftParen, "(", (8, 9)),
+                make_token(Number, "1", (9, 10)),
+                make_token(Comma, ",", (10, 11)),
+                make_token(Number, "2", (11, 12)),
+                make_token(Comma, ",", (12, 13)),
+                make_token(Number, "3", (13, 14)),
+                make_token(RightParen, ")", (14, 15)),
+                make_token(EOF, "", (15, 16)),
+            ],
+            &[Op::Const(1.0), Op::Const(2.0), Op::Const(3.0), Op::Parallel, Op::Parallel]
+        );
+    }
+
+    #[test]
+    fn test_postfix_factorial_binds_tighter_than_negation() {
+        assert_parse(
+            vec![
+                make_token(Minus, "-", (0, 1)),
+                make_token(Number, "3", (1, 2)),
+                make_token(Bang, "!", (2, 3)),
+                make_token(EOF, "", (3, 4)),
+            ],
+            &[Op::Const(3.0), Op::Factorial, Op::Negate]
+        );
+    }
+
+    #[test]
+    fn test_index_binds_tighter_than_power() {
+        // -xs[2]^2
+        assert_parse(
+            vec![
+                make_token(Minus, "-", (0, 1)),
+                make_token(Identifier, "xs", (1, 3)),
+                make_token(LeftBracket, "[", (3, 4)),
+                make_token(Number, "2", (4, 5)),
+                make_token(RightBracket, "]", (5, 6)),
+                make_token(Caret, "^", (6, 7)),
+                make_token(Number, "2", (7, 8)),
+                make_token(EOF, "", (8, 9)),
+            ],
+            &[
+                Op::Load("xs".to_string()),
+                Op::Const(2.0),
+                Op::Index,
+                Op::Const(2.0),
+                Op::Power,
+                Op::Negate,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_literal() {
+        assert_parse(
+            vec![
+                make_token(LeftBracket, "[", (0, 1)),
+                make_token(Number, "1", (1, 2)),
+                make_token(Comma, ",", (2, 3)),
+                make_token(Minus, "-", (3, 4)),
+                make_token(Number, "2", (4, 5)),
+                make_token(RightBracket, "]", (5, 6)),
+                make_token(EOF, "", (6, 7)),
+            ],
+            &[Op::Const(1.0), Op::Const(2.0), Op::Negate, Op::MakeList(2)]
+        );
+        assert_parse(
+            vec![
+                make_token(LeftBracket, "[", (0, 1)),
+                make_token(RightBracket, "]", (1, 2)),
+                make_token(EOF, "", (2, 3)),
+            ],
+            &[Op::MakeList(0)]
+        );
+    }
+
+    #[test]
+    fn test_unclosed_list() {
+        assert_parse_error(
+            vec![
+                make_token(LeftBracket, "[", (0, 1)),
+                make_token(Number, "1", (1, 2)),
+                make_token(EOF, "", (2, 3)),
+            ],
+            ExpectRightBracketAfterElements {
+                token: make_token(EOF, "", (2, 3)),
+            },
+        );
+    }
+
+    #[test]
+    fn test_variadic_single_argument() {
+        assert_parse(
+            vec![
+                make_token(Max, "max", (0, 3)),
+                make_token(LeftParen, "(", (3, 4)),
+                make_token(Number, "1", (4, 5)),
+                make_token(RightParen, ")", (5, 6)),
+                make_token(EOF, "", (6, 7)),
+            ],
+            &[Op::Const(1.0)]
+        );
+        assert_parse_error(
+            vec![
+                make_token(Min, "min", (0, 3)),
+                make_token(LeftParen, "(", (3, 4)),
+                make_token(RightParen, ")", (4, 5)),
+                make_token(EOF, "", (5, 6)),
+            ],
+            ExpectArgumentCount {
+                token: make_token(Min, "min", (0, 3)),
+                found: 0,
+            },
+        );
+    }
+
+    #[test]
+    fn test_constants() {
+        for (token_type, lexeme) in [(Pi, "pi"), (E, "e"), (Tau, "tau"), (Phi, "phi")] {
+            assert_parse(
+                vec![
+                    make_token(token_type, lexeme, (0, lexeme.len())),
+                    make_token(EOF, "", (lexeme.len(), lexeme.len() + 1)),
+                ],
+                &[Op::Load(lexeme.to_string())],
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_with_spans() {
+        let tokens = vec![
+            make_token(TokenType::Number, "1", (0, 1)),
+            make_token(TokenType::Plus, "+", (2, 3)),
+            make_token(TokenType::Max, "max", (4, 7)),
+            make_token(TokenType::LeftParen, "(", (7, 8)),
+            make_token(TokenType::Number, "2", (8, 9)),
+            make_token(TokenType::Comma, ",", (9, 10)),
+            make_token(TokenType::Number, "3", (11, 12)),
+            make_token(TokenType::RightParen, ")", (12, 13)),
+            make_token(TokenType::EOF, "", (13, 13)),
+        ];
+        let (operations, spans) = parse_with_spans(tokens).unwrap();
+        assert_eq!(operations, vec![
+            Op::Const(1.0), Op::Const(2.0), Op::Const(3.0), Op::Max, Op::Add,
+        ]);
+        assert_eq!(spans, vec![(0, 1), (8, 9), (11, 12), (4, 13), (2, 3)]);
+    }
+
+    #[test]
+    fn test_bitwise_precedence() {
+        // 1 | 2 xor 3 & 4 << 5 + 6 == 1 | (2 xor (3 & (4 << (5 + 6))))
+        let tokens = vec![
+            make_token(TokenType::Number, "1", (0, 1)),
+            make_token(TokenType::Pipe, "|", (2, 3)),
+            make_token(TokenType::Number, "2", (4, 5)),
+            make_token(TokenType::Xor, "xor", (6, 9)),
+            make_token(TokenType::Number, "3", (10, 11)),
+            make_token(TokenType::Ampersand, "&", (12, 13)),
+            make_token(TokenType::Number, "4", (14, 15)),
+            make_token(TokenType::LessLess, "<<", (16, 18)),
+            make_token(TokenType::Number, "5", (19, 20)),
+            make_token(TokenType::Plus, "+", (21, 22)),
+            make_token(TokenType::Number, "6", (23, 24)),
+            make_token(TokenType::EOF, "", (24, 24)),
+        ];
+        assert_parse(tokens, &[
+            Op::Const(1.0), Op::Const(2.0), Op::Const(3.0), Op::Const(4.0),
+            Op::Const(5.0), Op::Const(6.0),
+            Op::Add, Op::ShiftLeft, Op::BitAnd, Op::BitXor, Op::BitOr,
+        ]);
+    }
+
+    #[test]
+    fn test_round_with_and_without_places() {
+        let tokens = vec![
+            make_token(TokenType::Round, "round", (0, 5)),
+            make_token(TokenType::Number, "2.5", (6, 9)),
+            make_token(TokenType::EOF, "", (9, 9)),
+        ];
+        assert_parse(tokens, &[Op::Const(2.5), Op::Round]);
+
+        let tokens = vec![
+            make_token(TokenType::Round, "round", (0, 5)),
+            make_token(TokenType::LeftParen, "(", (5, 6)),
+            make_token(TokenType::Number, "2.5", (6, 9)),
+            make_token(TokenType::Comma, ",", (9, 10)),
+            make_token(TokenType::Number, "1", (11, 12)),
+            make_token(TokenType::RightParen, ")", (12, 13)),
+            make_token(TokenType::EOF, "", (13, 13)),
+        ];
+        assert_parse(tokens, &[Op::Const(2.5), Op::Const(1.0), Op::RoundTo]);
+    }
+
+    #[test]
+    fn test_indexed_ans() {
+        let tokens = vec![
+            make_token(TokenType::Ans, "ans", (0, 3)),
+            make_token(TokenType::LeftParen, "(", (3, 4)),
+            make_token(TokenType::Number, "2", (4, 5)),
+            make_token(TokenType::RightParen, ")", (5, 6)),
+            make_token(TokenType::Plus, "+", (7, 8)),
+            make_token(TokenType::Ans, "ans", (9, 12)),
+            make_token(TokenType::EOF, "", (12, 12)),
+        ];
+        assert_parse(tokens, &[Op::Const(2.0), Op::AnsAt, Op::Ans, Op::Add]);
+    }
+
+    #[test]
+    fn test_parse_into_reuses_buffers() {
+        let mut tokens = vec![
+            make_token(TokenType::Number, "1", (0, 1)),
+            make_token(TokenType::Plus, "+", (2, 3)),
+            make_token(TokenType::Number, "2", (4, 5)),
+            make_token(TokenType::EOF, "", (5, 5)),
+        ];
+        let mut operations = vec![Op::Ans; 8];
+        let mut spans = vec![(0, 0); 8];
+        parse_into(&mut tokens, &mut operations, &mut spans).unwrap();
+        assert_eq!(operations, vec![Op::Const(1.0), Op::Const(2.0), Op::Add]);
+        assert_eq!(spans, vec![(0, 1), (4, 5), (2, 3)]);
+        assert!(operations.capacity() >= 8 && spans.capacity() >= 8);
+        assert_eq!(tokens.len(), 4);
+
+        tokens.truncate(2);
+        tokens.push(make_token(TokenType::EOF, "", (3, 3)));
+        assert!(parse_into(&mut tokens, &mut operations, &mut spans).is_err());
+        assert_eq!(tokens.len(), 3);
+    }
+
+    #[test]
+    fn test_assignment() {
+        let tokens = vec![
+            make_token(TokenType::Identifier, "x", (0, 1)),
+            make_token(TokenType::Equal, "=", (2, 3)),
+            make_token(TokenType::Pi, "pi", (4, 6)),
+            make_token(TokenType::Slash, "/", (7, 8)),
+            make_token(TokenType::Number, "2", (9, 10)),
+            make_token(TokenType::EOF, "", (10, 10)),
+        ];
+        assert_parse(tokens, &[
+            Op::Load("pi".to_string()), Op::Const(2.0), Op::Divide, Op::Store("x".to_string()),
+        ]);
+
+        let tokens = vec![
+            make_token(TokenType::Number, "1", (0, 1)),
+            make_token(TokenType::Equal, "=", (2, 3)),
+            make_token(TokenType::Number, "2", (4, 5)),
+            make_token(TokenType::EOF, "", (5, 5)),
+        ];
+        assert_parse_error(tokens, ExpectEndOfExpression);
+    }
+
+    #[test]
+    fn test_function_definition_and_call() {
+        let tokens = vec![
+            make_token(TokenType::Identifier, "f", (0, 1)),
+            make_token(TokenType::LeftParen, "(", (1, 2)),
+            make_token(TokenType::Identifier, "x", (2, 3)),
+            make_token(TokenType::Comma, ",", (3, 4)),
+            make_token(TokenType::Identifier, "y", (5, 6)),
+            make_token(TokenType::RightParen, ")", (6, 7)),
+            make_token(TokenType::Equal, "=", (8, 9)),
+            make_token(TokenType::Identifier, "x", (10, 11)),
+            make_token(TokenType::Star, "*", (12, 13)),
+            make_token(TokenType::Identifier, "y", (14, 15)),
+            make_token(TokenType::EOF, "", (15, 15)),
+        ];
+        let (operations, spans) = parse_with_spans(tokens).unwrap();
+        assert_eq!(operations, vec![Op::Define(Box::new(Function {
+            name: "f".to_string(),
+            params: vec!["x".to_string(), "y".to_string()],
+            body: vec![Op::Load("x".to_string()), Op::Load("y".to_string()), Op::Times],
+        }))]);
+        assert_eq!(spans, vec![(0, 15)]);
+
+        let tokens = vec![
+            make_token(TokenType::Identifier, "f", (0, 1)),
+            make_token(TokenType::LeftParen, "(", (1, 2)),
+            make_token(TokenType::Number, "2", (2, 3)),
+            make_token(TokenType::Comma, ",", (3, 4)),
+            make_token(TokenType::Number, "3", (5, 6)),
+            make_token(TokenType::RightParen, ")", (6, 7)),
+            make_token(TokenType::EOF, "", (7, 7)),
+        ];
+        assert_parse(tokens, &[Op::Const(2.0), Op::Const(3.0), Op::Call("f".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_let_in() {
+        let tokens = vec![
+            make_token(TokenType::Let, "let", (0, 3)),
+            make_token(TokenType::Identifier, "a", (4, 5)),
+            make_token(TokenType::Equal, "=", (6, 7)),
+            make_token(TokenType::Number, "3", (8, 9)),
+            make_token(TokenType::In, "in", (10, 12)),
+            make_token(TokenType::Identifier, "a", (13, 14)),
+            make_token(TokenType::Star, "*", (14, 15)),
+            make_token(TokenType::Identifier, "a", (15, 16)),
+            make_token(TokenType::EOF, "", (16, 16)),
+        ];
+        let (operations, spans) = parse_with_spans(tokens).unwrap();
+        assert_eq!(operations, vec![
+            Op::Const(3.0), Op::PushScope("a".to_string()),
+            Op::Load("a".to_string()), Op::Load("a".to_string()), Op::Times,
+            Op::PopScope,
+        ]);
+        assert_eq!(spans, vec![(8, 9), (4, 5), (13, 14), (15, 16), (14, 15), (0, 16)]);
+
+        let tokens = vec![
+            make_token(TokenType::Let, "let", (0, 3)),
+            make_token(TokenType::Identifier, "a", (4, 5)),
+            make_token(TokenType::Equal, "=", (6, 7)),
+            make_token(TokenType::Number, "3", (8, 9)),
+            make_token(TokenType::EOF, "", (9, 9)),
+        ];
+        assert_parse_error(tokens, ExpectInAfterLetValue {
+            token: make_token(TokenType::EOF, "", (9, 9)),
+        });
+    }
+
+    #[test]
+    fn test_comparison_binds_loosest() {
+        // 1 + 2 < 3 | 4
+        let tokens = vec![
+            make_token(TokenType::Number, "1", (0, 1)),
+            make_token(TokenType::Plus, "+", (2, 3)),
+            make_token(TokenType::Number, "2", (4, 5)),
+            make_token(TokenType::Less, "<", (6, 7)),
+            make_token(TokenType::Number, "3", (8, 9)),
+            make_token(TokenType::Pipe, "|", (10, 11)),
+            make_token(TokenType::Number, "4", (12, 13)),
+            make_token(TokenType::EOF, "", (13, 13)),
+        ];
+        assert_parse(tokens, &[
+            Op::Const(1.0), Op::Const(2.0), Op::Add,
+            Op::Const(3.0), Op::Const(4.0), Op::BitOr,
+            Op::Less,
+        ]);
+    }
+
+    #[test]
+    fn test_if_then_else() {
+        // if x < 0 then -x else x
+        let tokens = vec![
+            make_token(TokenType::If, "if", (0, 2)),
+            make_token(TokenType::Identifier, "x", (3, 4)),
+            make_token(TokenType::Less, "<", (5, 6)),
+            make_token(TokenType::Number, "0", (7, 8)),
+            make_token(TokenType::Then, "then", (9, 13)),
+            make_token(TokenType::Minus, "-", (14, 15)),
+            make_token(TokenType::Identifier, "x", (15, 16)),
+            make_token(TokenType::Else, "else", (17, 21)),
+            make_token(TokenType::Identifier, "x", (22, 23)),
+            make_token(TokenType::EOF, "", (23, 23)),
+        ];
+        let (operations, spans) = parse_with_spans(tokens).unwrap();
+        assert_eq!(operations, vec![
+            Op::Load("x".to_string()), Op::Const(0.0), Op::Less,
+            Op::If(Box::new(Branches {
+                then: vec![Op::Load("x".to_string()), Op::Negate],
+                otherwise: vec![Op::Load("x".to_string())],
+            })),
+        ]);
+        assert_eq!(spans, vec![(3, 4), (7, 8), (5, 6), (0, 23)]);
+
+        let tokens = vec![
+            make_token(TokenType::If, "if", (0, 2)),
+            make_token(TokenType::Number, "1", (3, 4)),
+            make_token(TokenType::Then, "then", (5, 9)),
+            make_token(TokenType::Number, "2", (10, 11)),
+            make_token(TokenType::EOF, "", (11, 11)),
+        ];
+        assert_parse_error(tokens, ExpectElseAfterBranch {
+            token: make_token(TokenType::EOF, "", (11, 11)),
+        });
+    }
+
+    #[test]
+    fn test_for_loop() {
+        // for i = 1 to 3 { s = s + i; i }
+        let tokens = vec![
+            make_token(TokenType::For, "for", (0, 3)),
+            make_token(TokenType::Identifier, "i", (4, 5)),
+            make_token(TokenType::Equal, "=", (6, 7)),
+            make_token(TokenType::Number, "1", (8, 9)),
+            make_token(TokenType::To, "to", (10, 12)),
+            make_token(TokenType::Number, "3", (13, 14)),
+            make_token(TokenType::LeftBrace, "{", (15, 16)),
+            make_token(TokenType::Identifier, "s", (17, 18)),
+            make_token(TokenType::Equal, "=", (19, 20)),
+            make_token(TokenType::Identifier, "s", (21, 22)),
+            make_token(TokenType::Plus, "+", (23, 24)),
+            make_token(TokenType::Identifier, "i", (25, 26)),
+            make_token(TokenType::Semicolon, ";", (26, 27)),
+            make_token(TokenType::Identifier, "i", (28, 29)),
+            make_token(TokenType::RightBrace, "}", (30, 31)),
+            make_token(TokenType::EOF, "", (31, 31)),
+        ];
+        let (operations, spans) = parse_with_spans(tokens).unwrap();
+        assert_eq!(operations, vec![
+            Op::Const(1.0), Op::Const(3.0),
+            Op::For(Box::new(Loop {
+                variable: "i".to_string(),
+                condition: Vec::new(),
+                body: vec![
+                    Op::Load("s".to_string()), Op::Load("i".to_string()), Op::Add,
+                    Op::Store("s".to_string()), Op::Load("i".to_string()), Op::Sequence,
+                ],
+            })),
+        ]);
+        assert_eq!(spans, vec![(8, 9), (13, 14), (0, 31)]);
+    }
+
+    #[test]
+    fn test_while_loop() {
+        // while x > 1 { x = x / 2 }
+        let tokens = vec![
+            make_token(TokenType::While, "while", (0, 5)),
+            make_token(TokenType::Identifier, "x", (6, 7)),
+            make_token(TokenType::Greater, ">", (8, 9)),
+            make_token(TokenType::Number, "1", (10, 11)),
+            make_token(TokenType::LeftBrace, "{", (12, 13)),
+            make_token(TokenType::Identifier, "x", (14, 15)),
+            make_token(TokenType::Equal, "=", (16, 17)),
+            make_token(TokenType::Identifier, "x", (18, 19)),
+            make_token(TokenType::Slash, "/", (20, 21)),
+            make_token(TokenType::Number, "2", (22, 23)),
+            make_token(TokenType::RightBrace, "}", (24, 25)),
+            make_token(TokenType::EOF, "", (25, 25)),
+        ];
+        assert_parse(tokens, &[Op::While(Box::new(Loop {
+            variable: String::new(),
+            condition: vec![Op::Load("x".to_string()), Op::Const(1.0), Op::Greater],
+            body: vec![
+                Op::Load("x".to_string()), Op::Const(2.0), Op::Divide, Op::Store("x".to_string()),
+            ],
+        }))]);
+
+        let tokens = vec![
+            make_token(TokenType::While, "while", (0, 5)),
+            make_token(TokenType::Number, "1", (6, 7)),
+            make_token(TokenType::LeftBrace, "{", (8, 9)),
+            make_token(TokenType::Number, "2", (10, 11)),
+            make_token(TokenType::EOF, "", (11, 11)),
+        ];
+        assert_parse_error(tokens, ExpectRightBraceAfterBlock {
+            token: make_token(TokenType::EOF, "", (11, 11)),
+        });
+    }
+
+    #[test]
+    fn test_solve_equation() {
+        // solve x + 2 = 7 for x
+        assert_parse(
+            vec![
+                make_token(Solve, "solve", (0, 5)),
+                make_token(Identifier, "x", (6, 7)),
+                make_token(Plus, "+", (8, 9)),
+                make_token(Number, "2", (10, 11)),
+                make_token(Equal, "=", (12, 13)),
+                make_token(Number, "7", (14, 15)),
+                make_token(For, "for", (16, 19)),
+                make_token(Identifier, "x", (20, 21)),
+                make_token(EOF, "", (21, 21)),
+            ],
+            &[Op::SolveFor(Box::new(Equation {
+                variable: "x".to_string(),
+                residual: vec![
+                    Op::Load("x".to_string()), Op::Const(2.0), Op::Add,
+                    Op::Const(7.0), Op::Subtract,
+                ],
+            }))],
+        );
+    }
 }
@@ -0,0 +1,149 @@
+/// Iterations of Durand–Kerner before giving up on further improvement.
+const MAX_ITERATIONS: usize = 1000;
+
+/// Roots with an imaginary part smaller than this, relative to their size,
+/// count as real.
+const REAL_EPS: f64 = 1e-7;
+
+/// Evaluates the polynomial with coefficients `coeffs`, highest degree
+/// first, at `x` by Horner's rule. `[1, -3, 2]` is `x^2 - 3x + 2`.
+pub fn eval(coeffs: &[f64], x: f64) -> f64 {
+    coeffs.iter().fold(0.0, |acc, c| acc * x + c)
+}
+
+fn derivative(coeffs: &[f64]) -> Vec<f64> {
+    let degree = coeffs.len().saturating_sub(1);
+    coeffs[..degree]
+        .iter()
+        .enumerate()
+        .map(|(i, c)| c * (degree - i) as f64)
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn add(self, other: Self) -> Self {
+        Complex { re: self.re + other.re, im: self.im + other.im }
+    }
+
+    fn sub(self, other: Self) -> Self {
+        Complex { re: self.re - other.re, im: self.im - other.im }
+    }
+
+    fn mul(self, other: Self) -> Self {
+        Complex {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        }
+    }
+
+    fn div(self, other: Self) -> Self {
+        let d = other.re * other.re + other.im * other.im;
+        Complex {
+            re: (self.re * other.re + self.im * other.im) / d,
+            im: (self.im * other.re - self.re * other.im) / d,
+        }
+    }
+
+    fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+}
+
+/// The real roots of a polynomial, ascending and repeated by multiplicity,
+/// or `None` if every coefficient is zero. All complex roots are found
+/// together by the Durand–Kerner method and the real ones are then
+/// refined with Newton's method.
+pub fn real_roots(coeffs: &[f64]) -> Option<Vec<f64>> {
+    let start = coeffs.iter().position(|&c| c != 0.0)?;
+    let coeffs = &coeffs[start..];
+    let degree = coeffs.len() - 1;
+    let monic: Vec<f64> = coeffs.iter().map(|c| c / coeffs[0]).collect();
+    let p = |z: Complex| {
+        monic.iter().fold(Complex { re: 0.0, im: 0.0 }, |acc, &c| {
+            acc.mul(z).add(Complex { re: c, im: 0.0 })
+        })
+    };
+
+    let seed = Complex { re: 0.4, im: 0.9 };
+    let mut roots: Vec<Complex> = Vec::with_capacity(degree);
+    let mut z = Complex { re: 1.0, im: 0.0 };
+    for _ in 0..degree {
+        roots.push(z);
+        z = z.mul(seed);
+    }
+    for _ in 0..MAX_ITERATIONS {
+        let mut change: f64 = 0.0;
+        for i in 0..degree {
+            let denominator = (0..degree)
+                .filter(|&j| j != i)
+                .fold(Complex { re: 1.0, im: 0.0 }, |acc, j| acc.mul(roots[i].sub(roots[j])));
+            let step = p(roots[i]).div(denominator);
+            if step.re.is_finite() && step.im.is_finite() {
+                roots[i] = roots[i].sub(step);
+                change = change.max(step.abs() / roots[i].abs().max(1.0));
+            }
+        }
+        if change < 1e-15 {
+            break;
+        }
+    }
+
+    let slope = derivative(coeffs);
+    let mut real: Vec<f64> = roots
+        .iter()
+        .filter(|z| z.im.abs() <= REAL_EPS * z.re.abs().max(1.0))
+        .map(|z| polish(coeffs, &slope, z.re))
+        .collect();
+    real.sort_by(|a, b| a.total_cmp(b));
+    Some(real)
+}
+
+/// A few Newton steps from `x`, kept only while they reduce the residual,
+/// which is not the case near a repeated root.
+fn polish(coeffs: &[f64], slope: &[f64], mut x: f64) -> f64 {
+    for _ in 0..5 {
+        let next = x - eval(coeffs, x) / eval(slope, x);
+        if !next.is_finite() || eval(coeffs, next).abs() >= eval(coeffs, x).abs() {
+            break;
+        }
+        x = next;
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(coeffs: &[f64], expected: &[f64]) {
+        let roots = real_roots(coeffs).unwrap();
+        assert_eq!(roots.len(), expected.len(), "{:?}", roots);
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!((root - expected).abs() < 1e-6, "{:?} != {:?}", roots, expected);
+        }
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval(&[1.0, -3.0, 2.0], 3.0), 2.0);
+        assert_eq!(eval(&[], 3.0), 0.0);
+    }
+
+    #[test]
+    fn test_real_roots() {
+        assert_roots(&[1.0, -3.0, 2.0], &[1.0, 2.0]);
+        assert_roots(&[0.0, 2.0, -1.0], &[0.5]);
+        assert_roots(&[1.0, -6.0, 11.0, -6.0], &[1.0, 2.0, 3.0]);
+        assert_roots(&[1.0, 0.0, 1.0], &[]);
+        assert_roots(&[1.0, -2.0, 1.0], &[1.0, 1.0]);
+        assert_roots(&[1.0, 0.0, 0.0, -8.0], &[2.0]);
+        assert_roots(&[5.0], &[]);
+        assert_eq!(real_roots(&[0.0, 0.0]), None);
+    }
+}
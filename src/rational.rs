@@ -0,0 +1,65 @@
+/// Up to `n` terms `[a0, a1, a2, ...]` of the continued fraction
+/// `x = a0 + 1/(a1 + 1/(a2 + ...))`, fewer if it ends. Later terms are only
+/// as good as the precision of `x` allows.
+pub fn continued_fraction(x: f64, n: usize) -> Vec<f64> {
+    let mut terms = Vec::new();
+    let mut y = x;
+    while terms.len() < n {
+        let a = y.floor();
+        terms.push(a);
+        let rest = y - a;
+        if rest == 0.0 || !rest.is_finite() {
+            break;
+        }
+        y = 1.0 / rest;
+    }
+    terms
+}
+
+/// The fraction `p / q` closest to `x` with `1 <= q <= max_denominator`,
+/// from the convergents of its continued fraction and the best
+/// semiconvergent between the last two.
+pub fn best_rational(x: f64, max_denominator: f64) -> (f64, f64) {
+    // The convergents before and at the current term.
+    let (mut p0, mut q0, mut p1, mut q1) = (0.0, 1.0, 1.0, 0.0);
+    let mut y = x;
+    loop {
+        let a = y.floor();
+        let q2 = q0 + a * q1;
+        if q2 > max_denominator {
+            break;
+        }
+        (p0, q0, p1, q1) = (p1, q1, p0 + a * p1, q2);
+        let rest = y - a;
+        if rest == 0.0 || p1 / q1 == x {
+            return (p1, q1);
+        }
+        y = 1.0 / rest;
+    }
+    let k = ((max_denominator - q0) / q1).floor();
+    let (p, q) = (p0 + k * p1, q0 + k * q1);
+    if (p / q - x).abs() < (p1 / q1 - x).abs() { (p, q) } else { (p1, q1) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_continued_fraction() {
+        assert_eq!(continued_fraction(4.25, 10), [4.0, 4.0]);
+        assert_eq!(continued_fraction(-0.5, 10), [-1.0, 2.0]);
+        assert_eq!(continued_fraction(std::f64::consts::PI, 5), [3.0, 7.0, 15.0, 1.0, 292.0]);
+        assert_eq!(continued_fraction(2f64.sqrt(), 4), [1.0, 2.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn test_best_rational() {
+        assert_eq!(best_rational(0.333333, 10_000.0), (1.0, 3.0));
+        assert_eq!(best_rational(0.1 + 0.2, 10_000.0), (3.0, 10.0));
+        assert_eq!(best_rational(std::f64::consts::PI, 100.0), (311.0, 99.0));
+        assert_eq!(best_rational(std::f64::consts::PI, 1000.0), (355.0, 113.0));
+        assert_eq!(best_rational(-1.75, 10.0), (-7.0, 4.0));
+        assert_eq!(best_rational(5.0, 1.0), (5.0, 1.0));
+    }
+}
@@ -0,0 +1,1161 @@
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::compiled::CompiledExpr;
+use crate::editor::{self, Editor};
+use crate::operation::Operation;
+use crate::token::{Span, Token};
+use crate::vm::{self, Snapshot, VirtualMachine};
+use crate::{bench, diagnostic, dual, explain, format, help, highlight, import, inspect, lexer, lint};
+use crate::{pager, rpn, script, vars};
+
+/// The state of an interactive session: the VM, the line editor and the
+/// REPL's own settings, which `:` commands change.
+pub struct Session {
+    pub vm: VirtualMachine,
+    pub editor: Editor,
+    history_path: Option<PathBuf>,
+    use_pager: bool,
+    use_lint: bool,
+    display_base: u32,
+    format: format::FormatOptions,
+    rpn: bool,
+    timing: bool,
+    /// The `:prompt` template, and the number of the next input for it.
+    prompt: String,
+    line: usize,
+    /// The lines of an input still being continued, joined by newlines.
+    pending: Option<String>,
+    /// The settings given on the command line, which `:reset` goes back to.
+    default_lint: bool,
+    default_strict: bool,
+    /// Buffers for scanning and compiling inputs, kept between inputs to
+    /// reuse their allocations.
+    tokens: Vec<Token>,
+    buffers: script::Buffers,
+    /// Where results and error messages go: standard output and standard
+    /// error, unless the session is replaying a transcript.
+    out: Box<dyn Write + Send>,
+    err: Box<dyn Write + Send>,
+    /// Loads `import`s for the whole session, so that a file imported twice
+    /// is only run once.
+    importer: import::Importer,
+    /// Whether error messages may be coloured.
+    colour: bool,
+    /// Whether someone is at the prompt to answer questions, such as the
+    /// confirmation of `:clear`; not when replaying a transcript.
+    interactive: bool,
+}
+
+const DEFAULT_PROMPT: &str = ">> ";
+
+/// What `:save` writes: the VM's state, the REPL's own settings and the
+/// input history. Files saved before the REPL settings were included load
+/// with their defaults.
+#[derive(Serialize, Deserialize)]
+struct SessionFile {
+    #[serde(flatten)]
+    vm: Snapshot,
+    #[serde(default)]
+    format: format::FormatOptions,
+    #[serde(default = "default_base")]
+    display_base: u32,
+    #[serde(default)]
+    rpn: bool,
+    #[serde(default)]
+    lint: bool,
+    #[serde(default)]
+    history: Vec<String>,
+}
+
+fn default_base() -> u32 {
+    10
+}
+
+/// A REPL command: `run` gets the text after the name and returns false
+/// if it does not understand it, in which case `usage` is shown.
+pub struct Command {
+    pub name: &'static str,
+    pub usage: &'static str,
+    run: fn(&mut Session, &str) -> io::Result<bool>,
+}
+
+const fn command(
+    name: &'static str,
+    usage: &'static str,
+    run: fn(&mut Session, &str) -> io::Result<bool>,
+) -> Command {
+    Command { name, usage, run }
+}
+
+pub const COMMANDS: &[Command] = &[
+    command(":help", ":help [name]", help_command),
+    command(":history", ":history [clear]", history_command),
+    command(":vars", ":vars", vars_command),
+    command(":clear", ":clear", clear_command),
+    command(":reset", ":reset", reset_command),
+    command(
+        ":mode",
+        ":mode [degree|radian|rpn|infix|normal|programmer [8|16|32|64]]",
+        mode_command,
+    ),
+    command(":format", ":format [plain|fixed <digits>|sci [digits]|eng [digits]|frac]", format_command),
+    command(":base", ":base 2|8|10|16", base_command),
+    command(":lint", ":lint on|off", lint_command),
+    command(":strict", ":strict on|off", strict_command),
+    command(":time", ":time on|off", time_command),
+    command(":prompt", ":prompt [default|<text>|\"<text>\"]", prompt_command),
+    command(":unsafe-override", ":unsafe-override on|off", unsafe_override_command),
+    command(":reset-builtin", ":reset-builtin pi|e|tau|phi", reset_builtin_command),
+    command(":recursion-limit", ":recursion-limit <depth>", recursion_limit_command),
+    command(":seed", ":seed <non-negative integer>", seed_command),
+    command(":save", ":save <file>", save_command),
+    command(":load", ":load <file>", load_command),
+    command(":run", ":run <file>", run_command),
+    command(":trace", ":trace <expression>", trace_command),
+    command(":explain", ":explain <expression>", explain_command),
+    command(":ast", ":ast <expression>", ast_command),
+    command(":ops", ":ops <expression>", ops_command),
+    command(":derive", ":derive <expression>", derive_command),
+    command(":profile", ":profile <expression>", profile_command),
+    command(":bench", ":bench <runs> <expression>", bench_command),
+];
+
+/// Runs the startup file, unless `use_rc` is false, and then the
+/// interactive prompt until `q`, `exit` or the end of input.
+pub fn run(use_pager: bool, use_lint: bool, strict: bool, use_rc: bool) -> io::Result<()> {
+    let mut session = Session::new(use_pager, use_lint, strict);
+    let reading = Arc::new(AtomicBool::new(false));
+    let shown_prompt = Arc::new(Mutex::new(String::new()));
+    install_interrupt_handler(&session.vm, Arc::clone(&reading), Arc::clone(&shown_prompt));
+    if use_rc && let Some(path) = rc_path() {
+        session.run_rc(&path)?;
+    }
+
+    loop {
+        let words = lexer::KEYWORDS.iter().copied().chain(COMMANDS.iter().map(|c| c.name));
+        let words = words.map(String::from).chain(session.vm.names()).collect();
+        session.editor.set_completions(words);
+        let prompt = if session.is_continuing() { ".. ".to_string() } else { session.prompt() };
+        *shown_prompt.lock().unwrap() = prompt.clone();
+        reading.store(true, Ordering::Relaxed);
+        let line = session.editor.read_line(&prompt)?;
+        reading.store(false, Ordering::Relaxed);
+        let Some(line) = line else {
+            // An unfinished input still runs, as a blank line would make it.
+            if session.is_continuing() {
+                session.enter("")?;
+            }
+            println!("\nExiting...");
+            break;
+        };
+        if !session.enter(&line)? {
+            break;
+        }
+    }
+    if let Some(path) = &session.history_path
+        && let Err(e) = session.editor.save_history(path)
+    {
+        eprintln!("Could not save history to {}: {}", path.display(), e);
+    }
+    Ok(())
+}
+
+/// The startup file: `$XDG_CONFIG_HOME/calc/init.calc` if it exists,
+/// otherwise `~/.calcrc` if that does.
+pub fn rc_path() -> Option<PathBuf> {
+    let non_empty = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    let config = non_empty("XDG_CONFIG_HOME").map(|config| PathBuf::from(config).join("calc/init.calc"));
+    let home = non_empty("HOME").map(|home| PathBuf::from(home).join(".calcrc"));
+    config.into_iter().chain(home).find(|path| path.is_file())
+}
+
+impl Session {
+    pub fn new(use_pager: bool, use_lint: bool, strict: bool) -> Self {
+        let mut session = Session::with_output(
+            VirtualMachine::builder().strict(strict).build(),
+            Box::new(io::stdout()),
+            Box::new(io::stderr()),
+        );
+        let colour = std::env::var_os("NO_COLOR").is_none();
+        if colour {
+            session.editor.set_highlighter(highlight::highlight);
+        }
+        session.colour = colour && io::stderr().is_terminal();
+        session.interactive = true;
+        session.history_path = editor::history_path();
+        if let Some(path) = &session.history_path
+            && let Err(e) = session.editor.load_history(path)
+        {
+            eprintln!("Could not load history from {}: {}", path.display(), e);
+        }
+        session.use_pager = use_pager;
+        session.use_lint = use_lint;
+        session.default_lint = use_lint;
+        session.default_strict = strict;
+        session
+    }
+
+    /// A session that writes everything it would print, including what
+    /// `print` writes and error messages, to `output`, for replaying a
+    /// transcript. It has no history file, and never stops to ask for
+    /// confirmation.
+    pub fn capturing<W: Write + Clone + Send + 'static>(output: W) -> Self {
+        let mut vm = VirtualMachine::new();
+        vm.output = Box::new(output.clone());
+        Session::with_output(vm, Box::new(output.clone()), Box::new(output))
+    }
+
+    fn with_output(vm: VirtualMachine, out: Box<dyn Write + Send>, err: Box<dyn Write + Send>) -> Self {
+        Session {
+            vm,
+            editor: Editor::new(),
+            history_path: None,
+            use_pager: false,
+            use_lint: false,
+            display_base: 10,
+            format: format::FormatOptions::default(),
+            rpn: false,
+            timing: false,
+            prompt: DEFAULT_PROMPT.to_string(),
+            line: 1,
+            pending: None,
+            default_lint: false,
+            default_strict: false,
+            tokens: Vec::new(),
+            buffers: script::Buffers::default(),
+            out,
+            err,
+            importer: import::Importer::new(vec![PathBuf::from(".")]),
+            colour: false,
+            interactive: false,
+        }
+    }
+
+    /// Takes one line as typed at the prompt. A line that leaves the input
+    /// unfinished, like `(1 +`, is held until a later line completes it, or
+    /// a blank one gives up and runs it as it is; see
+    /// [`is_continuing`](Self::is_continuing). A complete input has `!!` and
+    /// `!n` expanded from the history, showing the expansion, is added to
+    /// the history and is run. Returns false for `q` and `exit`.
+    pub fn enter(&mut self, line: &str) -> io::Result<bool> {
+        let input = match self.pending.take() {
+            Some(pending) if line.trim().is_empty() => pending,
+            Some(pending) => pending + "\n" + line,
+            None => line.to_string(),
+        };
+        // RPN input always ends with an operator, so it is never continued.
+        if !line.trim().is_empty()
+            && !self.rpn
+            && !input.trim_start().starts_with(':')
+            && script::is_incomplete(&input)
+        {
+            self.pending = Some(input);
+            return Ok(true);
+        }
+
+        let input = match self.editor.expand_history(&input) {
+            Some(expanded) if expanded != input => {
+                writeln!(self.out, "{}", expanded)?;
+                expanded
+            }
+            Some(input) => input,
+            None => {
+                writeln!(self.err, "No such line in the history; ':history' lists them")?;
+                return Ok(true);
+            }
+        };
+        self.editor.add_history(&input.replace('\n', " "));
+        self.run_input(&input)
+    }
+
+    /// Whether the last line entered left the input unfinished, so that the
+    /// next one continues it.
+    pub fn is_continuing(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Runs one complete input as typed at the prompt: a `:` command or
+    /// statements. Returns false for `q` and `exit`.
+    pub fn run_input(&mut self, input: &str) -> io::Result<bool> {
+        match input.trim() {
+            "q" | "exit" => return Ok(false),
+            "" => return Ok(true),
+            line if line.starts_with(':') => self.dispatch(line)?,
+            _ => self.evaluate(input)?,
+        }
+        self.line += 1;
+        Ok(true)
+    }
+
+    /// Runs the `:` command on `line`, showing its usage if the arguments
+    /// are wrong.
+    pub fn dispatch(&mut self, line: &str) -> io::Result<()> {
+        let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let Some(command) = COMMANDS.iter().find(|command| command.name == name) else {
+            let names: Vec<&str> = COMMANDS.iter().map(|command| command.name).collect();
+            writeln!(self.err, "Unknown command '{}'; the commands are {}", name, names.join(" "))?;
+            return Ok(());
+        };
+        if !(command.run)(self, args.trim())? {
+            writeln!(self.err, "Usage: {}", command.usage)?;
+        }
+        Ok(())
+    }
+
+    /// Runs a startup file line by line before the first prompt: `:` lines
+    /// as commands and the rest as statements, without showing their
+    /// results. An error is reported with its line and the rest still runs.
+    pub fn run_rc(&mut self, path: &Path) -> io::Result<()> {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                writeln!(self.err, "Could not read {}: {}", path.display(), e)?;
+                return Ok(());
+            }
+        };
+        self.importer.set_directory(path.parent().map(Path::to_path_buf));
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.starts_with(':') {
+                self.dispatch(line)?;
+                continue;
+            }
+            let result = script::for_each_statement(line, script::EmptyStatements::Skip, |buffers| {
+                if let Some(name) = script::import_name(&buffers.statement) {
+                    return self
+                        .importer
+                        .import(&mut self.vm, name)
+                        .map_err(script::StatementError::Import);
+                }
+                self.compile_into(buffers)?;
+                self.vm
+                    .interpret(&buffers.operations)
+                    .map_err(|e| script::StatementError::Runtime(e.locate(&buffers.spans)))?;
+                Ok(())
+            });
+            if let Err(e) = result {
+                writeln!(self.err, "{}: line {}: {}", path.display(), i + 1, e.error)?;
+            }
+        }
+        self.importer.set_directory(None);
+        Ok(())
+    }
+
+    /// Evaluates each statement of `input` and prints its result, stopping
+    /// at the first error. With `:time on`, the time spent scanning,
+    /// compiling and running all of them follows.
+    pub fn evaluate(&mut self, input: &str) -> io::Result<()> {
+        // The buffers are lent out for the input and then put back.
+        let mut tokens = std::mem::take(&mut self.tokens);
+        let mut buffers = std::mem::take(&mut self.buffers);
+        let result = self.evaluate_in(input, &mut tokens, &mut buffers);
+        self.tokens = tokens;
+        self.buffers = buffers;
+        result
+    }
+
+    fn evaluate_in(
+        &mut self,
+        input: &str,
+        tokens: &mut Vec<Token>,
+        buffers: &mut script::Buffers,
+    ) -> io::Result<()> {
+        let start = Instant::now();
+        if let Err(e) = lexer::scan_into(input, tokens) {
+            self.report(input, &script::StatementError::Lex(e))?;
+            return Ok(());
+        }
+        let lex = start.elapsed();
+        let (mut parse, mut eval) = (Duration::ZERO, Duration::ZERO);
+        let mut compiled_any = false;
+
+        let mut tokens = tokens.drain(..);
+        while script::next_statement(&mut tokens, &mut buffers.statement) {
+            if script::is_empty(&buffers.statement) {
+                continue;
+            }
+            if let Some(name) = script::import_name(&buffers.statement) {
+                if let Err(e) = self.importer.import(&mut self.vm, name) {
+                    writeln!(self.err, "{}", e)?;
+                    break;
+                }
+                continue;
+            }
+            let start = Instant::now();
+            let compiled = self.compile_into(buffers);
+            parse += start.elapsed();
+            if let Err(e) = compiled {
+                self.report(input, &e)?;
+                break;
+            }
+            compiled_any = true;
+            if self.use_lint {
+                for warning in lint::lint(&buffers.operations) {
+                    writeln!(self.err, "Warning: {}", warning)?;
+                }
+            }
+            let start = Instant::now();
+            let result = self.vm.interpret(&buffers.operations);
+            eval += start.elapsed();
+            match result {
+                Ok(output) => {
+                    let text = self.render(&output);
+                    self.print(&text)?
+                }
+                Err(e) => {
+                    writeln!(self.err, "{}", e.locate(&buffers.spans))?;
+                    break;
+                }
+            }
+        }
+        if self.timing && compiled_any {
+            writeln!(
+                self.out,
+                "Time: lex {} µs, parse {} µs, eval {} µs",
+                lex.as_micros(),
+                parse.as_micros(),
+                eval.as_micros()
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the session's modes, answers, variables, functions and
+    /// history to `path` as JSON.
+    fn save(&self, path: &str) -> io::Result<()> {
+        let file = SessionFile {
+            vm: self.vm.snapshot(),
+            format: self.format.clone(),
+            display_base: self.display_base,
+            rpn: self.rpn,
+            lint: self.use_lint,
+            history: self.editor.history().to_vec(),
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&file)?)
+    }
+
+    /// Restores a session saved with `:save`, replacing the current one.
+    fn load(&mut self, path: &str) -> io::Result<()> {
+        let json = std::fs::read_to_string(path)?;
+        let file: SessionFile = serde_json::from_str(&json)?;
+        self.vm.restore(file.vm);
+        self.format = file.format;
+        self.display_base = file.display_base;
+        self.rpn = file.rpn;
+        self.use_lint = file.lint;
+        self.editor.clear_history();
+        for line in &file.history {
+            self.editor.add_history(line);
+        }
+        Ok(())
+    }
+
+    /// Compiles a statement in the current notation, infix or RPN.
+    fn compile(
+        &self,
+        statement: Vec<Token>,
+    ) -> Result<(Vec<Operation>, Vec<Span>), script::StatementError> {
+        let mut buffers = script::Buffers { statement, ..script::Buffers::default() };
+        self.compile_into(&mut buffers)?;
+        Ok((buffers.operations, buffers.spans))
+    }
+
+    /// Like [`compile`](Self::compile), for the statement in `buffers`.
+    fn compile_into(&self, buffers: &mut script::Buffers) -> Result<(), script::StatementError> {
+        if self.rpn {
+            let statement = std::mem::take(&mut buffers.statement);
+            (buffers.operations, buffers.spans) = rpn::compile(statement)?;
+            Ok(())
+        } else {
+            script::continue_from_ans(&mut buffers.statement);
+            buffers.compile()
+        }
+    }
+
+    /// Prints a result, through the pager if it is on.
+    fn print(&mut self, text: &str) -> io::Result<()> {
+        if self.use_pager {
+            pager::print(text, true)
+        } else {
+            writeln!(self.out, "{}", text)
+        }
+    }
+
+    /// Prints an error in `input`, reprinting the line it is on with the
+    /// bad part underlined when the error has a span.
+    fn report(&mut self, input: &str, error: &script::StatementError) -> io::Result<()> {
+        match error.span() {
+            Some(span) => {
+                let caret = diagnostic::caret(input, span, &error.to_string(), self.colour);
+                writeln!(self.err, "{}", caret)
+            }
+            None => writeln!(self.err, "{}", error),
+        }
+    }
+
+    /// Formats a result in the `:format` notation, adding the word's bit
+    /// pattern in programmer mode or, for integers, the value in the `:base`
+    /// radix.
+    fn render(&self, output: &vm::InterpretOutput) -> String {
+        let text = format::format_output(output.result(), &self.format);
+        let Some(x) = output.result().as_number() else {
+            return text;
+        };
+        if let Some(bits) = self.vm.word_size {
+            format!("{} ({})", text, format::format_word(x as i64, bits))
+        } else if self.display_base != 10 && x.fract() == 0.0 && x.abs() < i64::MAX as f64 {
+            format!("{} ({})", text, format::format_radix(x as i64, self.display_base))
+        } else {
+            text
+        }
+    }
+
+    /// The prompt with the placeholders of its `:prompt` template filled
+    /// in: `{angle}` as `rad` or `deg`, `{line}` as the number of the input
+    /// and `{ans}` as `*` once there is a previous answer.
+    fn prompt(&self) -> String {
+        let angle = if self.vm.use_radians { "rad" } else { "deg" };
+        let ans = if self.vm.get_prev_ans().is_ok() { "*" } else { "" };
+        self.prompt
+            .replace("{angle}", angle)
+            .replace("{line}", &self.line.to_string())
+            .replace("{ans}", ans)
+    }
+
+    /// The current settings, one per line, as `:mode` shows them.
+    fn settings(&self) -> String {
+        let on_off = |on| if on { "on" } else { "off" };
+        let arithmetic = match self.vm.word_size {
+            Some(bits) => format!("programmer, {}-bit", bits),
+            None => "normal".to_string(),
+        };
+        [
+            ("angle", if self.vm.use_radians { "radian" } else { "degree" }.to_string()),
+            ("notation", if self.rpn { "rpn" } else { "infix" }.to_string()),
+            ("arithmetic", arithmetic),
+            ("format", format_name(&self.format)),
+            ("base", self.display_base.to_string()),
+            ("lint", on_off(self.use_lint).to_string()),
+            ("strict", on_off(self.vm.strict).to_string()),
+            ("time", on_off(self.timing).to_string()),
+        ]
+        .iter()
+        .map(|(name, value)| format!("{:<12}{}", name, value))
+        .collect::<Vec<_>>()
+        .join("\n")
+    }
+}
+
+fn help_command(session: &mut Session, name: &str) -> io::Result<bool> {
+    if name.is_empty() {
+        session.print(&help::index())?;
+    } else {
+        match help::describe(name) {
+            Some(text) => session.print(&text)?,
+            None => writeln!(session.err, "No builtin named '{}'; ':help' lists them", name)?,
+        }
+    }
+    Ok(true)
+}
+
+fn history_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    match args {
+        "" => {
+            let history = session.editor.history();
+            let width = history.len().to_string().len();
+            let lines: Vec<String> = history
+                .iter()
+                .enumerate()
+                .map(|(i, line)| format!("{:>w$}  {}", i + 1, line, w = width))
+                .collect();
+            session.print(&lines.join("\n"))?;
+        }
+        "clear" => {
+            session.editor.clear_history();
+            if let Some(path) = &session.history_path
+                && let Err(e) = session.editor.save_history(path)
+            {
+                writeln!(session.err, "Could not clear {}: {}", path.display(), e)?;
+            }
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn vars_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    if !args.is_empty() {
+        return Ok(false);
+    }
+    session.print(&vars::list(&session.vm))?;
+    Ok(true)
+}
+
+fn clear_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    if !args.is_empty() {
+        return Ok(false);
+    }
+    // Without anyone to ask, as when replaying, there is no confirmation.
+    if session.interactive {
+        let question = "Clear all variables, functions and previous answers? [y/N] ";
+        let answer = session.editor.read_line(question)?;
+        if !answer.is_some_and(|answer| matches!(answer.trim(), "y" | "Y" | "yes")) {
+            return Ok(true);
+        }
+    }
+    session.vm.clear();
+    writeln!(session.out, "Cleared")?;
+    Ok(true)
+}
+
+fn reset_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    if !args.is_empty() {
+        return Ok(false);
+    }
+    session.vm.reset_settings();
+    session.vm.strict = session.default_strict;
+    session.use_lint = session.default_lint;
+    session.display_base = 10;
+    session.format = format::FormatOptions::default();
+    session.rpn = false;
+    session.timing = false;
+    writeln!(session.out, "Settings restored to their defaults")?;
+    Ok(true)
+}
+
+fn mode_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    let args: Vec<&str> = args.split_whitespace().collect();
+    match args[..] {
+        [] => {
+            writeln!(session.out, "{}", session.settings())?;
+            return Ok(true);
+        }
+        ["degree"] => session.vm.use_radians = false,
+        ["radian"] => session.vm.use_radians = true,
+        ["rpn"] => session.rpn = true,
+        ["infix"] => session.rpn = false,
+        ["normal"] => session.vm.word_size = None,
+        ["programmer"] => session.vm.word_size = Some(64),
+        ["programmer", bits] if matches!(bits, "8" | "16" | "32" | "64") => {
+            session.vm.word_size = bits.parse().ok();
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// The most digits `:format` shows after the point.
+const MAX_DIGITS: usize = 20;
+
+/// The `:format` setting as it was given, e.g. `fixed 2`.
+fn format_name(options: &format::FormatOptions) -> String {
+    match (options.notation, options.precision) {
+        (format::Notation::Plain, Some(digits)) => format!("fixed {}", digits),
+        (notation, Some(digits)) => format!("{} {}", notation, digits),
+        (notation, None) => notation.to_string(),
+    }
+}
+
+fn format_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    use format::Notation::*;
+    let args: Vec<&str> = args.split_whitespace().collect();
+    let (notation, digits) = match args[..] {
+        [] => {
+            writeln!(session.out, "{}", format_name(&session.format))?;
+            return Ok(true);
+        }
+        ["plain"] => (Plain, None),
+        ["fixed", digits] => (Plain, Some(digits)),
+        ["sci"] => (Scientific, None),
+        ["sci", digits] => (Scientific, Some(digits)),
+        ["eng"] => (Engineering, None),
+        ["eng", digits] => (Engineering, Some(digits)),
+        ["frac"] => (Fraction, None),
+        _ => return Ok(false),
+    };
+    let precision = match digits.map(str::parse::<usize>) {
+        Some(Ok(digits)) if digits <= MAX_DIGITS => Some(digits),
+        Some(_) => return Ok(false),
+        None => None,
+    };
+    session.format.notation = notation;
+    session.format.precision = precision;
+    Ok(true)
+}
+
+fn base_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    match args {
+        "2" | "8" | "10" | "16" => session.display_base = args.parse().unwrap(),
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Parses the `on|off` argument of a switch.
+fn switch(args: &str) -> Option<bool> {
+    match args {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn lint_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    let Some(on) = switch(args) else {
+        return Ok(false);
+    };
+    session.use_lint = on;
+    Ok(true)
+}
+
+fn strict_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    let Some(on) = switch(args) else {
+        return Ok(false);
+    };
+    session.vm.strict = on;
+    Ok(true)
+}
+
+fn time_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    let Some(on) = switch(args) else {
+        return Ok(false);
+    };
+    session.timing = on;
+    Ok(true)
+}
+
+/// Sets the prompt template. Quotes keep any spaces at its ends, which
+/// are otherwise dropped.
+fn prompt_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    match args {
+        "" => writeln!(session.out, "\"{}\"", session.prompt)?,
+        "default" => session.prompt = DEFAULT_PROMPT.to_string(),
+        _ => {
+            let text = args
+                .strip_prefix('"')
+                .and_then(|args| args.strip_suffix('"'))
+                .unwrap_or(args);
+            session.prompt = text.to_string();
+        }
+    }
+    Ok(true)
+}
+
+fn unsafe_override_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    let Some(on) = switch(args) else {
+        return Ok(false);
+    };
+    session.vm.allow_builtin_override = on;
+    Ok(true)
+}
+
+fn reset_builtin_command(session: &mut Session, name: &str) -> io::Result<bool> {
+    Ok(session.vm.reset_builtin(name))
+}
+
+fn recursion_limit_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    let Ok(depth) = args.parse::<usize>() else {
+        return Ok(false);
+    };
+    if (1..=vm::MAX_CALL_DEPTH).contains(&depth) {
+        session.vm.limits.max_call_depth = depth;
+    } else {
+        writeln!(session.err, "The recursion limit must be from 1 to {}", vm::MAX_CALL_DEPTH)?;
+    }
+    Ok(true)
+}
+
+fn seed_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    let Ok(seed) = args.parse::<u64>() else {
+        return Ok(false);
+    };
+    session.vm.seed(seed);
+    Ok(true)
+}
+
+fn save_command(session: &mut Session, path: &str) -> io::Result<bool> {
+    if path.is_empty() {
+        return Ok(false);
+    }
+    if let Err(e) = session.save(path) {
+        writeln!(session.err, "{}", e)?;
+    }
+    Ok(true)
+}
+
+fn load_command(session: &mut Session, path: &str) -> io::Result<bool> {
+    if path.is_empty() {
+        return Ok(false);
+    }
+    if let Err(e) = session.load(path) {
+        writeln!(session.err, "{}", e)?;
+    }
+    Ok(true)
+}
+
+fn run_command(session: &mut Session, path: &str) -> io::Result<bool> {
+    if path.is_empty() {
+        return Ok(false);
+    }
+    if let Err(e) = run_file(session, path) {
+        writeln!(session.err, "{}", e)?;
+    }
+    Ok(true)
+}
+
+fn trace_command(session: &mut Session, source: &str) -> io::Result<bool> {
+    if source.is_empty() {
+        return Ok(false);
+    }
+    trace(session, source)?;
+    Ok(true)
+}
+
+fn explain_command(session: &mut Session, source: &str) -> io::Result<bool> {
+    if source.is_empty() {
+        return Ok(false);
+    }
+    match explain::explain(&mut session.vm, source, &session.format) {
+        Ok(text) => session.print(&text)?,
+        Err(e) => writeln!(session.err, "{}", e)?,
+    }
+    Ok(true)
+}
+
+fn ast_command(session: &mut Session, source: &str) -> io::Result<bool> {
+    if source.is_empty() {
+        return Ok(false);
+    }
+    let compiled = lexer::scan(source)
+        .map_err(script::StatementError::Lex)
+        .and_then(|tokens| session.compile(tokens));
+    match compiled {
+        Ok((operations, _)) => session.print(&inspect::ast(&operations))?,
+        Err(e) => session.report(source, &e)?,
+    }
+    Ok(true)
+}
+
+fn ops_command(session: &mut Session, source: &str) -> io::Result<bool> {
+    if source.is_empty() {
+        return Ok(false);
+    }
+    let compiled = lexer::scan(source)
+        .map_err(script::StatementError::Lex)
+        .and_then(|tokens| session.compile(tokens));
+    match compiled {
+        Ok((operations, spans)) => {
+            session.print(&inspect::ops(&operations, &spans, source))?
+        }
+        Err(e) => session.report(source, &e)?,
+    }
+    Ok(true)
+}
+
+fn derive_command(session: &mut Session, source: &str) -> io::Result<bool> {
+    if source.is_empty() {
+        return Ok(false);
+    }
+    derive(session, source)?;
+    Ok(true)
+}
+
+fn profile_command(session: &mut Session, source: &str) -> io::Result<bool> {
+    if source.is_empty() {
+        return Ok(false);
+    }
+    profile(session, source)?;
+    Ok(true)
+}
+
+fn bench_command(session: &mut Session, args: &str) -> io::Result<bool> {
+    let Some((runs, source)) = args.split_once(char::is_whitespace) else {
+        return Ok(false);
+    };
+    let runs = match runs.parse::<usize>() {
+        Ok(runs) if runs > 0 => runs,
+        _ => return Ok(false),
+    };
+    let source = source.trim();
+    let compiled = lexer::scan(source)
+        .map_err(script::StatementError::Lex)
+        .and_then(|tokens| session.compile(tokens));
+    let (operations, spans) = match compiled {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            session.report(source, &e)?;
+            return Ok(true);
+        }
+    };
+    let expr = CompiledExpr::from_operations(operations);
+    match bench::bench(&mut session.vm, &expr, runs) {
+        Ok(stats) => writeln!(session.out, "{}", stats)?,
+        Err(e) => writeln!(session.err, "{}", e.locate(&spans))?,
+    }
+    Ok(true)
+}
+
+/// Makes Ctrl-C cancel rather than exit. At the prompt the terminal has
+/// already discarded the pending line, so a fresh `prompt` is shown; during
+/// evaluation the VM is asked to stop. Ctrl-D still exits.
+fn install_interrupt_handler(
+    vm: &VirtualMachine,
+    reading: Arc<AtomicBool>,
+    prompt: Arc<Mutex<String>>,
+) {
+    let interrupt = vm.interrupt_handle();
+    let result = ctrlc::set_handler(move || {
+        if reading.load(Ordering::Relaxed) {
+            print!("\n{}", prompt.lock().unwrap());
+            let _ = io::stdout().flush();
+        } else {
+            interrupt.store(true, Ordering::Relaxed);
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("Could not install Ctrl-C handler: {}", e);
+    }
+}
+
+/// Evaluates `source` and its exact derivative with respect to `ans`, at
+/// the previous answer.
+fn derive(session: &mut Session, source: &str) -> io::Result<()> {
+    let x = match session.vm.get_prev_ans().map(|ans| ans.as_number()) {
+        Ok(Some(x)) => x,
+        Ok(None) => {
+            writeln!(session.err, "{}", vm::RuntimeError::TypeError)?;
+            return Ok(());
+        }
+        Err(e) => {
+            writeln!(session.err, "{}", e)?;
+            return Ok(());
+        }
+    };
+
+    let operations = lexer::scan(source)
+        .map_err(script::StatementError::Lex)
+        .and_then(script::compile);
+    let result = operations.and_then(|ops| {
+        dual::evaluate(&mut session.vm, &ops, x).map_err(script::StatementError::Runtime)
+    });
+    match result {
+        Ok(result) => {
+            writeln!(session.out, "f({}) = {}", x, result.re)?;
+            writeln!(session.out, "f'({}) = {}", x, result.eps)?;
+        }
+        Err(e) => writeln!(session.err, "{}", e)?,
+    }
+    Ok(())
+}
+
+/// Runs a script file as one unit and prints its final value. If any
+/// statement fails, nothing the file assigned or defined is kept.
+fn run_file(session: &mut Session, path: &str) -> io::Result<()> {
+    let source = std::fs::read_to_string(path)?;
+    let search_path = Path::new(path).parent().map(Path::to_path_buf).into_iter().collect();
+    match session.vm.run_script(&source, search_path) {
+        Ok(Some(value)) => session.print(&format::format_output(&value, &session.format)),
+        Ok(None) => Ok(()),
+        Err(e) => {
+            writeln!(session.err, "{}: {}", path, e)
+        }
+    }
+}
+
+/// Evaluates `source` with tracing on and prints each operation with the
+/// stack after it.
+fn trace(session: &mut Session, source: &str) -> io::Result<()> {
+    let operations = lexer::scan(source)
+        .map_err(script::StatementError::Lex)
+        .and_then(script::compile_with_spans);
+    let (operations, spans) = match operations {
+        Ok(compiled) => compiled,
+        Err(e) => {
+            writeln!(session.err, "{}", e)?;
+            return Ok(());
+        }
+    };
+
+    session.vm.tracing = true;
+    let result = session.vm.interpret(&operations);
+    session.vm.tracing = false;
+
+    let width = operations.iter().map(|op| format!("{:?}", op).len()).max().unwrap_or(0);
+    for (i, step) in session.vm.trace().iter().enumerate() {
+        let stack: Vec<String> = step.stack.iter().map(|v| v.to_string()).collect();
+        let operation = format!("{:?}", step.operation);
+        writeln!(session.out, "{:>3}  {:<width$}  [{}]", i + 1, operation, stack.join(", "), width = width)?;
+    }
+    match result {
+        Ok(output) => writeln!(session.out, "{}", format::format_output(output.result(), &session.format))?,
+        Err(e) => writeln!(session.err, "{}", e.locate(&spans))?,
+    }
+    Ok(())
+}
+
+/// Evaluates `source` and prints how many times each opcode ran and the
+/// time spent in it.
+#[cfg(feature = "profile")]
+fn profile(session: &mut Session, source: &str) -> io::Result<()> {
+    let operations = lexer::scan(source)
+        .map_err(script::StatementError::Lex)
+        .and_then(script::compile);
+    let operations = match operations {
+        Ok(operations) => operations,
+        Err(e) => {
+            writeln!(session.err, "{}", e)?;
+            return Ok(());
+        }
+    };
+
+    session.vm.reset_profile();
+    match session.vm.interpret(&operations) {
+        Ok(output) => writeln!(session.out, "{}", format::format_output(output.result(), &session.format))?,
+        Err(e) => writeln!(session.err, "{}", e)?,
+    }
+    let profile = session.vm.profile();
+    let total: f64 = profile.iter().map(|(_, stats)| stats.time.as_secs_f64()).sum();
+    writeln!(session.out, "{:>16} {:>8} {:>12} {:>6}", "operation", "count", "time", "time%")?;
+    for (name, stats) in profile {
+        let share = 100.0 * stats.time.as_secs_f64() / total.max(f64::MIN_POSITIVE);
+        writeln!(
+            session.out,
+            "{:>16} {:>8} {:>12} {:>5.1}%",
+            name, stats.count, format!("{:.1?}", stats.time), share
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "profile"))]
+fn profile(session: &mut Session, _source: &str) -> io::Result<()> {
+    writeln!(session.err, "Profiling is disabled; rebuild with `--features profile`.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_command() {
+        let mut session = Session::new(false, false, false);
+        session.dispatch(":mode degree").unwrap();
+        session.dispatch(":mode rpn").unwrap();
+        session.dispatch(":mode programmer 16").unwrap();
+        session.dispatch(":time on").unwrap();
+        assert!(!session.vm.use_radians);
+        assert!(session.rpn);
+        assert_eq!(session.vm.word_size, Some(16));
+        assert!(session.settings().contains("programmer, 16-bit"));
+
+        assert!(!mode_command(&mut session, "programmer 12").unwrap());
+        assert_eq!(session.vm.word_size, Some(16));
+        session.dispatch(":reset").unwrap();
+        assert!(!session.rpn);
+        assert!(!session.timing);
+        assert_eq!(session.vm.word_size, None);
+    }
+
+    #[test]
+    fn test_commands_are_distinct() {
+        for (i, command) in COMMANDS.iter().enumerate() {
+            assert!(command.name.starts_with(':'));
+            assert!(command.usage.starts_with(command.name));
+            assert!(COMMANDS[i + 1..].iter().all(|other| other.name != command.name));
+        }
+    }
+
+    #[test]
+    fn test_format_command() {
+        let mut session = Session::new(false, false, false);
+        session.dispatch(":format fixed 3").unwrap();
+        assert_eq!(format_name(&session.format), "fixed 3");
+        let output = session.vm.interpret(&[crate::operation::Operation::Const(0.5)]).unwrap();
+        assert_eq!(session.render(&output), "Output: 0.500");
+
+        session.dispatch(":format frac").unwrap();
+        assert_eq!(session.render(&output), "Output: 1/2");
+        assert!(!format_command(&mut session, "fixed 99").unwrap());
+        assert!(!format_command(&mut session, "frac 2").unwrap());
+        assert_eq!(format_name(&session.format), "frac");
+    }
+
+    #[test]
+    fn test_save_and_load_session() {
+        let path = std::env::temp_dir().join(format!("calc-session-{}.calc", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut session = Session::new(false, false, false);
+        session.editor.clear_history();
+        session.evaluate("sq(x) = x^2; y = sq(3)").unwrap();
+        session.editor.add_history("y = sq(3)");
+        session.dispatch(":mode degree").unwrap();
+        session.dispatch(":format fixed 2").unwrap();
+        session.save(path).unwrap();
+
+        let mut restored = Session::new(false, false, false);
+        restored.load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(!restored.vm.use_radians);
+        assert_eq!(format_name(&restored.format), "fixed 2");
+        assert_eq!(restored.editor.history(), ["y = sq(3)"]);
+        let call = [crate::operation::Operation::Load("y".to_string())];
+        assert_eq!(restored.vm.interpret(&call).unwrap().result().as_number(), Some(9.0));
+        assert_eq!(restored.vm.functions()[0].name, "sq");
+    }
+
+    #[test]
+    fn test_run_rc() {
+        let path = std::env::temp_dir().join(format!("calc-rc-{}.calc", std::process::id()));
+        let rc = "# defaults\n:mode degree\ng = 9.81\nhalf(x) = x / 2\nnope +\n:format frac\n";
+        std::fs::write(&path, rc).unwrap();
+        let mut session = Session::new(false, false, false);
+        session.run_rc(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!session.vm.use_radians);
+        assert_eq!(session.format.notation, format::Notation::Fraction);
+        let call = [Operation::Load("g".to_string()), Operation::Call("half".to_string(), 1)];
+        assert_eq!(session.vm.interpret(&call).unwrap().result().as_number(), Some(4.905));
+    }
+
+    #[test]
+    fn test_prompt() {
+        let mut session = Session::new(false, false, false);
+        assert_eq!(session.prompt(), ">> ");
+        session.dispatch(":prompt \"{angle}:{line}{ans}> \"").unwrap();
+        assert_eq!(session.prompt(), "rad:1> ");
+
+        session.dispatch(":mode degree").unwrap();
+        session.line = 2;
+        session.vm.interpret(&[Operation::Const(1.0)]).unwrap();
+        assert_eq!(session.prompt(), "deg:2*> ");
+
+        session.dispatch(":prompt calc>").unwrap();
+        assert_eq!(session.prompt(), "calc>");
+        session.dispatch(":prompt default").unwrap();
+        assert_eq!(session.prompt(), DEFAULT_PROMPT);
+    }
+
+    #[test]
+    fn test_imports_once_per_session() {
+        let dir = std::env::temp_dir().join(format!("calc-rc-import-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.calc"), "k = 1\n").unwrap();
+        std::fs::write(dir.join("init.calc"), "import \"lib.calc\"\n").unwrap();
+        let mut session = Session::capturing(Vec::new());
+        session.run_rc(&dir.join("init.calc")).unwrap();
+        let k = [Operation::Load("k".to_string())];
+        assert_eq!(session.vm.interpret(&k).unwrap().result().as_number(), Some(1.0));
+
+        session.evaluate("k = 5").unwrap();
+        session.evaluate(&format!("import \"{}\"", dir.join("lib.calc").display())).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(session.vm.interpret(&k).unwrap().result().as_number(), Some(5.0));
+    }
+}
@@ -0,0 +1,269 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::repl::Session;
+
+const PROMPT: &str = ">>";
+const CONTINUATION: &str = "..";
+
+/// Relative tolerance used when comparing numbers in recorded outputs.
+pub const DEFAULT_TOLERANCE: f64 = 1e-9;
+
+/// An input whose outputs no longer match the transcript.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// 1-based line of the input in the transcript.
+    pub line: usize,
+    pub input: String,
+    pub expected: Vec<String>,
+    pub actual: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayReport {
+    pub inputs: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+/// Re-executes a session transcript, as copied from the REPL, in a fresh
+/// session. Each `>> ` line is an input, continued by any `.. ` lines right
+/// after it, and the lines up to the next prompt are what it printed:
+/// results, messages and errors. Inputs are entered exactly as at the
+/// prompt, so `:` commands such as `:mode`, `:format` and `:base` apply to
+/// the inputs after them and `!!` re-runs the previous input. Numbers in
+/// the outputs are compared with a relative `tolerance`; all other text
+/// must match exactly.
+pub fn replay(transcript: &str, tolerance: f64) -> ReplayReport {
+    let capture = Capture::default();
+    let mut session = Session::capturing(capture.clone());
+    let mut report = ReplayReport { inputs: 0, divergences: Vec::new() };
+
+    let lines: Vec<&str> = transcript.lines().collect();
+    let mut i = 0;
+    'inputs: while i < lines.len() {
+        let Some(first) = lines[i].strip_prefix(PROMPT) else {
+            i += 1;
+            continue;
+        };
+        let line = i + 1;
+        let mut entered = vec![first.trim()];
+        i += 1;
+        while let Some(more) = lines.get(i).and_then(|l| l.strip_prefix(CONTINUATION)) {
+            entered.push(more.trim());
+            i += 1;
+        }
+
+        let start = i;
+        while i < lines.len() && !lines[i].starts_with(PROMPT) {
+            i += 1;
+        }
+        if entered == [""] {
+            continue;
+        }
+        let expected = output_lines(&lines[start..i].join("\n"));
+
+        let mut actual = Vec::new();
+        for text in &entered {
+            let result = session.enter(text);
+            actual.extend(output_lines(&capture.take()));
+            match result {
+                Ok(true) => {}
+                Ok(false) => break 'inputs,
+                Err(e) => actual.push(e.to_string()),
+            }
+        }
+        report.inputs += 1;
+        let matches = expected.len() == actual.len()
+            && expected.iter().zip(&actual).all(|(e, a)| outputs_match(e, a, tolerance));
+        if !matches {
+            report.divergences.push(Divergence {
+                line,
+                input: entered.join("\n"),
+                expected,
+                actual,
+            });
+        }
+    }
+    report
+}
+
+/// The non-blank lines of what an input printed.
+fn output_lines(text: &str) -> Vec<String> {
+    text.lines().filter(|line| !line.trim().is_empty()).map(String::from).collect()
+}
+
+/// A buffer shared by a session's output, its error messages and the
+/// VM's `print`, so that they stay in the order they were written.
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Capture {
+    /// Empties the buffer, returning what was written since the last call.
+    fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compares two output lines piece by piece, where pieces are separated by
+/// whitespace and list punctuation. Pieces that are both numbers may differ
+/// by `tolerance` relative to their magnitude (at least 1).
+fn outputs_match(expected: &str, actual: &str, tolerance: f64) -> bool {
+    let (expected, actual) = (pieces(expected), pieces(actual));
+    expected.len() == actual.len()
+        && expected.iter().zip(&actual).all(|(e, a)| {
+            match (e.parse::<f64>(), a.parse::<f64>()) {
+                (Ok(e), Ok(a)) if e.is_nan() && a.is_nan() => true,
+                (Ok(e), Ok(a)) => (e - a).abs() <= tolerance * e.abs().max(a.abs()).max(1.0),
+                _ => e == a,
+            }
+        })
+}
+
+/// Splits a line into words and single separator characters.
+fn pieces(line: &str) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    for (i, c) in line.char_indices() {
+        if c.is_whitespace() || "[](),".contains(c) {
+            if start < i {
+                pieces.push(&line[start..i]);
+            }
+            pieces.push(&line[i..i + c.len_utf8()]);
+            start = i + c.len_utf8();
+        }
+    }
+    if start < line.len() {
+        pieces.push(&line[start..]);
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_transcript() {
+        let transcript = "\
+>> 1 + 1
+Output: 2
+>> [1, 2] * 2; 1 / 0
+Output: [2, 4]
+math error in 1 / 0 (at 14..15)
+>> :seed 7
+>> 
+";
+        let report = replay(transcript, DEFAULT_TOLERANCE);
+        assert_eq!(report, ReplayReport { inputs: 3, divergences: vec![] });
+    }
+
+    #[test]
+    fn test_divergence_is_reported() {
+        let transcript = ">> 2 ^ 10\nOutput: 1000\n>> 3\nOutput: 3\n";
+        let report = replay(transcript, DEFAULT_TOLERANCE);
+        assert_eq!(report.inputs, 2);
+        assert_eq!(report.divergences, vec![Divergence {
+            line: 1,
+            input: "2 ^ 10".to_string(),
+            expected: vec!["Output: 1000".to_string()],
+            actual: vec!["Output: 1024".to_string()],
+        }]);
+    }
+
+    #[test]
+    fn test_numbers_compare_within_tolerance() {
+        assert!(outputs_match("Output: 1.4142135623", "Output: 1.4142135623730951", 1e-9));
+        assert!(!outputs_match("Output: 1.41", "Output: 1.4142135623730951", 1e-9));
+        assert!(outputs_match("Output: [1, 2.0000000001]", "Output: [1, 2]", 1e-9));
+        assert!(!outputs_match("Output: [1, 2]", "Output: [1, 2, 3]", 1e-9));
+        assert!(outputs_match("Output: NaN", "Output: NaN", 1e-9));
+    }
+
+    #[test]
+    fn test_degree_mode_is_replayed() {
+        let transcript = ">> :mode degree\n>> sin(90)\nOutput: 1\n";
+        assert!(replay(transcript, DEFAULT_TOLERANCE).divergences.is_empty());
+    }
+
+    #[test]
+    fn test_repl_settings_are_replayed() {
+        let transcript = "\
+>> :format fixed 2
+>> 1 / 3
+Output: 0.33
+>> :format plain
+>> :base 16
+>> 255
+Output: 255 (0xff)
+>> :mode programmer 8
+>> 200 + 100
+Output: 44 (0x2c, 0b101100)
+>> :mode normal
+>> :mode rpn
+>> 2 3 +
+Output: 5 (0x5)
+";
+        let report = replay(transcript, DEFAULT_TOLERANCE);
+        assert_eq!(report.divergences, vec![]);
+    }
+
+    #[test]
+    fn test_print_output_is_compared() {
+        let transcript = ">> print(7) + 1\n7\nOutput: 8\n>> print(2)\nOutput: 2\n";
+        let report = replay(transcript, DEFAULT_TOLERANCE);
+        assert_eq!(report.divergences, vec![Divergence {
+            line: 4,
+            input: "print(2)".to_string(),
+            expected: vec!["Output: 2".to_string()],
+            actual: vec!["2".to_string(), "Output: 2".to_string()],
+        }]);
+    }
+
+    #[test]
+    fn test_errors_are_shown_as_in_the_repl() {
+        let transcript = ">> 1 +* 2\n1 +* 2\n   ^ Expected an expression at 3\n>> :mode octal\nUsage: :mode [degree|radian|rpn|infix|normal|programmer [8|16|32|64]]\n";
+        let report = replay(transcript, DEFAULT_TOLERANCE);
+        assert_eq!(report.divergences, vec![]);
+    }
+
+    #[test]
+    fn test_inputs_are_added_to_the_history() {
+        let transcript = ">> 1 + 1\nOutput: 2\n>> :mode degree\n>> :history\n1  1 + 1\n2  :mode degree\n3  :history\n";
+        let report = replay(transcript, DEFAULT_TOLERANCE);
+        assert_eq!(report.divergences, vec![]);
+    }
+
+    #[test]
+    fn test_continued_inputs_are_joined() {
+        let transcript = ">> (1 +\n.. 2) *\n.. 3\nOutput: 9\n>> [1,\n..\n[1,\n   ^ Expected an expression at 3\n";
+        let report = replay(transcript, DEFAULT_TOLERANCE);
+        assert_eq!(report.inputs, 2);
+        assert_eq!(report.divergences, vec![]);
+    }
+
+    #[test]
+    fn test_history_is_expanded() {
+        let transcript = ">> 5\nOutput: 5\n>> !!\n5\nOutput: 5\n>> !1 + 1\n5 + 1\nOutput: 6\n>> !9\nNo such line in the history; ':history' lists them\n";
+        let report = replay(transcript, DEFAULT_TOLERANCE);
+        assert_eq!(report.divergences, vec![]);
+    }
+
+    #[test]
+    fn test_clear_does_not_ask() {
+        let transcript = ">> x = 1\nOutput: 1\n>> :clear\nCleared\n>> :vars\nNo variables\nNo functions\nNo previous answer\n";
+        let report = replay(transcript, DEFAULT_TOLERANCE);
+        assert_eq!(report.divergences, vec![]);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::operation::Operation;
+use crate::parser::{self, ParseError};
+use crate::script::StatementError;
+use crate::token::{Span, Token, TokenType};
+use crate::verify;
+
+/// Compiles a statement written in reverse Polish notation, e.g. `2 3 + 4 *`,
+/// for the REPL's `:mode rpn`. Each token is one operation: numbers and
+/// names push their value, operators pop their operands, and single-argument
+/// builtins such as `sqrt` apply to the top of the stack. Since `-` always
+/// subtracts, `0 x -` negates. Also returns the span of each operation, as
+/// [`script::compile_with_spans`](crate::script::compile_with_spans) does.
+pub fn compile(statement: Vec<Token>) -> Result<(Vec<Operation>, Vec<Span>), StatementError> {
+    use TokenType::*;
+    let mut operations = Vec::new();
+    let mut spans = Vec::new();
+    for token in statement {
+        let operation = match token.token_type {
+            EOF => break,
+            Number => Operation::Const(token.lexeme.parse().unwrap()),
+            Identifier | E | Pi | Tau | Phi => Operation::Load(token.lexeme.clone()),
+            Ans => Operation::Ans,
+            Bang => Operation::Factorial,
+            ref token_type => match parser::binary_operation(token_type)
+                .or_else(|| parser::unary_operation(token_type))
+            {
+                Some(operation) => operation,
+                None => return Err(StatementError::Parse(ParseError::ExpectExpression { token })),
+            },
+        };
+        operations.push(operation);
+        spans.push(token.span);
+    }
+    verify::verify(&operations).map_err(StatementError::Verify)?;
+    Ok((operations, spans))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+    use crate::verify::VerifyError;
+    use crate::vm::VirtualMachine;
+
+    fn evaluate(source: &str) -> Result<f64, StatementError> {
+        let (operations, _) = compile(lexer::scan(source).unwrap())?;
+        let output = VirtualMachine::new().interpret(&operations).unwrap();
+        Ok(output.result().as_number().unwrap())
+    }
+
+    #[test]
+    fn test_compile() {
+        assert_eq!(evaluate("2 3 + 4 *"), Ok(20.0));
+        assert_eq!(evaluate("2 3 4 * +"), Ok(14.0));
+        assert_eq!(evaluate("0 9 sqrt -"), Ok(-3.0));
+        assert_eq!(evaluate("3 ! 2 ^"), Ok(36.0));
+        assert_eq!(evaluate("pi 2 /"), Ok(std::f64::consts::FRAC_PI_2));
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        assert_eq!(
+            evaluate("1 +"),
+            Err(StatementError::Verify(VerifyError::Underflow { index: 1, needed: 2, available: 1 }))
+        );
+        assert_eq!(evaluate("1 2"), Err(StatementError::Verify(VerifyError::FinalDepth(2))));
+        let error = evaluate("1 ( 2").unwrap_err();
+        assert_eq!(error.span(), Some((2, 3)));
+    }
+}
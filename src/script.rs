@@ -0,0 +1,553 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::format::{format_output, FormatOptions};
+use crate::import::{ImportError, Importer};
+use crate::lexer::{self, LexError};
+use crate::lint;
+use crate::operation::Operation;
+use crate::parser::{self, ParseError};
+use crate::token::{Span, Token, TokenType};
+use crate::value::Value;
+use crate::verify::{self, VerifyError};
+use crate::vm::{RuntimeError, VirtualMachine};
+
+/// How a script treats statements with no expression in them: blank or
+/// whitespace-only lines, comment-only lines, and the gaps left by repeated
+/// or trailing `;` separators.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmptyStatements {
+    /// Ignore empty statements (the default).
+    Skip,
+    /// Report empty statements as `ExpectExpression` errors, which is
+    /// useful for catching mistakes in generated scripts.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptOptions {
+    pub empty_statements: EmptyStatements,
+    /// Write `Warning: ...` lines for likely mistakes before each result.
+    pub lint: bool,
+    /// Directories searched by `import`, see [`Importer::new`].
+    pub search_path: Vec<PathBuf>,
+    /// How results are written.
+    pub format: FormatOptions,
+}
+
+impl Default for ScriptOptions {
+    fn default() -> Self {
+        Self {
+            empty_statements: EmptyStatements::Skip,
+            lint: false,
+            search_path: Vec::new(),
+            format: FormatOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatementError {
+    Lex(LexError),
+    Parse(ParseError),
+    Verify(VerifyError),
+    Runtime(RuntimeError),
+    Import(ImportError),
+    Io(String),
+}
+
+impl StatementError {
+    /// The source span a lex or parse error points at.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            StatementError::Lex(e) => Some(e.span()),
+            StatementError::Parse(e) => e.span(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for StatementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatementError::Lex(e) => write!(f, "{}", e),
+            StatementError::Parse(e) => write!(f, "{}", e),
+            StatementError::Verify(e) => write!(f, "{}", e),
+            StatementError::Runtime(e) => write!(f, "{}", e),
+            StatementError::Import(e) => write!(f, "{}", e),
+            StatementError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// An error in a script, tagged with its 1-based line number.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub error: StatementError,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+/// Splits a line's tokens on `;` into statements, each terminated by its own
+/// `EOF` token. A `;` inside a loop body's `{ }` separates statements of the
+/// body instead. Empty statements are kept so callers can decide what to do
+/// with them.
+pub fn statements(tokens: impl IntoIterator<Item = Token>) -> Vec<Vec<Token>> {
+    let mut tokens = tokens.into_iter();
+    let mut statements = Vec::new();
+    let mut statement = Vec::new();
+    while next_statement(&mut tokens, &mut statement) {
+        statements.push(std::mem::take(&mut statement));
+    }
+    statements
+}
+
+/// Like [`statements`], but moves only the next statement of `tokens` into
+/// `statement`, which is cleared first, so that one buffer serves a whole
+/// line. Returns false once `tokens` is used up.
+pub fn next_statement(tokens: &mut impl Iterator<Item = Token>, statement: &mut Vec<Token>) -> bool {
+    statement.clear();
+    let mut depth = 0usize;
+    for token in tokens {
+        match token.token_type {
+            TokenType::LeftBrace => {
+                depth += 1;
+                statement.push(token);
+            }
+            TokenType::RightBrace => {
+                depth = depth.saturating_sub(1);
+                statement.push(token);
+            }
+            TokenType::Semicolon if depth > 0 => statement.push(token),
+            TokenType::Semicolon | TokenType::EOF => {
+                let eof = Token {
+                    token_type: TokenType::EOF,
+                    lexeme: String::new(),
+                    span: token.span,
+                };
+                statement.push(eof);
+                return true;
+            }
+            _ => statement.push(token),
+        }
+    }
+    !statement.is_empty()
+}
+
+/// The buffers a statement is compiled in: its tokens, its operations and
+/// their spans. Kept between statements, they are reused rather than
+/// allocated for each.
+#[derive(Debug, Default)]
+pub struct Buffers {
+    pub statement: Vec<Token>,
+    pub operations: Vec<Operation>,
+    pub spans: Vec<Span>,
+}
+
+impl Buffers {
+    /// Compiles `statement` into `operations` and `spans`, as
+    /// [`compile_with_spans`] does.
+    pub fn compile(&mut self) -> Result<(), StatementError> {
+        parser::parse_into(&mut self.statement, &mut self.operations, &mut self.spans)
+            .map_err(StatementError::Parse)?;
+        verify::verify(&self.operations).map_err(StatementError::Verify)
+    }
+}
+
+/// Whether a statement produced by [`statements`] contains only its `EOF`.
+pub fn is_empty(statement: &[Token]) -> bool {
+    statement.len() <= 1
+}
+
+/// Makes a statement that starts with an operator, like `*2`, continue from
+/// the previous answer as handheld calculators do, by inserting `ans`.
+pub fn continue_from_ans(statement: &mut Vec<Token>) {
+    if let Some(first) = statement.first()
+        && parser::is_infix_only(&first.token_type)
+    {
+        let ans = Token {
+            token_type: TokenType::Ans,
+            lexeme: "ans".to_string(),
+            span: (first.span.0, first.span.0),
+        };
+        statement.insert(0, ans);
+    }
+}
+
+/// Whether `source` is unfinished: it has an unclosed `(`, `[` or `{`, or
+/// ends with a binary operator, `=` or `,`, so that the REPL should read
+/// another line and append it. Source that cannot be scanned is not, so
+/// that its error is reported.
+pub fn is_incomplete(source: &str) -> bool {
+    use TokenType::*;
+    let Ok(tokens) = lexer::scan(source) else {
+        return false;
+    };
+    let mut depth = 0i64;
+    for token in &tokens {
+        match token.token_type {
+            LeftParen | LeftBracket | LeftBrace => depth += 1,
+            RightParen | RightBracket | RightBrace => depth -= 1,
+            _ => {}
+        }
+    }
+    let last = tokens.iter().rev().find(|token| token.token_type != EOF);
+    depth > 0
+        || last.is_some_and(|token| {
+            matches!(
+                token.token_type,
+                Plus | Minus | Star | Slash | SlashSlash | Caret | Mod | At
+                    | Ampersand | Pipe | Xor | LessLess | GreaterGreater
+                    | Less | LessEqual | Greater | GreaterEqual | EqualEqual | BangEqual
+                    | Equal | Comma
+            )
+        })
+}
+
+/// The file name of an `import "file"` statement.
+pub fn import_name(statement: &[Token]) -> Option<&str> {
+    match statement {
+        [import, name, _] if import.token_type == TokenType::Import
+            && name.token_type == TokenType::Str => Some(&name.lexeme),
+        _ => None,
+    }
+}
+
+/// Parses a statement and checks that its operations leave exactly one
+/// value, so that malformed sequences are reported before they run.
+pub fn compile(statement: Vec<Token>) -> Result<Vec<Operation>, StatementError> {
+    let operations = parser::parse(statement).map_err(StatementError::Parse)?;
+    verify::verify(&operations).map_err(StatementError::Verify)?;
+    Ok(operations)
+}
+
+/// Like [`compile`], but also returns the source span of each operation,
+/// for locating runtime errors with [`RuntimeError::locate`].
+pub fn compile_with_spans(
+    statement: Vec<Token>,
+) -> Result<(Vec<Operation>, Vec<Span>), StatementError> {
+    let mut buffers = Buffers { statement, ..Buffers::default() };
+    buffers.compile()?;
+    Ok((buffers.operations, buffers.spans))
+}
+
+/// Calls `run_statement` with each statement of a script, line by line,
+/// stopping at the first error. Each statement is handed over in the
+/// `statement` of the same [`Buffers`], ready to be compiled there.
+pub fn for_each_statement<F>(
+    source: &str,
+    empty_statements: EmptyStatements,
+    mut run_statement: F,
+) -> Result<(), ScriptError>
+where
+    F: FnMut(&mut Buffers) -> Result<(), StatementError>,
+{
+    let mut tokens = Vec::new();
+    let mut buffers = Buffers::default();
+    for (i, line) in source.lines().enumerate() {
+        let error = |error| ScriptError { line: i + 1, error };
+        lexer::scan_into(line, &mut tokens).map_err(|e| error(StatementError::Lex(e)))?;
+
+        let mut line_tokens = tokens.drain(..);
+        while next_statement(&mut line_tokens, &mut buffers.statement) {
+            if is_empty(&buffers.statement) && empty_statements == EmptyStatements::Skip {
+                continue;
+            }
+            run_statement(&mut buffers).map_err(error)?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs a script line by line, writing each statement's result to `out`.
+/// Execution stops at the first error.
+pub fn run<W: Write>(
+    vm: &mut VirtualMachine,
+    source: &str,
+    options: &ScriptOptions,
+    out: &mut W,
+) -> Result<(), ScriptError> {
+    let io_error = |e: io::Error| StatementError::Io(e.to_string());
+    let mut importer = Importer::new(options.search_path.clone());
+    for_each_statement(source, options.empty_statements, |buffers| {
+        if let Some(name) = import_name(&buffers.statement) {
+            return importer.import(vm, name).map_err(StatementError::Import);
+        }
+        buffers.compile()?;
+        if options.lint {
+            for warning in lint::lint(&buffers.operations) {
+                writeln!(out, "Warning: {}", warning).map_err(io_error)?;
+            }
+        }
+        let output = vm
+            .interpret(&buffers.operations)
+            .map_err(|e| StatementError::Runtime(e.locate(&buffers.spans)))?;
+        writeln!(out, "{}", format_output(output.result(), &options.format)).map_err(io_error)
+    })
+}
+
+impl VirtualMachine {
+    /// Evaluates every statement of `source`, separated by newlines or `;`,
+    /// and returns the value of the last one, or `None` if there are none.
+    /// The script runs atomically: if any statement fails, every variable,
+    /// function and answer it set is rolled back. Its imports are looked up
+    /// in `search_path`, usually the script's directory.
+    pub fn run_script(
+        &mut self,
+        source: &str,
+        search_path: Vec<PathBuf>,
+    ) -> Result<Option<Value>, ScriptError> {
+        let checkpoint = self.checkpoint();
+        let mut importer = Importer::new(search_path);
+        let mut last = None;
+        let result = for_each_statement(source, EmptyStatements::Skip, |buffers| {
+            if let Some(name) = import_name(&buffers.statement) {
+                return importer.import(self, name).map_err(StatementError::Import);
+            }
+            buffers.compile()?;
+            let output = self
+                .interpret(&buffers.operations)
+                .map_err(|e| StatementError::Runtime(e.locate(&buffers.spans)))?;
+            last = Some(output.result().clone());
+            Ok(())
+        });
+        match result {
+            Ok(()) => Ok(last),
+            Err(error) => {
+                self.rollback(checkpoint);
+                Err(error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_script(source: &str, options: &ScriptOptions) -> Result<String, ScriptError> {
+        let mut vm = VirtualMachine::new();
+        let mut out = Vec::new();
+        run(&mut vm, source, options, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    fn strict() -> ScriptOptions {
+        ScriptOptions { empty_statements: EmptyStatements::Error, ..ScriptOptions::default() }
+    }
+
+    #[test]
+    fn test_blank_and_whitespace_lines_are_skipped() {
+        let output = run_script("1 + 1\n\n   \n\t\n2 * 3\n", &ScriptOptions::default());
+        assert_eq!(output.unwrap(), "Output: 2\nOutput: 6\n");
+    }
+
+    #[test]
+    fn test_comment_only_lines_are_skipped() {
+        let output = run_script("# header\n1 # trailing\n   # indented\n", &ScriptOptions::default());
+        assert_eq!(output.unwrap(), "Output: 1\n");
+    }
+
+    #[test]
+    fn test_repeated_and_trailing_separators_are_skipped() {
+        let output = run_script(";1;;2;\n;;\n", &ScriptOptions::default());
+        assert_eq!(output.unwrap(), "Output: 1\nOutput: 2\n");
+    }
+
+    #[test]
+    fn test_strict_mode_reports_empty_statements() {
+        let error = run_script("1\n\n2", &strict()).unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(matches!(
+            error.error,
+            StatementError::Parse(ParseError::ExpectExpression { .. })
+        ));
+
+        let error = run_script("1;;2", &strict()).unwrap_err();
+        assert_eq!(error.line, 1);
+    }
+
+    #[test]
+    fn test_error_reports_line() {
+        let error = run_script("1\n1/0\n2", &ScriptOptions::default()).unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.to_string(), "line 2: math error in 1 / 0 (at 1..2)");
+    }
+
+    #[test]
+    fn test_lint_warns_before_result() {
+        let options = ScriptOptions { lint: true, ..ScriptOptions::default() };
+        let output = run_script("2 - 2\n3", &options);
+        assert_eq!(
+            output.unwrap(),
+            "Warning: expression subtracted from itself is always 0\nOutput: 0\nOutput: 3\n"
+        );
+
+        let output = run_script("2 - 2", &ScriptOptions::default());
+        assert_eq!(output.unwrap(), "Output: 0\n");
+    }
+
+    #[test]
+    fn test_continue_from_ans() {
+        let mut vm = VirtualMachine::new();
+        let mut eval = |source: &str| {
+            let mut statement = statements(lexer::scan(source).unwrap()).remove(0);
+            continue_from_ans(&mut statement);
+            let output = vm.interpret(&compile(statement).unwrap()).unwrap();
+            format_output(output.result(), &FormatOptions::default())
+        };
+
+        assert_eq!(eval("10"), "Output: 10");
+        assert_eq!(eval("+5"), "Output: 15");
+        assert_eq!(eval("*2"), "Output: 30");
+        assert_eq!(eval("/3 + 1"), "Output: 11");
+        assert_eq!(eval("-1"), "Output: -1");
+    }
+
+    #[test]
+    fn test_loop_body_keeps_its_separators() {
+        let source = "n = 0; for i = 1 to 4 { n = n + i; n } ; n";
+        let output = run_script(source, &ScriptOptions::default());
+        assert_eq!(output.unwrap(), "Output: 0\nOutput: 10\nOutput: 10\n");
+    }
+
+    #[test]
+    fn test_statements_reuse_one_buffer() {
+        let mut tokens = lexer::scan("1 + 2; ; x = 3").unwrap().into_iter();
+        let mut buffers = Buffers::default();
+        let mut compiled = Vec::new();
+        while next_statement(&mut tokens, &mut buffers.statement) {
+            if is_empty(&buffers.statement) {
+                continue;
+            }
+            buffers.compile().unwrap();
+            compiled.push(buffers.operations.clone());
+        }
+        assert_eq!(compiled, [
+            vec![Operation::Const(1.0), Operation::Const(2.0), Operation::Add],
+            vec![Operation::Const(3.0), Operation::Store("x".to_string())],
+        ]);
+        assert_eq!(buffers.spans, [(13, 14), (9, 14)]);
+    }
+
+    #[test]
+    fn test_recursive_function() {
+        let source = "fib(n) = if n < 2 then n else fib(n - 1) + fib(n - 2)\nfib(15)";
+        let output = run_script(source, &ScriptOptions::default());
+        assert_eq!(output.unwrap(), "Output: fib(n)\nOutput: 610\n");
+    }
+
+    #[test]
+    fn test_run_script_returns_last_value() {
+        let mut vm = VirtualMachine::new();
+        let result = vm.run_script("r = 2\narea(r) = pi * r^2; # comment\n\narea(r) / pi", Vec::new());
+        assert_eq!(result, Ok(Some(Value::Number(4.0))));
+        assert_eq!(vm.run_script("# nothing\n", Vec::new()), Ok(None));
+    }
+
+    #[test]
+    fn test_run_script_rolls_back_on_error() {
+        let mut vm = VirtualMachine::new();
+        vm.run_script("x = 1", Vec::new()).unwrap();
+
+        let error = vm.run_script("x = 2; f(y) = y\n1 +", Vec::new()).unwrap_err();
+        assert_eq!(error.line, 2);
+        let error = vm.run_script("x = 3; g(y) = y\nln(0)", Vec::new()).unwrap_err();
+        assert_eq!(error.line, 2);
+
+        assert_eq!(vm.run_script("x", Vec::new()), Ok(Some(Value::Number(1.0))));
+        assert!(vm.run_script("g(1)", Vec::new()).is_err());
+        assert_eq!(vm.get_prev_ans(), Ok(Value::Number(1.0)));
+    }
+
+    #[test]
+    fn test_functions_are_values() {
+        let mut vm = VirtualMachine::new();
+        let source = "f(x) = x^2; twice(g, x) = g(g(x)); sum(map(f, [1, 2, 3])) + twice(f, 3)";
+        assert_eq!(vm.run_script(source, Vec::new()), Ok(Some(Value::Number(14.0 + 81.0))));
+    }
+
+    #[test]
+    fn test_builtins_are_values() {
+        let mut vm = VirtualMachine::new();
+        assert_eq!(vm.run_script("map(sqrt, [4, 9])", Vec::new()), Ok(Some(Value::from(vec![2.0, 3.0]))));
+        let Ok(Some(Value::Number(x))) = vm.run_script("integrate(sin, 0, pi)", Vec::new()) else { panic!() };
+        assert!((x - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_solve_equation() {
+        let mut vm = VirtualMachine::new();
+        assert_eq!(vm.run_script("solve x + 2 = 7 for x", Vec::new()), Ok(Some(Value::Number(5.0))));
+        assert_eq!(vm.run_script("a = 4; solve a * x = 1 for x", Vec::new()), Ok(Some(Value::Number(0.25))));
+        let Ok(Some(Value::Number(x))) = vm.run_script("solve ln(x) = 1 for x", Vec::new()) else { panic!() };
+        assert!((x - std::f64::consts::E).abs() < 1e-12);
+        assert!(vm.run_script("solve x^2 = -1 for x", Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_deriv_gives_text() {
+        let mut vm = VirtualMachine::new();
+        let result = vm.run_script("deriv(x * sin(x), x)", Vec::new());
+        assert_eq!(result, Ok(Some(Value::Str("sin(x) + x * cos(x)".to_string()))));
+    }
+
+    #[test]
+    fn test_equiv() {
+        let mut vm = VirtualMachine::new();
+        let result = vm.run_script("equiv(sin(a + b), sin(a)*cos(b) + cos(a)*sin(b))", Vec::new());
+        assert_eq!(result, Ok(Some(Value::Str("equivalent at 100 random points".to_string()))));
+        // The points come from a generator of their own, so seeded random
+        // numbers are the same with or without an equiv in between.
+        vm.seed(7);
+        let first = vm.run_script("rand()", Vec::new());
+        vm.seed(7);
+        vm.run_script("equiv(x, x)", Vec::new()).unwrap();
+        assert_eq!(vm.run_script("rand()", Vec::new()), first);
+        let Ok(Some(Value::Str(text))) = vm.run_script("equiv((x + 1)^2, x^2 + 1)", Vec::new()) else { panic!() };
+        assert!(text.starts_with("not equivalent: at x = "), "{}", text);
+        // ln(x) is only defined for half of the points.
+        let result = vm.run_script("equiv(ln(x^2), 2*ln(x))", Vec::new());
+        assert!(matches!(result, Ok(Some(Value::Str(text))) if text.starts_with("equivalent at ")));
+    }
+
+    #[test]
+    fn test_error_span() {
+        let error = compile(lexer::scan("1 +* 2").unwrap()).unwrap_err();
+        assert_eq!(error.span(), Some((3, 4)));
+        let error = StatementError::Lex(lexer::scan("1 $").unwrap_err());
+        assert_eq!(error.span(), Some((2, 3)));
+        assert_eq!(StatementError::Io("gone".to_string()).span(), None);
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(is_incomplete("sqrt(2 +"));
+        assert!(is_incomplete("[1, 2,"));
+        assert!(is_incomplete("1 +"));
+        assert!(is_incomplete("f(x) ="));
+        assert!(is_incomplete("for i = 1 to 3 {"));
+        assert!(!is_incomplete("5!"));
+        assert!(!is_incomplete("(1 + 2) * 3"));
+        assert!(!is_incomplete("1)"));
+        assert!(!is_incomplete("\"unterminated"));
+        assert!(!is_incomplete(""));
+    }
+
+    #[test]
+    fn test_run_script_imports_from_search_path() {
+        let dir = std::env::temp_dir().join(format!("calc-run-script-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.calc"), "double(x) = 2 * x\n").unwrap();
+        let mut vm = VirtualMachine::new();
+        let result = vm.run_script("import \"lib.calc\"\ndouble(21)", vec![dir.clone()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result, Ok(Some(Value::Number(42.0))));
+    }
+}
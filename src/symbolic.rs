@@ -0,0 +1,337 @@
+use std::fmt;
+
+use crate::format::{format_number, FormatOptions};
+use crate::operation::Operation;
+use crate::operation::Operation::*;
+use crate::vm::RuntimeError;
+
+/// An expression tree rebuilt from compiled operations, for rewriting
+/// expressions rather than evaluating them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Variable(String),
+    Negate(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Subtract(Box<Expr>, Box<Expr>),
+    Times(Box<Expr>, Box<Expr>),
+    Divide(Box<Expr>, Box<Expr>),
+    Power(Box<Expr>, Box<Expr>),
+    /// A builtin function of one argument, such as `Sin`.
+    Call(Operation, Box<Expr>),
+}
+
+use Expr::Number;
+
+impl Expr {
+    /// Rebuilds the tree of an expression compiled to `operations`. Only
+    /// arithmetic and the functions [`derivative`] knows are supported.
+    pub fn from_operations(operations: &[Operation]) -> Result<Self, RuntimeError> {
+        let mut stack: Vec<Expr> = Vec::new();
+        for op in operations {
+            let expr = match op {
+                Const(x) => Number(*x),
+                Load(name) => Expr::Variable(name.clone()),
+                Ans => Expr::Variable("ans".to_string()),
+                Negate => negate(pop(&mut stack)?),
+                Add | Subtract | Times | Divide | Power => {
+                    let b = Box::new(pop(&mut stack)?);
+                    let a = Box::new(pop(&mut stack)?);
+                    match op {
+                        Add => Expr::Add(a, b),
+                        Subtract => Expr::Subtract(a, b),
+                        Times => Expr::Times(a, b),
+                        Divide => Expr::Divide(a, b),
+                        _ => Expr::Power(a, b),
+                    }
+                }
+                op if function_name(op).is_some() => Expr::Call(op.clone(), Box::new(pop(&mut stack)?)),
+                op => return Err(RuntimeError::NotDifferentiable(op.name().to_lowercase())),
+            };
+            stack.push(expr);
+        }
+        match (stack.pop(), stack.is_empty()) {
+            (Some(expr), true) => Ok(expr),
+            _ => Err(RuntimeError::Underflow),
+        }
+    }
+
+    fn contains(&self, variable: &str) -> bool {
+        match self {
+            Number(_) => false,
+            Expr::Variable(name) => name == variable,
+            Expr::Negate(a) | Expr::Call(_, a) => a.contains(variable),
+            Expr::Add(a, b) | Expr::Subtract(a, b) | Expr::Times(a, b) |
+            Expr::Divide(a, b) | Expr::Power(a, b) => a.contains(variable) || b.contains(variable),
+        }
+    }
+
+    /// Binding strength when printed, matching the parser's precedence.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Add(..) | Expr::Subtract(..) => 1,
+            Expr::Times(..) | Expr::Divide(..) => 2,
+            Expr::Negate(_) => 3,
+            Number(x) if *x < 0.0 => 3,
+            Expr::Power(..) => 4,
+            _ => 5,
+        }
+    }
+}
+
+fn pop(stack: &mut Vec<Expr>) -> Result<Expr, RuntimeError> {
+    stack.pop().ok_or(RuntimeError::Underflow)
+}
+
+/// The keyword of a function with a derivative rule.
+fn function_name(op: &Operation) -> Option<&'static str> {
+    Some(match op {
+        Sin => "sin",
+        Cos => "cos",
+        Tan => "tan",
+        ArcSin => "arcsin",
+        ArcCos => "arccos",
+        ArcTan => "arctan",
+        Sinh => "sinh",
+        Cosh => "cosh",
+        Tanh => "tanh",
+        Exp => "exp",
+        Ln => "ln",
+        Log10 => "log10",
+        Log2 => "log2",
+        Sqrt => "sqrt",
+        Cbrt => "cbrt",
+        Abs => "abs",
+        Sign => "sign",
+        _ => return None,
+    })
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Wraps an operand in parentheses if it binds less tightly than
+        // `min` requires.
+        let operand = |expr: &Expr, min: u8| {
+            if expr.precedence() < min { format!("({})", expr) } else { expr.to_string() }
+        };
+        match self {
+            Number(x) => write!(f, "{}", format_number(*x, &FormatOptions::default())),
+            Expr::Variable(name) => write!(f, "{}", name),
+            Expr::Negate(a) => write!(f, "-{}", operand(a, 4)),
+            Expr::Add(a, b) => write!(f, "{} + {}", operand(a, 1), operand(b, 2)),
+            Expr::Subtract(a, b) => write!(f, "{} - {}", operand(a, 1), operand(b, 2)),
+            Expr::Times(a, b) => write!(f, "{} * {}", operand(a, 2), operand(b, 2)),
+            Expr::Divide(a, b) => write!(f, "{} / {}", operand(a, 2), operand(b, 4)),
+            Expr::Power(a, b) => write!(f, "{}^{}", operand(a, 5), operand(b, 4)),
+            Expr::Call(op, a) => write!(f, "{}({})", function_name(op).unwrap_or("?"), a),
+        }
+    }
+}
+
+// Constructors that simplify as they build, so that derivatives come out
+// as `2 * x` rather than `2 * x^(2 - 1) * 1`.
+
+fn negate(a: Expr) -> Expr {
+    match a {
+        Number(x) => Number(-x),
+        Expr::Negate(a) => *a,
+        Expr::Times(a, b) if matches!(*a, Number(_)) => times(negate(*a), *b),
+        Expr::Divide(a, b) if matches!(*a, Number(_)) => divide(negate(*a), *b),
+        a => Expr::Negate(Box::new(a)),
+    }
+}
+
+/// Whether `expr` is a negative number or has a negative coefficient, so
+/// that adding it reads better as a subtraction.
+fn is_negative(expr: &Expr) -> bool {
+    match expr {
+        Number(x) => *x < 0.0,
+        Expr::Times(a, _) | Expr::Divide(a, _) => matches!(**a, Number(x) if x < 0.0),
+        _ => false,
+    }
+}
+
+fn add(a: Expr, b: Expr) -> Expr {
+    match (a, b) {
+        (Number(x), Number(y)) => Number(x + y),
+        (Number(0.0), a) | (a, Number(0.0)) => a,
+        (a, Expr::Negate(b)) => subtract(a, *b),
+        (a, b) if is_negative(&b) => subtract(a, negate(b)),
+        (a, b) if a == b => times(Number(2.0), a),
+        (a, b) => Expr::Add(Box::new(a), Box::new(b)),
+    }
+}
+
+fn subtract(a: Expr, b: Expr) -> Expr {
+    match (a, b) {
+        (Number(x), Number(y)) => Number(x - y),
+        (a, Number(0.0)) => a,
+        (Number(0.0), b) => negate(b),
+        (a, b) if a == b => Number(0.0),
+        (Expr::Add(a, b), c) if *a == c => *b,
+        (Expr::Add(a, b), c) if *b == c => *a,
+        (a, Expr::Negate(b)) => add(a, *b),
+        (a, b) if is_negative(&b) => add(a, negate(b)),
+        (a, b) => Expr::Subtract(Box::new(a), Box::new(b)),
+    }
+}
+
+fn times(a: Expr, b: Expr) -> Expr {
+    match (a, b) {
+        (Number(x), Number(y)) => Number(x * y),
+        (Number(0.0), _) | (_, Number(0.0)) => Number(0.0),
+        (Number(1.0), a) | (a, Number(1.0)) => a,
+        (Number(-1.0), a) | (a, Number(-1.0)) => negate(a),
+        (Expr::Negate(a), b) => negate(times(*a, b)),
+        (a, Expr::Negate(b)) => negate(times(a, *b)),
+        (Expr::Divide(one, b), a) | (a, Expr::Divide(one, b)) if *one == Number(1.0) => divide(a, *b),
+        // Coefficients go first and are combined.
+        (a, Number(x)) => times(Number(x), a),
+        (Number(x), Expr::Times(a, b)) if matches!(*a, Number(_)) => {
+            let Number(y) = *a else { unreachable!() };
+            times(Number(x * y), *b)
+        }
+        (a, b) if a == b => power(a, Number(2.0)),
+        (a, b) => Expr::Times(Box::new(a), Box::new(b)),
+    }
+}
+
+fn divide(a: Expr, b: Expr) -> Expr {
+    match (a, b) {
+        // Only exact quotients are folded, so `1 / 3` stays a fraction.
+        (Number(x), Number(y)) if y != 0.0 && x % y == 0.0 => Number(x / y),
+        (Number(0.0), _) => Number(0.0),
+        (a, Number(1.0)) => a,
+        (a, b) if a == b => Number(1.0),
+        (Expr::Negate(a), b) => negate(divide(*a, b)),
+        (a, b) => Expr::Divide(Box::new(a), Box::new(b)),
+    }
+}
+
+fn power(a: Expr, b: Expr) -> Expr {
+    match (a, b) {
+        (_, Number(0.0)) => Number(1.0),
+        (a, Number(1.0)) => a,
+        (Number(x), Number(y)) => Number(x.powf(y)),
+        (Expr::Power(a, b), c) => power(*a, times(*b, c)),
+        (a, b) => Expr::Power(Box::new(a), Box::new(b)),
+    }
+}
+
+fn call(op: Operation, a: Expr) -> Expr {
+    Expr::Call(op, Box::new(a))
+}
+
+/// The derivative of `expr` with respect to `variable` by the sum, product,
+/// quotient, power and chain rules. Other variables are constants. Trig
+/// functions take degrees unless `use_radians` is set, which scales their
+/// derivatives by `pi / 180`.
+pub fn derivative(expr: &Expr, variable: &str, use_radians: bool) -> Result<Expr, RuntimeError> {
+    let d = |e: &Expr| derivative(e, variable, use_radians);
+    Ok(match expr {
+        Number(_) => Number(0.0),
+        Expr::Variable(name) => Number(if name == variable { 1.0 } else { 0.0 }),
+        Expr::Negate(a) => negate(d(a)?),
+        Expr::Add(a, b) => add(d(a)?, d(b)?),
+        Expr::Subtract(a, b) => subtract(d(a)?, d(b)?),
+        Expr::Times(a, b) => {
+            add(times(d(a)?, (**b).clone()), times((**a).clone(), d(b)?))
+        }
+        Expr::Divide(a, b) => {
+            let numerator = subtract(times(d(a)?, (**b).clone()), times((**a).clone(), d(b)?));
+            divide(numerator, power((**b).clone(), Number(2.0)))
+        }
+        Expr::Power(a, b) if !b.contains(variable) => {
+            let reduced = power((**a).clone(), subtract((**b).clone(), Number(1.0)));
+            times(times((**b).clone(), reduced), d(a)?)
+        }
+        Expr::Power(a, b) => {
+            // d(a^b) = a^b * (b' ln a + b a' / a)
+            let log = times(d(b)?, call(Ln, (**a).clone()));
+            let rest = divide(times((**b).clone(), d(a)?), (**a).clone());
+            times(expr.clone(), add(log, rest))
+        }
+        Expr::Call(op, a) => times(outer_derivative(op, a, use_radians)?, d(a)?),
+    })
+}
+
+/// The derivative of the function `op` evaluated at `a`, for the chain
+/// rule.
+fn outer_derivative(op: &Operation, a: &Expr, use_radians: bool) -> Result<Expr, RuntimeError> {
+    let a = a.clone();
+    let degrees = divide(Expr::Variable("pi".to_string()), Number(180.0));
+    let (to_radians, from_radians) = if use_radians {
+        (Number(1.0), Number(1.0))
+    } else {
+        (degrees.clone(), divide(Number(1.0), degrees))
+    };
+    let one_minus_square = || subtract(Number(1.0), power(a.clone(), Number(2.0)));
+    Ok(match op {
+        Sin => times(to_radians, call(Cos, a)),
+        Cos => negate(times(to_radians, call(Sin, a))),
+        Tan => divide(to_radians, power(call(Cos, a), Number(2.0))),
+        ArcSin => divide(from_radians, call(Sqrt, one_minus_square())),
+        ArcCos => negate(divide(from_radians, call(Sqrt, one_minus_square()))),
+        ArcTan => divide(from_radians, add(Number(1.0), power(a, Number(2.0)))),
+        Sinh => call(Cosh, a),
+        Cosh => call(Sinh, a),
+        Tanh => divide(Number(1.0), power(call(Cosh, a), Number(2.0))),
+        Exp => call(Exp, a),
+        Ln => divide(Number(1.0), a),
+        Log10 => divide(Number(1.0), times(a, call(Ln, Number(10.0)))),
+        Log2 => divide(Number(1.0), times(a, call(Ln, Number(2.0)))),
+        Sqrt => divide(Number(1.0), times(Number(2.0), call(Sqrt, a))),
+        Cbrt => divide(Number(1.0), times(Number(3.0), power(call(Cbrt, a), Number(2.0)))),
+        Abs => call(Sign, a),
+        Sign => Number(0.0),
+        op => return Err(RuntimeError::NotDifferentiable(op.name().to_lowercase())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parser};
+
+    fn deriv(source: &str) -> String {
+        let operations = parser::parse(lexer::scan(source).unwrap()).unwrap();
+        let expr = Expr::from_operations(&operations).unwrap();
+        derivative(&expr, "x", true).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_polynomials() {
+        assert_eq!(deriv("x^3 - 2*x + 7"), "3 * x^2 - 2");
+        assert_eq!(deriv("a * x^2"), "a * 2 * x");
+        assert_eq!(deriv("(x + 1)^2"), "2 * (x + 1)");
+        assert_eq!(deriv("-x^2"), "-2 * x");
+        assert_eq!(deriv("y"), "0");
+        assert_eq!(deriv("x^-2 + x"), "-2 * x^(-3) + 1");
+        assert_eq!(deriv("x - 3 / x"), "1 + 3 / x^2");
+    }
+
+    #[test]
+    fn test_product_quotient_and_chain_rules() {
+        assert_eq!(deriv("x * sin(x)"), "sin(x) + x * cos(x)");
+        assert_eq!(deriv("1 / x"), "-1 / x^2");
+        assert_eq!(deriv("x / (x + 1)"), "1 / (x + 1)^2");
+        assert_eq!(deriv("sin(x^2)"), "cos(x^2) * 2 * x");
+        assert_eq!(deriv("ln(cos(x))"), "-(sin(x) / cos(x))");
+        assert_eq!(deriv("x^x"), "x^x * (ln(x) + 1)");
+        assert_eq!(deriv("2^x"), "2^x * ln(2)");
+    }
+
+    #[test]
+    fn test_degrees() {
+        let operations = parser::parse(lexer::scan("sin(x)").unwrap()).unwrap();
+        let expr = Expr::from_operations(&operations).unwrap();
+        assert_eq!(derivative(&expr, "x", false).unwrap().to_string(), "pi / 180 * cos(x)");
+    }
+
+    #[test]
+    fn test_unsupported_function() {
+        let operations = parser::parse(lexer::scan("gamma(x)").unwrap()).unwrap();
+        let error = Expr::from_operations(&operations).unwrap_err();
+        assert_eq!(error, RuntimeError::NotDifferentiable("gamma".to_string()));
+    }
+}
@@ -1,35 +1,105 @@
 use std::fmt::Display;
 
 #[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum TokenType {    
     LeftParen,
     RightParen,
+    LeftBracket,
+    RightBracket,
+    LeftBrace,
+    RightBrace,
     Comma,
+    Semicolon,
+    Ampersand,
+    Pipe,
+    Tilde,
+    LessLess,
+    GreaterGreater,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    EqualEqual,
+    BangEqual,
+    Xor,
     
+    Bang,
     Caret,
     Minus,
     Plus,
     Slash,
+    SlashSlash,
     Star,
+    Mod,
+    At,
 
     Number,
+    Str,
     
     Sin, Cos, Tan,
-    ArcSin, ArcCos, ArcTan,
+    SinD, CosD, TanD, Deg2Rad, Rad2Deg,
+    ArcSin, ArcCos, ArcTan, ArcTan2,
+    Hypot, ToPolar, ToRect,
+    Sinh, Cosh, Tanh,
+    ArcSinh, ArcCosh, ArcTanh,
 
     Ln, Exp,
-    // TODO: Log10, will not be parsed as an identifier, which will be a problem.
+    Log, Log10, Log2,
+    Sqrt, Cbrt, Root,
+    Abs, Sign, Floor, Ceil, Round, Trunc, Frac,
+    SigFig,
+    Min, Max,
+    Haversine, Bearing,
+    Db, UnDb, DbmToMw, MwToDbm,
+    Parallel, VoltageDivider,
+    Odds, Prob, Bayes,
+    Compound, Pv, Fv, Npv, Irr,
+    Convert,
+    ToHex, ToBin, ToOct,
+    Hex2Rgb, Rgb2Hex, SrgbToLinear, LinearToSrgb,
 
-    Ans, Pi, E,
+    Digits, DigitSum, ReverseDigits,
+    Gcd, Lcm,
+    IsPrime, NextPrime, Factor,
+    PowMod,
+    Gamma, Fact,
+    Erf, Erfc, NormPdf, NormCdf,
+    Rand, RandInt, RandN,
+    Print, Assert,
+    Mean, Median, Mode, Var, Stdev, Sum, Prod,
+    WMean, Normalize, ZScore, LinReg,
+    Sort, Percentile, Iqr,
+    Unique, Union, Intersect,
+    CumSum, CumProd,
+    Len, Range,
+    Reverse, Head, Tail, Concat,
+    Transpose, Det, Inv,
+    Dot, Cross, Norm,
+    LinSolve,
+    PolyVal, PolyRoots,
+    Rationalize, ConFrac,
+    Integrate, Diff, Solve, Newton, Minimize, Maximize, Deriv, Equiv,
+    Map, Filter, Reduce,
+
+    Ans, Pi, E, Tau, Phi,
+    Identifier, Equal,
+    Let, In,
+    If, Then, Else,
+    For, To, While,
+    Import,
 
     EOF,
 }
 
+/// Byte offsets `(start, end)` of a token in the source line.
+pub type Span = (usize, usize);
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
-    pub span: (usize, usize),
+    pub span: Span,
 }
 impl Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
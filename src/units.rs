@@ -0,0 +1,155 @@
+/// Physical quantity a unit measures. Only units of the same dimension can
+/// be converted into each other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dimension {
+    Length,
+    Mass,
+    Time,
+    Volume,
+    Speed,
+    Area,
+    Energy,
+    Data,
+}
+
+use Dimension::*;
+
+/// Unit names and their size in the SI unit of their dimension (metres,
+/// kilograms, seconds, cubic metres, metres per second, square metres,
+/// joules), or in bytes for data. US customary volumes are used.
+const UNITS: &[(&str, Dimension, f64)] = &[
+    ("m", Length, 1.0),
+    ("km", Length, 1e3),
+    ("cm", Length, 1e-2),
+    ("mm", Length, 1e-3),
+    ("um", Length, 1e-6),
+    ("nm", Length, 1e-9),
+    ("in", Length, 0.0254),
+    ("ft", Length, 0.3048),
+    ("yd", Length, 0.9144),
+    ("mi", Length, 1609.344),
+    ("nmi", Length, 1852.0),
+
+    ("kg", Mass, 1.0),
+    ("g", Mass, 1e-3),
+    ("mg", Mass, 1e-6),
+    ("t", Mass, 1e3),
+    ("oz", Mass, 0.028_349_523_125),
+    ("lb", Mass, 0.453_592_37),
+    ("st", Mass, 6.350_293_18),
+
+    ("s", Time, 1.0),
+    ("ms", Time, 1e-3),
+    ("us", Time, 1e-6),
+    ("ns", Time, 1e-9),
+    ("min", Time, 60.0),
+    ("h", Time, 3600.0),
+    ("day", Time, 86400.0),
+    ("week", Time, 604_800.0),
+    ("yr", Time, 31_557_600.0),
+
+    ("m3", Volume, 1.0),
+    ("cm3", Volume, 1e-6),
+    ("l", Volume, 1e-3),
+    ("L", Volume, 1e-3),
+    ("ml", Volume, 1e-6),
+    ("mL", Volume, 1e-6),
+    ("gal", Volume, 3.785_411_784e-3),
+    ("qt", Volume, 9.463_529_46e-4),
+    ("pt", Volume, 4.731_764_73e-4),
+    ("cup", Volume, 2.365_882_365e-4),
+    ("floz", Volume, 2.957_352_956_25e-5),
+    ("tbsp", Volume, 1.478_676_478_125e-5),
+    ("tsp", Volume, 4.928_921_593_75e-6),
+
+    ("m/s", Speed, 1.0),
+    ("km/h", Speed, 1.0 / 3.6),
+    ("mph", Speed, 0.447_04),
+    ("ft/s", Speed, 0.3048),
+    ("kn", Speed, 1852.0 / 3600.0),
+
+    ("m2", Area, 1.0),
+    ("cm2", Area, 1e-4),
+    ("km2", Area, 1e6),
+    ("ha", Area, 1e4),
+    ("in2", Area, 6.4516e-4),
+    ("ft2", Area, 0.092_903_04),
+    ("acre", Area, 4_046.856_422_4),
+    ("mi2", Area, 2_589_988.110_336),
+
+    ("J", Energy, 1.0),
+    ("kJ", Energy, 1e3),
+    ("cal", Energy, 4.184),
+    ("kcal", Energy, 4184.0),
+    ("Wh", Energy, 3600.0),
+    ("kWh", Energy, 3.6e6),
+    ("eV", Energy, 1.602_176_634e-19),
+    ("BTU", Energy, 1_055.055_852_62),
+
+    ("bit", Data, 0.125),
+    ("B", Data, 1.0),
+    ("KB", Data, 1e3),
+    ("MB", Data, 1e6),
+    ("GB", Data, 1e9),
+    ("TB", Data, 1e12),
+    ("KiB", Data, 1024.0),
+    ("MiB", Data, 1_048_576.0),
+    ("GiB", Data, 1_073_741_824.0),
+    ("TiB", Data, 1_099_511_627_776.0),
+];
+
+/// Looks up a unit by its exact, case-sensitive name, e.g. `B` is a byte
+/// and `bit` a bit.
+pub fn lookup(name: &str) -> Option<(Dimension, f64)> {
+    UNITS
+        .iter()
+        .find(|(unit, _, _)| *unit == name)
+        .map(|&(_, dimension, factor)| (dimension, factor))
+}
+
+/// Converts `value` from one unit to another, or returns `None` if either
+/// unit is unknown or they measure different things.
+pub fn convert(value: f64, from: &str, to: &str) -> Option<f64> {
+    let (from_dimension, from_factor) = lookup(from)?;
+    let (to_dimension, to_factor) = lookup(to)?;
+    if from_dimension != to_dimension {
+        return None;
+    }
+    Some(value * from_factor / to_factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_converts(value: f64, from: &str, to: &str, expected: f64) {
+        let result = convert(value, from, to).unwrap();
+        assert!((result - expected).abs() <= 1e-12 * expected.abs(), "{} != {}", result, expected);
+    }
+
+    #[test]
+    fn test_convert_each_dimension() {
+        assert_converts(26.2, "mi", "km", 42.164_812_8);
+        assert_converts(1.0, "lb", "oz", 16.0);
+        assert_converts(2.0, "h", "min", 120.0);
+        assert_converts(1.0, "gal", "L", 3.785_411_784);
+        assert_converts(100.0, "km/h", "m/s", 27.777_777_777_777_78);
+        assert_converts(1.0, "acre", "ft2", 43_560.0);
+        assert_converts(1.0, "kWh", "kJ", 3600.0);
+        assert_converts(1.0, "GiB", "MB", 1_073.741_824);
+        assert_converts(1.0, "B", "bit", 8.0);
+    }
+
+    #[test]
+    fn test_unit_names_are_unique() {
+        for (i, (name, _, _)) in UNITS.iter().enumerate() {
+            assert!(UNITS[i + 1..].iter().all(|(other, _, _)| other != name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_convert_rejects_unknown_or_mismatched_units() {
+        assert_eq!(convert(1.0, "furlong", "m"), None);
+        assert_eq!(convert(1.0, "kg", "m"), None);
+    }
+}
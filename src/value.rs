@@ -0,0 +1,51 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::format::{format_value, FormatOptions};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+    Number(f64),
+    List(Vec<Value>),
+    Str(String),
+    /// A user function referred to by name, as in `map(f, xs)`.
+    Function(String),
+}
+
+impl Value {
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    /// Whether every number in the value, including inside lists, is
+    /// neither NaN nor infinite.
+    pub fn is_finite(&self) -> bool {
+        match self {
+            Value::Number(x) => x.is_finite(),
+            Value::List(xs) => xs.iter().all(Value::is_finite),
+            Value::Str(_) | Value::Function(_) => true,
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Number(x)
+    }
+}
+
+impl From<Vec<f64>> for Value {
+    fn from(xs: Vec<f64>) -> Self {
+        Value::List(xs.into_iter().map(Value::Number).collect())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_value(self, &FormatOptions::default()))
+    }
+}
@@ -0,0 +1,92 @@
+use crate::format::{format_value, FormatOptions};
+use crate::operation::Function;
+use crate::symbolic::Expr;
+use crate::value::Value;
+use crate::vm::VirtualMachine;
+
+/// Lists the variables and user functions defined in a session, sorted by
+/// name, and the previous answer.
+pub fn list(vm: &VirtualMachine) -> String {
+    let options = FormatOptions::default();
+    let mut text = String::new();
+
+    let variables = vm.variables();
+    if variables.is_empty() {
+        text.push_str("No variables\n");
+    } else {
+        text.push_str("Variables:\n");
+        for (name, value) in variables {
+            text.push_str(&binding(name, value, &options));
+        }
+    }
+
+    let functions = vm.functions();
+    if functions.is_empty() {
+        text.push_str("No functions\n");
+    } else {
+        text.push_str("Functions:\n");
+        for function in functions {
+            text.push_str(&format!("  {}\n", signature(function)));
+        }
+    }
+
+    match vm.get_prev_ans() {
+        Ok(ans) => text.push_str(binding("ans", &ans, &options).trim_start()),
+        Err(_) => text.push_str("No previous answer\n"),
+    }
+    text.trim_end().to_string()
+}
+
+/// `  name = value`, with the rows of a matrix lined up under the first.
+fn binding(name: &str, value: &Value, options: &FormatOptions) -> String {
+    let prefix = format!("  {} = ", name);
+    let value = format_value(value, options).replace('\n', &format!("\n{:1$}", "", prefix.len()));
+    format!("{}{}\n", prefix, value)
+}
+
+/// `f(x, y) = body` when the body is arithmetic that can be printed, and
+/// just `f(x, y)` otherwise.
+fn signature(function: &Function) -> String {
+    let call = format!("{}({})", function.name, function.params.join(", "));
+    match Expr::from_operations(&function.body) {
+        Ok(body) => format!("{} = {}", call, body),
+        Err(_) => call,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+    use crate::script;
+
+    fn run(vm: &mut VirtualMachine, source: &str) {
+        for statement in script::statements(lexer::scan(source).unwrap()) {
+            vm.interpret(&script::compile(statement).unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_list_empty_session() {
+        let vm = VirtualMachine::new();
+        assert_eq!(list(&vm), "No variables\nNo functions\nNo previous answer");
+    }
+
+    #[test]
+    fn test_list() {
+        let mut vm = VirtualMachine::new();
+        run(&mut vm, "y = [1, 2]; x = 5; f(t) = t^2 + 1; g(n) = if n then 1 else 0; f(x)");
+        assert_eq!(
+            list(&vm),
+            "Variables:\n  x = 5\n  y = [1, 2]\nFunctions:\n  f(t) = t^2 + 1\n  g(n)\nans = 26"
+        );
+    }
+
+    #[test]
+    fn test_list_shows_overridden_constants() {
+        let mut vm = VirtualMachine::new();
+        vm.allow_builtin_override = true;
+        run(&mut vm, "pi = 3");
+        assert!(list(&vm).starts_with("Variables:\n  pi = 3\n"));
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+
+use crate::operation::Operation;
+
+/// A sequence of operations that cannot run to a single result.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyError {
+    /// The operation at `index` pops more values than are on the stack.
+    Underflow { index: usize, needed: usize, available: usize },
+    /// The operations leave this many values instead of exactly one.
+    FinalDepth(usize),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::Underflow { index, needed, available } => write!(
+                f,
+                "Stack underflow at operation {}: needs {} values but {} available",
+                index + 1,
+                needed,
+                available
+            ),
+            VerifyError::FinalDepth(depth) => {
+                write!(f, "Operations leave {} values on the stack, expected 1", depth)
+            }
+        }
+    }
+}
+
+/// Checks the stack effect of `operations` without running them: no
+/// operation may pop more values than are on the stack, and exactly one
+/// value must remain at the end. Sub-programs, such as the branches of an
+/// `if`, are checked the same way; an error in one has its index within it.
+pub fn verify(operations: &[Operation]) -> Result<(), VerifyError> {
+    let mut depth = 0;
+    for (index, op) in operations.iter().enumerate() {
+        let needed = op.arity();
+        if depth < needed {
+            return Err(VerifyError::Underflow { index, needed, available: depth });
+        }
+        for (_, program) in op.sub_programs() {
+            verify(program)?;
+        }
+        depth = depth - needed + 1;
+    }
+
+    match depth {
+        1 => Ok(()),
+        depth => Err(VerifyError::FinalDepth(depth)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operation::Operation::*;
+    use crate::operation::{Branches, Function};
+
+    #[test]
+    fn test_valid_sequences() {
+        assert_eq!(verify(&[Const(1.0)]), Ok(()));
+        assert_eq!(verify(&[Const(1.0), Const(2.0), Add, Negate]), Ok(()));
+        assert_eq!(verify(&[MakeList(0)]), Ok(()));
+        assert_eq!(verify(&[Const(1.0), Const(2.0), Const(3.0), MakeList(3), Sum]), Ok(()));
+    }
+
+    #[test]
+    fn test_underflow() {
+        assert_eq!(
+            verify(&[Const(1.0), Add]),
+            Err(VerifyError::Underflow { index: 1, needed: 2, available: 1 })
+        );
+        assert_eq!(
+            verify(&[Const(1.0), MakeList(2)]),
+            Err(VerifyError::Underflow { index: 1, needed: 2, available: 1 })
+        );
+    }
+
+    #[test]
+    fn test_final_depth() {
+        assert_eq!(verify(&[]), Err(VerifyError::FinalDepth(0)));
+        assert_eq!(verify(&[Const(1.0), Const(2.0)]), Err(VerifyError::FinalDepth(2)));
+    }
+
+    #[test]
+    fn test_sub_programs() {
+        let branches = |then, otherwise| If(Box::new(Branches { then, otherwise }));
+        let valid = [Const(1.0), branches(vec![Const(2.0)], vec![Const(3.0), Negate])];
+        assert_eq!(verify(&valid), Ok(()));
+
+        let malformed = [Const(1.0), branches(vec![Const(2.0)], vec![Const(3.0), Add])];
+        assert_eq!(verify(&malformed), Err(VerifyError::Underflow { index: 1, needed: 2, available: 1 }));
+
+        let function = Function { name: "f".to_string(), params: vec!["x".to_string()], body: Vec::new() };
+        assert_eq!(verify(&[Define(Box::new(function))]), Err(VerifyError::FinalDepth(0)));
+
+        // Nested sub-programs are checked too.
+        let nested = branches(vec![Const(2.0), Const(3.0)], vec![Const(4.0)]);
+        let program = [Const(1.0), branches(vec![Const(1.0), nested], vec![Const(5.0)])];
+        assert_eq!(verify(&program), Err(VerifyError::FinalDepth(2)));
+    }
+
+    #[test]
+    fn test_display() {
+        let error = VerifyError::Underflow { index: 0, needed: 2, available: 0 };
+        assert_eq!(error.to_string(), "Stack underflow at operation 1: needs 2 values but 0 available");
+    }
+}
@@ -1,388 +1,5038 @@
-use std::collections::HashMap;
-use std::f64::consts::E;
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts::{E, PI, TAU};
 use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, LazyLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::thread;
 
-use crate::operation::Operation;
+use serde::{Deserialize, Serialize};
+
+use crate::compiled;
+use crate::format::{format_inline, format_number, format_radix, format_value, FormatOptions};
+use crate::matrix::{self, Lu, Matrix};
+use crate::operation::{Equation, Equivalence, Function, Loop, Operation};
 use crate::operation::Operation::*;
+use crate::lexer;
+use crate::numeric::{self, RootError};
+use crate::optimize;
+use crate::parser;
+use crate::poly;
+use crate::rational;
+use crate::rng::Rng;
+use crate::symbolic;
+use crate::token::Span;
+use crate::units;
+use crate::value::Value;
 
 const EPS: f64 = 1e-10;
+#[allow(dead_code)]
 const EPS_INTERNAL: f64 = 1e-15;
 
+/// Constants every VM starts with. They are variables, but assigning to
+/// them is an error unless `allow_builtin_override` is set.
+pub const BUILTINS: [(&str, f64); 4] = [
+    ("pi", PI),
+    ("e", E),
+    ("tau", TAU),
+    ("phi", 1.618_033_988_749_895),
+];
+
+fn builtin_table() -> HashMap<String, Value> {
+    BUILTINS.iter().map(|&(name, value)| (name.to_string(), Value::Number(value))).collect()
+}
+
+/// The most that [`Limits::max_call_depth`] may be set to.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// Nested user function calls continue on a fresh native stack of
+/// `CALL_STACK_SIZE` bytes every `CALLS_PER_STACK` levels, so that deep
+/// recursion fails with `RecursionLimit` rather than overflowing the stack
+/// of whichever thread is running the VM.
+const CALLS_PER_STACK: usize = 32;
+const CALL_STACK_SIZE: usize = 4 << 20;
+
+/// Number of previous answers kept for `ans(n)`.
+pub const ANS_HISTORY: usize = 100;
+
+/// Mean Earth radius in kilometres, used by `haversine` when no radius is given.
+pub const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+/// Absolute error `integrate` aims for when no tolerance is given.
+pub const INTEGRATE_TOLERANCE: f64 = 1e-10;
+
+/// Largest denominator `rationalize` tries when none is given.
+pub const RATIONALIZE_MAX_DENOMINATOR: f64 = 10_000.0;
+
+/// How many random points `equiv` compares two expressions at, and how far
+/// apart, relative to their size, the results may be.
+const EQUIV_SAMPLES: usize = 100;
+const EQUIV_TOLERANCE: f64 = 1e-9;
+/// `equiv` draws its points from a generator of its own with this seed, so
+/// that it gives the same answer every time and leaves the `:seed` sequence
+/// of `rand()` alone.
+const EQUIV_SEED: u64 = 0x5eed;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum RuntimeError {
     MathError,
     DomainError,
+    TypeError,
+    LengthMismatch,
     Underflow,
     NotImplemented,
-    NoPreviousAnswer
+    NoPreviousAnswer,
+    Interrupted,
+    AssertionFailed,
+    /// Writing the output of `print` failed.
+    Output(String),
+    UndefinedVariable(String),
+    ProtectedBuiltin(String),
+    UndefinedFunction(String),
+    ArgumentCount { function: String, expected: usize, found: usize },
+    RecursionLimit(usize),
+    IndexOutOfRange { index: i64, len: usize },
+    ShapeMismatch { left: (usize, usize), right: (usize, usize) },
+    NotSquare { rows: usize, cols: usize },
+    Singular,
+    /// `deriv` met a function it has no derivative rule for.
+    NotDifferentiable(String),
+    /// `solve` was given an interval whose ends have the same sign.
+    NoSignChange { a: f64, fa: f64, b: f64, fb: f64 },
+    /// A root finder gave up, with its last estimate.
+    NoConvergence { iterations: usize, x: f64, fx: f64 },
+    LimitExceeded(Limit),
+    /// An error raised by the operation at `index`, with the operands it was
+    /// applied to and, once located, the source it was compiled from.
+    Operation {
+        error: Box<RuntimeError>,
+        index: usize,
+        operation: Operation,
+        operands: Vec<Value>,
+        span: Option<Span>,
+    },
+}
+impl RuntimeError {
+    /// The underlying error without the operation that raised it.
+    pub fn kind(&self) -> &RuntimeError {
+        match self {
+            RuntimeError::Operation { error, .. } => error.kind(),
+            error => error,
+        }
+    }
+
+    /// Attaches the source span of the failing operation, given the spans
+    /// the parser recorded for each operation.
+    pub fn locate(self, spans: &[Span]) -> Self {
+        match self {
+            RuntimeError::Operation { error, index, operation, operands, .. } => {
+                let span = spans.get(index).copied();
+                RuntimeError::Operation { error, index, operation, operands, span }
+            }
+            error => error,
+        }
+    }
 }
 impl fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            RuntimeError::MathError => write!(f, "math error"),
+            RuntimeError::DomainError => write!(f, "domain error"),
+            RuntimeError::TypeError => write!(f, "type error"),
+            RuntimeError::LengthMismatch => write!(f, "list length mismatch"),
+            RuntimeError::Underflow => write!(f, "stack underflow"),
+            RuntimeError::NotImplemented => write!(f, "not implemented"),
+            RuntimeError::NoPreviousAnswer => write!(f, "no previous answer"),
+            RuntimeError::Interrupted => write!(f, "interrupted"),
+            RuntimeError::AssertionFailed => write!(f, "assertion failed"),
+            RuntimeError::Output(e) => write!(f, "could not write output: {}", e),
+            RuntimeError::UndefinedVariable(name) => write!(f, "undefined variable '{}'", name),
+            RuntimeError::UndefinedFunction(name) => write!(f, "undefined function '{}'", name),
+            RuntimeError::ArgumentCount { function, expected, found } => write!(
+                f,
+                "{} expects {} arguments, got {}",
+                function, expected, found
+            ),
+            RuntimeError::RecursionLimit(max) => {
+                write!(f, "function calls nested more than {} deep", max)
+            }
+            RuntimeError::IndexOutOfRange { index, len } => write!(
+                f,
+                "index {} is out of range for a list of length {}",
+                index, len
+            ),
+            RuntimeError::ShapeMismatch { left, right } => write!(
+                f,
+                "cannot multiply a {}x{} matrix by a {}x{} matrix",
+                left.0, left.1, right.0, right.1
+            ),
+            RuntimeError::NotSquare { rows, cols } => {
+                write!(f, "expected a square matrix, got {}x{}", rows, cols)
+            }
+            RuntimeError::Singular => write!(f, "matrix is singular"),
+            RuntimeError::NotDifferentiable(name) => {
+                write!(f, "cannot differentiate '{}' symbolically", name)
+            }
+            RuntimeError::NoSignChange { a, fa, b, fb } => write!(
+                f,
+                "f({}) = {} and f({}) = {} have the same sign, so there may be no root between them",
+                a, fa, b, fb
+            ),
+            RuntimeError::NoConvergence { iterations, x, fx } => write!(
+                f,
+                "no root found within {} iterations; last estimate x = {}, f(x) = {}",
+                iterations, x, fx
+            ),
+            RuntimeError::ProtectedBuiltin(name) => write!(
+                f,
+                "cannot assign to builtin '{}'; use :unsafe-override on to allow it",
+                name
+            ),
+            RuntimeError::LimitExceeded(limit) => write!(f, "limit exceeded: {}", limit),
+            RuntimeError::Operation { error, operation, operands, span, .. } => {
+                // Only the outermost of nested calls, branches and loops is
+                // named, so that deep recursion gives a short message.
+                let mut error = &**error;
+                while let RuntimeError::Operation {
+                    error: inner,
+                    operation: Call(..) | If(_) | For(_) | While(_),
+                    ..
+                } = error
+                {
+                    error = inner;
+                }
+                let options = FormatOptions::default();
+                let operands: Vec<String> =
+                    operands.iter().map(|v| format_inline(v, &options)).collect();
+                match (error.kind(), expected_domain(operation)) {
+                    // The branch's own error already names what failed.
+                    _ if matches!(operation, If(_) | For(_))
+                        && matches!(error, RuntimeError::Operation { .. }) =>
+                    {
+                        write!(f, "{}", error)?
+                    }
+                    (RuntimeError::DomainError, Some(domain)) => write!(
+                        f,
+                        "{}: {} expects {}, got {}",
+                        error, function_name(operation), domain, operands.join(", ")
+                    )?,
+                    _ => write!(f, "{} in {}", error, written(operation, &operands))?,
+                }
+                match span {
+                    Some((start, end)) => write!(f, " (at {}..{})", start, end),
+                    None => Ok(()),
+                }
+            }
+        }
     }
 }
 
+/// A resource limit on a single evaluation, see [`Limits`].
 #[derive(Debug, Clone, PartialEq)]
-pub struct InterpretOutput {
-    result: f64,
+pub enum Limit {
+    Operations(usize),
+    StackDepth(usize),
+    Timeout(Duration),
+    Iterations(usize),
+    ListLength(usize),
+    Evaluations(usize),
 }
-impl fmt::Display for InterpretOutput {
+impl fmt::Display for Limit {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Output: {}", self.result)
+        match self {
+            Limit::Operations(max) => write!(f, "more than {} operations executed", max),
+            Limit::StackDepth(max) => write!(f, "stack deeper than {} values", max),
+            Limit::Timeout(max) => write!(f, "ran for longer than {:?}", max),
+            Limit::Iterations(max) => write!(f, "loop ran more than {} times", max),
+            Limit::ListLength(max) => write!(f, "list longer than {} elements", max),
+            Limit::Evaluations(max) => {
+                write!(f, "function evaluated more than {} times by a numerical method", max)
+            }
+        }
     }
 }
 
-pub struct VirtualMachine {
-    pub use_radians: bool,
-    prev_ans: Vec<InterpretOutput>,
-    table: HashMap<String, f64>,
+/// Bounds on a single evaluation, so that runaway computations stop with
+/// `LimitExceeded` instead of hanging. `None` means unlimited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    pub max_operations: Option<usize>,
+    pub max_stack_depth: Option<usize>,
+    pub timeout: Option<Duration>,
+    /// Iterations of any single `for` or `while` loop.
+    pub max_iterations: Option<usize>,
+    /// Length of a list made by `range`, checked before it is allocated.
+    pub max_list_len: Option<usize>,
+    /// Function evaluations by one numerical method such as `integrate`.
+    pub max_evaluations: Option<usize>,
+    /// Iterations of `solve` and `newton` before they report
+    /// `NoConvergence`.
+    pub max_solver_iterations: usize,
+    /// Nesting of user function calls, e.g. in recursion, beyond which a
+    /// call fails with `RecursionLimit`. Always bounded, and capped at
+    /// [`MAX_CALL_DEPTH`].
+    pub max_call_depth: usize,
 }
-impl VirtualMachine {
-    pub fn new() -> Self {
+impl Default for Limits {
+    fn default() -> Self {
         Self {
-            use_radians: true,
-            prev_ans: Vec::new(),
-            table: HashMap::new(),
+            max_operations: Some(10_000_000),
+            max_stack_depth: Some(100_000),
+            timeout: Some(Duration::from_secs(30)),
+            max_iterations: Some(1_000_000),
+            max_list_len: Some(1_000_000),
+            max_evaluations: Some(100_000),
+            max_solver_iterations: 100,
+            max_call_depth: 1000,
         }
     }
+}
 
-    pub fn interpret(
-        &mut self,
-        operations: &Vec<Operation>,
-    ) -> Result<InterpretOutput, RuntimeError> {
-        let stack = &mut Vec::new();
-
-        for op in operations {
-            match op {
-                Add => interpret_add(stack)?,
-                Subtract => interpret_subtract(stack)?,
-                Times => interpret_times(stack)?,
-                Divide => interpret_divide(stack)?,
-                Negate => interpret_negate(stack)?,
-                Power => interpret_power(stack)?,
-
-                Sin | Cos | Tan => interpret_trig(
-                    stack,
-                    op,
-                    self.use_radians
-                )?,
-
-                ArcSin | ArcCos | ArcTan => interpret_inv_trig(
-                    stack,
-                    op,
-                    self.use_radians
-                )?,
-
-                Ans => interpret_const(
-                    stack,
-                    self.get_prev_ans()?
-                )?,
-                Ln => interpret_log(
-                    stack,
-                    E
-                )?,
-                Exp => interpret_exp(stack)?,
-                Const(val) => interpret_const(
-                    stack,
-                    *val)?,
-                _ => {
-                    return Err(RuntimeError::NotImplemented);
-                }
-            }
-        }
+/// The operands of an operation about to run, kept for its error message
+/// in case it fails. Numbers, by far the most common, are copied without
+/// allocating; other values only as far as the message shows them, so that
+/// e.g. indexing into a long list does not copy the list.
+enum Operands {
+    Numbers([f64; 3], usize),
+    Values(Vec<Value>),
+}
 
-        match stack.pop() {
-            Some(val) => {
-                let output = InterpretOutput { result: val };
-                self.prev_ans.push(output.clone());
-                Ok(output)
-            }
-            None => {
-                Err(RuntimeError::Underflow)
-            }
+impl Operands {
+    fn of(values: &[Value]) -> Self {
+        let mut numbers = [0.0; 3];
+        if values.len() <= numbers.len()
+            && values.iter().zip(&mut numbers).all(|(value, number)| match value {
+                Value::Number(x) => {
+                    *number = *x;
+                    true
+                }
+                _ => false,
+            })
+        {
+            return Operands::Numbers(numbers, values.len());
         }
+        Operands::Values(values.iter().map(preview).collect())
     }
 
-    fn get_prev_ans(&self) -> Result<f64, RuntimeError> {
-        let n = self.prev_ans.len();
-        match self.prev_ans.get(n - 1) {
-            Some(output) => Ok(output.result),
-            None => {
-                Err(RuntimeError::NoPreviousAnswer)
-            } 
+    fn into_values(self) -> Vec<Value> {
+        match self {
+            Operands::Numbers(numbers, len) => numbers[..len].iter().map(|&x| Value::Number(x)).collect(),
+            Operands::Values(values) => values,
         }
     }
 }
 
-fn interpret_log(stack: &mut Vec<f64>, base: f64) -> Result<(), RuntimeError> {
-    if let Some(x) = stack.pop() {
-        if x > 0.0 {
-            return Err(RuntimeError::DomainError);
-        } else {
-            let val = f64::ln(x) / f64::ln(base);
-            stack.push(val);
-            return Ok(());
+/// A copy of `value` that formats the same in an error message: lists too
+/// long to be shown in full keep only the items that are shown.
+fn preview(value: &Value) -> Value {
+    let max_items = FormatOptions::default().max_items;
+    match value {
+        Value::List(xs) if xs.len() > max_items + 1 => {
+            Value::List(xs[..max_items].iter().chain(xs.last()).map(preview).collect())
         }
+        Value::List(xs) => Value::List(xs.iter().map(preview).collect()),
+        value => value.clone(),
     }
-    Err(RuntimeError::Underflow)
 }
 
-fn interpret_exp(stack: &mut Vec<f64>) -> Result<(), RuntimeError> {
-    if let Some(a) = stack.pop() {
-        let val = E.powf(a);
-        stack.push(val);
-        return Ok(());
-    }
+/// How an operator is written, e.g. `//` for `FloorDivide`.
+fn operator_symbol(op: &Operation) -> Option<&'static str> {
+    let symbol = match op {
+        Add => "+",
+        Subtract | Negate => "-",
+        Times => "*",
+        Divide => "/",
+        FloorDivide => "//",
+        Modulo => "mod",
+        Power => "^",
+        MatMul => "@",
+        BitAnd => "&",
+        BitOr => "|",
+        BitXor => "xor",
+        BitNot => "~",
+        ShiftLeft => "<<",
+        ShiftRight => ">>",
+        Less => "<",
+        LessEqual => "<=",
+        Greater => ">",
+        GreaterEqual => ">=",
+        Equal => "==",
+        NotEqual => "!=",
+        _ => return None,
+    };
+    Some(symbol)
+}
 
-    Err(RuntimeError::Underflow)
+/// The name an operation is called by, e.g. `round` for `RoundTo`.
+fn function_name(op: &Operation) -> String {
+    let name = match op {
+        Call(function, _) => return function.clone(),
+        AnsAt => "ans",
+        Factorial => "fact",
+        ArcTan2 => "atan2",
+        LogBase => "log",
+        RoundTo => "round",
+        DbmToMw => "dbm_to_mw",
+        MwToDbm => "mw_to_dbm",
+        VoltageDivider => "voltage_divider",
+        ToHex => "to_hex",
+        ToBin => "to_bin",
+        ToOct => "to_oct",
+        SrgbToLinear => "srgb2linear",
+        LinearToSrgb => "linear2srgb",
+        _ => return op.name().to_lowercase(),
+    };
+    name.to_string()
 }
 
-fn interpret_power(stack: &mut Vec<f64>) -> Result<(), RuntimeError> {
-    if let (Some(b), Some(a)) = (stack.pop(), stack.pop()) {
-        let val = a.powf(b);
-        stack.push(val);
-        return Ok(());
+/// An operation applied to its formatted `operands` as it would be
+/// written, e.g. `1 // 0`, `xs[5]` or `round(2.5, x)`.
+fn written(op: &Operation, operands: &[String]) -> String {
+    match (operator_symbol(op), operands) {
+        (Some(symbol), [x, y]) => format!("{} {} {}", x, symbol, y),
+        (Some(symbol), [x]) => format!("{}{}", symbol, x),
+        _ => match (op, operands) {
+            (Index, [xs, i]) => format!("{}[{}]", xs, i),
+            (MakeList(_), _) => format!("[{}]", operands.join(", ")),
+            _ => format!("{}({})", function_name(op), operands.join(", ")),
+        },
     }
-    Err(RuntimeError::Underflow)
 }
 
-fn interpret_add(stack: &mut Vec<f64>) -> Result<(), RuntimeError> {
-    if let (Some(x), Some(y)) = (stack.pop(), stack.pop()) {
-        stack.push(y + x);
-        return Ok(());
+/// The inputs an operation accepts, for operations with a simple domain.
+fn expected_domain(op: &Operation) -> Option<&'static str> {
+    match op {
+        ArcSin | ArcCos => Some("[-1, 1]"),
+        ArcCosh => Some("[1, inf)"),
+        ArcTanh => Some("(-1, 1)"),
+        Ln | Log10 | Log2 | Db | MwToDbm => Some("(0, inf)"),
+        Sqrt | Prob => Some("[0, inf)"),
+        Odds => Some("[0, 1]"),
+        Factorial => Some("a non-negative integer"),
+        Cross => Some("two vectors of length 3"),
+        Rationalize => Some("a finite number and a whole maximum denominator of at least 1"),
+        ConFrac => Some("a finite number and a whole number of terms of at least 1"),
+        _ => None,
     }
-
-    Err(RuntimeError::Underflow)
 }
 
-fn interpret_const(stack: &mut Vec<f64>, value: f64) -> Result<(), RuntimeError> {
-    stack.push(value.clone());
-    Ok(())
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterpretOutput {
+    result: Value,
 }
-
-fn interpret_subtract(stack: &mut Vec<f64>) -> Result<(), RuntimeError> {
-    if let (Some(x), Some(y)) = (stack.pop(), stack.pop()) {
-        stack.push(y - x);
-        return Ok(());
+impl InterpretOutput {
+    pub fn result(&self) -> &Value {
+        &self.result
     }
-
-    Err(RuntimeError::Underflow)
 }
 
-fn interpret_times(stack: &mut Vec<f64>) -> Result<(), RuntimeError> {
-    if let (Some(x), Some(y)) = (stack.pop(), stack.pop()) {
-        stack.push(y * x);
-        return Ok(());
-    }
+/// How often an opcode ran successfully and the time spent in it.
+#[cfg(feature = "profile")]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpStats {
+    pub count: usize,
+    pub time: Duration,
+}
 
-    Err(RuntimeError::Underflow)
+/// The settings and results that carry over between evaluations, for
+/// saving a session and restoring it later, e.g. as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub use_radians: bool,
+    pub word_size: Option<u32>,
+    pub strict: bool,
+    /// Previous answers, oldest first, at most [`ANS_HISTORY`] of them.
+    pub answers: Vec<Value>,
+    pub variables: HashMap<String, Value>,
+    /// User functions by name. Older snapshots have none.
+    #[serde(default)]
+    pub functions: Vec<Function>,
 }
 
-fn interpret_divide(stack: &mut Vec<f64>) -> Result<(), RuntimeError> {
-    if let (Some(x), Some(y)) = (stack.pop(), stack.pop()) {
-        return if x == 0.0 {
-            Err(RuntimeError::MathError)
-        } else {
-            stack.push(y / x);
-            return Ok(());
-        };
-    }
+/// Session state saved by [`VirtualMachine::checkpoint`].
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    answers: VecDeque<InterpretOutput>,
+    variables: HashMap<String, Value>,
+    functions: HashMap<String, Arc<Function>>,
+}
 
-    Err(RuntimeError::Underflow)
+/// An executed operation and the stack after it, recorded in trace mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    pub operation: Operation,
+    pub stack: Vec<Value>,
 }
 
-fn interpret_negate(stack: &mut Vec<f64>) -> Result<(), RuntimeError> {
-    if let Some(val) = stack.pop() {
-        stack.push(-val);
-        return Ok(());
+/// Configures a [`VirtualMachine`] before it is created, e.g.
+/// `VirtualMachine::builder().strict(true).build()`.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualMachineBuilder {
+    strict: bool,
+}
+impl VirtualMachineBuilder {
+    /// See [`VirtualMachine::strict`].
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
     }
 
-    Err(RuntimeError::Underflow)
+    pub fn build(self) -> VirtualMachine {
+        let mut vm = VirtualMachine::new();
+        vm.strict = self.strict;
+        vm
+    }
 }
 
-fn interpret_trig(
-    stack: &mut Vec<f64>,
-    op: &Operation,
-    use_radians: bool,
-) -> Result<(), RuntimeError> {
-    if let Some(val) = stack.pop() {
-        let operand = if use_radians { val } else { val.to_radians() };
-        let result = match op {
-            Sin => operand.sin(),
-            Cos => operand.cos(),
-            Tan => operand.tan(),
-            _ => {
-                return Err(RuntimeError::NotImplemented);
-            }
-        };
+pub struct VirtualMachine {
+    pub use_radians: bool,
+    /// Word size in bits when in programmer mode. Every result is then
+    /// truncated to an integer and wrapped to a signed word.
+    pub word_size: Option<u32>,
+    /// Raise `MathError` as soon as an operation produces NaN or infinity.
+    /// Otherwise they propagate as in IEEE arithmetic and print as `nan`
+    /// and `inf`.
+    pub strict: bool,
+    /// Record every executed operation and the stack after it, available
+    /// from [`trace`](Self::trace) until the next evaluation.
+    pub tracing: bool,
+    pub limits: Limits,
+    /// Allow assigning to builtin constants such as `pi`.
+    pub allow_builtin_override: bool,
+    /// Where `print` writes its values, one per line. Standard output by
+    /// default; replace it to capture what a script prints.
+    pub output: Box<dyn Write + Send>,
+    stack: Vec<Value>,
+    /// Operations run and start time of the current evaluation, including
+    /// those run inside function calls.
+    executed: usize,
+    started: Instant,
+    functions: HashMap<String, Arc<Function>>,
+    /// Local bindings, innermost last: the parameters of each user function
+    /// call in progress and the names bound by `let` inside them. Names are
+    /// looked up from `frame` upwards, so a function body cannot see the
+    /// locals of its caller.
+    scopes: Vec<HashMap<String, Value>>,
+    /// Index in `scopes` of the innermost function call's parameters.
+    frame: usize,
+    /// Number of user function calls in progress.
+    depth: usize,
+    trace: Vec<TraceStep>,
+    prev_ans: VecDeque<InterpretOutput>,
+    rng: Rng,
+    interrupt: Arc<AtomicBool>,
+    table: HashMap<String, Value>,
+    #[cfg(feature = "profile")]
+    profile: HashMap<String, OpStats>,
+}
+impl VirtualMachine {
+    pub fn builder() -> VirtualMachineBuilder {
+        VirtualMachineBuilder::default()
+    }
 
-        stack.push(result);
-        return Ok(());
+    pub fn new() -> Self {
+        Self {
+            use_radians: true,
+            word_size: None,
+            strict: false,
+            tracing: false,
+            limits: Limits::default(),
+            allow_builtin_override: false,
+            output: Box::new(io::stdout()),
+            stack: Vec::new(),
+            executed: 0,
+            started: Instant::now(),
+            functions: HashMap::new(),
+            scopes: Vec::new(),
+            frame: 0,
+            depth: 0,
+            trace: Vec::new(),
+            prev_ans: VecDeque::new(),
+            rng: Rng::from_time(),
+            interrupt: Arc::new(AtomicBool::new(false)),
+            table: builtin_table(),
+            #[cfg(feature = "profile")]
+            profile: HashMap::new(),
+        }
     }
 
-    Err(RuntimeError::Underflow)
-}
+    /// A flag that, once set (e.g. from a Ctrl-C handler), makes the running
+    /// evaluation stop with `Interrupted` before its next operation. The
+    /// flag is cleared when it is acted on.
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.interrupt)
+    }
 
-fn interpret_inv_trig(
-    stack: &mut Vec<f64>,
-    op: &Operation,
-    use_radians: bool,
-) -> Result<(), RuntimeError> {
-    if let Some(val) = stack.pop() {
-        let result = match op {
-            ArcSin => val.asin(),
-            ArcCos => val.acos(),
-            ArcTan => val.atan(),
-            _ => {
-                return Err(RuntimeError::NotImplemented);
-            }
-        };
+    /// Reseeds the random number generator so that `rand`, `randint` and
+    /// `randn` produce a reproducible sequence.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
 
-        if result.is_nan() {
-            return Err(RuntimeError::DomainError);
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            use_radians: self.use_radians,
+            word_size: self.word_size,
+            strict: self.strict,
+            answers: self.prev_ans.iter().map(|output| output.result.clone()).collect(),
+            variables: self.table.clone(),
+            functions: self.functions().into_iter().cloned().collect(),
         }
+    }
 
-        let result = if use_radians {
-            result
-        } else {
-            result.to_degrees()
-        };
-        stack.push(result);
+    /// Replaces the session state with `snapshot`. Limits, tracing and the
+    /// random number generator are left as they are.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.use_radians = snapshot.use_radians;
+        self.word_size = snapshot.word_size;
+        self.strict = snapshot.strict;
+        let skip = snapshot.answers.len().saturating_sub(ANS_HISTORY);
+        self.prev_ans = snapshot
+            .answers
+            .into_iter()
+            .skip(skip)
+            .map(|result| InterpretOutput { result })
+            .collect();
+        self.table = snapshot.variables;
+        self.functions = snapshot
+            .functions
+            .into_iter()
+            .map(|function| (function.name.clone(), Arc::new(function)))
+            .collect();
+    }
 
-        return Ok(());
+    /// Saves the variables, functions and previous answers, for
+    /// [`rollback`](Self::rollback) to return to.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            answers: self.prev_ans.clone(),
+            variables: self.table.clone(),
+            functions: self.functions.clone(),
+        }
     }
 
-    Err(RuntimeError::Underflow)
-}
+    /// Undoes every assignment, definition and answer since `checkpoint`.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.prev_ans = checkpoint.answers;
+        self.table = checkpoint.variables;
+        self.functions = checkpoint.functions;
+    }
 
-#[cfg(test)]
-mod tests {
-    use core::f64;
-    use std::f64::consts::{FRAC_PI_4, FRAC_PI_2, PI};
+    /// Forgets every variable, function and previous answer, restoring the
+    /// builtin constants.
+    pub fn clear(&mut self) {
+        self.prev_ans.clear();
+        self.functions.clear();
+        self.table = builtin_table();
+    }
 
-    use super::*;
+    /// Restores the angle mode, word size, limits and other settings to
+    /// their defaults, keeping the variables, functions and answers.
+    pub fn reset_settings(&mut self) {
+        let defaults = Self::new();
+        self.use_radians = defaults.use_radians;
+        self.word_size = defaults.word_size;
+        self.strict = defaults.strict;
+        self.tracing = defaults.tracing;
+        self.limits = defaults.limits;
+        self.allow_builtin_override = defaults.allow_builtin_override;
+    }
 
-    fn eval(ops: Vec<Operation>) -> f64 {
-        let mut vm = VirtualMachine::new();
-        vm.interpret(&ops).unwrap().result
+    /// The steps of the last evaluation, if it ran with `tracing` on. An
+    /// evaluation that failed ends with the last step that succeeded.
+    pub fn trace(&self) -> &[TraceStep] {
+        &self.trace
     }
 
-    fn assert_approx_eq(a: f64, b: f64) {
-        assert!(approx_eq(a, b, EPS_INTERNAL))
+    pub fn interpret(
+        &mut self,
+        operations: &[Operation],
+    ) -> Result<InterpretOutput, RuntimeError> {
+        self.interpret_observed(operations, |_, _| {})
     }
 
-    fn assert_runtime_error(ops: Vec<Operation>, expected_error: RuntimeError) {
-        let mut vm = VirtualMachine::new();
-        let result = vm.interpret(&ops);
-        match result {
-            Ok(_) => panic!("Expected runtime error {:?}, but got Ok", expected_error),
-            Err(e) => assert_eq!(e, expected_error),
+    /// Like [`interpret`](Self::interpret), but calls `observe` with each
+    /// operation's index and the stack after it has run.
+    pub fn interpret_observed<F>(
+        &mut self,
+        operations: &[Operation],
+        observe: F,
+    ) -> Result<InterpretOutput, RuntimeError>
+    where
+        F: FnMut(usize, &[Value]),
+    {
+        // The stack's allocation is kept between evaluations.
+        let mut stack = std::mem::take(&mut self.stack);
+        stack.clear();
+        let result = self.run(&mut stack, operations, observe);
+        self.stack = stack;
+
+        let output = InterpretOutput { result: result? };
+        if self.prev_ans.len() == ANS_HISTORY {
+            self.prev_ans.pop_front();
         }
+        self.prev_ans.push_back(output.clone());
+        Ok(output)
     }
 
-    fn approx_eq(a: f64, b: f64, eps: f64) -> bool {
-        (a - b).abs() < eps
+    /// Like [`interpret`](Self::interpret), but without recording the
+    /// result as an answer, so that `ans` is unchanged. Used to load
+    /// definitions.
+    pub fn evaluate(&mut self, operations: &[Operation]) -> Result<Value, RuntimeError> {
+        self.evaluate_with(operations, HashMap::new())
     }
 
-    #[test]
-    fn test_simple_const() {
-        let ops = vec![Const(42.0)];
-        assert_eq!(eval(ops), 42.0);
+    /// Like [`evaluate`](Self::evaluate), with `locals` bound around the
+    /// whole program as if by a `let`, so that they shadow variables of the
+    /// same name without assigning them.
+    pub fn evaluate_with(
+        &mut self,
+        operations: &[Operation],
+        locals: HashMap<String, Value>,
+    ) -> Result<Value, RuntimeError> {
+        let mut stack = std::mem::take(&mut self.stack);
+        stack.clear();
+        self.start();
+        self.scopes.push(locals);
+        let result = self.run_ops(&mut stack, operations, |_, _| {});
+        self.scopes.clear();
+        self.stack = stack;
+        result
     }
 
-    #[test]
-    fn test_addition() {
-        let ops = vec![Const(1.0), Const(2.0), Add];
-        assert_eq!(eval(ops), 3.0);
+    fn run<F>(
+        &mut self,
+        stack: &mut Vec<Value>,
+        operations: &[Operation],
+        observe: F,
+    ) -> Result<Value, RuntimeError>
+    where
+        F: FnMut(usize, &[Value]),
+    {
+        self.start();
+        self.run_ops(stack, operations, observe)
     }
 
-    #[test]
-    fn test_subtraction() {
-        let ops = vec![Const(10.0), Const(3.0), Subtract];
-        assert_eq!(eval(ops), 7.0);
+    /// Resets the per-evaluation state before a program runs.
+    fn start(&mut self) {
+        self.trace.clear();
+        self.started = Instant::now();
+        self.executed = 0;
+        self.scopes.clear();
+        self.frame = 0;
+        self.depth = 0;
     }
 
-    #[test]
-    fn test_multiplication() {
-        let ops = vec![Const(6.0), Const(7.0), Times];
-        assert_eq!(eval(ops), 42.0);
-    }
+    fn run_ops<F>(
+        &mut self,
+        stack: &mut Vec<Value>,
+        operations: &[Operation],
+        mut observe: F,
+    ) -> Result<Value, RuntimeError>
+    where
+        F: FnMut(usize, &[Value]),
+    {
+        for (i, op) in operations.iter().enumerate() {
+            self.run_op(stack, i, op)?;
+            observe(i, stack);
+        }
 
-    #[test]
-    fn test_division() {
-        let ops = vec![Const(20.0), Const(4.0), Divide];
-        assert_eq!(eval(ops), 5.0);
+        stack.pop().ok_or(RuntimeError::Underflow)
     }
 
-    #[test]
-    fn test_unary_negation() {
-        let ops = vec![Const(5.0), Negate];
-        assert_eq!(eval(ops), -5.0);
+    /// Runs operation `i` of a program, checking for interrupts and limits
+    /// and attaching the operation to any error it raises.
+    fn run_op(&mut self, stack: &mut Vec<Value>, i: usize, op: &Operation) -> Result<(), RuntimeError> {
+        if self.interrupt.swap(false, Ordering::Relaxed) {
+            return Err(RuntimeError::Interrupted);
+        }
+        self.check_limits()?;
+        self.executed += 1;
+
+        let arity = op.arity();
+        let operands = Operands::of(&stack[stack.len().saturating_sub(arity)..]);
+        let in_operation = |error| RuntimeError::Operation {
+            error: Box::new(error),
+            index: i,
+            operation: op.clone(),
+            operands: operands.into_values(),
+            span: None,
+        };
+        #[cfg(feature = "profile")]
+        let op_started = Instant::now();
+        // Sub-programs skip `step`, whose large stack frame would otherwise
+        // be paid once per nested call.
+        let stepped = match op {
+            Call(..) | If(_) | For(_) | While(_) |
+            Map | Filter | Reduce | Integrate | Diff |
+            Solve | Newton | Minimize | Maximize |
+            SolveFor(_) | Equiv(_) => self.control_flow(stack, op),
+            _ => self.step(stack, op),
+        };
+        match stepped {
+            Err(error @ (RuntimeError::Underflow | RuntimeError::ProtectedBuiltin(_))) => {
+                return Err(error);
+            }
+            Err(error) if arity == 0 => return Err(error),
+            Err(error) => return Err(in_operation(error)),
+            Ok(()) => {}
+        }
+        #[cfg(feature = "profile")]
+        {
+            let stats = self.profile.entry(op.name()).or_default();
+            stats.count += 1;
+            stats.time += op_started.elapsed();
+        }
+        if let Some(max) = self.limits.max_stack_depth
+            && stack.len() > max
+        {
+            return Err(RuntimeError::LimitExceeded(Limit::StackDepth(max)));
+        }
+        if self.strict && stack.last().is_some_and(|top| !top.is_finite()) {
+            return Err(in_operation(RuntimeError::MathError));
+        }
+        if let Some(bits) = self.word_size
+            && let Some(top) = stack.last_mut()
+        {
+            wrap_value(top, bits);
+        }
+        if self.tracing {
+            self.trace.push(TraceStep { operation: op.clone(), stack: stack.clone() });
+        }
+        Ok(())
     }
 
-    #[test]
-    fn test_chained_expression() {
-        // Equivalent to: 1 + 2 * 3  → RPN: 1 2 3 * +
-        let ops = vec![Const(1.0), Const(2.0), Const(3.0), Times, Add];
-        assert_eq!(eval(ops), 7.0);
+    /// Fails if running one more operation would go past the operation
+    /// count or time [`Limits`] of the current evaluation.
+    fn check_limits(&self) -> Result<(), RuntimeError> {
+        let limits = &self.limits;
+        if let Some(max) = limits.max_operations
+            && self.executed >= max
+        {
+            return Err(RuntimeError::LimitExceeded(Limit::Operations(max)));
+        }
+        if let Some(max) = limits.timeout
+            && self.started.elapsed() > max
+        {
+            return Err(RuntimeError::LimitExceeded(Limit::Timeout(max)));
+        }
+        Ok(())
     }
 
-    #[test]
-    fn test_division_by_zero() {
-        let ops = vec![Const(5.0), Const(0.0), Divide];
+    /// Runs one operation. Element-wise operations with list operands are
+    /// broadcast: a scalar pairs with every element, lists of equal length
+    /// pair element by element, and nested lists are handled recursively.
+    fn step(&mut self, stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+        let arity = op.arity();
+        let has_list = stack.len() >= arity
+            && stack[stack.len() - arity..].iter().any(|v| matches!(v, Value::List(_)));
+        if !op.is_elementwise() || !has_list {
+            return self.execute(stack, op);
+        }
 
-        assert_runtime_error(ops, RuntimeError::MathError);
+        let args = stack.split_off(stack.len() - arity);
+        let mut len = None;
+        for arg in &args {
+            if let Value::List(xs) = arg {
+                match len {
+                    Some(n) if n != xs.len() => return Err(RuntimeError::LengthMismatch),
+                    _ => len = Some(xs.len()),
+                }
+            }
+        }
+
+        let mut results = Vec::new();
+        for i in 0..len.unwrap_or(0) {
+            let mut elements: Vec<Value> = args
+                .iter()
+                .map(|arg| match arg {
+                    Value::List(xs) => xs[i].clone(),
+                    scalar => scalar.clone(),
+                })
+                .collect();
+            self.step(&mut elements, op)?;
+            results.extend(elements.pop());
+        }
+        stack.push(Value::List(results));
+        Ok(())
     }
 
-    #[test]
-    fn test_stack_underflow() {
-        let ops = vec![Add];
+    /// Applies one operation to `operands` and returns its value, as
+    /// [`step`](Self::step) would, without setting up a whole evaluation.
+    /// Errors are not attached to the operation.
+    pub fn apply(&mut self, op: &Operation, operands: Vec<Value>) -> Result<Value, RuntimeError> {
+        let mut stack = operands;
+        self.step(&mut stack, op)?;
+        stack.pop().ok_or(RuntimeError::Underflow)
+    }
 
-        assert_runtime_error(ops, RuntimeError::Underflow);
+    /// Runs the operations that evaluate sub-programs.
+    fn control_flow(&mut self, stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+        let result = match op {
+            Call(name, argc) => {
+                if stack.len() < *argc {
+                    return Err(RuntimeError::Underflow);
+                }
+                let args = stack.split_off(stack.len() - argc);
+                self.call_function(name, args)?
+            }
+            If(branches) => {
+                let branch = match stack.pop().ok_or(RuntimeError::Underflow)? {
+                    Value::Number(x) if x != 0.0 => &branches.then,
+                    Value::Number(_) => &branches.otherwise,
+                    _ => return Err(RuntimeError::TypeError),
+                };
+                self.run_block(branch)?
+            }
+            For(body) => {
+                let last = pop_number(stack)?;
+                let first = pop_number(stack)?;
+                self.run_for(body, first, last)?
+            }
+            While(body) => self.run_while(body)?,
+            Map | Filter => {
+                let xs = pop_list(stack)?;
+                let function = pop_function(stack)?;
+                self.map_filter(op, &function, xs)?
+            }
+            Reduce => {
+                let xs = pop_list(stack)?;
+                let init = stack.pop().ok_or(RuntimeError::Underflow)?;
+                let function = pop_function(stack)?;
+                let mut acc = init;
+                for x in xs {
+                    acc = self.call_function(&function, vec![acc, x])?;
+                }
+                acc
+            }
+            Integrate => {
+                let tolerance = pop_number(stack)?;
+                let (b, a) = (pop_number(stack)?, pop_number(stack)?);
+                let function = pop_function(stack)?;
+                if tolerance.is_nan() || tolerance <= 0.0 {
+                    return Err(RuntimeError::DomainError);
+                }
+                let integral = self.with_numeric_function(&function, |f| {
+                    numeric::integrate(f, a, b, tolerance)
+                })?;
+                Value::Number(integral)
+            }
+            Diff => {
+                let x = pop_number(stack)?;
+                let function = pop_function(stack)?;
+                let slope = self.with_numeric_function(&function, |f| numeric::derivative(f, x))?;
+                Value::Number(slope)
+            }
+            Solve | Newton => {
+                let iterations = self.limits.max_solver_iterations;
+                let (a, b) = match op {
+                    Solve => {
+                        let b = pop_number(stack)?;
+                        (pop_number(stack)?, b)
+                    }
+                    _ => (pop_number(stack)?, 0.0),
+                };
+                let function = pop_function(stack)?;
+                let root = self.with_numeric_function(&function, |f| {
+                    let root = match op {
+                        Solve => numeric::brent(f, a, b, iterations),
+                        _ => numeric::newton(f, a, iterations),
+                    };
+                    root.map_err(|e| match e {
+                        RootError::Function(e) => e,
+                        RootError::NoSignChange { fa, fb } => RuntimeError::NoSignChange { a, fa, b, fb },
+                        RootError::NoConvergence { x, fx } => RuntimeError::NoConvergence { iterations, x, fx },
+                    })
+                })?;
+                Value::Number(root)
+            }
+            Minimize | Maximize => {
+                let b = pop_number(stack)?;
+                let a = pop_number(stack)?;
+                let function = pop_function(stack)?;
+                let iterations = self.limits.max_solver_iterations;
+                // Maximizing `f` is minimizing `-f`.
+                let sign = if *op == Minimize { 1.0 } else { -1.0 };
+                let (x, fx) = self.with_numeric_function(&function, |f| {
+                    numeric::minimize(&mut |x| Ok(sign * f(x)?), a, b, iterations)
+                })?;
+                Value::from(vec![x, sign * fx])
+            }
+            SolveFor(equation) => Value::Number(self.solve_equation(equation)?),
+            Equiv(equivalence) => Value::Str(self.check_equivalence(equivalence)?),
+            _ => return Err(RuntimeError::NotImplemented),
+        };
+        stack.push(result);
+        Ok(())
     }
 
-    #[test]
-    fn test_overflow_behavior() {
-        let ops = vec![Const(f64::MAX), Const(2.0), Times];
+    fn execute(&mut self, stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+        match op {
+            Add => interpret_add(stack)?,
+            Subtract => interpret_subtract(stack)?,
+            Times => interpret_times(stack)?,
+            Divide => interpret_divide(stack)?,
+            FloorDivide | Modulo => interpret_floor_divide(stack, op)?,
+            Negate => interpret_negate(stack)?,
+            Power => interpret_power(stack)?,
+            Min | Max => interpret_min_max(stack, op)?,
+            BitAnd | BitOr | BitXor |
+            ShiftLeft | ShiftRight => interpret_bitwise(stack, op)?,
+            BitNot => interpret_bit_not(stack)?,
 
-        let mut vm = VirtualMachine::new();
-        let result = vm.interpret(&ops).unwrap().result;
-        assert!(result.is_infinite());
+            Sin | Cos | Tan => interpret_trig(
+                stack,
+                op,
+                self.use_radians
+            )?,
+
+            ArcSin | ArcCos | ArcTan | ArcTan2 => interpret_inv_trig(
+                stack,
+                op,
+                self.use_radians
+            )?,
+
+            SinD | CosD | TanD => interpret_trig_degrees(stack, op)?,
+            Deg2Rad => interpret_unary(stack, f64::to_radians)?,
+            Rad2Deg => interpret_unary(stack, f64::to_degrees)?,
+            Hypot => interpret_hypot(stack)?,
+            ToPolar | ToRect => interpret_polar(stack, op, self.use_radians)?,
+
+            Sinh | Cosh | Tanh |
+            ArcSinh | ArcCosh | ArcTanh => interpret_hyperbolic(
+                stack,
+                op
+            )?,
+
+            Ans => interpret_const(
+                stack,
+                self.get_prev_ans()?
+            )?,
+            Load(name) => interpret_const(stack, self.get_variable(name)?)?,
+            Define(function) => {
+                let signature = self.define_function(function);
+                stack.push(signature);
+            }
+            Deriv(derivative) => {
+                let expr = symbolic::Expr::from_operations(&derivative.expression)?;
+                let slope = symbolic::derivative(&expr, &derivative.variable, self.use_radians)?;
+                stack.push(Value::Str(slope.to_string()));
+            }
+            Call(..) | If(_) | For(_) | While(_) |
+            Map | Filter | Reduce | Integrate | Diff |
+            Solve | Newton | Minimize | Maximize |
+            SolveFor(_) | Equiv(_) => self.control_flow(stack, op)?,
+            Sequence => {
+                let last = stack.pop().ok_or(RuntimeError::Underflow)?;
+                stack.pop().ok_or(RuntimeError::Underflow)?;
+                stack.push(last);
+            }
+            Less | LessEqual | Greater | GreaterEqual | Equal | NotEqual => {
+                interpret_compare(stack, op)?
+            }
+            PushScope(name) => {
+                let value = stack.last().cloned().ok_or(RuntimeError::Underflow)?;
+                self.scopes.push(HashMap::from([(name.clone(), value)]));
+            }
+            PopScope => {
+                let result = stack.pop().ok_or(RuntimeError::Underflow)?;
+                stack.pop().ok_or(RuntimeError::Underflow)?;
+                self.scopes.pop();
+                stack.push(result);
+            }
+
+            Store(name) => {
+                let value = stack.last().cloned().ok_or(RuntimeError::Underflow)?;
+                self.store_variable(name, value)?;
+            }
+            AnsAt => {
+                let n = as_integer(pop_number(stack)?)?;
+                interpret_const(stack, self.nth_prev_ans(n)?)?
+            }
+            Ln | Log10 | Log2 | LogBase => interpret_log(
+                stack,
+                op
+            )?,
+            Exp => interpret_exp(stack)?,
+            Sqrt | Cbrt | Root => interpret_root(stack, op)?,
+            Abs | Sign | Floor | Ceil |
+            Round | Trunc | Frac => interpret_rounding(stack, op)?,
+            RoundTo | SigFig => interpret_round_to(stack, op)?,
+            Db | UnDb | DbmToMw | MwToDbm => interpret_decibel(stack, op)?,
+            Parallel => interpret_parallel(stack)?,
+            VoltageDivider => interpret_voltage_divider(stack)?,
+            Odds | Prob => interpret_odds(stack, op)?,
+            Bayes => interpret_bayes(stack)?,
+            Compound => interpret_compound(stack)?,
+            Pv | Fv => interpret_time_value(stack, op)?,
+            Npv => interpret_npv(stack)?,
+            Irr => interpret_irr(stack)?,
+            Convert => interpret_convert(stack)?,
+            ToHex | ToBin | ToOct => interpret_to_radix(stack, op)?,
+            Haversine => interpret_haversine(stack)?,
+            Bearing => interpret_bearing(stack)?,
+
+            Digits | DigitSum | ReverseDigits => interpret_digits(
+                stack,
+                op
+            )?,
+            Gcd | Lcm => interpret_gcd(stack, op)?,
+            IsPrime | NextPrime | Factor => interpret_prime(stack, op)?,
+            PowMod => interpret_powmod(stack)?,
+            Gamma => interpret_gamma(stack)?,
+            Erf | Erfc => interpret_erf(stack, op)?,
+            NormPdf | NormCdf => interpret_normal(stack, op)?,
+            Rand => push_number(stack, self.rng.next_f64()),
+            RandN => push_number(stack, self.rng.next_normal()),
+            RandInt => interpret_randint(stack, &mut self.rng)?,
+            Print => interpret_print(stack, &mut self.output)?,
+            Assert => interpret_assert(stack)?,
+            Mean | Median | Mode | Var | Stdev |
+            Sum | Prod => interpret_statistics(stack, op)?,
+            WMean => interpret_wmean(stack)?,
+            LinReg => interpret_linreg(stack)?,
+            Normalize | ZScore => interpret_rescale(stack, op)?,
+            Sort => interpret_sort(stack)?,
+            Percentile | Iqr => interpret_percentile(stack, op)?,
+            Unique | Union | Intersect => interpret_set(stack, op)?,
+            CumSum | CumProd => interpret_cumulative(stack, op)?,
+            Reverse | Head | Tail | Concat => interpret_list(stack, op)?,
+            MatMul => interpret_matmul(stack)?,
+            Transpose | Det | Inv | LinSolve => interpret_matrix(stack, op)?,
+            Dot | Cross | Norm => interpret_vector(stack, op)?,
+            PolyVal | PolyRoots => interpret_poly(stack, op)?,
+            Rationalize | ConFrac => interpret_rational(stack, op, self.limits.max_list_len)?,
+            Len => interpret_len(stack)?,
+            Index => interpret_index(stack)?,
+            Range => interpret_range(stack, self.limits.max_list_len)?,
+            Factorial => interpret_factorial(stack)?,
+
+            Hex2Rgb => interpret_hex2rgb(stack)?,
+            Rgb2Hex => interpret_rgb2hex(stack)?,
+            SrgbToLinear | LinearToSrgb => interpret_srgb(stack, op)?,
+
+            Const(val) => interpret_const(
+                stack,
+                Value::Number(*val))?,
+            Str(val) => interpret_const(
+                stack,
+                Value::Str(val.clone()))?,
+            MakeList(len) => interpret_make_list(stack, *len)?,
+        }
+        Ok(())
     }
 
-    #[test]
-    fn test_sin() {
-        let ops = vec![Const(2.0 * PI), Sin];
-        assert_approx_eq(eval(ops), 0.0);
+    /// Execution statistics by opcode name since the last reset, the
+    /// operations that took the most time first.
+    #[cfg(feature = "profile")]
+    pub fn profile(&self) -> Vec<(String, OpStats)> {
+        let mut stats: Vec<(String, OpStats)> = self
+            .profile
+            .iter()
+            .map(|(name, stats)| (name.clone(), stats.clone()))
+            .collect();
+        stats.sort_by(|a, b| b.1.time.cmp(&a.1.time).then_with(|| a.0.cmp(&b.0)));
+        stats
     }
 
-    #[test]
-    fn test_cos() {
-        let ops = vec![Const(0.0), Cos];
-        assert_approx_eq(eval(ops), 1.0);
+    #[cfg(feature = "profile")]
+    pub fn reset_profile(&mut self) {
+        self.profile.clear();
     }
 
-    #[test]
-    fn test_tan() {
-        let ops = vec![Const(FRAC_PI_2), Tan];
-        assert!(eval(ops).abs() > 1.0 / EPS_INTERNAL);
+    /// The `n`-th most recent answer, where `ans(1)` is the same as `ans`.
+    fn nth_prev_ans(&self, n: i64) -> Result<Value, RuntimeError> {
+        if n < 1 {
+            return Err(RuntimeError::DomainError);
+        }
+        self.prev_ans
+            .len()
+            .checked_sub(n as usize)
+            .map(|i| self.prev_ans[i].result.clone())
+            .ok_or(RuntimeError::NoPreviousAnswer)
     }
 
-    #[test]
-    fn test_arcsin() {
-        let ops = vec![Const(1.0), ArcSin];
-        assert_approx_eq(eval(ops), FRAC_PI_2);
+    pub fn set_variable(&mut self, name: &str, value: Value) {
+        self.table.insert(name.to_string(), value);
     }
 
-    #[test]
+    /// The global variables by name, leaving out builtin constants that
+    /// still have their original value.
+    pub fn variables(&self) -> Vec<(&str, &Value)> {
+        let mut variables: Vec<(&str, &Value)> = self
+            .table
+            .iter()
+            .filter(|&(name, value)| {
+                !BUILTINS.iter().any(|&(builtin, x)| builtin == name && *value == Value::Number(x))
+            })
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        variables.sort_by_key(|&(name, _)| name);
+        variables
+    }
+
+    /// The user functions by name.
+    pub fn functions(&self) -> Vec<&Function> {
+        let mut functions: Vec<&Function> = self.functions.values().map(Arc::as_ref).collect();
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+        functions
+    }
+
+    /// The user function called `name`, if one is defined.
+    pub fn function(&self, name: &str) -> Option<Arc<Function>> {
+        self.functions.get(name).cloned()
+    }
+
+    /// The names of the global variables and user functions, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> =
+            self.table.keys().chain(self.functions.keys()).cloned().collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Assigns a variable from a statement like `x = 5`.
+    fn store_variable(&mut self, name: &str, value: Value) -> Result<(), RuntimeError> {
+        if !self.allow_builtin_override && BUILTINS.iter().any(|&(builtin, _)| builtin == name) {
+            return Err(RuntimeError::ProtectedBuiltin(name.to_string()));
+        }
+        self.set_variable(name, value);
+        Ok(())
+    }
+
+    /// Restores a builtin constant to its original value, returning false if
+    /// `name` is not a builtin.
+    pub fn reset_builtin(&mut self, name: &str) -> bool {
+        match BUILTINS.iter().find(|&&(builtin, _)| builtin == name) {
+            Some(&(_, value)) => {
+                self.set_variable(name, Value::Number(value));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Looks a name up among the locals of the innermost function call,
+    /// innermost scope first, then among the global variables.
+    pub fn get_variable(&self, name: &str) -> Result<Value, RuntimeError> {
+        self.scopes[self.frame.min(self.scopes.len())..]
+            .iter()
+            .rev()
+            .find_map(|locals| locals.get(name))
+            .or_else(|| self.table.get(name))
+            .cloned()
+            .or_else(|| {
+                (self.functions.contains_key(name) || builtin_function(name).is_some())
+                    .then(|| Value::Function(name.to_string()))
+            })
+            .ok_or_else(|| RuntimeError::UndefinedVariable(name.to_string()))
+    }
+
+    /// Stores a function definition, optimizing its body once so that
+    /// every call benefits.
+    fn define_function(&mut self, function: &Function) -> Value {
+        let signature = format!("{}({})", function.name, function.params.join(", "));
+        let optimized = Function {
+            name: function.name.clone(),
+            params: function.params.clone(),
+            body: optimize::optimize(function.body.clone()),
+        };
+        self.functions.insert(function.name.clone(), Arc::new(optimized));
+        Value::Str(signature)
+    }
+
+    /// Calls a user function with its parameters bound to `args` in a new
+    /// frame. The body sees its parameters and global variables only.
+    /// `name` may also be a variable holding a function value, such as a
+    /// parameter `g` in `twice(g, x) = g(g(x))`.
+    fn call_function(&mut self, name: &str, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let function = match (self.functions.get(name), self.get_variable(name)) {
+            (Some(function), _) => function.clone(),
+            (None, Ok(Value::Function(target))) => match self.functions.get(&target) {
+                Some(function) => function.clone(),
+                None => return self.call_builtin(&target, args),
+            },
+            (None, _) => return self.call_builtin(name, args),
+        };
+        if args.len() != function.params.len() {
+            return Err(RuntimeError::ArgumentCount {
+                function: name.to_string(),
+                expected: function.params.len(),
+                found: args.len(),
+            });
+        }
+        let max_depth = self.limits.max_call_depth.min(MAX_CALL_DEPTH);
+        if self.depth >= max_depth {
+            return Err(RuntimeError::RecursionLimit(max_depth));
+        }
+
+        let (caller_scopes, caller_frame) = (self.scopes.len(), self.frame);
+        self.frame = caller_scopes;
+        self.scopes.push(function.params.iter().cloned().zip(args).collect());
+        self.depth += 1;
+        let result = if self.depth.is_multiple_of(CALLS_PER_STACK) {
+            self.run_block_on_new_stack(&function.body)
+        } else {
+            self.run_block(&function.body)
+        };
+        self.depth -= 1;
+        self.scopes.truncate(caller_scopes);
+        self.frame = caller_frame;
+        result
+    }
+
+    /// Calls a single-argument builtin passed as a function value, such as
+    /// `sqrt` in `map(sqrt, xs)`.
+    fn call_builtin(&mut self, name: &str, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        let Some(op) = builtin_function(name) else {
+            return Err(RuntimeError::UndefinedFunction(name.to_string()));
+        };
+        if args.len() != 1 {
+            return Err(RuntimeError::ArgumentCount {
+                function: name.to_string(),
+                expected: 1,
+                found: args.len(),
+            });
+        }
+        let mut stack = args;
+        self.step(&mut stack, &op)?;
+        stack.pop().ok_or(RuntimeError::Underflow)
+    }
+
+    /// Runs a numerical method with `function` as a function of one number,
+    /// limiting how often it may be evaluated.
+    fn with_numeric_function<T>(
+        &mut self,
+        function: &str,
+        method: impl FnOnce(&mut dyn FnMut(f64) -> Result<f64, RuntimeError>) -> Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        self.with_evaluator(|vm, x| vm.call_function(function, vec![Value::Number(x)]), method)
+    }
+
+    /// Runs a numerical method on `evaluate`, counting its evaluations
+    /// against the limit.
+    fn with_evaluator<T>(
+        &mut self,
+        mut evaluate: impl FnMut(&mut Self, f64) -> Result<Value, RuntimeError>,
+        method: impl FnOnce(&mut dyn FnMut(f64) -> Result<f64, RuntimeError>) -> Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        let max = self.limits.max_evaluations;
+        let mut evaluations = 0;
+        method(&mut |x| {
+            if let Some(max) = max
+                && evaluations >= max
+            {
+                return Err(RuntimeError::LimitExceeded(Limit::Evaluations(max)));
+            }
+            evaluations += 1;
+            let y = evaluate(self, x)?;
+            y.as_number().ok_or(RuntimeError::TypeError)
+        })
+    }
+
+    /// Solves `equation` for its variable with [`numeric::find_root`]. Where
+    /// the equation is undefined, e.g. `ln x` for `x <= 0`, its residual is
+    /// taken as `NaN` so the search can step over it.
+    fn solve_equation(&mut self, equation: &Equation) -> Result<f64, RuntimeError> {
+        let iterations = self.limits.max_solver_iterations;
+        let residual = |vm: &mut Self, x: f64| {
+            vm.scopes.push(HashMap::from([(equation.variable.clone(), Value::Number(x))]));
+            let value = vm.run_block(&equation.residual);
+            vm.scopes.pop();
+            match value {
+                Err(e) if matches!(e.kind(), RuntimeError::DomainError | RuntimeError::MathError) => {
+                    Ok(Value::Number(f64::NAN))
+                }
+                value => value,
+            }
+        };
+        self.with_evaluator(residual, |f| {
+            numeric::find_root(f, iterations).map_err(|e| match e {
+                RootError::Function(e) => e,
+                RootError::NoSignChange { fa, fb } => RuntimeError::NoSignChange { a: 0.0, fa, b: 0.0, fb },
+                RootError::NoConvergence { x, fx } => RuntimeError::NoConvergence { iterations, x, fx },
+            })
+        })
+    }
+
+    /// Calls `function` on each element of `xs`, giving the results for
+    /// `map` and the elements with a non-zero result for `filter`.
+    fn map_filter(&mut self, op: &Operation, function: &str, xs: Vec<Value>) -> Result<Value, RuntimeError> {
+        let mut results = Vec::with_capacity(xs.len());
+        for x in xs {
+            let y = self.call_function(function, vec![x.clone()])?;
+            match (op, y) {
+                (Map, y) => results.push(y),
+                (_, Value::Number(keep)) if keep != 0.0 => results.push(x),
+                (_, Value::Number(_)) => {}
+                (_, _) => return Err(RuntimeError::TypeError),
+            }
+        }
+        Ok(Value::List(results))
+    }
+
+    /// Evaluates both sides of `equivalence` with their variables set to
+    /// random values in [-10, 10], to two decimal places, and describes the
+    /// first point where they differ. Points where either side is undefined
+    /// are skipped.
+    fn check_equivalence(&mut self, equivalence: &Equivalence) -> Result<String, RuntimeError> {
+        let mut variables = Vec::new();
+        compiled::free_variables(&equivalence.left, &mut Vec::new(), &mut variables);
+        compiled::free_variables(&equivalence.right, &mut Vec::new(), &mut variables);
+        let options = FormatOptions::default();
+        let mut rng = Rng::new(EQUIV_SEED);
+        let mut compared = 0;
+        for _ in 0..EQUIV_SAMPLES {
+            let point: Vec<(String, f64)> = variables
+                .iter()
+                .map(|name| (name.clone(), (2000.0 * rng.next_f64()).round() / 100.0 - 10.0))
+                .collect();
+            self.scopes.push(point.iter().map(|(name, x)| (name.clone(), Value::Number(*x))).collect());
+            let sides = self.run_block(&equivalence.left).and_then(|left| {
+                Ok((left, self.run_block(&equivalence.right)?))
+            });
+            self.scopes.pop();
+            let (left, right) = match sides {
+                Ok((Value::Number(left), Value::Number(right))) => (left, right),
+                Ok(_) => return Err(RuntimeError::TypeError),
+                Err(e) if matches!(e.kind(), RuntimeError::DomainError | RuntimeError::MathError) => continue,
+                Err(e) => return Err(e),
+            };
+            if left.is_nan() || right.is_nan() {
+                continue;
+            }
+            compared += 1;
+            if (left - right).abs() > EQUIV_TOLERANCE * left.abs().max(right.abs()).max(1.0) {
+                let at: Vec<String> = point
+                    .iter()
+                    .map(|(name, x)| format!("{} = {}", name, format_number(*x, &options)))
+                    .collect();
+                return Ok(format!(
+                    "not equivalent: at {} the left side is {} and the right side is {}",
+                    at.join(", "),
+                    format_number(left, &options),
+                    format_number(right, &options),
+                ));
+            }
+        }
+        Ok(match compared {
+            0 => "cannot tell: the sides are never both defined at the sampled points".to_string(),
+            n => format!("equivalent at {} random points", n),
+        })
+    }
+
+    /// Like [`run_block`](Self::run_block), but on a new thread with a
+    /// stack of its own, while this one waits.
+    fn run_block_on_new_stack(&mut self, operations: &[Operation]) -> Result<Value, RuntimeError> {
+        let depth = self.depth;
+        thread::scope(|scope| {
+            let spawned = thread::Builder::new()
+                .stack_size(CALL_STACK_SIZE)
+                .spawn_scoped(scope, || self.run_block(operations));
+            match spawned {
+                Ok(handle) => handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+                Err(_) => Err(RuntimeError::RecursionLimit(depth)),
+            }
+        })
+    }
+
+    /// Runs a `for` loop body with its variable bound to `first`, `first +
+    /// 1`, ... up to `last`. Gives the body's last value, or 0 if it never ran.
+    fn run_for(&mut self, body: &Loop, first: f64, last: f64) -> Result<Value, RuntimeError> {
+        let mut result = Value::Number(0.0);
+        let mut counter = first;
+        let mut iterations = 0;
+        while counter <= last {
+            self.check_iterations(iterations)?;
+            self.scopes.push(HashMap::from([(body.variable.clone(), Value::Number(counter))]));
+            let value = self.run_block(&body.body);
+            self.scopes.pop();
+            result = value?;
+            counter += 1.0;
+            iterations += 1;
+        }
+        Ok(result)
+    }
+
+    /// Runs a `while` loop. Gives the body's last value, or 0 if it never
+    /// ran.
+    fn run_while(&mut self, body: &Loop) -> Result<Value, RuntimeError> {
+        let mut result = Value::Number(0.0);
+        let mut iterations = 0;
+        loop {
+            let condition = self.run_block(&body.condition)?;
+            match condition {
+                Value::Number(x) if x != 0.0 => {}
+                Value::Number(_) => return Ok(result),
+                _ => return Err(RuntimeError::TypeError),
+            }
+            self.check_iterations(iterations)?;
+            result = self.run_block(&body.body)?;
+            iterations += 1;
+        }
+    }
+
+    /// Fails if a loop that has run `iterations` times may not run again.
+    fn check_iterations(&self, iterations: usize) -> Result<(), RuntimeError> {
+        match self.limits.max_iterations {
+            Some(max) if iterations >= max => {
+                Err(RuntimeError::LimitExceeded(Limit::Iterations(max)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs a sub-program, such as a function body or the chosen branch of
+    /// an `if`, on a stack of its own and returns its result.
+    fn run_block(&mut self, operations: &[Operation]) -> Result<Value, RuntimeError> {
+        let mut stack = Vec::new();
+        for (i, op) in operations.iter().enumerate() {
+            self.run_op(&mut stack, i, op)?;
+        }
+        stack.pop().ok_or(RuntimeError::Underflow)
+    }
+
+    pub fn get_prev_ans(&self) -> Result<Value, RuntimeError> {
+        match self.prev_ans.back() {
+            Some(output) => Ok(output.result.clone()),
+            None => {
+                Err(RuntimeError::NoPreviousAnswer)
+            } 
+        }
+    }
+}
+
+fn pop_number(stack: &mut Vec<Value>) -> Result<f64, RuntimeError> {
+    stack
+        .pop()
+        .ok_or(RuntimeError::Underflow)?
+        .as_number()
+        .ok_or(RuntimeError::TypeError)
+}
+
+fn pop_string(stack: &mut Vec<Value>) -> Result<String, RuntimeError> {
+    match stack.pop() {
+        Some(Value::Str(s)) => Ok(s),
+        Some(_) => Err(RuntimeError::TypeError),
+        None => Err(RuntimeError::Underflow),
+    }
+}
+
+/// The single-argument builtin functions by name, from the keywords that
+/// scan to one, built the first time a name is looked up.
+static BUILTIN_FUNCTIONS: LazyLock<HashMap<&str, Operation>> = LazyLock::new(|| {
+    lexer::KEYWORDS
+        .iter()
+        .filter_map(|&name| {
+            let token = lexer::scan(name).ok()?.into_iter().next()?;
+            Some((name, parser::unary_operation(&token.token_type)?))
+        })
+        .collect()
+});
+
+/// The operation of a single-argument builtin function by name.
+pub fn builtin_function(name: &str) -> Option<Operation> {
+    BUILTIN_FUNCTIONS.get(name).cloned()
+}
+
+fn pop_function(stack: &mut Vec<Value>) -> Result<String, RuntimeError> {
+    match stack.pop() {
+        Some(Value::Function(name)) => Ok(name),
+        Some(_) => Err(RuntimeError::TypeError),
+        None => Err(RuntimeError::Underflow),
+    }
+}
+
+fn pop_list(stack: &mut Vec<Value>) -> Result<Vec<Value>, RuntimeError> {
+    match stack.pop() {
+        Some(Value::List(xs)) => Ok(xs),
+        Some(_) => Err(RuntimeError::TypeError),
+        None => Err(RuntimeError::Underflow),
+    }
+}
+
+/// Pops a list whose elements must all be numbers.
+fn pop_numbers(stack: &mut Vec<Value>) -> Result<Vec<f64>, RuntimeError> {
+    pop_list(stack)?
+        .iter()
+        .map(|x| x.as_number().ok_or(RuntimeError::TypeError))
+        .collect()
+}
+
+fn push_number(stack: &mut Vec<Value>, x: f64) {
+    stack.push(Value::Number(x));
+}
+
+/// Converts `x` to an integer, failing with a `DomainError` if it has a
+/// fractional part or does not fit in an `i64`.
+fn as_integer(x: f64) -> Result<i64, RuntimeError> {
+    if x.fract() != 0.0 || x.abs() >= i64::MAX as f64 {
+        return Err(RuntimeError::DomainError);
+    }
+    Ok(x as i64)
+}
+
+/// Truncates `x` and wraps it to a two's complement word of `bits` bits.
+fn wrap_to_word(x: f64, bits: u32) -> f64 {
+    if !x.is_finite() {
+        return x;
+    }
+    let shift = 128 - bits;
+    (((x.trunc() as i128) << shift) >> shift) as f64
+}
+
+fn wrap_value(value: &mut Value, bits: u32) {
+    match value {
+        Value::Number(x) => *x = wrap_to_word(*x, bits),
+        Value::List(xs) => xs.iter_mut().for_each(|x| wrap_value(x, bits)),
+        Value::Str(_) | Value::Function(_) => {}
+    }
+}
+
+/// Bitwise operations act on the two's complement form of integer operands.
+/// Shifts are arithmetic and the shift amount must be in `0..64`.
+fn interpret_bitwise(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let (b, a) = (as_integer(pop_number(stack)?)?, as_integer(pop_number(stack)?)?);
+    let shift = || u32::try_from(b).ok().filter(|&b| b < 64).ok_or(RuntimeError::DomainError);
+    let result = match op {
+        BitAnd => a & b,
+        BitOr => a | b,
+        BitXor => a ^ b,
+        ShiftLeft => a.wrapping_shl(shift()?),
+        ShiftRight => a >> shift()?,
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result as f64);
+    Ok(())
+}
+
+fn interpret_bit_not(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let a = as_integer(pop_number(stack)?)?;
+    push_number(stack, !a as f64);
+    Ok(())
+}
+
+/// `LogBase` pops the argument first and then the base, i.e. `log(b, x)`
+/// compiles to `b x LogBase`.
+fn interpret_log(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let x = pop_number(stack)?;
+    let base = match op {
+        Ln => E,
+        Log10 => 10.0,
+        Log2 => 2.0,
+        LogBase => pop_number(stack)?,
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    if x <= 0.0 || base <= 0.0 || base == 1.0 {
+        return Err(RuntimeError::DomainError);
+    }
+
+    let result = match op {
+        Ln => x.ln(),
+        Log10 => x.log10(),
+        Log2 => x.log2(),
+        _ => x.ln() / base.ln(),
+    };
+    push_number(stack, result);
+    Ok(())
+}
+
+fn interpret_exp(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let a = pop_number(stack)?;
+    push_number(stack, E.powf(a));
+    Ok(())
+}
+
+fn interpret_power(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let (b, a) = (pop_number(stack)?, pop_number(stack)?);
+    push_number(stack, a.powf(b));
+    Ok(())
+}
+
+/// Unlike `f64::min`/`f64::max`, a NaN operand propagates to the result
+/// instead of being silently dropped.
+fn interpret_min_max(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let (x, y) = (pop_number(stack)?, pop_number(stack)?);
+    let result = match op {
+        _ if x.is_nan() || y.is_nan() => f64::NAN,
+        Min => y.min(x),
+        Max => y.max(x),
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+/// Writes the value on top of the stack to `out` and leaves it there.
+fn interpret_print(stack: &mut [Value], out: &mut dyn Write) -> Result<(), RuntimeError> {
+    let value = stack.last().ok_or(RuntimeError::Underflow)?;
+    writeln!(out, "{}", format_value(value, &FormatOptions::default()))
+        .map_err(|e| RuntimeError::Output(e.to_string()))
+}
+
+/// Fails unless the condition is non-zero, which is then the result.
+fn interpret_assert(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let condition = pop_number(stack)?;
+    if condition == 0.0 {
+        return Err(RuntimeError::AssertionFailed);
+    }
+    push_number(stack, condition);
+    Ok(())
+}
+
+/// Comparisons give 1 for true and 0 for false.
+fn interpret_compare(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let (x, y) = (pop_number(stack)?, pop_number(stack)?);
+    let result = match op {
+        Less => y < x,
+        LessEqual => y <= x,
+        Greater => y > x,
+        GreaterEqual => y >= x,
+        Equal => y == x,
+        NotEqual => y != x,
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, if result { 1.0 } else { 0.0 });
+    Ok(())
+}
+
+fn interpret_add(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let (x, y) = (pop_number(stack)?, pop_number(stack)?);
+    push_number(stack, y + x);
+    Ok(())
+}
+
+fn interpret_const(stack: &mut Vec<Value>, value: Value) -> Result<(), RuntimeError> {
+    stack.push(value);
+    Ok(())
+}
+
+fn interpret_make_list(stack: &mut Vec<Value>, len: usize) -> Result<(), RuntimeError> {
+    if stack.len() < len {
+        return Err(RuntimeError::Underflow);
+    }
+    let elements = stack.split_off(stack.len() - len);
+    stack.push(Value::List(elements));
+    Ok(())
+}
+
+fn interpret_len(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let len = match stack.pop().ok_or(RuntimeError::Underflow)? {
+        Value::List(xs) => xs.len(),
+        Value::Str(s) => s.chars().count(),
+        Value::Number(_) | Value::Function(_) => return Err(RuntimeError::TypeError),
+    };
+    push_number(stack, len as f64);
+    Ok(())
+}
+
+/// Indexes a list from 1, like `ans(n)`, so `xs[1]` is the first element.
+fn interpret_index(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let i = pop_number(stack)?;
+    let mut xs = pop_list(stack)?;
+    if i.fract() != 0.0 {
+        return Err(RuntimeError::DomainError);
+    }
+    if i < 1.0 || i > xs.len() as f64 {
+        return Err(RuntimeError::IndexOutOfRange { index: i as i64, len: xs.len() });
+    }
+    stack.push(xs.swap_remove(i as usize - 1));
+    Ok(())
+}
+
+/// Steps from `first` towards `last`, including `last` when a whole number
+/// of steps (to within rounding) reaches it.
+fn interpret_range(stack: &mut Vec<Value>, max_len: Option<usize>) -> Result<(), RuntimeError> {
+    let (step, last, first) = (pop_number(stack)?, pop_number(stack)?, pop_number(stack)?);
+    if step == 0.0 || !(first.is_finite() && last.is_finite() && step.is_finite()) {
+        return Err(RuntimeError::DomainError);
+    }
+    let steps = ((last - first) / step + 1e-9).floor();
+    let len = if steps < 0.0 { 0.0 } else { steps + 1.0 };
+    if let Some(max) = max_len
+        && len > max as f64
+    {
+        return Err(RuntimeError::LimitExceeded(Limit::ListLength(max)));
+    }
+    let xs: Vec<f64> = (0..len as usize).map(|i| first + i as f64 * step).collect();
+    stack.push(Value::from(xs));
+    Ok(())
+}
+
+fn interpret_subtract(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let (x, y) = (pop_number(stack)?, pop_number(stack)?);
+    push_number(stack, y - x);
+    Ok(())
+}
+
+fn interpret_times(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let (x, y) = (pop_number(stack)?, pop_number(stack)?);
+    push_number(stack, y * x);
+    Ok(())
+}
+
+fn interpret_divide(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let (x, y) = (pop_number(stack)?, pop_number(stack)?);
+    if x == 0.0 {
+        Err(RuntimeError::MathError)
+    } else {
+        push_number(stack, y / x);
+        Ok(())
+    }
+}
+
+/// Floor division rounds the quotient towards negative infinity, and
+/// `Modulo` is the matching remainder, so `y == x * (y // x) + (y mod x)` and
+/// the remainder takes the sign of the divisor (e.g. `-7 mod 3 == 2`,
+/// `7 mod -3 == -2`). This differs from the truncated `%` of C and Rust.
+fn interpret_floor_divide(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let (x, y) = (pop_number(stack)?, pop_number(stack)?);
+    if x == 0.0 {
+        return Err(RuntimeError::MathError);
+    }
+
+    let quotient = (y / x).floor();
+    let result = match op {
+        FloorDivide => quotient,
+        Modulo => y - x * quotient,
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+fn interpret_negate(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let val = pop_number(stack)?;
+    push_number(stack, -val);
+    Ok(())
+}
+
+fn interpret_trig(
+    stack: &mut Vec<Value>,
+    op: &Operation,
+    use_radians: bool,
+) -> Result<(), RuntimeError> {
+    let val = pop_number(stack)?;
+    let operand = if use_radians { val } else { val.to_radians() };
+    let result = match op {
+        Sin => operand.sin(),
+        Cos => operand.cos(),
+        Tan => operand.tan(),
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+/// Degree-based trig functions, independent of the angle mode. Multiples
+/// of 90 degrees give exact results, and `tand` has no value at odd ones.
+fn interpret_trig_degrees(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let degrees = pop_number(stack)?.rem_euclid(360.0);
+    let (sin, cos) = match degrees {
+        0.0 => (0.0, 1.0),
+        90.0 => (1.0, 0.0),
+        180.0 => (0.0, -1.0),
+        270.0 => (-1.0, 0.0),
+        _ => degrees.to_radians().sin_cos(),
+    };
+    let result = match op {
+        SinD => sin,
+        CosD => cos,
+        TanD if cos == 0.0 => {
+            return Err(RuntimeError::DomainError);
+        }
+        TanD => sin / cos,
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+fn interpret_unary(stack: &mut Vec<Value>, f: fn(f64) -> f64) -> Result<(), RuntimeError> {
+    let x = pop_number(stack)?;
+    push_number(stack, f(x));
+    Ok(())
+}
+
+fn interpret_inv_trig(
+    stack: &mut Vec<Value>,
+    op: &Operation,
+    use_radians: bool,
+) -> Result<(), RuntimeError> {
+    let val = pop_number(stack)?;
+    let result = match op {
+        ArcSin => val.asin(),
+        ArcCos => val.acos(),
+        ArcTan => val.atan(),
+        // atan2(y, x) compiles to `y x ArcTan2`, so `val` is x.
+        ArcTan2 => pop_number(stack)?.atan2(val),
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    if result.is_nan() {
+        return Err(RuntimeError::DomainError);
+    }
+
+    let result = if use_radians {
+        result
+    } else {
+        result.to_degrees()
+    };
+    push_number(stack, result);
+
+    Ok(())
+}
+
+fn interpret_hypot(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let (y, x) = (pop_number(stack)?, pop_number(stack)?);
+    push_number(stack, x.hypot(y));
+    Ok(())
+}
+
+/// `topolar(x, y)` gives `[r, theta]` and `torect(r, theta)` gives `[x, y]`,
+/// with `theta` in degrees unless in radian mode.
+fn interpret_polar(
+    stack: &mut Vec<Value>,
+    op: &Operation,
+    use_radians: bool,
+) -> Result<(), RuntimeError> {
+    let (b, a) = (pop_number(stack)?, pop_number(stack)?);
+    let result = match op {
+        ToPolar => {
+            let theta = b.atan2(a);
+            vec![a.hypot(b), if use_radians { theta } else { theta.to_degrees() }]
+        }
+        ToRect => {
+            let theta = if use_radians { b } else { b.to_radians() };
+            vec![a * theta.cos(), a * theta.sin()]
+        }
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    stack.push(Value::from(result));
+    Ok(())
+}
+
+/// Hyperbolic functions take and return plain numbers, so unlike the
+/// circular trig functions they ignore the angle mode.
+fn interpret_hyperbolic(
+    stack: &mut Vec<Value>,
+    op: &Operation,
+) -> Result<(), RuntimeError> {
+    let val = pop_number(stack)?;
+    let result = match op {
+        Sinh => val.sinh(),
+        Cosh => val.cosh(),
+        Tanh => val.tanh(),
+        ArcSinh => val.asinh(),
+        ArcCosh if val < 1.0 => {
+            return Err(RuntimeError::DomainError);
+        }
+        ArcCosh => val.acosh(),
+        ArcTanh if val.abs() >= 1.0 => {
+            return Err(RuntimeError::DomainError);
+        }
+        ArcTanh => val.atanh(),
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+/// `Root` pops the radicand first and then the degree, i.e. `root(n, x)`
+/// compiles to `n x Root`.
+fn interpret_root(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let x = pop_number(stack)?;
+    let n = match op {
+        Sqrt => 2.0,
+        Cbrt => 3.0,
+        Root => pop_number(stack)?,
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, nth_root(x, n)?);
+    Ok(())
+}
+
+/// Real n-th root. Negative radicands only have a real root when `n` is an
+/// odd integer.
+fn nth_root(x: f64, n: f64) -> Result<f64, RuntimeError> {
+    if n == 0.0 {
+        return Err(RuntimeError::DomainError);
+    }
+    if n == 2.0 && x >= 0.0 {
+        return Ok(x.sqrt());
+    }
+    if n == 3.0 {
+        return Ok(x.cbrt());
+    }
+
+    if x >= 0.0 {
+        Ok(x.powf(1.0 / n))
+    } else if n.fract() == 0.0 && n % 2.0 != 0.0 {
+        Ok(-(-x).powf(1.0 / n))
+    } else {
+        Err(RuntimeError::DomainError)
+    }
+}
+
+/// `round(x, n)` rounds to `n` decimal places (tens, hundreds, ... for
+/// negative `n`) and `sigfig(x, n)` to `n` significant figures, with ties
+/// away from zero.
+fn interpret_round_to(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let n = as_integer(pop_number(stack)?)?;
+    let x = pop_number(stack)?;
+    if x == 0.0 || !x.is_finite() {
+        push_number(stack, x);
+        return Ok(());
+    }
+
+    let places = match op {
+        RoundTo => n,
+        SigFig if n < 1 => {
+            return Err(RuntimeError::DomainError);
+        }
+        SigFig => n - 1 - x.abs().log10().floor() as i64,
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, round_decimal(x, places));
+    Ok(())
+}
+
+/// Rounds to `places` decimal places on the shortest decimal form of `x`,
+/// so that values like 1.005 (stored as 1.00499...) round as written.
+fn round_decimal(x: f64, places: i64) -> f64 {
+    let text = format!("{:e}", x);
+    let Some((mantissa, exponent)) = text.split_once('e') else {
+        return x;
+    };
+    let exponent: i64 = exponent.parse().unwrap_or(0);
+    let negative = mantissa.starts_with('-');
+    let digits: Vec<u8> = mantissa.bytes().filter(u8::is_ascii_digit).map(|d| d - b'0').collect();
+
+    // Number of leading digits to keep: those before the decimal point
+    // (exponent + 1) plus `places` after it.
+    let keep = exponent + 1 + places;
+    if keep < 0 {
+        return 0.0_f64.copysign(x);
+    }
+    let keep = keep as usize;
+    if keep >= digits.len() {
+        // Already has no more than `places` decimals.
+        return x;
+    }
+
+    let mut kept: Vec<u8> = digits[..keep].to_vec();
+    if digits[keep] >= 5 {
+        let mut i = kept.len();
+        loop {
+            if i == 0 {
+                kept.insert(0, 1);
+                break;
+            }
+            i -= 1;
+            if kept[i] == 9 {
+                kept[i] = 0;
+            } else {
+                kept[i] += 1;
+                break;
+            }
+        }
+    }
+    let carried = kept.len() as i64 - keep as i64;
+
+    let digits: String = kept.iter().map(|d| char::from(b'0' + d)).collect();
+    let digits = if digits.is_empty() { "0".to_string() } else { digits };
+    let value: f64 = format!("{}e{}", digits, exponent + 1 + carried - digits.len() as i64)
+        .parse()
+        .unwrap_or(x);
+    if negative { -value } else { value }
+}
+
+/// Decibel conversions use the power convention (10·log10), so `db(2)` is
+/// about 3 dB. `dbm_to_mw`/`mw_to_dbm` convert between dBm and milliwatts.
+fn interpret_decibel(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let x = pop_number(stack)?;
+    let result = match op {
+        Db | MwToDbm if x <= 0.0 => {
+            return Err(RuntimeError::DomainError);
+        }
+        Db | MwToDbm => 10.0 * x.log10(),
+        UnDb | DbmToMw => 10.0_f64.powf(x / 10.0),
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+/// Combines two resistances in parallel. A zero resistance shorts the pair.
+fn interpret_parallel(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let (r2, r1) = (pop_number(stack)?, pop_number(stack)?);
+    if r1 < 0.0 || r2 < 0.0 {
+        return Err(RuntimeError::DomainError);
+    }
+
+    let result = if r1 == 0.0 || r2 == 0.0 {
+        0.0
+    } else {
+        1.0 / (1.0 / r1 + 1.0 / r2)
+    };
+    push_number(stack, result);
+    Ok(())
+}
+
+/// Output voltage across `r2` for `voltage_divider(vin, r1, r2)`.
+fn interpret_voltage_divider(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let (r2, r1, vin) = (pop_number(stack)?, pop_number(stack)?, pop_number(stack)?);
+    if r1 < 0.0 || r2 < 0.0 {
+        return Err(RuntimeError::DomainError);
+    }
+    if r1 + r2 == 0.0 {
+        return Err(RuntimeError::MathError);
+    }
+
+    push_number(stack, vin * r2 / (r1 + r2));
+    Ok(())
+}
+
+fn check_probability(p: f64) -> Result<f64, RuntimeError> {
+    if (0.0..=1.0).contains(&p) {
+        Ok(p)
+    } else {
+        Err(RuntimeError::DomainError)
+    }
+}
+
+/// Converts between a probability `p` and the odds in favour `p / (1 - p)`.
+fn interpret_odds(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let x = pop_number(stack)?;
+    let result = match op {
+        Odds if x == 1.0 => {
+            return Err(RuntimeError::MathError);
+        }
+        Odds => {
+            let p = check_probability(x)?;
+            p / (1.0 - p)
+        }
+        Prob if x < 0.0 => {
+            return Err(RuntimeError::DomainError);
+        }
+        Prob => x / (1.0 + x),
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+/// Posterior probability of a condition given a positive test, for
+/// `bayes(prior, sensitivity, specificity)`.
+fn interpret_bayes(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let specificity = check_probability(pop_number(stack)?)?;
+    let sensitivity = check_probability(pop_number(stack)?)?;
+    let prior = check_probability(pop_number(stack)?)?;
+
+    let true_positive = sensitivity * prior;
+    let false_positive = (1.0 - specificity) * (1.0 - prior);
+    if true_positive + false_positive == 0.0 {
+        return Err(RuntimeError::MathError);
+    }
+
+    push_number(stack, true_positive / (true_positive + false_positive));
+    Ok(())
+}
+
+/// Parses `"rrggbb"`, `"#rrggbb"` or the shorthand `"rgb"` into `[r, g, b]`.
+fn interpret_hex2rgb(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let hex = pop_string(stack)?;
+    let hex = hex.trim_start_matches('#');
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(RuntimeError::DomainError);
+    }
+
+    let channels: Vec<String> = match hex.len() {
+        3 => hex.chars().map(|c| format!("{}{}", c, c)).collect(),
+        6 => (0..6).step_by(2).map(|i| hex[i..i + 2].to_string()).collect(),
+        _ => {
+            return Err(RuntimeError::DomainError);
+        }
+    };
+
+    let rgb = channels
+        .iter()
+        .map(|c| u8::from_str_radix(c, 16).map(f64::from))
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| RuntimeError::DomainError)?;
+
+    stack.push(Value::from(rgb));
+    Ok(())
+}
+
+fn interpret_rgb2hex(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let (b, g, r) = (pop_number(stack)?, pop_number(stack)?, pop_number(stack)?);
+    let mut hex = String::from("#");
+    for channel in [r, g, b] {
+        let channel = as_integer(channel)?;
+        if !(0..=255).contains(&channel) {
+            return Err(RuntimeError::DomainError);
+        }
+        hex.push_str(&format!("{:02x}", channel));
+    }
+
+    stack.push(Value::Str(hex));
+    Ok(())
+}
+
+/// Converts a channel value in `[0, 1]` (or a list of them) between the sRGB
+/// transfer curve and linear light.
+fn interpret_srgb(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let convert = |c: f64| -> Result<f64, RuntimeError> {
+        if !(0.0..=1.0).contains(&c) {
+            return Err(RuntimeError::DomainError);
+        }
+        Ok(match op {
+            SrgbToLinear if c <= 0.04045 => c / 12.92,
+            SrgbToLinear => ((c + 0.055) / 1.055).powf(2.4),
+            _ if c <= 0.0031308 => c * 12.92,
+            _ => 1.055 * c.powf(1.0 / 2.4) - 0.055,
+        })
+    };
+
+    let result = match stack.pop() {
+        Some(Value::Number(c)) => Value::Number(convert(c)?),
+        Some(Value::List(cs)) => Value::List(
+            cs.iter()
+                .map(|c| c.as_number().ok_or(RuntimeError::TypeError).and_then(convert))
+                .map(|c| c.map(Value::Number))
+                .collect::<Result<Vec<Value>, _>>()?,
+        ),
+        Some(_) => {
+            return Err(RuntimeError::TypeError);
+        }
+        None => {
+            return Err(RuntimeError::Underflow);
+        }
+    };
+
+    stack.push(result);
+    Ok(())
+}
+
+/// Pops `lat1 lon1 lat2 lon2` (in degrees, independent of the angle mode).
+fn pop_coordinates(stack: &mut Vec<Value>) -> Result<(f64, f64, f64, f64), RuntimeError> {
+    let (lon2, lat2) = (pop_number(stack)?, pop_number(stack)?);
+    let (lon1, lat1) = (pop_number(stack)?, pop_number(stack)?);
+    if lat1.abs() > 90.0 || lat2.abs() > 90.0 {
+        return Err(RuntimeError::DomainError);
+    }
+    Ok((
+        lat1.to_radians(),
+        lon1.to_radians(),
+        lat2.to_radians(),
+        lon2.to_radians(),
+    ))
+}
+
+/// Great-circle distance between two points on a sphere. The result is in
+/// the same unit as the radius, which is on top of the stack.
+fn interpret_haversine(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let radius = pop_number(stack)?;
+    if radius < 0.0 {
+        return Err(RuntimeError::DomainError);
+    }
+    let (lat1, lon1, lat2, lon2) = pop_coordinates(stack)?;
+
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    let c = 2.0 * a.sqrt().min(1.0).asin();
+
+    push_number(stack, radius * c);
+    Ok(())
+}
+
+/// Initial bearing from the first point to the second, in degrees
+/// clockwise from north within `[0, 360)`.
+fn interpret_bearing(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let (lat1, lon1, lat2, lon2) = pop_coordinates(stack)?;
+
+    let y = (lon2 - lon1).sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * (lon2 - lon1).cos();
+
+    push_number(stack, y.atan2(x).to_degrees().rem_euclid(360.0));
+    Ok(())
+}
+
+/// `Round` rounds half-way cases away from zero, and `Frac` keeps the sign
+/// of its input so that `trunc(x) + frac(x) == x`.
+fn interpret_rounding(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let x = pop_number(stack)?;
+    let result = match op {
+        Abs => x.abs(),
+        Sign if x == 0.0 => 0.0,
+        Sign => x.signum(),
+        Floor => x.floor(),
+        Ceil => x.ceil(),
+        Round => x.round(),
+        Trunc => x.trunc(),
+        Frac => x.fract(),
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+/// Digit utilities work on the decimal digits of `|x|`, so the sign of the
+/// input only survives in `ReverseDigits`.
+fn interpret_digits(
+    stack: &mut Vec<Value>,
+    op: &Operation,
+) -> Result<(), RuntimeError> {
+    let n = as_integer(pop_number(stack)?)?;
+    let digits: Vec<f64> = n
+        .unsigned_abs()
+        .to_string()
+        .bytes()
+        .map(|b| (b - b'0') as f64)
+        .collect();
+
+    let result = match op {
+        Digits => Value::from(digits),
+        DigitSum => Value::Number(digits.iter().sum()),
+        ReverseDigits => {
+            let reversed = digits.iter().rev().fold(0.0, |acc, d| acc * 10.0 + d);
+            Value::Number(reversed * n.signum() as f64)
+        }
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    stack.push(result);
+    Ok(())
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// Both `Gcd` and `Lcm` require integer operands and return a non-negative
+/// result, with `gcd(0, 0) == 0` and `lcm(0, n) == 0`.
+fn interpret_gcd(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let b = as_integer(pop_number(stack)?)?.unsigned_abs();
+    let a = as_integer(pop_number(stack)?)?.unsigned_abs();
+
+    let g = gcd(a, b);
+    let result = match op {
+        Gcd => g as f64,
+        Lcm if g == 0 => 0.0,
+        Lcm => (a as u128 / g as u128 * b as u128) as f64,
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    (a as u128 * b as u128 % m as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exp: u64, m: u64) -> u64 {
+    let mut result = 1 % m;
+    base %= m;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul_mod(result, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    result
+}
+
+/// Deterministic Miller-Rabin: the first twelve primes as witnesses are
+/// sufficient for every 64-bit integer.
+fn is_prime(n: u64) -> bool {
+    const WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if n < 2 {
+        return false;
+    }
+    for p in WITNESSES {
+        if n.is_multiple_of(p) {
+            return n == p;
+        }
+    }
+
+    let (mut d, mut r) = (n - 1, 0);
+    while d % 2 == 0 {
+        d /= 2;
+        r += 1;
+    }
+
+    'witness: for a in WITNESSES {
+        let mut x = pow_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+        for _ in 1..r {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+/// Finds a non-trivial factor of the odd composite `n` with Pollard's rho.
+fn pollard_rho(n: u64) -> u64 {
+    for c in 1.. {
+        let f = |x: u64| (mul_mod(x, x, n) + c) % n;
+        let (mut x, mut y, mut d) = (2, 2, 1);
+        while d == 1 {
+            x = f(x);
+            y = f(f(y));
+            d = gcd(x.abs_diff(y), n);
+        }
+        if d != n {
+            return d;
+        }
+    }
+    unreachable!()
+}
+
+/// Prime factors of `n` in ascending order, with multiplicity. Small factors
+/// are removed by trial division before falling back to Pollard's rho.
+fn prime_factors(mut n: u64) -> Vec<u64> {
+    let mut factors = Vec::new();
+    for p in (2..1000).filter(|&p| is_prime(p)) {
+        while n.is_multiple_of(p) {
+            factors.push(p);
+            n /= p;
+        }
+    }
+
+    let mut pending = vec![n];
+    while let Some(m) = pending.pop() {
+        if m == 1 {
+            continue;
+        }
+        if is_prime(m) {
+            factors.push(m);
+        } else {
+            let d = pollard_rho(m);
+            pending.push(d);
+            pending.push(m / d);
+        }
+    }
+
+    factors.sort_unstable();
+    factors
+}
+
+/// `IsPrime` returns 1 or 0, `NextPrime` the smallest prime greater than its
+/// argument, and `Factor` the list of prime factors with multiplicity
+/// (`factor(12) == [2, 2, 3]`).
+fn interpret_prime(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let n = as_integer(pop_number(stack)?)?;
+    let result = match op {
+        IsPrime => Value::Number(if n > 0 && is_prime(n as u64) { 1.0 } else { 0.0 }),
+        NextPrime => {
+            let mut candidate = n.max(1) as u64 + 1;
+            while !is_prime(candidate) {
+                candidate += 1;
+            }
+            Value::Number(candidate as f64)
+        }
+        Factor if n < 1 => {
+            return Err(RuntimeError::DomainError);
+        }
+        Factor => Value::from(
+            prime_factors(n as u64).into_iter().map(|p| p as f64).collect::<Vec<f64>>()
+        ),
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    stack.push(result);
+    Ok(())
+}
+
+/// `powmod(base, exp, m)` by square-and-multiply in 128-bit intermediates,
+/// so it stays exact where `base^exp` itself would overflow an `f64`. The
+/// result lies in `[0, m)`, even for a negative base.
+fn interpret_powmod(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let m = as_integer(pop_number(stack)?)?;
+    let exp = as_integer(pop_number(stack)?)?;
+    let base = as_integer(pop_number(stack)?)?;
+    if m <= 0 || exp < 0 {
+        return Err(RuntimeError::DomainError);
+    }
+
+    let base = base.rem_euclid(m) as u64;
+    push_number(stack, pow_mod(base, exp as u64, m as u64) as f64);
+    Ok(())
+}
+
+const LANCZOS_G: f64 = 7.0;
+const LANCZOS_COEFFICIENTS: [f64; 9] = [
+    0.999_999_999_999_809_9,
+    676.520_368_121_885_1,
+    -1_259.139_216_722_402_8,
+    771.323_428_777_653_1,
+    -176.615_029_162_140_6,
+    12.507_343_278_686_905,
+    -0.138_571_095_265_720_12,
+    9.984_369_578_019_572e-6,
+    1.505_632_735_149_311_6e-7,
+];
+
+/// Gamma function via the Lanczos approximation (g = 7, n = 9), using the
+/// reflection formula for `x < 0.5`. Non-positive integers are poles, and
+/// positive ones give the exact factorial `(x - 1)!`.
+fn gamma(x: f64) -> Result<f64, RuntimeError> {
+    if x.fract() == 0.0 {
+        return if x <= 0.0 { Err(RuntimeError::DomainError) } else { Ok(factorial(x - 1.0)) };
+    }
+    if x < 0.5 {
+        return Ok(std::f64::consts::PI / ((std::f64::consts::PI * x).sin() * gamma(1.0 - x)?));
+    }
+
+    let x = x - 1.0;
+    let t = x + LANCZOS_G + 0.5;
+    let series = LANCZOS_COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(LANCZOS_COEFFICIENTS[0], |acc, (i, c)| acc + c / (x + i as f64 + 1.0));
+
+    Ok((2.0 * std::f64::consts::PI).sqrt() * t.powf(x + 0.5) * (-t).exp() * series)
+}
+
+fn interpret_gamma(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let x = pop_number(stack)?;
+    push_number(stack, gamma(x)?);
+    Ok(())
+}
+
+/// Factorials of non-negative integers are computed exactly by repeated
+/// multiplication; anything else falls back to `gamma(x + 1)`.
+fn interpret_factorial(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let x = pop_number(stack)?;
+    let result = if x >= 0.0 && x.fract() == 0.0 { factorial(x) } else { gamma(x + 1.0)? };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+/// `n!` of a non-negative integer `n` by repeated multiplication, which is
+/// infinite from `171!` on.
+fn factorial(n: f64) -> f64 {
+    (1..=n.min(171.0) as u64).fold(1.0, |acc, k| acc * k as f64)
+}
+
+/// Weighted arithmetic mean, `wmean(values, weights)`.
+fn interpret_wmean(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let weights = pop_numbers(stack)?;
+    let values = pop_numbers(stack)?;
+    if values.len() != weights.len() {
+        return Err(RuntimeError::LengthMismatch);
+    }
+
+    let total_weight: f64 = weights.iter().sum();
+    if total_weight == 0.0 {
+        return Err(RuntimeError::MathError);
+    }
+
+    let weighted_sum: f64 = values.iter().zip(&weights).map(|(x, w)| x * w).sum();
+    push_number(stack, weighted_sum / total_weight);
+    Ok(())
+}
+
+/// Least-squares fit of `ys` against `xs`, returned as
+/// `[slope, intercept, r²]`. A fit through constant `ys` has `r² = 1`.
+fn interpret_linreg(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let ys = pop_numbers(stack)?;
+    let xs = pop_numbers(stack)?;
+    if xs.len() != ys.len() {
+        return Err(RuntimeError::LengthMismatch);
+    }
+    if xs.len() < 2 {
+        return Err(RuntimeError::MathError);
+    }
+
+    let n = xs.len() as f64;
+    let (mean_x, mean_y) = (xs.iter().sum::<f64>() / n, ys.iter().sum::<f64>() / n);
+    let sxx: f64 = xs.iter().map(|x| (x - mean_x).powi(2)).sum();
+    let syy: f64 = ys.iter().map(|y| (y - mean_y).powi(2)).sum();
+    let sxy: f64 = xs.iter().zip(&ys).map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    if sxx == 0.0 {
+        return Err(RuntimeError::MathError);
+    }
+
+    let slope = sxy / sxx;
+    let intercept = mean_y - slope * mean_x;
+    let r_squared = if syy == 0.0 { 1.0 } else { sxy * sxy / (sxx * syy) };
+
+    stack.push(Value::from(vec![slope, intercept, r_squared]));
+    Ok(())
+}
+
+/// `Normalize` scales a list to sum to 1. `ZScore` standardises each element
+/// using the population standard deviation.
+fn interpret_rescale(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let xs = pop_numbers(stack)?;
+    if xs.is_empty() {
+        return Err(RuntimeError::MathError);
+    }
+
+    let n = xs.len() as f64;
+    let sum: f64 = xs.iter().sum();
+    let result: Vec<f64> = match op {
+        Normalize if sum == 0.0 => {
+            return Err(RuntimeError::MathError);
+        }
+        Normalize => xs.iter().map(|x| x / sum).collect(),
+        ZScore => {
+            let mean = sum / n;
+            let stdev = (xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n).sqrt();
+            if stdev == 0.0 {
+                return Err(RuntimeError::MathError);
+            }
+            xs.iter().map(|x| (x - mean) / stdev).collect()
+        }
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    stack.push(Value::from(result));
+    Ok(())
+}
+
+/// Summary statistics of a list. `var` and `stdev` are the population forms
+/// (dividing by `n`), matching `zscore`; `mode` picks the smallest of tied
+/// values. Only `sum` and `prod` accept an empty list.
+fn interpret_statistics(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let xs = pop_numbers(stack)?;
+    if xs.is_empty() && !matches!(op, Sum | Prod) {
+        return Err(RuntimeError::MathError);
+    }
+
+    let n = xs.len() as f64;
+    let mean = xs.iter().sum::<f64>() / n;
+    let variance = || xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+    let result = match op {
+        Mean => mean,
+        Median => percentile(&sorted(xs.clone()), 50.0)?,
+        Mode => {
+            let xs = sorted(xs.clone());
+            let mut best = (xs[0], 0);
+            for run in xs.chunk_by(|a, b| a == b) {
+                if run.len() > best.1 {
+                    best = (run[0], run.len());
+                }
+            }
+            best.0
+        }
+        Var => variance(),
+        Stdev => variance().sqrt(),
+        Sum => xs.iter().sum(),
+        Prod => xs.iter().product(),
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+/// Sorts ascending. NaNs compare greater than every number and end up last.
+fn sorted(mut xs: Vec<f64>) -> Vec<f64> {
+    xs.sort_by(|a, b| a.total_cmp(b));
+    xs
+}
+
+/// Percentile `p` (in `[0, 100]`) of ascending `xs`, linearly interpolating
+/// between the two closest ranks: the value at rank `p/100 * (n - 1)`. This
+/// matches the default method of NumPy and of spreadsheet `PERCENTILE`.
+fn percentile(xs: &[f64], p: f64) -> Result<f64, RuntimeError> {
+    if xs.is_empty() {
+        return Err(RuntimeError::MathError);
+    }
+    if !(0.0..=100.0).contains(&p) {
+        return Err(RuntimeError::DomainError);
+    }
+
+    let rank = p / 100.0 * (xs.len() - 1) as f64;
+    let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+    Ok(xs[lo] + (xs[hi] - xs[lo]) * (rank - lo as f64))
+}
+
+/// Sorts ascending and stably by IEEE total order, so `-0` comes before `0`
+/// and NaNs go last.
+fn interpret_sort(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let xs = pop_numbers(stack)?;
+    stack.push(Value::from(sorted(xs)));
+    Ok(())
+}
+
+/// `Percentile` pops `p` and then the list, i.e. `percentile(xs, p)`.
+/// `Iqr` is the distance between the 75th and 25th percentiles.
+fn interpret_percentile(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let result = match op {
+        Percentile => {
+            let p = pop_number(stack)?;
+            percentile(&sorted(pop_numbers(stack)?), p)?
+        }
+        Iqr => {
+            let xs = sorted(pop_numbers(stack)?);
+            percentile(&xs, 75.0)? - percentile(&xs, 25.0)?
+        }
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+/// Tolerant float equality, relative to the magnitude of the operands (and
+/// absolute near zero), used wherever lists are compared element-wise.
+fn nearly_equal(a: f64, b: f64) -> bool {
+    a == b || (a - b).abs() <= EPS * a.abs().max(b.abs()).max(1.0)
+}
+
+/// Drops elements that are nearly equal to an earlier one, keeping the order
+/// of first occurrence.
+fn unique(xs: impl IntoIterator<Item = f64>) -> Vec<f64> {
+    let mut result: Vec<f64> = Vec::new();
+    for x in xs {
+        if !result.iter().any(|y| nearly_equal(x, *y)) {
+            result.push(x);
+        }
+    }
+    result
+}
+
+fn interpret_set(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let result = match op {
+        Unique => unique(pop_numbers(stack)?),
+        Union => {
+            let b = pop_numbers(stack)?;
+            let a = pop_numbers(stack)?;
+            unique(a.into_iter().chain(b))
+        }
+        Intersect => {
+            let b = pop_numbers(stack)?;
+            let a = pop_numbers(stack)?;
+            unique(a.into_iter().filter(|x| b.iter().any(|y| nearly_equal(*x, *y))))
+        }
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    stack.push(Value::from(result));
+    Ok(())
+}
+
+/// List manipulation that works on elements of any type. `head` gives the
+/// first element and `tail` the list without it.
+fn interpret_list(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let mut xs = pop_list(stack)?;
+    let result = match op {
+        Reverse => {
+            xs.reverse();
+            Value::List(xs)
+        }
+        Head | Tail if xs.is_empty() => {
+            return Err(RuntimeError::IndexOutOfRange { index: 1, len: 0 });
+        }
+        Head => xs.swap_remove(0),
+        Tail => Value::List(xs.split_off(1)),
+        Concat => {
+            let mut a = pop_list(stack)?;
+            a.append(&mut xs);
+            Value::List(a)
+        }
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    stack.push(result);
+    Ok(())
+}
+
+/// The numbers of a flat list, if it is one.
+fn as_vector(value: &Value) -> Option<Vec<f64>> {
+    match value {
+        Value::List(xs) => xs.iter().map(Value::as_number).collect(),
+        _ => None,
+    }
+}
+
+/// A matrix from a non-empty list of equal-length, non-empty lists of
+/// numbers.
+fn to_matrix(value: Value) -> Result<Matrix, RuntimeError> {
+    let Value::List(rows) = value else {
+        return Err(RuntimeError::TypeError);
+    };
+    let rows: Matrix = rows
+        .iter()
+        .map(|row| match row {
+            Value::List(_) => as_vector(row).ok_or(RuntimeError::TypeError),
+            _ => Err(RuntimeError::TypeError),
+        })
+        .collect::<Result<_, _>>()?;
+    let cols = rows.first().map_or(0, Vec::len);
+    if cols == 0 {
+        return Err(RuntimeError::TypeError);
+    }
+    if rows.iter().any(|row| row.len() != cols) {
+        return Err(RuntimeError::LengthMismatch);
+    }
+    Ok(rows)
+}
+
+fn matrix_value(rows: Matrix) -> Value {
+    Value::List(rows.into_iter().map(Value::from).collect())
+}
+
+/// Pops `b` and then `a` and pushes `a @ b`. Products with vectors give
+/// vectors, and a row vector times a column vector gives a number.
+fn interpret_matmul(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let b = stack.pop().ok_or(RuntimeError::Underflow)?;
+    let a = stack.pop().ok_or(RuntimeError::Underflow)?;
+    let (a, row_vector) = match as_vector(&a) {
+        Some(xs) => (vec![xs], true),
+        None => (to_matrix(a)?, false),
+    };
+    let (b, column_vector) = match as_vector(&b) {
+        Some(xs) => (xs.into_iter().map(|x| vec![x]).collect(), true),
+        None => (to_matrix(b)?, false),
+    };
+    let shape = |m: &Matrix| (m.len(), m.first().map_or(0, Vec::len));
+    if shape(&a).1 != b.len() {
+        return Err(RuntimeError::ShapeMismatch { left: shape(&a), right: shape(&b) });
+    }
+
+    let mut product = matrix::multiply(&a, &b);
+    let result = match (row_vector, column_vector) {
+        (true, true) => Value::Number(product[0][0]),
+        (true, false) => Value::from(product.swap_remove(0)),
+        (false, true) => Value::from(product.into_iter().map(|row| row[0]).collect::<Vec<_>>()),
+        (false, false) => matrix_value(product),
+    };
+    stack.push(result);
+    Ok(())
+}
+
+fn pop_vector(stack: &mut Vec<Value>) -> Result<Vec<f64>, RuntimeError> {
+    as_vector(&stack.pop().ok_or(RuntimeError::Underflow)?).ok_or(RuntimeError::TypeError)
+}
+
+/// `norm` is the Euclidean length of a vector.
+fn interpret_vector(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let b = pop_vector(stack)?;
+    let result = match op {
+        Norm => Value::Number(b.iter().map(|x| x * x).sum::<f64>().sqrt()),
+        Dot => {
+            let a = pop_vector(stack)?;
+            if a.len() != b.len() {
+                return Err(RuntimeError::LengthMismatch);
+            }
+            Value::Number(a.iter().zip(&b).map(|(x, y)| x * y).sum())
+        }
+        Cross => {
+            let a = pop_vector(stack)?;
+            let (&[a1, a2, a3], &[b1, b2, b3]) = (&a[..], &b[..]) else {
+                return Err(RuntimeError::DomainError);
+            };
+            Value::from(vec![a2 * b3 - a3 * b2, a3 * b1 - a1 * b3, a1 * b2 - a2 * b1])
+        }
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    stack.push(result);
+    Ok(())
+}
+
+/// `polyval(coeffs, x)` also takes a list of `x` values. `polyroots` gives
+/// only the real roots, ascending.
+fn interpret_poly(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let result = match op {
+        PolyVal => {
+            let x = stack.pop().ok_or(RuntimeError::Underflow)?;
+            let coeffs = pop_vector(stack)?;
+            match (x.as_number(), as_vector(&x)) {
+                (Some(x), _) => Value::Number(poly::eval(&coeffs, x)),
+                (_, Some(xs)) => Value::from(xs.iter().map(|&x| poly::eval(&coeffs, x)).collect::<Vec<_>>()),
+                _ => return Err(RuntimeError::TypeError),
+            }
+        }
+        PolyRoots => Value::from(poly::real_roots(&pop_vector(stack)?).ok_or(RuntimeError::DomainError)?),
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    stack.push(result);
+    Ok(())
+}
+
+/// `rationalize(x, max_den)` gives text such as `-7/4`, or just the
+/// numerator when the denominator is 1. `confrac(x, n)` is limited to the
+/// maximum list length.
+fn interpret_rational(stack: &mut Vec<Value>, op: &Operation, max_len: Option<usize>) -> Result<(), RuntimeError> {
+    let n = pop_number(stack)?;
+    let x = pop_number(stack)?;
+    if !x.is_finite() || n < 1.0 || n.fract() != 0.0 {
+        return Err(RuntimeError::DomainError);
+    }
+    let result = match op {
+        Rationalize => {
+            let options = FormatOptions::default();
+            match rational::best_rational(x, n) {
+                (p, 1.0) => Value::Str(format_number(p, &options)),
+                (p, q) => Value::Str(format!("{}/{}", format_number(p, &options), format_number(q, &options))),
+            }
+        }
+        ConFrac => {
+            if let Some(max) = max_len
+                && n > max as f64
+            {
+                return Err(RuntimeError::LimitExceeded(Limit::ListLength(max)));
+            }
+            Value::from(rational::continued_fraction(x, n as usize))
+        }
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    stack.push(result);
+    Ok(())
+}
+
+/// `linsolve` pops the right-hand side `b` before the square matrix `A`, and
+/// solves by Gaussian elimination with partial pivoting.
+fn interpret_matrix(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let b = if *op == LinSolve { Some(pop_vector(stack)?) } else { None };
+    let a = to_matrix(stack.pop().ok_or(RuntimeError::Underflow)?)?;
+    if *op == Transpose {
+        stack.push(matrix_value(matrix::transpose(&a)));
+        return Ok(());
+    }
+    let (rows, cols) = (a.len(), a[0].len());
+    if rows != cols {
+        return Err(RuntimeError::NotSquare { rows, cols });
+    }
+    let result = match op {
+        Det => Value::Number(matrix::det(&a)),
+        Inv => matrix_value(Lu::new(&a).ok_or(RuntimeError::Singular)?.inverse()),
+        LinSolve => {
+            let b = b.unwrap_or_default();
+            if b.len() != rows {
+                return Err(RuntimeError::LengthMismatch);
+            }
+            Value::from(Lu::new(&a).ok_or(RuntimeError::Singular)?.solve(&b))
+        }
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    stack.push(result);
+    Ok(())
+}
+
+/// Running totals: element `i` of the result combines elements `0..=i`.
+fn interpret_cumulative(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let xs = pop_numbers(stack)?;
+    let (init, combine): (f64, fn(f64, f64) -> f64) = match op {
+        CumSum => (0.0, |acc, x| acc + x),
+        CumProd => (1.0, |acc, x| acc * x),
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    let result: Vec<f64> = xs
+        .iter()
+        .scan(init, |acc, x| {
+            *acc = combine(*acc, *x);
+            Some(*acc)
+        })
+        .collect();
+
+    stack.push(Value::from(result));
+    Ok(())
+}
+
+/// Uniform random integer in the inclusive range `randint(a, b)`.
+fn interpret_randint(stack: &mut Vec<Value>, rng: &mut Rng) -> Result<(), RuntimeError> {
+    let high = as_integer(pop_number(stack)?)?;
+    let low = as_integer(pop_number(stack)?)?;
+    if low > high {
+        return Err(RuntimeError::DomainError);
+    }
+
+    push_number(stack, rng.next_in_range(low, high) as f64);
+    Ok(())
+}
+
+/// Error function. Below `|x| = 2` this sums the series
+/// `erf(x) = 2/sqrt(pi) * exp(-x^2) * sum(2^n x^(2n+1) / (1*3*...*(2n+1)))`,
+/// whose terms are all positive so there is no cancellation; above it uses
+/// the complement of the continued fraction in [`erfc`].
+fn erf(x: f64) -> f64 {
+    if x.is_nan() {
+        return x;
+    }
+    if x.abs() >= 2.0 {
+        return x.signum() * (1.0 - erfc(x.abs()));
+    }
+
+    let mut term = x;
+    let mut sum = x;
+    let mut n = 0.0;
+    while term.abs() > sum.abs() * f64::EPSILON {
+        n += 1.0;
+        term *= 2.0 * x * x / (2.0 * n + 1.0);
+        sum += term;
+    }
+    2.0 / std::f64::consts::PI.sqrt() * (-x * x).exp() * sum
+}
+
+/// Complementary error function. For `x >= 2` it evaluates the continued
+/// fraction `erfc(x) = exp(-x^2)/sqrt(pi) / (x + (1/2)/(x + 1/(x + (3/2)/(x + ...))))`
+/// from the tail, which keeps full relative accuracy far into the tail.
+fn erfc(x: f64) -> f64 {
+    if x < 2.0 {
+        return 1.0 - erf(x);
+    }
+
+    let mut fraction = x;
+    for n in (1..=200).rev() {
+        fraction = x + (n as f64 / 2.0) / fraction;
+    }
+    (-x * x).exp() / std::f64::consts::PI.sqrt() / fraction
+}
+
+fn interpret_erf(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let x = pop_number(stack)?;
+    let result = match op {
+        Erf => erf(x),
+        Erfc => erfc(x),
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+/// Normal density and cumulative distribution for `normpdf(x, mu, sigma)`;
+/// the one-argument forms compile with `mu = 0` and `sigma = 1`.
+fn interpret_normal(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let sigma = pop_number(stack)?;
+    let mu = pop_number(stack)?;
+    let x = pop_number(stack)?;
+    if sigma <= 0.0 {
+        return Err(RuntimeError::DomainError);
+    }
+
+    let z = (x - mu) / sigma;
+    let result = match op {
+        NormPdf => (-z * z / 2.0).exp() / (sigma * (2.0 * std::f64::consts::PI).sqrt()),
+        NormCdf => 0.5 * erfc(-z / std::f64::consts::SQRT_2),
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+/// `compound(principal, rate, years, n)`: principal after `years` at an
+/// annual `rate` compounded `n` times a year (once when omitted).
+fn interpret_compound(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let n = pop_number(stack)?;
+    let years = pop_number(stack)?;
+    let rate = pop_number(stack)?;
+    let principal = pop_number(stack)?;
+    if n <= 0.0 || rate / n <= -1.0 {
+        return Err(RuntimeError::DomainError);
+    }
+
+    push_number(stack, principal * (1.0 + rate / n).powf(n * years));
+    Ok(())
+}
+
+/// `fv(rate, n, pv, pmt)` and `pv(rate, n, fv, pmt)` move a lump sum plus
+/// an optional payment at the end of each of `n` periods forward or back in
+/// time. All amounts are treated as positive; there is no sign convention.
+fn interpret_time_value(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let payment = pop_number(stack)?;
+    let amount = pop_number(stack)?;
+    let periods = pop_number(stack)?;
+    let rate = pop_number(stack)?;
+    if rate <= -1.0 {
+        return Err(RuntimeError::DomainError);
+    }
+
+    let growth = (1.0 + rate).powf(periods);
+    // Future value of the payments; `n * pmt` in the limit of a zero rate.
+    let annuity = if rate == 0.0 {
+        payment * periods
+    } else {
+        payment * (growth - 1.0) / rate
+    };
+    let result = match op {
+        Fv => amount * growth + annuity,
+        Pv => (amount + annuity) / growth,
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    push_number(stack, result);
+    Ok(())
+}
+
+/// Net present value with the first cash flow at time zero, so that
+/// `npv(irr(cfs), cfs) == 0`.
+fn npv(rate: f64, cashflows: &[f64]) -> f64 {
+    cashflows
+        .iter()
+        .rev()
+        .fold(0.0, |acc, cf| acc / (1.0 + rate) + cf)
+}
+
+fn interpret_npv(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let cashflows = pop_numbers(stack)?;
+    let rate = pop_number(stack)?;
+    if rate <= -1.0 {
+        return Err(RuntimeError::DomainError);
+    }
+
+    push_number(stack, npv(rate, &cashflows));
+    Ok(())
+}
+
+/// Internal rate of return: the rate above -100% at which the NPV is zero.
+/// The rate is bracketed by doubling the upper bound and then found by
+/// bisection. Cash flows without a sign change have no IRR.
+fn interpret_irr(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let cashflows = pop_numbers(stack)?;
+    let f = |rate| npv(rate, &cashflows);
+
+    let (mut lo, mut hi) = (-1.0 + 1e-9, 1.0);
+    while f(lo).signum() == f(hi).signum() {
+        hi *= 2.0;
+        if hi > 1e6 {
+            return Err(RuntimeError::MathError);
+        }
+    }
+
+    for _ in 0..200 {
+        let mid = lo + (hi - lo) / 2.0;
+        if mid == lo || mid == hi {
+            break;
+        }
+        if f(mid).signum() == f(lo).signum() {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+
+    push_number(stack, lo + (hi - lo) / 2.0);
+    Ok(())
+}
+
+/// `convert(value, "from", "to")`. Unknown units, and units of different
+/// dimensions (e.g. `"kg"` to `"m"`), are domain errors.
+fn interpret_convert(stack: &mut Vec<Value>) -> Result<(), RuntimeError> {
+    let to = pop_string(stack)?;
+    let from = pop_string(stack)?;
+    let value = pop_number(stack)?;
+    let result = units::convert(value, &from, &to).ok_or(RuntimeError::DomainError)?;
+
+    push_number(stack, result);
+    Ok(())
+}
+
+/// Renders an integer as a prefixed string in base 16, 2 or 8, e.g.
+/// `to_hex(-255)` is `"-0xff"`.
+fn interpret_to_radix(stack: &mut Vec<Value>, op: &Operation) -> Result<(), RuntimeError> {
+    let x = as_integer(pop_number(stack)?)?;
+    let radix = match op {
+        ToHex => 16,
+        ToBin => 2,
+        ToOct => 8,
+        _ => {
+            return Err(RuntimeError::NotImplemented);
+        }
+    };
+
+    stack.push(Value::Str(format_radix(x, radix)));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use core::f64;
+    use std::f64::consts::{FRAC_PI_4, FRAC_PI_2, PI};
+
+    use super::*;
+    use crate::format::format_output;
+    use crate::operation::Branches;
+
+    fn eval(ops: Vec<Operation>) -> f64 {
+        eval_value(ops).as_number().expect("Expected a number")
+    }
+
+    fn eval_value(ops: Vec<Operation>) -> Value {
+        let mut vm = VirtualMachine::new();
+        vm.interpret(&ops).unwrap().result
+    }
+
+    fn assert_approx_eq(a: f64, b: f64) {
+        assert!(approx_eq(a, b, EPS_INTERNAL))
+    }
+
+    fn assert_runtime_error(ops: Vec<Operation>, expected_error: RuntimeError) {
+        let mut vm = VirtualMachine::new();
+        let result = vm.interpret(&ops);
+        match result {
+            Ok(_) => panic!("Expected runtime error {:?}, but got Ok", expected_error),
+            Err(e) => assert_eq!(e.kind(), &expected_error),
+        }
+    }
+
+    fn approx_eq(a: f64, b: f64, eps: f64) -> bool {
+        (a - b).abs() < eps
+    }
+
+    #[test]
+    fn test_simple_const() {
+        let ops = vec![Const(42.0)];
+        assert_eq!(eval(ops), 42.0);
+    }
+
+    #[test]
+    fn test_addition() {
+        let ops = vec![Const(1.0), Const(2.0), Add];
+        assert_eq!(eval(ops), 3.0);
+    }
+
+    #[test]
+    fn test_subtraction() {
+        let ops = vec![Const(10.0), Const(3.0), Subtract];
+        assert_eq!(eval(ops), 7.0);
+    }
+
+    #[test]
+    fn test_multiplication() {
+        let ops = vec![Const(6.0), Const(7.0), Times];
+        assert_eq!(eval(ops), 42.0);
+    }
+
+    #[test]
+    fn test_division() {
+        let ops = vec![Const(20.0), Const(4.0), Divide];
+        assert_eq!(eval(ops), 5.0);
+    }
+
+    #[test]
+    fn test_unary_negation() {
+        let ops = vec![Const(5.0), Negate];
+        assert_eq!(eval(ops), -5.0);
+    }
+
+    #[test]
+    fn test_chained_expression() {
+        // Equivalent to: 1 + 2 * 3  → RPN: 1 2 3 * +
+        let ops = vec![Const(1.0), Const(2.0), Const(3.0), Times, Add];
+        assert_eq!(eval(ops), 7.0);
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let ops = vec![Const(5.0), Const(0.0), Divide];
+
+        assert_runtime_error(ops, RuntimeError::MathError);
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let ops = vec![Add];
+
+        assert_runtime_error(ops, RuntimeError::Underflow);
+    }
+
+    #[test]
+    fn test_overflow_behavior() {
+        let ops = vec![Const(f64::MAX), Const(2.0), Times];
+
+        assert!(eval(ops).is_infinite());
+    }
+
+    #[test]
+    fn test_sin() {
+        let ops = vec![Const(2.0 * PI), Sin];
+        assert_approx_eq(eval(ops), 0.0);
+    }
+
+    #[test]
+    fn test_cos() {
+        let ops = vec![Const(0.0), Cos];
+        assert_approx_eq(eval(ops), 1.0);
+    }
+
+    #[test]
+    fn test_tan() {
+        let ops = vec![Const(FRAC_PI_2), Tan];
+        assert!(eval(ops).abs() > 1.0 / EPS_INTERNAL);
+    }
+
+    #[test]
+    fn test_arcsin() {
+        let ops = vec![Const(1.0), ArcSin];
+        assert_approx_eq(eval(ops), FRAC_PI_2);
+    }
+
+    #[test]
     fn test_arccos() {
         let ops = vec![Const(1.0), ArcCos];
         assert_approx_eq(eval(ops), 0.0);
     }
 
     #[test]
-    fn test_arctan() {
-        let ops = vec![Const(1.0), ArcTan];
-        assert_approx_eq(eval(ops), FRAC_PI_4);
+    fn test_arctan() {
+        let ops = vec![Const(1.0), ArcTan];
+        assert_approx_eq(eval(ops), FRAC_PI_4);
+    }
+
+    #[test]
+    fn test_digits() {
+        let ops = vec![Const(-1203.0), Digits];
+        assert_eq!(eval_value(ops), Value::from(vec![1.0, 2.0, 0.0, 3.0]));
+
+        let ops = vec![Const(0.0), Digits];
+        assert_eq!(eval_value(ops), Value::from(vec![0.0]));
+    }
+
+    #[test]
+    fn test_digitsum() {
+        let ops = vec![Const(9875.0), DigitSum];
+        assert_eq!(eval(ops), 29.0);
+    }
+
+    #[test]
+    fn test_reversedigits() {
+        let ops = vec![Const(1230.0), ReverseDigits];
+        assert_eq!(eval(ops), 321.0);
+
+        let ops = vec![Const(-45.0), ReverseDigits];
+        assert_eq!(eval(ops), -54.0);
+    }
+
+    #[test]
+    fn test_digits_non_integer() {
+        let ops = vec![Const(1.5), Digits];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_sqrt() {
+        let ops = vec![Const(16.0), Sqrt];
+        assert_eq!(eval(ops), 4.0);
+
+        let ops = vec![Const(-4.0), Sqrt];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_cbrt() {
+        let ops = vec![Const(-27.0), Cbrt];
+        assert_eq!(eval(ops), -3.0);
+    }
+
+    #[test]
+    fn test_root() {
+        let ops = vec![Const(4.0), Const(81.0), Root];
+        assert_approx_eq(eval(ops), 3.0);
+
+        let ops = vec![Const(5.0), Const(-32.0), Root];
+        assert_approx_eq(eval(ops), -2.0);
+
+        let ops = vec![Const(4.0), Const(-16.0), Root];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+
+        let ops = vec![Const(0.0), Const(2.0), Root];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_haversine() {
+        // One degree of longitude along the equator.
+        let ops = vec![Const(0.0), Const(0.0), Const(0.0), Const(1.0), Const(EARTH_RADIUS_KM), Haversine];
+        assert!(approx_eq(eval(ops), EARTH_RADIUS_KM * PI / 180.0, EPS));
+
+        // London to Paris is roughly 344 km.
+        let ops = vec![Const(51.5074), Const(-0.1278), Const(48.8566), Const(2.3522), Const(EARTH_RADIUS_KM), Haversine];
+        assert!(approx_eq(eval(ops), 343.56, 0.1));
+
+        let ops = vec![Const(91.0), Const(0.0), Const(0.0), Const(0.0), Const(1.0), Haversine];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_bearing() {
+        let ops = vec![Const(0.0), Const(0.0), Const(10.0), Const(0.0), Bearing];
+        assert_approx_eq(eval(ops), 0.0);
+
+        let ops = vec![Const(0.0), Const(0.0), Const(0.0), Const(-10.0), Bearing];
+        assert_approx_eq(eval(ops), 270.0);
+    }
+
+    #[test]
+    fn test_ln() {
+        let ops = vec![Const(E), Ln];
+        assert_approx_eq(eval(ops), 1.0);
+
+        let ops = vec![Const(0.0), Ln];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_log10_and_log2() {
+        let ops = vec![Const(1000.0), Log10];
+        assert_eq!(eval(ops), 3.0);
+
+        let ops = vec![Const(1024.0), Log2];
+        assert_eq!(eval(ops), 10.0);
+
+        let ops = vec![Const(-1.0), Log2];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_log_base() {
+        let ops = vec![Const(3.0), Const(81.0), LogBase];
+        assert_approx_eq(eval(ops), 4.0);
+
+        let ops = vec![Const(1.0), Const(5.0), LogBase];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_hyperbolic() {
+        let ops = vec![Const(0.0), Sinh];
+        assert_approx_eq(eval(ops), 0.0);
+
+        let ops = vec![Const(0.0), Cosh];
+        assert_approx_eq(eval(ops), 1.0);
+
+        let ops = vec![Const(1.0), Tanh];
+        assert_approx_eq(eval(ops), (E * E - 1.0) / (E * E + 1.0));
+    }
+
+    #[test]
+    fn test_hyperbolic_ignores_angle_mode() {
+        let mut vm = VirtualMachine::new();
+        vm.use_radians = false;
+        let result = vm.interpret(&[Const(1.0), Sinh]).unwrap().result;
+        assert_eq!(result, Value::Number(1.0_f64.sinh()));
+    }
+
+    #[test]
+    fn test_inverse_hyperbolic() {
+        let ops = vec![Const(2.0), Sinh, ArcSinh];
+        assert_approx_eq(eval(ops), 2.0);
+
+        let ops = vec![Const(1.0), ArcCosh];
+        assert_approx_eq(eval(ops), 0.0);
+
+        let ops = vec![Const(0.5), ArcTanh];
+        assert_approx_eq(eval(ops), 0.5_f64.atanh());
+    }
+
+    #[test]
+    fn test_inverse_hyperbolic_domain() {
+        let ops = vec![Const(0.5), ArcCosh];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+
+        let ops = vec![Const(1.0), ArcTanh];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+
+        let ops = vec![Const(-1.5), ArcTanh];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_db() {
+        let ops = vec![Const(100.0), Db];
+        assert_eq!(eval(ops), 20.0);
+
+        let ops = vec![Const(30.0), UnDb];
+        assert_approx_eq(eval(ops), 1000.0);
+
+        let ops = vec![Const(0.0), Db];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_dbm_conversion() {
+        let ops = vec![Const(0.0), DbmToMw];
+        assert_eq!(eval(ops), 1.0);
+
+        let ops = vec![Const(1000.0), MwToDbm];
+        assert_eq!(eval(ops), 30.0);
+
+        let ops = vec![Const(-3.0), MwToDbm];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_hex2rgb() {
+        let ops = vec![Str("ff8800".into()), Hex2Rgb];
+        assert_eq!(eval_value(ops), Value::from(vec![255.0, 136.0, 0.0]));
+
+        let ops = vec![Str("#0f0".into()), Hex2Rgb];
+        assert_eq!(eval_value(ops), Value::from(vec![0.0, 255.0, 0.0]));
+
+        let ops = vec![Str("ff88zz".into()), Hex2Rgb];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+
+        let ops = vec![Const(1.0), Hex2Rgb];
+        assert_runtime_error(ops, RuntimeError::TypeError);
+    }
+
+    #[test]
+    fn test_rgb2hex() {
+        let ops = vec![Const(255.0), Const(136.0), Const(0.0), Rgb2Hex];
+        assert_eq!(eval_value(ops), Value::Str("#ff8800".into()));
+
+        let ops = vec![Const(256.0), Const(0.0), Const(0.0), Rgb2Hex];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_srgb_linear_round_trip() {
+        let ops = vec![Const(0.5), SrgbToLinear];
+        assert!(approx_eq(eval(ops), 0.214041, 1e-6));
+
+        let ops = vec![Const(0.5), SrgbToLinear, LinearToSrgb];
+        assert_approx_eq(eval(ops), 0.5);
+
+        let ops = vec![Const(1.5), LinearToSrgb];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_abs_and_sign() {
+        assert_eq!(eval(vec![Const(-3.5), Abs]), 3.5);
+        assert_eq!(eval(vec![Const(-3.5), Sign]), -1.0);
+        assert_eq!(eval(vec![Const(2.0), Sign]), 1.0);
+        assert_eq!(eval(vec![Const(0.0), Sign]), 0.0);
+    }
+
+    #[test]
+    fn test_floor_ceil_trunc() {
+        assert_eq!(eval(vec![Const(-2.5), Floor]), -3.0);
+        assert_eq!(eval(vec![Const(-2.5), Ceil]), -2.0);
+        assert_eq!(eval(vec![Const(-2.5), Trunc]), -2.0);
+        assert_eq!(eval(vec![Const(2.5), Floor]), 2.0);
+    }
+
+    #[test]
+    fn test_round_ties() {
+        assert_eq!(eval(vec![Const(2.5), Round]), 3.0);
+        assert_eq!(eval(vec![Const(-2.5), Round]), -3.0);
+        assert_eq!(eval(vec![Const(-2.4), Round]), -2.0);
+    }
+
+    #[test]
+    fn test_frac() {
+        assert_eq!(eval(vec![Const(3.25), Frac]), 0.25);
+        assert_eq!(eval(vec![Const(-3.25), Frac]), -0.25);
+    }
+
+    #[test]
+    fn test_floor_division() {
+        assert_eq!(eval(vec![Const(7.0), Const(2.0), FloorDivide]), 3.0);
+        assert_eq!(eval(vec![Const(-7.0), Const(2.0), FloorDivide]), -4.0);
+
+        let ops = vec![Const(1.0), Const(0.0), FloorDivide];
+        assert_runtime_error(ops, RuntimeError::MathError);
+    }
+
+    #[test]
+    fn test_modulo_sign_follows_divisor() {
+        assert_eq!(eval(vec![Const(7.0), Const(3.0), Modulo]), 1.0);
+        assert_eq!(eval(vec![Const(-7.0), Const(3.0), Modulo]), 2.0);
+        assert_eq!(eval(vec![Const(7.0), Const(-3.0), Modulo]), -2.0);
+        assert_eq!(eval(vec![Const(5.5), Const(2.0), Modulo]), 1.5);
+
+        let ops = vec![Const(1.0), Const(0.0), Modulo];
+        assert_runtime_error(ops, RuntimeError::MathError);
+    }
+
+    #[test]
+    fn test_parallel() {
+        let ops = vec![Const(100.0), Const(100.0), Parallel];
+        assert_approx_eq(eval(ops), 50.0);
+
+        let ops = vec![Const(6.0), Const(3.0), Const(2.0), Parallel, Parallel];
+        assert_approx_eq(eval(ops), 1.0);
+
+        let ops = vec![Const(100.0), Const(0.0), Parallel];
+        assert_eq!(eval(ops), 0.0);
+
+        let ops = vec![Const(100.0), Const(-1.0), Parallel];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_voltage_divider() {
+        let ops = vec![Const(12.0), Const(1000.0), Const(2000.0), VoltageDivider];
+        assert_approx_eq(eval(ops), 8.0);
+
+        let ops = vec![Const(12.0), Const(0.0), Const(0.0), VoltageDivider];
+        assert_runtime_error(ops, RuntimeError::MathError);
+    }
+
+    #[test]
+    fn test_odds_and_prob() {
+        assert_eq!(eval(vec![Const(0.75), Odds]), 3.0);
+        assert_eq!(eval(vec![Const(3.0), Prob]), 0.75);
+
+        assert_runtime_error(vec![Const(1.0), Odds], RuntimeError::MathError);
+        assert_runtime_error(vec![Const(1.5), Odds], RuntimeError::DomainError);
+        assert_runtime_error(vec![Const(-1.0), Prob], RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_bayes() {
+        // 1% prevalence, 90% sensitivity, 91% specificity.
+        let ops = vec![Const(0.01), Const(0.9), Const(0.91), Bayes];
+        assert!(approx_eq(eval(ops), 0.0917431, 1e-6));
+
+        let ops = vec![Const(0.0), Const(0.9), Const(1.0), Bayes];
+        assert_runtime_error(ops, RuntimeError::MathError);
+
+        let ops = vec![Const(0.5), Const(1.2), Const(0.9), Bayes];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_gcd() {
+        assert_eq!(eval(vec![Const(48.0), Const(18.0), Gcd]), 6.0);
+        assert_eq!(eval(vec![Const(-48.0), Const(18.0), Gcd]), 6.0);
+        assert_eq!(eval(vec![Const(0.0), Const(0.0), Gcd]), 0.0);
+
+        let ops = vec![Const(4.5), Const(3.0), Gcd];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_lcm() {
+        assert_eq!(eval(vec![Const(4.0), Const(6.0), Lcm]), 12.0);
+        assert_eq!(eval(vec![Const(0.0), Const(6.0), Lcm]), 0.0);
+
+        let ops = vec![Const(4.0), Const(0.5), Lcm];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_factorial() {
+        assert_eq!(eval(vec![Const(0.0), Factorial]), 1.0);
+        assert_eq!(eval(vec![Const(5.0), Factorial]), 120.0);
+        assert_eq!(eval(vec![Const(20.0), Factorial]), 2432902008176640000.0);
+        assert!(eval(vec![Const(200.0), Factorial]).is_infinite());
+    }
+
+    #[test]
+    fn test_factorial_non_integer() {
+        // 0.5! = sqrt(pi) / 2
+        let ops = vec![Const(0.5), Factorial];
+        assert!(approx_eq(eval(ops), PI.sqrt() / 2.0, EPS));
+
+        let ops = vec![Const(-1.0), Factorial];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_gamma() {
+        assert!(approx_eq(eval(vec![Const(5.0), Gamma]), 24.0, EPS));
+        assert!(approx_eq(eval(vec![Const(0.5), Gamma]), PI.sqrt(), EPS));
+        assert!(approx_eq(eval(vec![Const(-0.5), Gamma]), -2.0 * PI.sqrt(), EPS));
+
+        assert_runtime_error(vec![Const(0.0), Gamma], RuntimeError::DomainError);
+        assert_runtime_error(vec![Const(-3.0), Gamma], RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_builtin_function() {
+        assert_eq!(builtin_function("sqrt"), Some(Sqrt));
+        assert_eq!(builtin_function("arcsinh"), Some(ArcSinh));
+        assert_eq!(builtin_function("fact"), Some(Factorial));
+        assert_eq!(builtin_function("atan2"), None);
+        assert_eq!(builtin_function("pi"), None);
+        assert_eq!(builtin_function("sqrt2"), None);
+    }
+
+    #[test]
+    fn test_gamma_of_integers_is_exact() {
+        for n in 1..=30 {
+            let factorial = eval(vec![Const(n as f64 - 1.0), Factorial]);
+            assert_eq!(eval(vec![Const(n as f64), Gamma]), factorial, "gamma({})", n);
+        }
+        assert_eq!(eval(vec![Const(5.0), Gamma]), 24.0);
+    }
+
+    fn list(xs: &[f64]) -> Vec<Operation> {
+        let mut ops: Vec<Operation> = xs.iter().map(|x| Const(*x)).collect();
+        ops.push(MakeList(xs.len()));
+        ops
+    }
+
+    #[test]
+    fn test_make_list() {
+        assert_eq!(eval_value(list(&[1.0, 2.0])), Value::from(vec![1.0, 2.0]));
+        assert_eq!(eval_value(list(&[])), Value::List(vec![]));
+        assert_runtime_error(vec![Const(1.0), MakeList(2)], RuntimeError::Underflow);
+    }
+
+    #[test]
+    fn test_wmean() {
+        let ops = [list(&[1.0, 2.0, 3.0]), list(&[3.0, 1.0, 0.0]), vec![WMean]].concat();
+        assert_eq!(eval(ops), 1.25);
+
+        let ops = [list(&[1.0, 2.0]), list(&[1.0]), vec![WMean]].concat();
+        assert_runtime_error(ops, RuntimeError::LengthMismatch);
+
+        let ops = [list(&[1.0, 2.0]), list(&[0.0, 0.0]), vec![WMean]].concat();
+        assert_runtime_error(ops, RuntimeError::MathError);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let ops = [list(&[1.0, 3.0]), vec![Normalize]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![0.25, 0.75]));
+
+        let ops = [list(&[1.0, -1.0]), vec![Normalize]].concat();
+        assert_runtime_error(ops, RuntimeError::MathError);
+    }
+
+    #[test]
+    fn test_zscore() {
+        let ops = [list(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]), vec![ZScore]].concat();
+        assert_eq!(
+            eval_value(ops),
+            Value::from(vec![-1.5, -0.5, -0.5, -0.5, 0.0, 0.0, 1.0, 2.0])
+        );
+
+        let ops = [list(&[3.0, 3.0]), vec![ZScore]].concat();
+        assert_runtime_error(ops, RuntimeError::MathError);
+
+        let ops = vec![Const(1.0), ZScore];
+        assert_runtime_error(ops, RuntimeError::TypeError);
+    }
+
+    #[test]
+    fn test_sort() {
+        let ops = [list(&[3.0, -1.0, 2.0, -1.0]), vec![Sort]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![-1.0, -1.0, 2.0, 3.0]));
+    }
+
+    #[test]
+    fn test_percentile_interpolates() {
+        let xs = [15.0, 20.0, 35.0, 40.0, 50.0];
+        assert_eq!(eval([list(&xs), vec![Const(0.0), Percentile]].concat()), 15.0);
+        assert_eq!(eval([list(&xs), vec![Const(50.0), Percentile]].concat()), 35.0);
+        assert_eq!(eval([list(&xs), vec![Const(100.0), Percentile]].concat()), 50.0);
+        assert_eq!(eval([list(&xs), vec![Const(40.0), Percentile]].concat()), 29.0);
+    }
+
+    #[test]
+    fn test_percentile_errors() {
+        let ops = [list(&[1.0]), vec![Const(101.0), Percentile]].concat();
+        assert_runtime_error(ops, RuntimeError::DomainError);
+
+        let ops = [list(&[]), vec![Const(50.0), Percentile]].concat();
+        assert_runtime_error(ops, RuntimeError::MathError);
+    }
+
+    #[test]
+    fn test_iqr() {
+        let ops = [list(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]), vec![Iqr]].concat();
+        assert_eq!(eval(ops), 4.0);
+    }
+
+    #[test]
+    fn test_unique_is_tolerant() {
+        let ops = [list(&[0.1 + 0.2, 1.0, 0.3, 1.0]), vec![Unique]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![0.1 + 0.2, 1.0]));
+    }
+
+    #[test]
+    fn test_union() {
+        let ops = [list(&[1.0, 2.0]), list(&[2.0, 3.0, 1.0]), vec![Union]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![1.0, 2.0, 3.0]));
+    }
+
+    #[test]
+    fn test_intersect() {
+        let ops = [list(&[1.0, 2.0, 0.3, 2.0]), list(&[2.0, 0.1 + 0.2]), vec![Intersect]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![2.0, 0.3]));
+
+        let ops = [list(&[1.0]), list(&[]), vec![Intersect]].concat();
+        assert_eq!(eval_value(ops), Value::List(vec![]));
+    }
+
+    #[test]
+    fn test_min_max_fold() {
+        let ops = vec![Const(3.0), Const(1.0), Const(4.0), Const(1.0), Const(5.0), Min, Min, Min, Min];
+        assert_eq!(eval(ops), 1.0);
+
+        let ops = vec![Const(3.0), Const(1.0), Const(4.0), Max, Max];
+        assert_eq!(eval(ops), 4.0);
+
+        let ops = vec![Const(-0.5), Const(-2.0), Max];
+        assert_eq!(eval(ops), -0.5);
+    }
+
+    #[test]
+    fn test_min_max_nan_propagates() {
+        assert!(eval(vec![Const(1.0), Const(f64::NAN), Min]).is_nan());
+        assert!(eval(vec![Const(f64::NAN), Const(1.0), Max]).is_nan());
+    }
+
+    #[test]
+    fn test_cumsum() {
+        let ops = [list(&[1.0, 2.0, 3.0, -4.0]), vec![CumSum]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![1.0, 3.0, 6.0, 2.0]));
+
+        let ops = [list(&[]), vec![CumSum]].concat();
+        assert_eq!(eval_value(ops), Value::List(vec![]));
+    }
+
+    #[test]
+    fn test_cumprod() {
+        let ops = [list(&[1.0, 2.0, 3.0, 4.0]), vec![CumProd]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![1.0, 2.0, 6.0, 24.0]));
+    }
+
+    #[test]
+    fn test_atan2_quadrants() {
+        assert_approx_eq(eval(vec![Const(1.0), Const(1.0), ArcTan2]), FRAC_PI_4);
+        assert_approx_eq(eval(vec![Const(1.0), Const(-1.0), ArcTan2]), 3.0 * FRAC_PI_4);
+        assert_approx_eq(eval(vec![Const(-1.0), Const(-1.0), ArcTan2]), -3.0 * FRAC_PI_4);
+        assert_eq!(eval(vec![Const(0.0), Const(0.0), ArcTan2]), 0.0);
+    }
+
+    #[test]
+    fn test_atan2_degrees() {
+        let mut vm = VirtualMachine::new();
+        vm.use_radians = false;
+        let result = vm.interpret(&[Const(1.0), Const(-1.0), ArcTan2]).unwrap().result;
+        assert_eq!(result, Value::Number(135.0));
+    }
+
+    #[test]
+    fn test_hypot() {
+        assert_eq!(eval(vec![Const(3.0), Const(4.0), Hypot]), 5.0);
+        assert_eq!(eval(vec![Const(-5.0), Const(12.0), Hypot]), 13.0);
+    }
+
+    #[test]
+    fn test_output_indents_matrix_rows() {
+        let ops = [list(&[1.0, 2.0]), list(&[3.0, 4.0]), vec![MakeList(2)]].concat();
+        let mut vm = VirtualMachine::new();
+        let output = vm.interpret(&ops).unwrap();
+        assert_eq!(format_output(output.result(), &FormatOptions::default()), "Output: [[1, 2],\n         [3, 4]]");
+    }
+
+    #[test]
+    fn test_seeded_rand_is_reproducible() {
+        let ops = vec![Rand, RandN, Add, Const(1.0), Const(6.0), RandInt, Add];
+        let mut a = VirtualMachine::new();
+        let mut b = VirtualMachine::new();
+        a.seed(42);
+        b.seed(42);
+        assert_eq!(a.interpret(&ops).unwrap(), b.interpret(&ops).unwrap());
+    }
+
+    #[test]
+    fn test_randint() {
+        let mut vm = VirtualMachine::new();
+        for _ in 0..100 {
+            let x = vm.interpret(&[Const(1.0), Const(6.0), RandInt]).unwrap().result;
+            let x = x.as_number().unwrap();
+            assert!((1.0..=6.0).contains(&x) && x.fract() == 0.0);
+        }
+
+        assert_runtime_error(vec![Const(6.0), Const(1.0), RandInt], RuntimeError::DomainError);
+        assert_runtime_error(vec![Const(0.5), Const(1.0), RandInt], RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_isprime() {
+        let primes: Vec<f64> = (0..30)
+            .map(f64::from)
+            .filter(|&n| eval(vec![Const(n), IsPrime]) == 1.0)
+            .collect();
+        assert_eq!(primes, vec![2.0, 3.0, 5.0, 7.0, 11.0, 13.0, 17.0, 19.0, 23.0, 29.0]);
+
+        assert_eq!(eval(vec![Const(-7.0), IsPrime]), 0.0);
+        assert_eq!(eval(vec![Const(2147483647.0), IsPrime]), 1.0);
+        assert_eq!(eval(vec![Const(3215031751.0), IsPrime]), 0.0);
+        assert_runtime_error(vec![Const(7.5), IsPrime], RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_nextprime() {
+        assert_eq!(eval(vec![Const(-10.0), NextPrime]), 2.0);
+        assert_eq!(eval(vec![Const(7.0), NextPrime]), 11.0);
+        assert_eq!(eval(vec![Const(1000.0), NextPrime]), 1009.0);
+    }
+
+    #[test]
+    fn test_factor() {
+        assert_eq!(eval_value(vec![Const(360.0), Factor]), Value::from(vec![2.0, 2.0, 2.0, 3.0, 3.0, 5.0]));
+        assert_eq!(eval_value(vec![Const(1.0), Factor]), Value::List(vec![]));
+        assert_eq!(eval_value(vec![Const(97.0), Factor]), Value::from(vec![97.0]));
+
+        // Product of two primes above the trial-division bound.
+        assert_eq!(
+            eval_value(vec![Const(1000003.0 * 1000033.0), Factor]),
+            Value::from(vec![1000003.0, 1000033.0])
+        );
+
+        assert_runtime_error(vec![Const(0.0), Factor], RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_powmod() {
+        assert_eq!(eval(vec![Const(2.0), Const(128.0), Const(97.0), PowMod]), 35.0);
+        assert_eq!(eval(vec![Const(4.0), Const(13.0), Const(497.0), PowMod]), 445.0);
+        assert_eq!(eval(vec![Const(-2.0), Const(3.0), Const(5.0), PowMod]), 2.0);
+        assert_eq!(eval(vec![Const(5.0), Const(0.0), Const(1.0), PowMod]), 0.0);
+    }
+
+    #[test]
+    fn test_powmod_domain() {
+        assert_runtime_error(vec![Const(2.0), Const(-1.0), Const(5.0), PowMod], RuntimeError::DomainError);
+        assert_runtime_error(vec![Const(2.0), Const(3.0), Const(0.0), PowMod], RuntimeError::DomainError);
+        assert_runtime_error(vec![Const(2.5), Const(3.0), Const(7.0), PowMod], RuntimeError::DomainError);
+    }
+
+    fn assert_rel_eq(a: f64, b: f64, tolerance: f64) {
+        assert!(((a - b) / b).abs() < tolerance, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_erf_known_values() {
+        assert_eq!(eval(vec![Const(0.0), Erf]), 0.0);
+        assert_rel_eq(eval(vec![Const(0.5), Erf]), 0.520_499_877_813_046_5, 1e-14);
+        assert_rel_eq(eval(vec![Const(1.0), Erf]), 0.842_700_792_949_714_9, 1e-14);
+        assert_rel_eq(eval(vec![Const(-1.0), Erf]), -0.842_700_792_949_714_9, 1e-14);
+        assert_rel_eq(eval(vec![Const(2.0), Erf]), 0.995_322_265_018_952_7, 1e-14);
+        assert_eq!(eval(vec![Const(10.0), Erf]), 1.0);
+    }
+
+    #[test]
+    fn test_erfc_tail_accuracy() {
+        assert_rel_eq(eval(vec![Const(1.0), Erfc]), 0.157_299_207_050_285_13, 1e-13);
+        assert_rel_eq(eval(vec![Const(3.0), Erfc]), 2.209_049_699_858_544e-5, 1e-13);
+        assert_rel_eq(eval(vec![Const(5.0), Erfc]), 1.537_459_794_428_035e-12, 1e-13);
+        assert_rel_eq(eval(vec![Const(-3.0), Erfc]), 1.999_977_909_503_001_4, 1e-14);
+    }
+
+    #[test]
+    fn test_normpdf() {
+        let ops = vec![Const(0.0), Const(0.0), Const(1.0), NormPdf];
+        assert_rel_eq(eval(ops), 0.398_942_280_401_432_7, 1e-14);
+
+        let ops = vec![Const(12.0), Const(10.0), Const(2.0), NormPdf];
+        assert_rel_eq(eval(ops), 0.120_985_362_259_571_7, 1e-14);
+
+        let ops = vec![Const(0.0), Const(0.0), Const(0.0), NormPdf];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_normcdf() {
+        let ops = vec![Const(0.0), Const(0.0), Const(1.0), NormCdf];
+        assert_eq!(eval(ops), 0.5);
+
+        let ops = vec![Const(1.96), Const(0.0), Const(1.0), NormCdf];
+        assert_rel_eq(eval(ops), 0.975_002_104_851_780, 1e-13);
+
+        let ops = vec![Const(-6.0), Const(0.0), Const(1.0), NormCdf];
+        assert_rel_eq(eval(ops), 9.865_876_450_376_946e-10, 1e-12);
+    }
+
+    #[cfg(feature = "profile")]
+    #[test]
+    fn test_profile() {
+        let mut vm = VirtualMachine::new();
+        vm.interpret(&[Const(1.0), Const(2.0), Add, Const(3.0), Times]).unwrap();
+        let mut counts: Vec<(String, usize)> =
+            vm.profile().into_iter().map(|(name, stats)| (name, stats.count)).collect();
+        counts.sort();
+        assert_eq!(counts, vec![
+            ("Add".to_string(), 1),
+            ("Const".to_string(), 3),
+            ("Times".to_string(), 1),
+        ]);
+
+        vm.reset_profile();
+        assert!(vm.profile().is_empty());
+    }
+
+    #[test]
+    fn test_statistics() {
+        let xs = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_eq!(eval([list(&xs), vec![Mean]].concat()), 5.0);
+        assert_eq!(eval([list(&xs), vec![Median]].concat()), 4.5);
+        assert_eq!(eval([list(&xs), vec![Mode]].concat()), 4.0);
+        assert_eq!(eval([list(&xs), vec![Var]].concat()), 4.0);
+        assert_eq!(eval([list(&xs), vec![Stdev]].concat()), 2.0);
+        assert_eq!(eval([list(&xs), vec![Sum]].concat()), 40.0);
+        assert_eq!(eval([list(&[1.0, 2.0, 3.0, 4.0]), vec![Prod]].concat()), 24.0);
+    }
+
+    #[test]
+    fn test_mode_prefers_smallest_tie() {
+        assert_eq!(eval([list(&[3.0, 1.0, 3.0, 1.0, 2.0]), vec![Mode]].concat()), 1.0);
+    }
+
+    #[test]
+    fn test_statistics_empty_list() {
+        assert_eq!(eval(vec![MakeList(0), Sum]), 0.0);
+        assert_eq!(eval(vec![MakeList(0), Prod]), 1.0);
+        assert_runtime_error(vec![MakeList(0), Mean], RuntimeError::MathError);
+        assert_runtime_error(vec![MakeList(0), Mode], RuntimeError::MathError);
+        assert_runtime_error(vec![Const(1.0), Stdev], RuntimeError::TypeError);
+    }
+
+    #[test]
+    fn test_linreg() {
+        let ops = [list(&[1.0, 2.0, 3.0, 4.0]), list(&[3.0, 5.0, 7.0, 9.0]), vec![LinReg]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![2.0, 1.0, 1.0]));
+
+        let ops = [list(&[1.0, 2.0, 3.0]), list(&[1.0, 3.0, 2.0]), vec![LinReg]].concat();
+        let Value::List(fit) = eval_value(ops) else { panic!("expected a list") };
+        assert_approx_eq(fit[0].as_number().unwrap(), 0.5);
+        assert_approx_eq(fit[1].as_number().unwrap(), 1.0);
+        assert_approx_eq(fit[2].as_number().unwrap(), 0.25);
+    }
+
+    #[test]
+    fn test_linreg_errors() {
+        let ops = [list(&[1.0, 2.0]), list(&[1.0]), vec![LinReg]].concat();
+        assert_runtime_error(ops, RuntimeError::LengthMismatch);
+
+        let ops = [list(&[2.0, 2.0]), list(&[1.0, 3.0]), vec![LinReg]].concat();
+        assert_runtime_error(ops, RuntimeError::MathError);
+    }
+
+    #[test]
+    fn test_compound() {
+        let ops = vec![Const(1000.0), Const(0.05), Const(10.0), Const(1.0), Compound];
+        assert_approx_eq(eval(ops), 1628.894626777442);
+
+        let ops = vec![Const(1000.0), Const(0.05), Const(10.0), Const(12.0), Compound];
+        assert!(approx_eq(eval(ops), 1647.00949769028, 1e-9));
+    }
+
+    #[test]
+    fn test_present_and_future_value() {
+        let ops = vec![Const(0.1), Const(2.0), Const(100.0), Const(0.0), Fv];
+        assert!(approx_eq(eval(ops), 121.0, 1e-12));
+
+        let ops = vec![Const(0.1), Const(2.0), Const(121.0), Const(0.0), Pv];
+        assert!(approx_eq(eval(ops), 100.0, 1e-12));
+
+        // Two end-of-period payments of 100 at 10%.
+        let ops = vec![Const(0.1), Const(2.0), Const(0.0), Const(100.0), Fv];
+        assert!(approx_eq(eval(ops), 210.0, 1e-12));
+        let ops = vec![Const(0.0), Const(3.0), Const(0.0), Const(100.0), Pv];
+        assert_eq!(eval(ops), 300.0);
+    }
+
+    #[test]
+    fn test_npv_and_irr() {
+        let cashflows = [-100.0, 60.0, 60.0];
+        let ops = [vec![Const(0.1)], list(&cashflows), vec![Npv]].concat();
+        assert!(approx_eq(eval(ops), 4.132231404958677, 1e-12));
+
+        let rate = eval([list(&cashflows), vec![Irr]].concat());
+        assert!(approx_eq(rate, 0.130_662_386_291_807_3, 1e-12));
+
+        let ops = [vec![Const(rate)], list(&cashflows), vec![Npv]].concat();
+        assert!(eval(ops).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_irr_without_sign_change() {
+        assert_runtime_error([list(&[100.0, 10.0]), vec![Irr]].concat(), RuntimeError::MathError);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut vm = VirtualMachine::new();
+        vm.interrupt_handle().store(true, std::sync::atomic::Ordering::Relaxed);
+        let result = vm.interpret(&[Const(1.0), Const(2.0), Add]);
+        assert_eq!(result, Err(RuntimeError::Interrupted));
+
+        // The flag is cleared once acted on.
+        assert_eq!(vm.interpret(&[Const(1.0)]).map(|out| out.result), Ok(Value::Number(1.0)));
+    }
+
+    #[test]
+    fn test_broadcast_scalar_and_list() {
+        let ops = [list(&[1.0, 2.0, 3.0]), vec![Const(10.0), Times]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![10.0, 20.0, 30.0]));
+
+        let ops = [vec![Const(1.0)], list(&[2.0, 4.0]), vec![Divide]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![0.5, 0.25]));
+    }
+
+    #[test]
+    fn test_broadcast_list_and_list() {
+        let ops = [list(&[1.0, 2.0]), list(&[3.0, 4.0]), vec![Power]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![1.0, 16.0]));
+
+        let ops = [list(&[1.0, 2.0]), list(&[3.0]), vec![Add]].concat();
+        assert_runtime_error(ops, RuntimeError::LengthMismatch);
+    }
+
+    #[test]
+    fn test_broadcast_unary_functions() {
+        let ops = [list(&[0.0, std::f64::consts::FRAC_PI_2]), vec![Sin]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![0.0, 1.0]));
+
+        let ops = [list(&[4.0, -1.0]), vec![Sqrt]].concat();
+        assert_runtime_error(ops, RuntimeError::DomainError);
+
+        let ops = vec![MakeList(0), Negate];
+        assert_eq!(eval_value(ops), Value::List(vec![]));
+    }
+
+    #[test]
+    fn test_broadcast_nested_lists() {
+        let ops = [list(&[1.0, 2.0]), list(&[3.0, 4.0]), vec![MakeList(2), Const(1.0), Add]].concat();
+        let expected = Value::List(vec![Value::from(vec![2.0, 3.0]), Value::from(vec![4.0, 5.0])]);
+        assert_eq!(eval_value(ops), expected);
+    }
+
+    #[test]
+    fn test_list_functions_do_not_broadcast() {
+        let ops = [list(&[3.0, 1.0, 2.0]), vec![Sort]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![1.0, 2.0, 3.0]));
+
+        let ops = [list(&[1.0]), vec![Str("a".into()), Add]].concat();
+        assert_runtime_error(ops, RuntimeError::TypeError);
+    }
+
+    #[test]
+    fn test_convert() {
+        let ops = vec![Const(26.2), Str("mi".into()), Str("km".into()), Convert];
+        assert!(approx_eq(eval(ops), 42.164_812_8, 1e-12));
+
+        let ops = vec![Const(1.0), Str("kg".into()), Str("m".into()), Convert];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+
+        let ops = vec![Const(1.0), Str("parsec".into()), Str("m".into()), Convert];
+        assert_runtime_error(ops, RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_bitwise() {
+        assert_eq!(eval(vec![Const(12.0), Const(10.0), BitAnd]), 8.0);
+        assert_eq!(eval(vec![Const(12.0), Const(10.0), BitOr]), 14.0);
+        assert_eq!(eval(vec![Const(12.0), Const(10.0), BitXor]), 6.0);
+        assert_eq!(eval(vec![Const(5.0), BitNot]), -6.0);
+        assert_eq!(eval(vec![Const(1.0), Const(10.0), ShiftLeft]), 1024.0);
+        assert_eq!(eval(vec![Const(-16.0), Const(2.0), ShiftRight]), -4.0);
+
+        assert_runtime_error(vec![Const(1.5), Const(1.0), BitAnd], RuntimeError::DomainError);
+        assert_runtime_error(vec![Const(1.0), Const(64.0), ShiftLeft], RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_programmer_mode_wraps_results() {
+        let mut vm = VirtualMachine::new();
+        vm.word_size = Some(8);
+        let eval = |vm: &mut VirtualMachine, ops: &[Operation]| {
+            vm.interpret(ops).unwrap().result.as_number().unwrap()
+        };
+
+        assert_eq!(eval(&mut vm, &[Const(127.0), Const(1.0), Add]), -128.0);
+        assert_eq!(eval(&mut vm, &[Const(7.0), Const(2.0), Divide]), 3.0);
+        assert_eq!(eval(&mut vm, &[Const(0.0), BitNot]), -1.0);
+        assert_eq!(eval(&mut vm, &[Const(1.0), Const(8.0), ShiftLeft]), 0.0);
+
+        vm.word_size = Some(16);
+        assert_eq!(eval(&mut vm, &[Const(255.0), Const(1.0), Add]), 256.0);
+    }
+
+    #[test]
+    fn test_to_radix() {
+        assert_eq!(eval_value(vec![Const(255.0), ToHex]), Value::Str("0xff".into()));
+        assert_eq!(eval_value(vec![Const(5.0), ToBin]), Value::Str("0b101".into()));
+        assert_eq!(eval_value(vec![Const(-8.0), ToOct]), Value::Str("-0o10".into()));
+        assert_eq!(eval_value(vec![Const(0.0), ToHex]), Value::Str("0x0".into()));
+        assert_runtime_error(vec![Const(1.5), ToHex], RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_polar_conversion() {
+        assert_eq!(
+            eval_value(vec![Const(3.0), Const(4.0), ToPolar]),
+            Value::from(vec![5.0, 4f64.atan2(3.0)])
+        );
+
+        let mut vm = VirtualMachine::new();
+        vm.use_radians = false;
+        let polar = vm.interpret(&[Const(0.0), Const(2.0), ToPolar]).unwrap();
+        assert_eq!(polar.result, Value::from(vec![2.0, 90.0]));
+
+        let rect = vm.interpret(&[Const(2.0), Const(60.0), ToRect]).unwrap();
+        let Value::List(xy) = rect.result else { panic!("expected a list") };
+        assert_approx_eq(xy[0].as_number().unwrap(), 1.0);
+        assert!(approx_eq(xy[1].as_number().unwrap(), 3f64.sqrt(), 1e-15));
+    }
+
+    #[test]
+    fn test_degree_trig_ignores_mode() {
+        let mut vm = VirtualMachine::new();
+        vm.use_radians = true;
+        let mut eval = |ops: &[Operation]| vm.interpret(ops).unwrap().result.as_number().unwrap();
+
+        assert_eq!(eval(&[Const(180.0), SinD]), 0.0);
+        assert_eq!(eval(&[Const(-90.0), CosD]), 0.0);
+        assert_eq!(eval(&[Const(450.0), SinD]), 1.0);
+        assert!(approx_eq(eval(&[Const(30.0), SinD]), 0.5, 1e-15));
+        assert!(approx_eq(eval(&[Const(45.0), TanD]), 1.0, 1e-15));
+        assert_runtime_error(vec![Const(270.0), TanD], RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_angle_unit_conversion() {
+        assert_eq!(eval(vec![Const(180.0), Deg2Rad]), std::f64::consts::PI);
+        assert_eq!(eval(vec![Const(std::f64::consts::PI), Rad2Deg]), 180.0);
+    }
+
+    #[test]
+    fn test_round_to_places() {
+        let round = |x: f64, n: f64| eval(vec![Const(x), Const(n), RoundTo]);
+        assert_eq!(round(12.3456, 2.0), 12.35);
+        assert_eq!(round(1.005, 2.0), 1.01);
+        assert_eq!(round(2.675, 2.0), 2.68);
+        assert_eq!(round(-2.5, 0.0), -3.0);
+        assert_eq!(round(9.99, 1.0), 10.0);
+        assert_eq!(round(1234.5, -2.0), 1200.0);
+        assert_eq!(round(0.4, 0.0), 0.0);
+        assert_eq!(round(0.004, 1.0), 0.0);
+        assert_runtime_error(vec![Const(1.0), Const(0.5), RoundTo], RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_sigfig() {
+        let sigfig = |x: f64, n: f64| eval(vec![Const(x), Const(n), SigFig]);
+        assert_eq!(sigfig(123456.0, 3.0), 123000.0);
+        assert_eq!(sigfig(0.00123456, 2.0), 0.0012);
+        assert_eq!(sigfig(-9.96, 2.0), -10.0);
+        assert_eq!(sigfig(0.0, 3.0), 0.0);
+        assert_runtime_error(vec![Const(1.0), Const(0.0), SigFig], RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_ieee_policy_propagates_non_finite() {
+        let mut vm = VirtualMachine::new();
+        let output = vm.interpret(&[Const(f64::MAX), Const(2.0), Times]).unwrap();
+        assert_eq!(format_output(output.result(), &FormatOptions::default()), "Output: inf");
+
+        let output = vm.interpret(&[Const(f64::INFINITY), Const(0.0), Times]).unwrap();
+        assert_eq!(format_output(output.result(), &FormatOptions::default()), "Output: nan");
+    }
+
+    #[test]
+    fn test_strict_policy_rejects_non_finite() {
+        let mut vm = VirtualMachine::builder().strict(true).build();
+        let result = vm.interpret(&[Const(f64::MAX), Const(2.0), Times]);
+        assert_eq!(result.unwrap_err().kind(), &RuntimeError::MathError);
+
+        let ops = [list(&[1.0, f64::MAX]), vec![Const(10.0), Times]].concat();
+        assert_eq!(vm.interpret(&ops).unwrap_err().kind(), &RuntimeError::MathError);
+
+        vm.strict = false;
+        assert!(vm.interpret(&[Const(f64::MAX), Const(2.0), Times]).is_ok());
+    }
+
+    #[test]
+    fn test_error_carries_operation_and_operands() {
+        let mut vm = VirtualMachine::new();
+        let error = vm.interpret(&[Const(1.0), Const(2.0), ArcSin, Add]).unwrap_err();
+        assert_eq!(error, RuntimeError::Operation {
+            error: Box::new(RuntimeError::DomainError),
+            index: 2,
+            operation: ArcSin,
+            operands: vec![Value::Number(2.0)],
+            span: None,
+        });
+        assert_eq!(error.to_string(), "domain error: arcsin expects [-1, 1], got 2");
+
+        let spans = [(0, 1), (11, 12), (4, 13), (2, 3)];
+        assert_eq!(
+            error.locate(&spans).to_string(),
+            "domain error: arcsin expects [-1, 1], got 2 (at 4..13)"
+        );
+
+        let error = vm.interpret(&[Const(1.0), Const(0.0), Divide]).unwrap_err();
+        assert_eq!(error.to_string(), "math error in 1 / 0");
+    }
+
+    #[test]
+    fn test_error_shows_operation_as_written() {
+        let mut vm = VirtualMachine::new();
+        let mut message = |ops: Vec<Operation>| vm.interpret(&ops).unwrap_err().to_string();
+        assert_eq!(message(vec![Const(7.0), Const(0.0), FloorDivide]), "math error in 7 // 0");
+        assert_eq!(message(vec![Const(2.5), Str("x".to_string()), RoundTo]), "type error in round(2.5, \"x\")");
+        assert_eq!(message(vec![Str("ff".to_string()), ToHex]), "type error in to_hex(\"ff\")");
+
+        let matrix = vec![Const(1.0), Const(2.0), MakeList(2), Const(3.0), Const(4.0), MakeList(2), MakeList(2)];
+        assert_eq!(
+            message([matrix, vec![Const(5.0), Index]].concat()),
+            "index 5 is out of range for a list of length 2 in [[1, 2], [3, 4]][5]"
+        );
+
+        let long = message(vec![Const(1.0), Const(100.0), Const(1.0), Range, Const(200.0), Index]);
+        assert!(long.ends_with("in [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, ..., 100][200]"));
+    }
+
+    #[test]
+    fn test_error_without_operands_is_bare() {
+        let mut vm = VirtualMachine::new();
+        assert_eq!(vm.interpret(&[Ans]), Err(RuntimeError::NoPreviousAnswer));
+        assert_eq!(vm.interpret(&[Add]), Err(RuntimeError::Underflow));
+    }
+
+    #[test]
+    fn test_trace_records_steps() {
+        let mut vm = VirtualMachine::new();
+        vm.interpret(&[Const(1.0), Const(2.0), Add]).unwrap();
+        assert!(vm.trace().is_empty());
+
+        vm.tracing = true;
+        vm.interpret(&[Const(1.0), Const(2.0), Const(3.0), Times, Add]).unwrap();
+        let stacks: Vec<Vec<Value>> = vm.trace().iter().map(|step| step.stack.clone()).collect();
+        assert_eq!(stacks, vec![
+            vec![Value::Number(1.0)],
+            vec![Value::Number(1.0), Value::Number(2.0)],
+            vec![Value::Number(1.0), Value::Number(2.0), Value::Number(3.0)],
+            vec![Value::Number(1.0), Value::Number(6.0)],
+            vec![Value::Number(7.0)],
+        ]);
+        assert_eq!(vm.trace()[3].operation, Times);
+
+        assert!(vm.interpret(&[Const(1.0), Const(0.0), Divide]).is_err());
+        assert_eq!(vm.trace().len(), 2);
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut vm = VirtualMachine::new();
+        vm.limits.max_operations = Some(3);
+        let ops = vec![Const(1.0), Const(2.0), Const(3.0), Add, Add];
+        let expected = RuntimeError::LimitExceeded(Limit::Operations(3));
+        assert_eq!(vm.interpret(&ops), Err(expected));
+
+        vm.limits = Limits { max_stack_depth: Some(2), ..Limits::default() };
+        let expected = RuntimeError::LimitExceeded(Limit::StackDepth(2));
+        assert_eq!(vm.interpret(&ops), Err(expected));
+        assert!(vm.interpret(&[Const(1.0), Const(2.0), Add]).is_ok());
+
+        vm.limits = Limits { timeout: Some(Duration::ZERO), ..Limits::default() };
+        let error = vm.interpret(&[Const(1.0), Const(2.0), Add]).unwrap_err();
+        assert_eq!(error.to_string(), "limit exceeded: ran for longer than 0ns");
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut vm = VirtualMachine::new();
+        vm.use_radians = false;
+        vm.strict = true;
+        vm.interpret(&[Const(1.0), Const(2.0), MakeList(2)]).unwrap();
+        vm.interpret(&[define("double", &["x"], vec![Load("x".to_string()), Const(2.0), Times])]).unwrap();
+        vm.interpret(&[Const(42.0)]).unwrap();
+
+        let json = serde_json::to_string(&vm.snapshot()).unwrap();
+        let mut restored = VirtualMachine::new();
+        restored.restore(serde_json::from_str(&json).unwrap());
+
+        assert_eq!(restored.snapshot(), vm.snapshot());
+        assert!(!restored.use_radians);
+        assert_eq!(restored.get_prev_ans(), Ok(Value::Number(42.0)));
+        assert_eq!(restored.interpret(&[Ans, Const(1.0), Add]).unwrap().result(), &Value::Number(43.0));
+        let call = [Const(4.0), Call("double".to_string(), 1)];
+        assert_eq!(restored.interpret(&call).unwrap().result(), &Value::Number(8.0));
+    }
+
+    #[test]
+    fn test_snapshot_without_functions() {
+        let json = r#"{"use_radians": true, "word_size": null, "strict": false,
+                       "answers": [], "variables": {"x": {"Number": 2.0}}}"#;
+        let snapshot: Snapshot = serde_json::from_str(json).unwrap();
+        assert!(snapshot.functions.is_empty());
+        let mut vm = VirtualMachine::new();
+        vm.restore(snapshot);
+        assert_eq!(vm.interpret(&[Load("x".to_string())]).unwrap().result(), &Value::Number(2.0));
+    }
+
+    #[test]
+    fn test_indexed_answers() {
+        let mut vm = VirtualMachine::new();
+        for x in [10.0, 20.0, 30.0] {
+            vm.interpret(&[Const(x)]).unwrap();
+        }
+        let ans = |vm: &mut VirtualMachine, n: f64| vm.interpret(&[Const(n), AnsAt]);
+        assert_eq!(ans(&mut vm, 1.0).unwrap().result(), &Value::Number(30.0));
+        // The previous line's result is now the most recent answer.
+        assert_eq!(ans(&mut vm, 3.0).unwrap().result(), &Value::Number(20.0));
+        assert_eq!(ans(&mut vm, 9.0).unwrap_err().kind(), &RuntimeError::NoPreviousAnswer);
+        assert_eq!(ans(&mut vm, 0.0).unwrap_err().kind(), &RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_answer_history_is_bounded() {
+        let mut vm = VirtualMachine::new();
+        for i in 0..ANS_HISTORY + 5 {
+            vm.interpret(&[Const(i as f64)]).unwrap();
+        }
+        assert_eq!(vm.snapshot().answers.len(), ANS_HISTORY);
+        let n = ANS_HISTORY as f64;
+        assert_eq!(vm.interpret(&[Const(n), AnsAt]).unwrap().result(), &Value::Number(5.0));
+    }
+
+    #[test]
+    fn test_load_variable() {
+        let mut vm = VirtualMachine::new();
+        let ops = vec![Load("x".to_string()), Const(2.0), Power];
+        assert_eq!(vm.interpret(&ops), Err(RuntimeError::UndefinedVariable("x".to_string())));
+
+        vm.set_variable("x", Value::Number(3.0));
+        assert_eq!(vm.interpret(&ops).unwrap().result(), &Value::Number(9.0));
+    }
+
+    #[test]
+    fn test_store_variable() {
+        let mut vm = VirtualMachine::new();
+        let ops = vec![Const(5.0), Store("x".to_string())];
+        assert_eq!(vm.interpret(&ops).unwrap().result(), &Value::Number(5.0));
+
+        let ops = vec![Load("x".to_string()), Const(2.0), Power, Load("x".to_string()), Add];
+        assert_eq!(vm.interpret(&ops).unwrap().result(), &Value::Number(30.0));
+    }
+
+    #[test]
+    fn test_builtin_constants() {
+        let constant = |name: &str| eval(vec![Load(name.to_string())]);
+        assert_eq!(constant("pi"), PI);
+        assert_eq!(constant("e"), E);
+        assert_eq!(constant("tau"), TAU);
+        assert_eq!(constant("phi"), (1.0 + 5.0_f64.sqrt()) / 2.0);
+    }
+
+    #[test]
+    fn test_builtins_are_protected() {
+        let mut vm = VirtualMachine::new();
+        let assign_pi = vec![Const(3.0), Store("pi".to_string())];
+        assert_eq!(vm.interpret(&assign_pi), Err(RuntimeError::ProtectedBuiltin("pi".to_string())));
+        assert_eq!(vm.interpret(&[Load("pi".to_string())]).unwrap().result(), &Value::Number(PI));
+
+        vm.allow_builtin_override = true;
+        vm.interpret(&assign_pi).unwrap();
+        assert_eq!(vm.interpret(&[Load("pi".to_string())]).unwrap().result(), &Value::Number(3.0));
+
+        assert!(vm.reset_builtin("pi"));
+        assert!(!vm.reset_builtin("x"));
+        assert_eq!(vm.interpret(&[Load("pi".to_string())]).unwrap().result(), &Value::Number(PI));
+    }
+
+    fn define(name: &str, params: &[&str], body: Vec<Operation>) -> Operation {
+        Define(Box::new(Function {
+            name: name.to_string(),
+            params: params.iter().map(|p| p.to_string()).collect(),
+            body,
+        }))
+    }
+
+    #[test]
+    fn test_user_function() {
+        let mut vm = VirtualMachine::new();
+        let body = vec![Load("x".to_string()), Const(2.0), Power, Const(1.0), Add];
+        let output = vm.interpret(&[define("f", &["x"], body)]).unwrap();
+        assert_eq!(output.result(), &Value::Str("f(x)".to_string()));
+
+        let call = vec![Const(3.0), Call("f".to_string(), 1)];
+        assert_eq!(vm.interpret(&call).unwrap().result(), &Value::Number(10.0));
+
+        // Parameters shadow globals only inside the call.
+        vm.set_variable("x", Value::Number(100.0));
+        assert_eq!(vm.interpret(&call).unwrap().result(), &Value::Number(10.0));
+        assert_eq!(vm.interpret(&[Load("x".to_string())]).unwrap().result(), &Value::Number(100.0));
+    }
+
+    #[test]
+    fn test_function_sees_globals_not_caller_parameters() {
+        let mut vm = VirtualMachine::new();
+        vm.interpret(&[define("g", &[], vec![Load("y".to_string())])]).unwrap();
+        vm.interpret(&[define("f", &["y"], vec![Call("g".to_string(), 0)])]).unwrap();
+        let result = vm.interpret(&[Const(1.0), Call("f".to_string(), 1)]);
+        assert_eq!(result.unwrap_err().kind(), &RuntimeError::UndefinedVariable("y".to_string()));
+    }
+
+    #[test]
+    fn test_function_call_errors() {
+        let mut vm = VirtualMachine::new();
+        let result = vm.interpret(&[Const(1.0), Call("f".to_string(), 1)]);
+        assert_eq!(result.unwrap_err().kind(), &RuntimeError::UndefinedFunction("f".to_string()));
+
+        vm.interpret(&[define("f", &["x"], vec![Load("x".to_string()), Ln])]).unwrap();
+        let result = vm.interpret(&[Const(1.0), Const(2.0), Call("f".to_string(), 2)]);
+        assert_eq!(result.unwrap_err().kind(), &RuntimeError::ArgumentCount {
+            function: "f".to_string(),
+            expected: 1,
+            found: 2,
+        });
+
+        let error = vm.interpret(&[Const(-1.0), Call("f".to_string(), 1)]).unwrap_err();
+        assert_eq!(error.to_string(), "domain error: ln expects (0, inf), got -1 in f(-1)");
+
+        vm.interpret(&[define("loop", &["x"], vec![Load("x".to_string()), Call("loop".to_string(), 1)])]).unwrap();
+        let result = vm.interpret(&[Const(1.0), Call("loop".to_string(), 1)]);
+        assert_eq!(result.unwrap_err().kind(), &RuntimeError::RecursionLimit(1000));
+    }
+
+    #[test]
+    fn test_let_scopes() {
+        let mut vm = VirtualMachine::new();
+        vm.set_variable("a", Value::Number(10.0));
+        let load = |name: &str| Load(name.to_string());
+        let bind = |name: &str| PushScope(name.to_string());
+
+        // let a = 3 in a*a + 1
+        let ops = [Const(3.0), bind("a"), load("a"), load("a"), Times, Const(1.0), Add, PopScope];
+        assert_eq!(vm.interpret(&ops).unwrap().result(), &Value::Number(10.0));
+        assert_eq!(vm.interpret(&[load("a")]).unwrap().result(), &Value::Number(10.0));
+
+        // let a = a + 1 in let a = a * 2 in a
+        let ops = [
+            load("a"), Const(1.0), Add, bind("a"),
+            load("a"), Const(2.0), Times, bind("a"),
+            load("a"), PopScope, PopScope,
+        ];
+        assert_eq!(vm.interpret(&ops).unwrap().result(), &Value::Number(22.0));
+
+        // A failed evaluation does not leave its scopes behind.
+        let ops = [Const(1.0), bind("b"), Const(0.0), Ln, PopScope];
+        assert!(vm.interpret(&ops).is_err());
+        let result = vm.interpret(&[load("b")]);
+        assert_eq!(result.unwrap_err().kind(), &RuntimeError::UndefinedVariable("b".to_string()));
+    }
+
+    #[test]
+    fn test_function_does_not_see_caller_let() {
+        let mut vm = VirtualMachine::new();
+        vm.interpret(&[define("g", &[], vec![Load("y".to_string())])]).unwrap();
+        let ops = [Const(1.0), PushScope("y".to_string()), Call("g".to_string(), 0), PopScope];
+        let result = vm.interpret(&ops);
+        assert_eq!(result.unwrap_err().kind(), &RuntimeError::UndefinedVariable("y".to_string()));
+    }
+
+    fn branches(then: Vec<Operation>, otherwise: Vec<Operation>) -> Operation {
+        If(Box::new(Branches { then, otherwise }))
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(eval(vec![Const(1.0), Const(2.0), Less]), 1.0);
+        assert_eq!(eval(vec![Const(2.0), Const(2.0), LessEqual]), 1.0);
+        assert_eq!(eval(vec![Const(1.0), Const(2.0), Greater]), 0.0);
+        assert_eq!(eval(vec![Const(1.0), Const(2.0), GreaterEqual]), 0.0);
+        assert_eq!(eval(vec![Const(2.0), Const(2.0), Equal]), 1.0);
+        assert_eq!(eval(vec![Const(f64::NAN), Const(f64::NAN), NotEqual]), 1.0);
+    }
+
+    #[test]
+    fn test_if_runs_only_the_chosen_branch() {
+        // if x < 0 then -x else x, with the unused branch failing if run.
+        let abs = |x: f64| branches(
+            vec![Const(x), Negate],
+            vec![Const(x), Const(0.0), Ln],
+        );
+        assert_eq!(eval(vec![Const(1.0), abs(-3.0)]), 3.0);
+
+        let mut vm = VirtualMachine::new();
+        let error = vm.interpret(&[Const(0.0), abs(-3.0)]).unwrap_err();
+        assert_eq!(error.kind(), &RuntimeError::DomainError);
+        assert_eq!(error.to_string(), "domain error: ln expects (0, inf), got 0");
+
+        let result = vm.interpret(&[Str("yes".to_string()), abs(1.0)]);
+        assert_eq!(result.unwrap_err().kind(), &RuntimeError::TypeError);
+    }
+
+    #[test]
+    fn test_for_loop() {
+        let mut vm = VirtualMachine::new();
+        vm.set_variable("total", Value::Number(0.0));
+        // for i = 1 to 100 { total = total + i }
+        let body = Loop {
+            variable: "i".to_string(),
+            condition: Vec::new(),
+            body: vec![
+                Load("total".to_string()), Load("i".to_string()), Add, Store("total".to_string()),
+            ],
+        };
+        let ops = [Const(1.0), Const(100.0), For(Box::new(body))];
+        assert_eq!(vm.interpret(&ops).unwrap().result(), &Value::Number(5050.0));
+        assert_eq!(vm.interpret(&[Load("total".to_string())]).unwrap().result(), &Value::Number(5050.0));
+
+        let result = vm.interpret(&[Load("i".to_string())]);
+        assert_eq!(result.unwrap_err().kind(), &RuntimeError::UndefinedVariable("i".to_string()));
+        assert_eq!(eval_value(vec![Const(2.0), Const(1.0), ops[2].clone()]), Value::Number(0.0));
+    }
+
+    #[test]
+    fn test_while_loop_and_iteration_limit() {
+        let mut vm = VirtualMachine::new();
+        vm.set_variable("x", Value::Number(1000.0));
+        // while x > 1 { x = x / 2 }
+        let body = Loop {
+            variable: String::new(),
+            condition: vec![Load("x".to_string()), Const(1.0), Greater],
+            body: vec![Load("x".to_string()), Const(2.0), Divide, Store("x".to_string())],
+        };
+        let ops = [While(Box::new(body))];
+        assert_eq!(vm.interpret(&ops).unwrap().result(), &Value::Number(0.9765625));
+
+        vm.limits.max_iterations = Some(5);
+        vm.set_variable("x", Value::Number(1000.0));
+        let error = vm.interpret(&ops).unwrap_err();
+        assert_eq!(error, RuntimeError::LimitExceeded(Limit::Iterations(5)));
+        assert_eq!(error.to_string(), "limit exceeded: loop ran more than 5 times");
+    }
+
+    #[test]
+    fn test_sequence_keeps_last_value() {
+        assert_eq!(eval(vec![Const(1.0), Const(2.0), Sequence]), 2.0);
+    }
+
+    #[test]
+    fn test_recursion_limit_is_configurable() {
+        let mut vm = VirtualMachine::new();
+        // down(n) = if n > 0 then down(n - 1) else 0
+        let body = vec![
+            Load("n".to_string()), Const(0.0), Greater,
+            branches(
+                vec![Load("n".to_string()), Const(1.0), Subtract, Call("down".to_string(), 1)],
+                vec![Const(0.0)],
+            ),
+        ];
+        vm.interpret(&[define("down", &["n"], body)]).unwrap();
+        let call = |n: f64| [Const(n), Call("down".to_string(), 1)];
+
+        assert!(vm.interpret(&call(2000.0)).is_err());
+        assert!(vm.interpret(&call(500.0)).is_ok());
+        vm.limits.max_call_depth = 100;
+        let error = vm.interpret(&call(200.0)).unwrap_err();
+        assert_eq!(error.kind(), &RuntimeError::RecursionLimit(100));
+        assert_eq!(error.to_string(), "function calls nested more than 100 deep in down(200)");
+        assert!(vm.interpret(&call(50.0)).is_ok());
+
+        vm.limits.max_call_depth = usize::MAX;
+        let error = vm.interpret(&call(20_000.0)).unwrap_err();
+        assert_eq!(error.kind(), &RuntimeError::RecursionLimit(MAX_CALL_DEPTH));
+    }
+
+    /// A writer whose contents can be read after the VM, which owns it, is
+    /// done with it.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_print_writes_to_output() {
+        let buffer = SharedBuffer::default();
+        let mut vm = VirtualMachine::new();
+        vm.output = Box::new(buffer.clone());
+
+        let ops = [Const(1.0), Const(2.0), MakeList(2), Print, Sum, Print, Const(1.0), Add];
+        assert_eq!(vm.interpret(&ops).unwrap().result(), &Value::Number(4.0));
+        assert_eq!(String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap(), "[1, 2]\n3\n");
+    }
+
+    #[test]
+    fn test_assert() {
+        assert_eq!(eval(vec![Const(2.0), Const(2.0), Equal, Assert]), 1.0);
+        let mut vm = VirtualMachine::new();
+        let error = vm.interpret(&[Const(1.0), Const(2.0), Equal, Assert]).unwrap_err();
+        assert_eq!(error.kind(), &RuntimeError::AssertionFailed);
+        assert_eq!(error.to_string(), "assertion failed in assert(0)");
+    }
+
+    #[test]
+    fn test_len() {
+        assert_eq!(eval([list(&[4.0, 5.0, 6.0]), vec![Len]].concat()), 3.0);
+        assert_eq!(eval(vec![MakeList(0), Len]), 0.0);
+        assert_eq!(eval(vec![Str("héllo".to_string()), Len]), 5.0);
+        assert_runtime_error(vec![Const(3.0), Len], RuntimeError::TypeError);
+    }
+
+    #[test]
+    fn test_index_from_one() {
+        let xs = list(&[4.0, 5.0, 6.0]);
+        assert_eq!(eval([xs.clone(), vec![Const(1.0), Index]].concat()), 4.0);
+        assert_eq!(eval([xs.clone(), vec![Const(3.0), Index]].concat()), 6.0);
+
+        let nested = [list(&[1.0]), list(&[2.0, 3.0]), vec![MakeList(2), Const(2.0), Index]].concat();
+        assert_eq!(eval_value(nested), Value::from(vec![2.0, 3.0]));
+
+        for i in [0.0, 4.0, -1.0] {
+            let ops = [xs.clone(), vec![Const(i), Index]].concat();
+            assert_runtime_error(ops, RuntimeError::IndexOutOfRange { index: i as i64, len: 3 });
+        }
+        assert_runtime_error([xs, vec![Const(1.5), Index]].concat(), RuntimeError::DomainError);
+        assert_runtime_error(vec![Const(2.0), Const(1.0), Index], RuntimeError::TypeError);
+    }
+
+    #[test]
+    fn test_map_filter_reduce() {
+        let mut vm = VirtualMachine::new();
+        let square = vec![Load("x".to_string()), Const(2.0), Power];
+        let is_odd = vec![Load("x".to_string()), Const(2.0), Modulo];
+        let add = vec![Load("a".to_string()), Load("b".to_string()), Add];
+        vm.interpret(&[define("square", &["x"], square)]).unwrap();
+        vm.interpret(&[define("odd", &["x"], is_odd)]).unwrap();
+        vm.interpret(&[define("add", &["a", "b"], add)]).unwrap();
+        let xs = list(&[1.0, 2.0, 3.0, 4.0]);
+        let mut run = |ops: Vec<Operation>| vm.interpret(&ops).map(|output| output.result().clone());
+
+        let map = [vec![Load("square".to_string())], xs.clone(), vec![Map]].concat();
+        assert_eq!(run(map), Ok(Value::from(vec![1.0, 4.0, 9.0, 16.0])));
+        let filter = [vec![Load("odd".to_string())], xs.clone(), vec![Filter]].concat();
+        assert_eq!(run(filter), Ok(Value::from(vec![1.0, 3.0])));
+        let reduce = [vec![Load("add".to_string()), Const(10.0)], xs.clone(), vec![Reduce]].concat();
+        assert_eq!(run(reduce), Ok(Value::Number(20.0)));
+        let empty = vec![Load("add".to_string()), Const(10.0), MakeList(0), Reduce];
+        assert_eq!(run(empty), Ok(Value::Number(10.0)));
+
+        let not_a_function = [vec![Const(1.0)], xs.clone(), vec![Map]].concat();
+        assert_eq!(run(not_a_function).unwrap_err().kind(), &RuntimeError::TypeError);
+        let wrong_arity = [vec![Load("add".to_string())], xs, vec![Map]].concat();
+        assert!(matches!(
+            run(wrong_arity).unwrap_err().kind(),
+            RuntimeError::ArgumentCount { expected: 2, found: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn test_range() {
+        let range = |first: f64, last: f64, step: f64| {
+            eval_value(vec![Const(first), Const(last), Const(step), Range])
+        };
+        assert_eq!(range(1.0, 4.0, 1.0), Value::from(vec![1.0, 2.0, 3.0, 4.0]));
+        assert_eq!(range(0.0, 5.0, 2.0), Value::from(vec![0.0, 2.0, 4.0]));
+        assert_eq!(range(3.0, 1.0, -1.0), Value::from(vec![3.0, 2.0, 1.0]));
+        assert_eq!(range(3.0, 1.0, 1.0), Value::List(vec![]));
+        assert_eq!(range(0.0, 0.3, 0.1), Value::from(vec![0.0, 0.1, 0.2, 0.30000000000000004]));
+
+        assert_runtime_error(vec![Const(1.0), Const(2.0), Const(0.0), Range], RuntimeError::DomainError);
+        let mut vm = VirtualMachine::new();
+        vm.limits.max_list_len = Some(100);
+        let result = vm.interpret(&[Const(1.0), Const(1e12), Const(1.0), Range]);
+        assert_eq!(result.unwrap_err().kind(), &RuntimeError::LimitExceeded(Limit::ListLength(100)));
+    }
+
+    #[test]
+    fn test_sort_is_total() {
+        let ops = [list(&[3.0, f64::NAN, 0.0, -0.0, -1.0]), vec![Sort]].concat();
+        let Value::List(xs) = eval_value(ops) else { panic!("Expected a list") };
+        let xs: Vec<f64> = xs.iter().map(|x| x.as_number().unwrap()).collect();
+        assert_eq!(xs[..4], [-1.0, -0.0, 0.0, 3.0]);
+        assert!(xs[1].is_sign_negative() && xs[4].is_nan());
+    }
+
+    #[test]
+    fn test_list_utilities() {
+        let xs = list(&[1.0, 2.0, 3.0]);
+        assert_eq!(eval_value([xs.clone(), vec![Reverse]].concat()), Value::from(vec![3.0, 2.0, 1.0]));
+        assert_eq!(eval([xs.clone(), vec![Head]].concat()), 1.0);
+        assert_eq!(eval_value([xs.clone(), vec![Tail]].concat()), Value::from(vec![2.0, 3.0]));
+        let ops = [xs.clone(), list(&[4.0]), vec![Concat]].concat();
+        assert_eq!(eval_value(ops), Value::from(vec![1.0, 2.0, 3.0, 4.0]));
+
+        let words = vec![Str("a".to_string()), Const(1.0), MakeList(2), Reverse];
+        assert_eq!(eval_value(words), Value::List(vec![Value::Number(1.0), Value::Str("a".to_string())]));
+        assert_eq!(eval_value(vec![Const(1.0), MakeList(1), Tail]), Value::List(vec![]));
+        assert_runtime_error(vec![MakeList(0), Head], RuntimeError::IndexOutOfRange { index: 1, len: 0 });
+        assert_runtime_error([xs, vec![Const(4.0), Concat]].concat(), RuntimeError::TypeError);
+    }
+
+    fn matrix(rows: &[&[f64]]) -> Vec<Operation> {
+        let mut ops: Vec<Operation> = rows.iter().flat_map(|row| list(row)).collect();
+        ops.push(MakeList(rows.len()));
+        ops
+    }
+
+    #[test]
+    fn test_matmul() {
+        let a = matrix(&[&[1.0, 2.0], &[3.0, 4.0]]);
+        let product = eval_value([a.clone(), matrix(&[&[0.0, 1.0], &[1.0, 0.0]]), vec![MatMul]].concat());
+        assert_eq!(product, eval_value(matrix(&[&[2.0, 1.0], &[4.0, 3.0]])));
+
+        let column = eval_value([a.clone(), list(&[1.0, 1.0]), vec![MatMul]].concat());
+        assert_eq!(column, Value::from(vec![3.0, 7.0]));
+        let row = eval_value([list(&[1.0, 1.0]), a.clone(), vec![MatMul]].concat());
+        assert_eq!(row, Value::from(vec![4.0, 6.0]));
+        assert_eq!(eval([list(&[1.0, 2.0]), list(&[3.0, 4.0]), vec![MatMul]].concat()), 11.0);
+
+        let ops = [a, matrix(&[&[1.0, 2.0, 3.0]]), vec![MatMul]].concat();
+        assert_runtime_error(ops, RuntimeError::ShapeMismatch { left: (2, 2), right: (1, 3) });
+        let ragged = [list(&[1.0]), list(&[2.0, 3.0]), vec![MakeList(2), Transpose]].concat();
+        assert_runtime_error(ragged, RuntimeError::LengthMismatch);
+    }
+
+    #[test]
+    fn test_transpose_det_inv() {
+        let a = matrix(&[&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]]);
+        let transposed = eval_value([a.clone(), vec![Transpose]].concat());
+        assert_eq!(transposed, eval_value(matrix(&[&[1.0, 4.0], &[2.0, 5.0], &[3.0, 6.0]])));
+        assert_runtime_error([a, vec![Det]].concat(), RuntimeError::NotSquare { rows: 2, cols: 3 });
+
+        let b = matrix(&[&[2.0, 0.0], &[0.0, 4.0]]);
+        assert_eq!(eval([b.clone(), vec![Det]].concat()), 8.0);
+        assert_eq!(eval_value([b, vec![Inv]].concat()), eval_value(matrix(&[&[0.5, 0.0], &[0.0, 0.25]])));
+
+        let singular = matrix(&[&[1.0, 2.0], &[2.0, 4.0]]);
+        assert_eq!(eval([singular.clone(), vec![Det]].concat()), 0.0);
+        assert_runtime_error([singular, vec![Inv]].concat(), RuntimeError::Singular);
+    }
+
+    #[test]
+    fn test_vector_products() {
+        let (a, b) = (list(&[1.0, 2.0, 3.0]), list(&[4.0, 5.0, 6.0]));
+        assert_eq!(eval([a.clone(), b.clone(), vec![Dot]].concat()), 32.0);
+        assert_eq!(eval_value([a.clone(), b.clone(), vec![Cross]].concat()), Value::from(vec![-3.0, 6.0, -3.0]));
+        assert_eq!(eval([list(&[3.0, 4.0]), vec![Norm]].concat()), 5.0);
+
+        assert_runtime_error([a.clone(), list(&[1.0]), vec![Dot]].concat(), RuntimeError::LengthMismatch);
+        assert_runtime_error([list(&[1.0, 2.0]), list(&[3.0, 4.0]), vec![Cross]].concat(), RuntimeError::DomainError);
+        assert_runtime_error([a, vec![Const(1.0), Dot]].concat(), RuntimeError::TypeError);
+    }
+
+    #[test]
+    fn test_linsolve() {
+        // y + z = 4, 2x + y = 5, x - y = 1, with a zero first pivot.
+        let a = matrix(&[&[0.0, 1.0, 1.0], &[2.0, 1.0, 0.0], &[1.0, -1.0, 0.0]]);
+        let x = eval_value([a.clone(), list(&[4.0, 5.0, 1.0]), vec![LinSolve]].concat());
+        assert_eq!(x, Value::from(vec![2.0, 1.0, 3.0]));
+
+        assert_runtime_error([a, list(&[1.0, 2.0]), vec![LinSolve]].concat(), RuntimeError::LengthMismatch);
+        let singular = matrix(&[&[1.0, 2.0], &[2.0, 4.0]]);
+        assert_runtime_error([singular, list(&[1.0, 2.0]), vec![LinSolve]].concat(), RuntimeError::Singular);
+    }
+
+    #[test]
+    fn test_polynomials() {
+        let p = list(&[1.0, -3.0, 2.0]);
+        assert_eq!(eval([p.clone(), vec![Const(3.0), PolyVal]].concat()), 2.0);
+        let ys = eval_value([p.clone(), list(&[0.0, 1.0]), vec![PolyVal]].concat());
+        assert_eq!(ys, Value::from(vec![2.0, 0.0]));
+
+        let Value::List(roots) = eval_value([p, vec![PolyRoots]].concat()) else { panic!("Expected a list") };
+        assert_eq!(roots.len(), 2);
+        for (root, expected) in roots.iter().zip([1.0, 2.0]) {
+            assert!((root.as_number().unwrap() - expected).abs() < 1e-12);
+        }
+        assert_runtime_error([list(&[0.0]), vec![PolyRoots]].concat(), RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_integrate() {
+        let mut vm = VirtualMachine::new();
+        vm.interpret(&[define("f", &["x"], vec![Load("x".to_string()), Const(2.0), Power])]).unwrap();
+        let integral = |vm: &mut VirtualMachine, f: &str, tolerance: f64| {
+            let ops = [Load(f.to_string()), Const(0.0), Const(3.0), Const(tolerance), Integrate];
+            vm.interpret(&ops).map(|output| output.result().as_number().unwrap())
+        };
+        assert!((integral(&mut vm, "f", 1e-10).unwrap() - 9.0).abs() < 1e-9);
+        assert!((integral(&mut vm, "sqrt", 1e-10).unwrap() - 2.0 * 3f64.sqrt()).abs() < 1e-9);
+        assert_eq!(integral(&mut vm, "f", 0.0).unwrap_err().kind(), &RuntimeError::DomainError);
+
+        vm.limits.max_evaluations = Some(10);
+        let error = integral(&mut vm, "sqrt", 1e-14).unwrap_err();
+        assert_eq!(error.kind(), &RuntimeError::LimitExceeded(Limit::Evaluations(10)));
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut vm = VirtualMachine::new();
+        vm.interpret(&[define("f", &["x"], vec![Load("x".to_string()), Const(3.0), Power])]).unwrap();
+        let slope = |vm: &mut VirtualMachine, f: &str, x: f64| {
+            let output = vm.interpret(&[Load(f.to_string()), Const(x), Diff]).unwrap();
+            output.result().as_number().unwrap()
+        };
+        assert!((slope(&mut vm, "f", 2.0) - 12.0).abs() < 1e-9);
+        assert!((slope(&mut vm, "ln", 4.0) - 0.25).abs() < 1e-12);
+
+        let result = vm.interpret(&[Const(1.0), Const(2.0), Diff]);
+        assert_eq!(result.unwrap_err().kind(), &RuntimeError::TypeError);
+    }
+
+    #[test]
+    fn test_solve_and_newton() {
+        let mut vm = VirtualMachine::new();
+        let body = vec![Load("x".to_string()), Const(2.0), Power, Const(2.0), Subtract];
+        vm.interpret(&[define("f", &["x"], body)]).unwrap();
+        let mut root = |ops: &[Operation]| vm.interpret(ops).map(|output| output.result().as_number().unwrap());
+
+        let x = root(&[Load("f".to_string()), Const(0.0), Const(2.0), Solve]).unwrap();
+        assert!((x - 2f64.sqrt()).abs() < 1e-14);
+        let x = root(&[Load("f".to_string()), Const(-1.0), Newton]).unwrap();
+        assert!((x + 2f64.sqrt()).abs() < 1e-14);
+        let x = root(&[Load("cos".to_string()), Const(1.0), Newton]).unwrap();
+        assert!((x - std::f64::consts::FRAC_PI_2).abs() < 1e-14);
+
+        let error = root(&[Load("f".to_string()), Const(2.0), Const(3.0), Solve]).unwrap_err();
+        assert_eq!(error.kind(), &RuntimeError::NoSignChange { a: 2.0, fa: 2.0, b: 3.0, fb: 7.0 });
+        let error = root(&[Load("exp".to_string()), Const(0.0), Newton]).unwrap_err();
+        assert!(matches!(error.kind(), RuntimeError::NoConvergence { iterations: 100, .. }));
+    }
+
+    #[test]
+    fn test_minimize_and_maximize() {
+        let mut vm = VirtualMachine::new();
+        let body = vec![Load("x".to_string()), Const(2.0), Subtract, Const(2.0), Power, Const(1.0), Add];
+        vm.interpret(&[define("f", &["x"], body)]).unwrap();
+        let mut optimum = |f: &str, a: f64, b: f64, op: Operation| {
+            let output = vm.interpret(&[Load(f.to_string()), Const(a), Const(b), op]).unwrap();
+            let Value::List(point) = output.result().clone() else { panic!() };
+            (point[0].as_number().unwrap(), point[1].as_number().unwrap())
+        };
+        let (x, fx) = optimum("f", 0.0, 5.0, Minimize);
+        assert!((x - 2.0).abs() < 1e-7 && (fx - 1.0).abs() < 1e-14);
+        let (x, fx) = optimum("sin", 0.0, 3.0, Maximize);
+        assert!((x - std::f64::consts::FRAC_PI_2).abs() < 1e-7 && (fx - 1.0).abs() < 1e-14);
+        assert_eq!(optimum("f", 0.0, 5.0, Maximize), (5.0, 10.0));
+    }
+
+    #[test]
+    fn test_rationalize_and_confrac() {
+        let text = |ops: Vec<Operation>| match eval_value(ops) {
+            Value::Str(text) => text,
+            value => panic!("{:?}", value),
+        };
+        assert_eq!(text(vec![Const(0.333333), Const(RATIONALIZE_MAX_DENOMINATOR), Rationalize]), "1/3");
+        assert_eq!(text(vec![Const(PI), Const(1000.0), Rationalize]), "355/113");
+        assert_eq!(text(vec![Const(-4.0), Const(10.0), Rationalize]), "-4");
+        assert_runtime_error(vec![Const(0.5), Const(0.5), Rationalize], RuntimeError::DomainError);
+
+        assert_eq!(eval_value(vec![Const(4.25), Const(10.0), ConFrac]), Value::from(vec![4.0, 4.0]));
+        assert_runtime_error(vec![Const(f64::NAN), Const(3.0), ConFrac], RuntimeError::DomainError);
+    }
+
+    #[test]
+    fn test_clear_and_reset_settings() {
+        let mut vm = VirtualMachine::new();
+        vm.allow_builtin_override = true;
+        vm.use_radians = false;
+        vm.limits.max_call_depth = 10;
+        vm.interpret(&[Const(3.0), Store("pi".to_string())]).unwrap();
+        let body = vec![Load("x".to_string()), Load("pi".to_string()), Add];
+        vm.interpret(&[define("f", &["x"], body)]).unwrap();
+
+        vm.reset_settings();
+        assert!(vm.use_radians && !vm.allow_builtin_override);
+        assert_eq!(vm.limits, Limits::default());
+        let call = [Const(1.0), Call("f".to_string(), 1)];
+        assert_eq!(vm.interpret(&call).unwrap().result(), &Value::Number(4.0));
+
+        vm.clear();
+        assert!(vm.variables().is_empty() && vm.functions().is_empty());
+        assert_eq!(vm.get_prev_ans(), Err(RuntimeError::NoPreviousAnswer));
+        let pi = vm.interpret(&[Load("pi".to_string())]).unwrap();
+        assert_eq!(pi.result(), &Value::Number(PI));
     }
 }